@@ -0,0 +1,156 @@
+//! Engine micro-benchmarks: `cargo bench`, or `cargo bench -- search` for one
+//! group. Everything but the save group runs on in-memory storage, so the
+//! numbers measure the engine rather than the disk.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rust_db::binary::{self, DataValue};
+use rust_db::{Database, DatabaseConfig, PersistencePolicy, StorageKind};
+use std::collections::HashMap;
+use std::hint::black_box;
+
+const ROWS: usize = 1_000;
+const TABLE: &str = "users";
+const COLUMNS: [&str; 3] = ["name", "age", "email"];
+
+fn row(i: usize) -> HashMap<String, String> {
+    HashMap::from([
+        ("name".to_string(), format!("User_{:05}", i)),
+        ("age".to_string(), (18 + i % 63).to_string()),
+        ("email".to_string(), format!("user{}@example.com", i)),
+    ])
+}
+
+/// An empty `users` table. Tables are only saved on checkpoint, so inserts
+/// measure the table and the WAL, not file writes.
+fn empty_db(config: DatabaseConfig, storage: StorageKind) -> Database {
+    let mut db = Database::builder()
+        .config(config.persistence(PersistencePolicy::Checkpoint))
+        .storage(storage.open())
+        .build();
+    db.create_table(TABLE).unwrap();
+    for column in COLUMNS {
+        db.add_column(TABLE, column).unwrap();
+    }
+    db
+}
+
+fn memory_db() -> Database {
+    empty_db(DatabaseConfig::default(), StorageKind::Memory)
+}
+
+fn filled(mut db: Database) -> Database {
+    for i in 0..ROWS {
+        db.insert_row(TABLE, &i.to_string(), row(i)).unwrap();
+    }
+    db
+}
+
+fn insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+    group.throughput(Throughput::Elements(ROWS as u64));
+    group.bench_function("rows", |b| {
+        b.iter_batched(
+            memory_db,
+            |mut db| {
+                for i in 0..ROWS {
+                    db.insert_row(TABLE, &i.to_string(), row(i)).unwrap();
+                }
+                db
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn point_lookup(c: &mut Criterion) {
+    let mut db = filled(memory_db());
+    let mut i = 0;
+    c.bench_function("point_lookup", |b| {
+        b.iter(|| {
+            i = (i + 7919) % ROWS;
+            black_box(db.get_row(TABLE, &i.to_string()).unwrap())
+        })
+    });
+}
+
+fn search(c: &mut Criterion) {
+    let mut indexed = filled(memory_db());
+    indexed.build_indexes();
+    let mut scanned = filled(memory_db());
+    scanned.indexer = None;
+    let name = row(ROWS / 2)["name"].clone();
+
+    let mut group = c.benchmark_group("search");
+    for (label, db) in [("indexed", &indexed), ("scan", &scanned)] {
+        group.bench_function(label, |b| {
+            b.iter(|| {
+                black_box(
+                    db.find_rows_by_value_in_table(TABLE, "name", &name, true, None)
+                        .unwrap(),
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+fn wal_flush(c: &mut Criterion) {
+    let mut group = c.benchmark_group("wal_flush");
+    group.throughput(Throughput::Elements(ROWS as u64));
+    group.bench_function("persist", |b| {
+        b.iter_batched(
+            || filled(memory_db()),
+            |mut db| {
+                db.persist_wal().unwrap();
+                db
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+/// The same table written as CSV by `save_table` and in the binary format.
+fn save(c: &mut Criterion) {
+    let dir = std::env::temp_dir().join(format!("rust_db_bench_{}", std::process::id()));
+    let config = DatabaseConfig::default().data_dir(&dir.to_string_lossy());
+    let db = filled(empty_db(config, StorageKind::Local));
+    let csv_path = db.config.table_path(TABLE);
+    let binary_path = dir.join("users.rdb").to_string_lossy().into_owned();
+
+    let table = &db.tables[TABLE];
+    let mut binary_table = binary::Table {
+        columns: COLUMNS.iter().map(|c| c.to_string()).collect(),
+        ..binary::Table::default()
+    };
+    for (row_id, data) in &table.rows {
+        let data = data
+            .iter()
+            .map(|(column, value)| (column.clone(), DataValue::Text(value.clone())))
+            .collect();
+        binary_table.rows.insert(
+            row_id.clone(),
+            binary::Row {
+                data,
+                ..binary::Row::default()
+            },
+        );
+    }
+    let mut binary_db = binary::Database::default();
+    binary_db.tables.insert(TABLE.to_string(), binary_table);
+
+    let mut group = c.benchmark_group("save");
+    group.throughput(Throughput::Elements(ROWS as u64));
+    group.bench_function("csv", |b| {
+        b.iter(|| db.save_table(TABLE, &csv_path).unwrap())
+    });
+    group.bench_function("binary", |b| {
+        b.iter(|| binary::write_database_to_binary(&binary_db, &binary_path).unwrap())
+    });
+    group.finish();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+criterion_group!(benches, insert, point_lookup, search, wal_flush, save);
+criterion_main!(benches);
@@ -0,0 +1,1126 @@
+//! The binary database file format (`RDB2`, and the older `RDBB`): tables of
+//! typed values with optional dictionary-encoded columns and per-row
+//! encryption under a rotating keyring.
+
+use aes_gcm::aead::{Aead, Generate, Key, KeyInit, Nonce};
+use aes_gcm::Aes256Gcm;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write, BufReader, BufWriter};
+#[cfg(feature = "native")]
+use std::thread::{self, JoinHandle};
+use tracing::{debug, info};
+
+/// Size in bytes of the per-row AES-GCM nonce stored ahead of the ciphertext.
+const NONCE_LEN: usize = 12;
+
+/// File header. `RDBB` files predate dictionaries and have no dictionary section.
+const MAGIC: &[u8; 4] = b"RDB2";
+const MAGIC_V1: &[u8; 4] = b"RDBB";
+
+/// The format version new files are written at.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// The file header for a format version.
+fn magic_for(version: u32) -> io::Result<&'static [u8; 4]> {
+    match version {
+        1 => Ok(MAGIC_V1),
+        2 => Ok(MAGIC),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unsupported format version {}", version),
+        )),
+    }
+}
+
+/// The format version a file header names.
+fn version_of(header: &[u8; 4]) -> io::Result<u32> {
+    match header {
+        h if h == MAGIC_V1 => Ok(1),
+        h if h == MAGIC => Ok(2),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid file header")),
+    }
+}
+
+/// Supported data types for row values.
+/// `Sealed` is a cell of an encrypted column that could not be decrypted on read;
+/// it is written back unchanged. `Code` is a cell of a dictionary-encoded column:
+/// an index into the table's dictionary for that column. `GeoPoint` is a
+/// latitude and longitude in degrees.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Text(String),
+    Sealed { key_id: u32, sealed: Vec<u8> },
+    Code(u32),
+    GeoPoint(f64, f64),
+}
+
+/// The distinct text values of a dictionary-encoded column, numbered in the
+/// order they were first seen.
+#[derive(Debug, Default, PartialEq)]
+pub struct Dictionary {
+    values: Vec<String>,
+    codes: HashMap<String, u32>,
+}
+
+impl Dictionary {
+    fn from_values(values: Vec<String>) -> Self {
+        let codes = values.iter().enumerate().map(|(i, v)| (v.clone(), i as u32)).collect();
+        Dictionary { values, codes }
+    }
+
+    /// The code for `value`, adding it to the dictionary if it is new.
+    pub fn encode(&mut self, value: &str) -> u32 {
+        if let Some(&code) = self.codes.get(value) {
+            return code;
+        }
+        let code = self.values.len() as u32;
+        self.values.push(value.to_string());
+        self.codes.insert(value.to_string(), code);
+        code
+    }
+
+    pub fn code(&self, value: &str) -> Option<u32> {
+        self.codes.get(value).copied()
+    }
+
+    pub fn value(&self, code: u32) -> Option<&str> {
+        self.values.get(code as usize).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// A row with its own data types and an encryption flag.
+/// When `encrypted` is set the row's entries are stored as AES-256-GCM ciphertext.
+/// A row that was read without the right key is "locked": `data` is empty and
+/// `sealed` keeps the nonce and ciphertext so the row can be written back untouched.
+/// `key_id` records which keyring entry encrypted the row; `None` means the active key.
+#[derive(Debug, Default)]
+pub struct Row {
+    pub data: HashMap<String, DataValue>,
+    pub encrypted: bool,
+    pub sealed: Option<Vec<u8>>,
+    pub key_id: Option<u32>,
+}
+
+impl Row {
+    /// True when the row is encrypted and could not be decrypted on read.
+    pub fn is_locked(&self) -> bool {
+        self.sealed.is_some()
+    }
+}
+
+/// A 256-bit key used to encrypt rows flagged as encrypted.
+#[derive(Clone)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        EncryptionKey(bytes)
+    }
+
+    /// Generate a fresh random key.
+    pub fn generate() -> Self {
+        let key = Key::<Aes256Gcm>::generate();
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&key);
+        EncryptionKey(bytes)
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(&self.0.into())
+    }
+}
+
+impl std::fmt::Debug for EncryptionKey {
+    // Never print key material.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// A set of encryption keys addressed by ID. New rows are encrypted with the
+/// active key; older rows keep decrypting with whichever key ID they recorded.
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    keys: HashMap<u32, EncryptionKey>,
+    active: u32,
+}
+
+impl Keyring {
+    /// Create a keyring whose active key is `key` under `id`.
+    pub fn new(id: u32, key: EncryptionKey) -> Self {
+        let mut keys = HashMap::new();
+        keys.insert(id, key);
+        Keyring { keys, active: id }
+    }
+
+    pub fn add_key(&mut self, id: u32, key: EncryptionKey) {
+        self.keys.insert(id, key);
+    }
+
+    /// Make `id` the key used for newly encrypted rows.
+    pub fn set_active(&mut self, id: u32) -> io::Result<()> {
+        if !self.keys.contains_key(&id) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("Unknown key id {}", id)));
+        }
+        self.active = id;
+        Ok(())
+    }
+
+    pub fn active_id(&self) -> u32 {
+        self.active
+    }
+
+    pub fn get(&self, id: u32) -> Option<&EncryptionKey> {
+        self.keys.get(&id)
+    }
+
+    /// Retire a key. Rows still encrypted with it will read back locked.
+    pub fn remove_key(&mut self, id: u32) -> Option<EncryptionKey> {
+        if id == self.active {
+            return None;
+        }
+        self.keys.remove(&id)
+    }
+}
+
+/// Table now uses the new Row type.
+/// Cells of `encrypted_columns` are stored as ciphertext even in unencrypted rows.
+/// Columns in `dictionaries` hold `DataValue::Code` cells instead of text.
+#[derive(Debug, Default)]
+pub struct Table {
+    pub columns: Vec<String>,
+    pub encrypted_columns: Vec<String>,
+    pub dictionaries: HashMap<String, Dictionary>,
+    pub rows: HashMap<String, Row>,
+}
+
+impl Table {
+    /// Store a low-cardinality text column as codes into a dictionary of its
+    /// distinct values. Dictionaries are written in plaintext, so encrypted
+    /// columns are refused and cells of encrypted rows keep their text.
+    /// Returns the number of distinct values.
+    pub fn dictionary_encode(&mut self, column: &str) -> io::Result<usize> {
+        if self.encrypted_columns.iter().any(|c| c == column) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Column '{}' is encrypted and cannot be dictionary encoded", column),
+            ));
+        }
+        let dictionary = self.dictionaries.entry(column.to_string()).or_default();
+        for row in self.rows.values_mut().filter(|row| !row.encrypted) {
+            if let Some(value) = row.data.get_mut(column) {
+                if let DataValue::Text(text) = value {
+                    *value = DataValue::Code(dictionary.encode(text));
+                }
+            }
+        }
+        Ok(dictionary.len())
+    }
+
+    /// The cell to store for `text` in `column`: a code for dictionary columns.
+    pub fn text_value(&mut self, column: &str, text: &str) -> DataValue {
+        match self.dictionaries.get_mut(column) {
+            Some(dictionary) => DataValue::Code(dictionary.encode(text)),
+            None => DataValue::Text(text.to_string()),
+        }
+    }
+
+    /// A cell with any dictionary code replaced by its text.
+    pub fn decode<'a>(&self, column: &str, value: &'a DataValue) -> Cow<'a, DataValue> {
+        let text = match value {
+            DataValue::Code(code) => self.dictionaries.get(column).and_then(|d| d.value(*code)),
+            _ => None,
+        };
+        match text {
+            Some(text) => Cow::Owned(DataValue::Text(text.to_string())),
+            None => Cow::Borrowed(value),
+        }
+    }
+
+    /// Replace every dictionary code with its text and drop the dictionaries.
+    pub fn decode_dictionaries(&mut self) {
+        for row in self.rows.values_mut() {
+            for (col, value) in row.data.iter_mut() {
+                if let DataValue::Code(code) = *value {
+                    if let Some(text) = self.dictionaries.get(col).and_then(|d| d.value(code)) {
+                        *value = DataValue::Text(text.to_string());
+                    }
+                }
+            }
+        }
+        self.dictionaries.clear();
+    }
+
+    /// Ids of the rows whose `column` is the text `value`. On a dictionary
+    /// column the value is looked up once and rows are matched by code.
+    pub fn rows_with_text(&self, column: &str, value: &str) -> Vec<&String> {
+        let code = self.dictionaries.get(column).and_then(|d| d.code(value));
+        self.rows
+            .iter()
+            .filter(|(_, row)| match row.data.get(column) {
+                Some(DataValue::Code(c)) => Some(*c) == code,
+                Some(DataValue::Text(text)) => text == value,
+                _ => false,
+            })
+            .map(|(row_id, _)| row_id)
+            .collect()
+    }
+}
+
+/// Database remains mostly the same.
+#[derive(Debug, Default)]
+pub struct Database {
+    pub tables: HashMap<String, Table>,
+}
+
+/// Upper bounds on the lengths and counts a file may declare. The reader
+/// checks each prefix against these before allocating, so a corrupt or hostile
+/// file fails with `InvalidData` instead of requesting gigabytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadLimits {
+    /// Bytes in one string or sealed value.
+    pub max_string_len: usize,
+    /// Rows in one table, and values in one column dictionary.
+    pub max_rows_per_table: usize,
+    /// Tables in one file.
+    pub max_tables: usize,
+    /// Columns of a table, and cells in one row.
+    pub max_columns: usize,
+}
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        Self {
+            max_string_len: 16 * 1024 * 1024,
+            max_rows_per_table: 10_000_000,
+            max_tables: 10_000,
+            max_columns: 4_096,
+        }
+    }
+}
+
+impl ReadLimits {
+    /// Limits for decoding `len` bytes already in memory: nothing in them can
+    /// declare more than `len` of anything.
+    fn within(len: usize) -> Self {
+        Self { max_string_len: len, max_rows_per_table: len, max_tables: len, max_columns: len }
+    }
+}
+
+/// Read a u32 length or count prefix, rejecting values above `max`.
+fn read_len<R: Read>(reader: &mut R, max: usize, what: &str) -> io::Result<usize> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    let len = u32::from_le_bytes(buf) as usize;
+    if len > max {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} of {} exceeds the limit of {}", what, len, max),
+        ));
+    }
+    Ok(len)
+}
+
+/// Helper function to write a string in binary form with a length prefix.
+fn write_string<W: Write>(writer: &mut W, s: &str) -> io::Result<()> {
+    let bytes = s.as_bytes();
+    let len = bytes.len() as u32;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+/// Helper function to read a length-prefixed string.
+fn read_string<R: Read>(reader: &mut R, limits: &ReadLimits) -> io::Result<String> {
+    let len = read_len(reader, limits.max_string_len, "String length")?;
+    let mut buffer = vec![0u8; len];
+    reader.read_exact(&mut buffer)?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+/// Write a DataValue to the writer in binary form.
+/// Format: variant id (u8) followed by the value.
+fn write_data_value<W: Write>(writer: &mut W, value: &DataValue) -> io::Result<()> {
+    match value {
+        DataValue::Int(i) => {
+            writer.write_all(&[0])?;
+            writer.write_all(&i.to_le_bytes())?;
+        },
+        DataValue::Float(f) => {
+            writer.write_all(&[1])?;
+            writer.write_all(&f.to_le_bytes())?;
+        },
+        DataValue::Bool(b) => {
+            writer.write_all(&[2])?;
+            writer.write_all(&[*b as u8])?;
+        },
+        DataValue::Text(s) => {
+            writer.write_all(&[3])?;
+            write_string(writer, s)?;
+        },
+        DataValue::Sealed { key_id, sealed } => {
+            writer.write_all(&[4])?;
+            writer.write_all(&key_id.to_le_bytes())?;
+            writer.write_all(&(sealed.len() as u32).to_le_bytes())?;
+            writer.write_all(sealed)?;
+        },
+        DataValue::Code(code) => {
+            writer.write_all(&[5])?;
+            writer.write_all(&code.to_le_bytes())?;
+        },
+        DataValue::GeoPoint(lat, lon) => {
+            writer.write_all(&[6])?;
+            writer.write_all(&lat.to_le_bytes())?;
+            writer.write_all(&lon.to_le_bytes())?;
+        },
+    }
+    Ok(())
+}
+
+/// Read a DataValue from the reader.
+fn read_data_value<R: Read>(reader: &mut R, limits: &ReadLimits) -> io::Result<DataValue> {
+    let mut variant = [0u8; 1];
+    reader.read_exact(&mut variant)?;
+    match variant[0] {
+        0 => {
+            let mut buf = [0u8; 8];
+            reader.read_exact(&mut buf)?;
+            Ok(DataValue::Int(i64::from_le_bytes(buf)))
+        },
+        1 => {
+            let mut buf = [0u8; 8];
+            reader.read_exact(&mut buf)?;
+            Ok(DataValue::Float(f64::from_le_bytes(buf)))
+        },
+        2 => {
+            let mut buf = [0u8; 1];
+            reader.read_exact(&mut buf)?;
+            Ok(DataValue::Bool(buf[0] != 0))
+        },
+        3 => {
+            let s = read_string(reader, limits)?;
+            Ok(DataValue::Text(s))
+        },
+        4 => {
+            let mut key_id_buf = [0u8; 4];
+            reader.read_exact(&mut key_id_buf)?;
+            let len = read_len(reader, limits.max_string_len, "Sealed value length")?;
+            let mut sealed = vec![0u8; len];
+            reader.read_exact(&mut sealed)?;
+            Ok(DataValue::Sealed { key_id: u32::from_le_bytes(key_id_buf), sealed })
+        },
+        5 => {
+            let mut buf = [0u8; 4];
+            reader.read_exact(&mut buf)?;
+            Ok(DataValue::Code(u32::from_le_bytes(buf)))
+        },
+        6 => {
+            let mut buf = [0u8; 16];
+            reader.read_exact(&mut buf)?;
+            let lat = f64::from_le_bytes(buf[..8].try_into().unwrap());
+            let lon = f64::from_le_bytes(buf[8..].try_into().unwrap());
+            Ok(DataValue::GeoPoint(lat, lon))
+        },
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown DataValue variant")),
+    }
+}
+
+/// Serialize a row's entries: count (u32) followed by (column, value) pairs.
+fn write_row_entries<W: Write>(writer: &mut W, data: &HashMap<String, DataValue>) -> io::Result<()> {
+    let num_entries = data.len() as u32;
+    writer.write_all(&num_entries.to_le_bytes())?;
+    for (col, value) in data {
+        write_string(writer, col)?;
+        write_data_value(writer, value)?;
+    }
+    Ok(())
+}
+
+fn read_row_entries<R: Read>(reader: &mut R, limits: &ReadLimits) -> io::Result<HashMap<String, DataValue>> {
+    let num_entries = read_len(reader, limits.max_columns, "Row entry count")?;
+    let mut row_data = HashMap::new();
+    for _ in 0..num_entries {
+        let col = read_string(reader, limits)?;
+        let val = read_data_value(reader, limits)?;
+        row_data.insert(col, val);
+    }
+    Ok(row_data)
+}
+
+/// Encrypt bytes with a fresh random nonce. Returns nonce || ciphertext.
+fn seal_bytes(key: &EncryptionKey, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+    let nonce = Nonce::<Aes256Gcm>::generate();
+    let ciphertext = key
+        .cipher()
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Encryption failed"))?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Decrypt nonce || ciphertext. Returns None for a wrong key or tampered data.
+fn open_bytes(key: &EncryptionKey, sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce_bytes, ciphertext) = sealed.split_at(NONCE_LEN);
+    let mut nonce = Nonce::<Aes256Gcm>::default();
+    nonce.copy_from_slice(nonce_bytes);
+    key.cipher().decrypt(&nonce, ciphertext).ok()
+}
+
+/// Encrypt a row's entries. Returns nonce || ciphertext.
+fn seal_row(key: &EncryptionKey, data: &HashMap<String, DataValue>) -> io::Result<Vec<u8>> {
+    let mut plaintext = Vec::new();
+    write_row_entries(&mut plaintext, data)?;
+    seal_bytes(key, &plaintext)
+}
+
+/// Decrypt a sealed row back into its entries.
+fn open_row(key: &EncryptionKey, sealed: &[u8]) -> Option<HashMap<String, DataValue>> {
+    let plaintext = open_bytes(key, sealed)?;
+    read_row_entries(&mut plaintext.as_slice(), &ReadLimits::within(plaintext.len())).ok()
+}
+
+/// Encrypt a single cell with the keyring's active key.
+fn seal_value(keyring: &Keyring, value: &DataValue) -> io::Result<DataValue> {
+    let key_id = keyring.active_id();
+    let key = keyring.get(key_id).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("Active key id {} is not in the keyring", key_id))
+    })?;
+    let mut plaintext = Vec::new();
+    write_data_value(&mut plaintext, value)?;
+    Ok(DataValue::Sealed { key_id, sealed: seal_bytes(key, &plaintext)? })
+}
+
+/// Decrypt every sealed cell the keyring has a key for; the rest stay sealed.
+fn open_cells(data: &mut HashMap<String, DataValue>, keyring: &Keyring) {
+    for value in data.values_mut() {
+        if let DataValue::Sealed { key_id, sealed } = value {
+            let opened = keyring
+                .get(*key_id)
+                .and_then(|key| open_bytes(key, sealed))
+                .and_then(|plaintext| {
+                    read_data_value(&mut plaintext.as_slice(), &ReadLimits::within(plaintext.len())).ok()
+                });
+            if let Some(opened) = opened {
+                *value = opened;
+            }
+        }
+    }
+}
+
+/// Serialize an unencrypted row, sealing the cells of the table's encrypted columns.
+fn write_plain_row<W: Write>(
+    writer: &mut W,
+    table: &Table,
+    row_id: &str,
+    data: &HashMap<String, DataValue>,
+    keyring: Option<&Keyring>,
+) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    for (col, value) in data {
+        write_string(writer, col)?;
+        let needs_sealing = table.encrypted_columns.contains(col)
+            && !matches!(value, DataValue::Sealed { .. });
+        if needs_sealing {
+            let keyring = keyring.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Column '{}' of row '{}' is encrypted but no key was provided", col, row_id),
+                )
+            })?;
+            // Sealing a code would leave its text readable in the dictionary.
+            write_data_value(writer, &seal_value(keyring, &table.decode(col, value))?)?;
+        } else {
+            write_data_value(writer, value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the Database state to a binary file.
+/// Rows flagged as encrypted require a keyring; use `write_database_to_binary_with_keyring`.
+pub fn write_database_to_binary(db: &Database, file_path: &str) -> io::Result<()> {
+    write_database_to_binary_with_keyring(db, file_path, None)
+}
+
+/// Writes the Database state to a binary file, encrypting rows flagged as encrypted
+/// with the key they record (or the keyring's active key).
+pub fn write_database_to_binary_with_keyring(
+    db: &Database,
+    file_path: &str,
+    keyring: Option<&Keyring>,
+) -> io::Result<()> {
+    write_database_at_version(db, file_path, keyring, FORMAT_VERSION)
+}
+
+/// Writes the Database state at a given format version. Version 1 has no
+/// dictionary section, so dictionary columns must be decoded first.
+fn write_database_at_version(
+    db: &Database,
+    file_path: &str,
+    keyring: Option<&Keyring>,
+    version: u32,
+) -> io::Result<()> {
+    let magic = magic_for(version)?;
+    if version < 2 && db.tables.values().any(|t| !t.dictionaries.is_empty()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Format version {} cannot store dictionary columns", version),
+        ));
+    }
+    let file = File::create(file_path)?;
+    let mut writer = BufWriter::new(file);
+
+    // Write the header naming the format version.
+    writer.write_all(magic)?;
+
+    // Write the number of tables.
+    let num_tables = db.tables.len() as u32;
+    writer.write_all(&num_tables.to_le_bytes())?;
+
+    for (table_name, table) in &db.tables {
+        // Write table name.
+        write_string(&mut writer, table_name)?;
+
+        // Write columns.
+        let num_columns = table.columns.len() as u32;
+        writer.write_all(&num_columns.to_le_bytes())?;
+        for col in &table.columns {
+            write_string(&mut writer, col)?;
+        }
+
+        // Write the encrypted column policy.
+        writer.write_all(&(table.encrypted_columns.len() as u32).to_le_bytes())?;
+        for col in &table.encrypted_columns {
+            write_string(&mut writer, col)?;
+        }
+
+        // Write the dictionaries: column, then its values in code order.
+        if version >= 2 {
+            writer.write_all(&(table.dictionaries.len() as u32).to_le_bytes())?;
+            for (col, dictionary) in &table.dictionaries {
+                write_string(&mut writer, col)?;
+                writer.write_all(&(dictionary.values.len() as u32).to_le_bytes())?;
+                for value in &dictionary.values {
+                    write_string(&mut writer, value)?;
+                }
+            }
+        }
+
+        // Write rows.
+        let num_rows = table.rows.len() as u32;
+        writer.write_all(&num_rows.to_le_bytes())?;
+        for (row_id, row) in &table.rows {
+            write_string(&mut writer, row_id)?;
+            
+            // Write encrypted flag (1 byte: 0 or 1).
+            writer.write_all(&[row.encrypted as u8])?;
+
+            if row.encrypted {
+                // Encrypted payload: key id, then length-prefixed nonce || ciphertext.
+                let (key_id, sealed) = match (&row.sealed, keyring) {
+                    // Locked rows are written back exactly as they were read.
+                    (Some(sealed), _) => (row.key_id.unwrap_or_default(), sealed.clone()),
+                    (None, Some(keyring)) => {
+                        let key_id = row.key_id.unwrap_or(keyring.active_id());
+                        let key = keyring.get(key_id).ok_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::NotFound,
+                                format!("Row '{}' needs key id {} which is not in the keyring", row_id, key_id),
+                            )
+                        })?;
+                        // Codes are decoded so the row's values are all in the ciphertext.
+                        let data: HashMap<String, DataValue> = row
+                            .data
+                            .iter()
+                            .map(|(col, value)| (col.clone(), table.decode(col, value).into_owned()))
+                            .collect();
+                        (key_id, seal_row(key, &data)?)
+                    }
+                    (None, None) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Row '{}' is flagged encrypted but no key was provided", row_id),
+                        ))
+                    }
+                };
+                writer.write_all(&key_id.to_le_bytes())?;
+                writer.write_all(&(sealed.len() as u32).to_le_bytes())?;
+                writer.write_all(&sealed)?;
+            } else {
+                write_plain_row(&mut writer, table, row_id, &row.data, keyring)?;
+            }
+        }
+    }
+    writer.flush()?;
+    info!("Database written to binary file: {}", file_path);
+    Ok(())
+}
+
+/// Reads the Database state from a binary file. Encrypted rows come back locked.
+pub fn read_database_from_binary(file_path: &str) -> io::Result<Database> {
+    read_database_from_binary_with_keyring(file_path, None)
+}
+
+/// Reads the Database state from a binary file, decrypting encrypted rows with the
+/// keyring entry each row recorded. Rows that cannot be decrypted (no keyring,
+/// retired key, tampered data) are returned locked.
+pub fn read_database_from_binary_with_keyring(
+    file_path: &str,
+    keyring: Option<&Keyring>,
+) -> io::Result<Database> {
+    read_database_from_binary_with_limits(file_path, keyring, &ReadLimits::default())
+}
+
+/// `read_database_from_binary_with_keyring` with explicit `ReadLimits`, for
+/// files larger than the defaults allow or untrusted files that warrant less.
+pub fn read_database_from_binary_with_limits(
+    file_path: &str,
+    keyring: Option<&Keyring>,
+    limits: &ReadLimits,
+) -> io::Result<Database> {
+    let file = File::open(file_path)?;
+    let mut reader = BufReader::new(file);
+
+    let mut header = [0u8; 4];
+    reader.read_exact(&mut header)?;
+    let has_dictionaries = version_of(&header)? >= 2;
+
+    let num_tables = read_len(&mut reader, limits.max_tables, "Table count")?;
+
+    let mut db = Database::default();
+    for _ in 0..num_tables {
+        // Read table name.
+        let table_name = read_string(&mut reader, limits)?;
+
+        // Read columns.
+        let num_columns = read_len(&mut reader, limits.max_columns, "Column count")?;
+        let mut columns = Vec::with_capacity(num_columns);
+        for _ in 0..num_columns {
+            columns.push(read_string(&mut reader, limits)?);
+        }
+
+        // Read the encrypted column policy.
+        let num_encrypted = read_len(&mut reader, limits.max_columns, "Encrypted column count")?;
+        let mut encrypted_columns = Vec::with_capacity(num_encrypted);
+        for _ in 0..num_encrypted {
+            encrypted_columns.push(read_string(&mut reader, limits)?);
+        }
+
+        // Read the dictionaries.
+        let mut dictionaries = HashMap::new();
+        if has_dictionaries {
+            let num_dicts = read_len(&mut reader, limits.max_columns, "Dictionary count")?;
+            for _ in 0..num_dicts {
+                let col = read_string(&mut reader, limits)?;
+                let num_values = read_len(&mut reader, limits.max_rows_per_table, "Dictionary size")?;
+                let mut values = Vec::with_capacity(num_values);
+                for _ in 0..num_values {
+                    values.push(read_string(&mut reader, limits)?);
+                }
+                dictionaries.insert(col, Dictionary::from_values(values));
+            }
+        }
+
+        // Read rows.
+        let num_rows = read_len(&mut reader, limits.max_rows_per_table, "Row count")?;
+        let mut rows = HashMap::new();
+        for _ in 0..num_rows {
+            let row_id = read_string(&mut reader, limits)?;
+            
+            // Read encrypted flag.
+            let mut flag_buf = [0u8; 1];
+            reader.read_exact(&mut flag_buf)?;
+            let encrypted = flag_buf[0] != 0;
+
+            if encrypted {
+                let mut key_id_buf = [0u8; 4];
+                reader.read_exact(&mut key_id_buf)?;
+                let key_id = u32::from_le_bytes(key_id_buf);
+                let len = read_len(&mut reader, limits.max_string_len, "Sealed row length")?;
+                let mut sealed = vec![0u8; len];
+                reader.read_exact(&mut sealed)?;
+                let key = keyring.and_then(|k| k.get(key_id));
+                let row = match key.and_then(|k| open_row(k, &sealed)) {
+                    Some(data) => Row { data, encrypted, sealed: None, key_id: Some(key_id) },
+                    None => Row { data: HashMap::new(), encrypted, sealed: Some(sealed), key_id: Some(key_id) },
+                };
+                rows.insert(row_id, row);
+            } else {
+                let mut row_data = read_row_entries(&mut reader, limits)?;
+                if let Some(keyring) = keyring {
+                    open_cells(&mut row_data, keyring);
+                }
+                rows.insert(row_id, Row { data: row_data, encrypted, sealed: None, key_id: None });
+            }
+        }
+
+        db.tables.insert(table_name, Table { columns, encrypted_columns, dictionaries, rows });
+    }
+    debug!("Database read from binary file: {}", file_path);
+    Ok(db)
+}
+
+/// The format version of a binary database file.
+pub fn format_version(file_path: &str) -> io::Result<u32> {
+    let mut header = [0u8; 4];
+    File::open(file_path)?.read_exact(&mut header)?;
+    version_of(&header)
+}
+
+/// Rewrite a binary database file at `target_version`, returning the version
+/// it was at. Downgrading to version 1 stores dictionary columns as plain
+/// text. Encrypted rows and cells are carried over sealed, so no key is
+/// needed. The file is rewritten to a temporary path and renamed over the
+/// original, so a failed conversion leaves it untouched.
+pub fn convert(file_path: &str, target_version: u32) -> io::Result<u32> {
+    magic_for(target_version)?;
+    let version = format_version(file_path)?;
+    if version == target_version {
+        return Ok(version);
+    }
+    let mut db = read_database_from_binary(file_path)?;
+    if target_version < 2 {
+        for table in db.tables.values_mut() {
+            table.decode_dictionaries();
+        }
+    }
+    let tmp_path = format!("{}.converting", file_path);
+    write_database_at_version(&db, &tmp_path, None, target_version)?;
+    fs::rename(&tmp_path, file_path)?;
+    Ok(version)
+}
+
+/// Move every row encrypted with `old_id` over to `new_id`. Decrypted rows are
+/// re-tagged so the next write seals them with the new key; locked rows are opened
+/// with the old key first. Returns the number of rows rotated.
+pub fn rotate_key(db: &mut Database, keyring: &Keyring, old_id: u32, new_id: u32) -> io::Result<usize> {
+    let old_key = keyring.get(old_id).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("Unknown key id {}", old_id))
+    })?;
+    if keyring.get(new_id).is_none() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("Unknown key id {}", new_id)));
+    }
+    let mut rotated = 0;
+    for table in db.tables.values_mut() {
+        for row in table.rows.values_mut() {
+            if !row.encrypted || row.key_id.unwrap_or(keyring.active_id()) != old_id {
+                continue;
+            }
+            if let Some(sealed) = row.sealed.take() {
+                match open_row(old_key, &sealed) {
+                    Some(data) => row.data = data,
+                    None => {
+                        row.sealed = Some(sealed);
+                        continue;
+                    }
+                }
+            }
+            row.key_id = Some(new_id);
+            rotated += 1;
+        }
+    }
+    Ok(rotated)
+}
+
+/// Re-encrypt a database file from `old_id` to `new_id` on a background thread.
+/// The file is rewritten to a temporary path and renamed over the original, so
+/// readers never observe a half-rotated file.
+#[cfg(feature = "native")]
+pub fn rotate_key_in_background(
+    file_path: &str,
+    keyring: Keyring,
+    old_id: u32,
+    new_id: u32,
+) -> JoinHandle<io::Result<usize>> {
+    let file_path = file_path.to_string();
+    thread::spawn(move || {
+        let mut db = read_database_from_binary_with_keyring(&file_path, Some(&keyring))?;
+        let rotated = rotate_key(&mut db, &keyring, old_id, new_id)?;
+        let tmp_path = format!("{}.rotating", file_path);
+        write_database_to_binary_with_keyring(&db, &tmp_path, Some(&keyring))?;
+        fs::rename(&tmp_path, &file_path)?;
+        Ok(rotated)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_write_and_read_database() {
+        let mut db = Database::default();
+        let mut table = Table { columns: vec!["name".to_string(), "age".to_string()], ..Table::default() };
+        let mut row_data = HashMap::new();
+        row_data.insert("name".to_string(), DataValue::Text("Alice".to_string()));
+        row_data.insert("age".to_string(), DataValue::Int(30));
+        // Create an unencrypted row.
+        table.rows.insert("1".to_string(), Row { data: row_data, ..Row::default() });
+        db.tables.insert("users".to_string(), table);
+
+        let file_path = "test_db.bin";
+        write_database_to_binary(&db, file_path).expect("Failed to write database");
+        let read_db = read_database_from_binary(file_path).expect("Failed to read database");
+
+        // Clean up test file.
+        fs::remove_file(file_path).unwrap();
+
+        // Verify read content.
+        assert!(read_db.tables.contains_key("users"));
+        let users_table = read_db.tables.get("users").unwrap();
+        assert_eq!(users_table.columns, vec!["name", "age"]);
+        let row = users_table.rows.get("1").unwrap();
+        assert!(!row.encrypted);
+        assert_eq!(row.data.get("name").unwrap(), &DataValue::Text("Alice".to_string()));
+        assert_eq!(row.data.get("age").unwrap(), &DataValue::Int(30));
+    }
+
+    #[test]
+    fn test_encrypted_row() {
+        let mut db = Database::default();
+        let mut table = Table { columns: vec!["message".to_string()], ..Table::default() };
+        let mut row_data = HashMap::new();
+        row_data.insert("message".to_string(), DataValue::Text("Secret".to_string()));
+        // Create an encrypted row.
+        table.rows.insert("encrypted1".to_string(), Row { data: row_data, encrypted: true, ..Row::default() });
+        db.tables.insert("secrets".to_string(), table);
+
+        let keyring = Keyring::new(1, EncryptionKey::generate());
+        let file_path = "encrypted_test_db.bin";
+        write_database_to_binary_with_keyring(&db, file_path, Some(&keyring)).expect("Failed to write encrypted database");
+        let raw = fs::read(file_path).unwrap();
+        let read_db = read_database_from_binary_with_keyring(file_path, Some(&keyring)).expect("Failed to read encrypted database");
+        let locked_db = read_database_from_binary(file_path).expect("Failed to read without key");
+
+        // Clean up test file.
+        fs::remove_file(file_path).unwrap();
+
+        // The plaintext must not appear anywhere in the file.
+        assert!(!raw.windows(b"Secret".len()).any(|w| w == b"Secret"));
+
+        let secrets_table = read_db.tables.get("secrets").unwrap();
+        let row = secrets_table.rows.get("encrypted1").unwrap();
+        assert!(row.encrypted);
+        assert!(!row.is_locked());
+        assert_eq!(row.data.get("message").unwrap(), &DataValue::Text("Secret".to_string()));
+
+        let locked_row = locked_db.tables.get("secrets").unwrap().rows.get("encrypted1").unwrap();
+        assert!(locked_row.is_locked());
+        assert!(locked_row.data.is_empty());
+    }
+
+    #[test]
+    fn test_encrypted_row_requires_key() {
+        let mut db = Database::default();
+        let mut table = Table { columns: vec!["message".to_string()], ..Table::default() };
+        table.rows.insert("e".to_string(), Row { encrypted: true, ..Row::default() });
+        db.tables.insert("secrets".to_string(), table);
+
+        let file_path = "no_key_test_db.bin";
+        let result = write_database_to_binary(&db, file_path);
+        let _ = fs::remove_file(file_path);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_encrypted_column() {
+        let mut db = Database::default();
+        let mut table = Table { columns: vec!["name".to_string(), "ssn".to_string()], ..Table::default() };
+        table.encrypted_columns = vec!["ssn".to_string()];
+        let mut row_data = HashMap::new();
+        row_data.insert("name".to_string(), DataValue::Text("Alice".to_string()));
+        row_data.insert("ssn".to_string(), DataValue::Text("123-45-6789".to_string()));
+        table.rows.insert("1".to_string(), Row { data: row_data, ..Row::default() });
+        db.tables.insert("people".to_string(), table);
+
+        let keyring = Keyring::new(1, EncryptionKey::generate());
+        let file_path = "column_test_db.bin";
+        write_database_to_binary_with_keyring(&db, file_path, Some(&keyring)).unwrap();
+        let raw = fs::read(file_path).unwrap();
+        let read_db = read_database_from_binary_with_keyring(file_path, Some(&keyring)).unwrap();
+        let locked_db = read_database_from_binary(file_path).unwrap();
+        fs::remove_file(file_path).unwrap();
+
+        // Only the encrypted column is ciphertext on disk.
+        assert!(raw.windows(b"Alice".len()).any(|w| w == b"Alice"));
+        assert!(!raw.windows(b"123-45-6789".len()).any(|w| w == b"123-45-6789"));
+
+        let row = read_db.tables.get("people").unwrap().rows.get("1").unwrap();
+        assert_eq!(row.data.get("ssn").unwrap(), &DataValue::Text("123-45-6789".to_string()));
+
+        let locked_row = locked_db.tables.get("people").unwrap().rows.get("1").unwrap();
+        assert_eq!(locked_row.data.get("name").unwrap(), &DataValue::Text("Alice".to_string()));
+        assert!(matches!(locked_row.data.get("ssn").unwrap(), DataValue::Sealed { key_id: 1, .. }));
+    }
+
+    #[test]
+    fn test_dictionary_column() {
+        let mut db = Database::default();
+        let mut table = Table { columns: vec!["status".to_string()], ..Table::default() };
+        for (row_id, status) in [("1", "active"), ("2", "blocked"), ("3", "active")] {
+            let mut row_data = HashMap::new();
+            row_data.insert("status".to_string(), DataValue::Text(status.to_string()));
+            table.rows.insert(row_id.to_string(), Row { data: row_data, ..Row::default() });
+        }
+        assert_eq!(table.dictionary_encode("status").unwrap(), 2);
+        let value = table.text_value("status", "pending");
+        table.rows.insert("4".to_string(), Row { data: HashMap::from([("status".to_string(), value)]), ..Row::default() });
+        db.tables.insert("accounts".to_string(), table);
+
+        let file_path = "dictionary_test_db.bin";
+        write_database_to_binary(&db, file_path).unwrap();
+        let raw = fs::read(file_path).unwrap();
+        let read_db = read_database_from_binary(file_path).unwrap();
+        fs::remove_file(file_path).unwrap();
+
+        // Each distinct value is written once, in the dictionary.
+        assert_eq!(raw.windows(b"active".len()).filter(|w| w == b"active").count(), 1);
+
+        let accounts = read_db.tables.get("accounts").unwrap();
+        let row = accounts.rows.get("3").unwrap();
+        let cell = row.data.get("status").unwrap();
+        assert!(matches!(cell, DataValue::Code(_)));
+        assert_eq!(*accounts.decode("status", cell), DataValue::Text("active".to_string()));
+        let mut active = accounts.rows_with_text("status", "active");
+        active.sort();
+        assert_eq!(active, vec!["1", "3"]);
+        assert_eq!(accounts.rows_with_text("status", "pending"), vec!["4"]);
+    }
+
+    #[test]
+    fn test_geo_point() {
+        let mut db = Database::default();
+        let mut table = Table { columns: vec!["location".to_string()], ..Table::default() };
+        let mut row_data = HashMap::new();
+        row_data.insert("location".to_string(), DataValue::GeoPoint(51.5072, -0.1276));
+        table.rows.insert("london".to_string(), Row { data: row_data, ..Row::default() });
+        db.tables.insert("cities".to_string(), table);
+
+        let file_path = "geo_test_db.bin";
+        write_database_to_binary(&db, file_path).unwrap();
+        let read_db = read_database_from_binary(file_path).unwrap();
+        fs::remove_file(file_path).unwrap();
+
+        assert_eq!(
+            read_db.tables["cities"].rows["london"].data["location"],
+            DataValue::GeoPoint(51.5072, -0.1276)
+        );
+    }
+
+    #[test]
+    fn test_rotate_key() {
+        let mut db = Database::default();
+        let mut table = Table { columns: vec!["message".to_string()], ..Table::default() };
+        let mut row_data = HashMap::new();
+        row_data.insert("message".to_string(), DataValue::Text("Secret".to_string()));
+        table.rows.insert("e".to_string(), Row { data: row_data, encrypted: true, ..Row::default() });
+        db.tables.insert("secrets".to_string(), table);
+
+        let mut keyring = Keyring::new(1, EncryptionKey::generate());
+        let file_path = "rotate_test_db.bin";
+        write_database_to_binary_with_keyring(&db, file_path, Some(&keyring)).unwrap();
+
+        keyring.add_key(2, EncryptionKey::generate());
+        keyring.set_active(2).unwrap();
+        let rotated = rotate_key_in_background(file_path, keyring.clone(), 1, 2).join().unwrap().unwrap();
+        assert_eq!(rotated, 1);
+
+        // With the old key retired the row must still open under key 2.
+        keyring.remove_key(1);
+        let read_db = read_database_from_binary_with_keyring(file_path, Some(&keyring)).unwrap();
+        fs::remove_file(file_path).unwrap();
+        let row = read_db.tables.get("secrets").unwrap().rows.get("e").unwrap();
+        assert_eq!(row.key_id, Some(2));
+        assert_eq!(row.data.get("message").unwrap(), &DataValue::Text("Secret".to_string()));
+    }
+
+    #[test]
+    fn test_read_limits() {
+        // A header claiming four billion tables, then a table whose name claims 4 GiB.
+        let file_path = "limits_test_db.bin";
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(file_path, &bytes).unwrap();
+        let err = read_database_from_binary(file_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        bytes.truncate(MAGIC.len());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(file_path, &bytes).unwrap();
+        let err = read_database_from_binary(file_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // A valid file is refused when it has more rows than the limits allow.
+        let mut db = Database::default();
+        let mut table = Table { columns: vec!["name".to_string()], ..Table::default() };
+        for id in ["1", "2"] {
+            let mut row_data = HashMap::new();
+            row_data.insert("name".to_string(), DataValue::Text(id.to_string()));
+            table.rows.insert(id.to_string(), Row { data: row_data, ..Row::default() });
+        }
+        db.tables.insert("users".to_string(), table);
+        write_database_to_binary(&db, file_path).unwrap();
+        let limits = ReadLimits { max_rows_per_table: 1, ..ReadLimits::default() };
+        let err = read_database_from_binary_with_limits(file_path, None, &limits).unwrap_err();
+        let read_db = read_database_from_binary(file_path).unwrap();
+        fs::remove_file(file_path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(read_db.tables["users"].rows.len(), 2);
+    }
+
+    #[test]
+    fn test_convert() {
+        let mut db = Database::default();
+        let mut table = Table { columns: vec!["status".to_string()], ..Table::default() };
+        for (row_id, status) in [("1", "active"), ("2", "active")] {
+            let mut row_data = HashMap::new();
+            row_data.insert("status".to_string(), DataValue::Text(status.to_string()));
+            table.rows.insert(row_id.to_string(), Row { data: row_data, ..Row::default() });
+        }
+        table.dictionary_encode("status").unwrap();
+        let mut row_data = HashMap::new();
+        row_data.insert("status".to_string(), DataValue::Text("hidden".to_string()));
+        table.rows.insert("e".to_string(), Row { data: row_data, encrypted: true, ..Row::default() });
+        db.tables.insert("accounts".to_string(), table);
+
+        let keyring = Keyring::new(1, EncryptionKey::generate());
+        let file_path = "convert_test_db.bin";
+        write_database_to_binary_with_keyring(&db, file_path, Some(&keyring)).unwrap();
+        assert_eq!(format_version(file_path).unwrap(), FORMAT_VERSION);
+
+        // Downgrading stores the dictionary column as text; the encrypted row stays sealed.
+        assert_eq!(convert(file_path, 1).unwrap(), 2);
+        assert_eq!(format_version(file_path).unwrap(), 1);
+        let v1 = read_database_from_binary_with_keyring(file_path, Some(&keyring)).unwrap();
+        let accounts = &v1.tables["accounts"];
+        assert!(accounts.dictionaries.is_empty());
+        assert_eq!(accounts.rows["1"].data["status"], DataValue::Text("active".to_string()));
+        assert_eq!(accounts.rows["e"].data["status"], DataValue::Text("hidden".to_string()));
+
+        assert_eq!(convert(file_path, 2).unwrap(), 1);
+        assert_eq!(format_version(file_path).unwrap(), 2);
+        assert_eq!(convert(file_path, 3).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let v2 = read_database_from_binary(file_path).unwrap();
+        fs::remove_file(file_path).unwrap();
+        assert_eq!(v2.tables["accounts"].rows.len(), 3);
+    }
+}
@@ -1,5 +1,6 @@
+use crate::commands::crypto::{self, ColumnCipher};
+use crate::commands::storage::Storage;
 use serde::{Serialize, Deserialize};
-use std::fs;
 
 #[derive(Serialize, Deserialize)]
 pub struct BloomFilter {
@@ -15,6 +16,11 @@ impl BloomFilter {
         }
     }
 
+    /// Number of bits.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     pub fn add(&mut self, item: &str) {
         let hash1 = Self::hash1(item) % self.size;
         let hash2 = Self::hash2(item) % self.size;
@@ -46,14 +52,37 @@ impl BloomFilter {
         hash
     }
 
-    pub fn save_to_file(&self, file_path: &str) -> std::io::Result<()> {
-        let serialized = serde_json::to_string(self).unwrap();
-        fs::write(file_path, serialized)
+    pub fn save_to_file(&self, storage: &dyn Storage, file_path: &str, cipher: Option<&ColumnCipher>) -> std::io::Result<()> {
+        let serialized = serde_json::to_string(self)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        crypto::write_file(storage, file_path, &serialized, cipher)
+    }
+
+    pub fn load_from_file(storage: &dyn Storage, file_path: &str, cipher: Option<&ColumnCipher>) -> std::io::Result<Self> {
+        let data = crypto::read_file(storage, file_path, cipher)?;
+        Self::from_bytes(data.as_bytes())
     }
 
-    pub fn load_from_file(file_path: &str) -> std::io::Result<Self> {
-        let data = fs::read_to_string(file_path)?;
-        let bf: BloomFilter = serde_json::from_str(&data).unwrap();
+    /// The filter serialized as JSON, for embedding in another file.
+    pub fn to_bytes(&self) -> std::io::Result<Vec<u8>> {
+        serde_json::to_vec(self)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn from_bytes(data: &[u8]) -> std::io::Result<Self> {
+        let bf: BloomFilter = serde_json::from_slice(data)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        // add/contains index the bit array modulo `size`, so a mismatch would panic later.
+        if bf.size == 0 || bf.bit_array.len() != bf.size {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "bloom filter size {} does not match its {} bits",
+                    bf.size,
+                    bf.bit_array.len()
+                ),
+            ));
+        }
         Ok(bf)
     }
 }
\ No newline at end of file
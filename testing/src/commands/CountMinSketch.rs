@@ -0,0 +1,122 @@
+//! Count-min sketch: how often each value was added, estimated in a fixed
+//! table of counters. An estimate is never below the true count and is over
+//! it by at most `total / width * e` with probability `1 - e^-depth`.
+//! `HeavyHitters` uses one to keep track of the most frequent values.
+
+use crate::commands::HyperLogLog::hash64;
+use std::collections::HashMap;
+
+#[derive(Clone)]
+pub struct CountMinSketch {
+    width: usize,
+    depth: usize,
+    /// `depth` rows of `width` counters.
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl CountMinSketch {
+    pub fn new(width: usize, depth: usize) -> Self {
+        let (width, depth) = (width.max(1), depth.max(1));
+        CountMinSketch {
+            width,
+            depth,
+            counts: vec![0; width * depth],
+            total: 0,
+        }
+    }
+
+    /// The counter of `item` in each row, by double hashing one 64-bit hash.
+    fn cells(&self, item: &str) -> impl Iterator<Item = usize> + '_ {
+        let hash = hash64(item);
+        let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        (0..self.depth).map(move |row| {
+            row * self.width + (h1.wrapping_add((row as u64).wrapping_mul(h2)) % self.width as u64) as usize
+        })
+    }
+
+    pub fn add(&mut self, item: &str, count: u64) {
+        let cells: Vec<usize> = self.cells(item).collect();
+        for cell in cells {
+            self.counts[cell] += count;
+        }
+        self.total += count;
+    }
+
+    /// Estimated number of times `item` was added.
+    pub fn estimate(&self, item: &str) -> u64 {
+        self.cells(item).map(|cell| self.counts[cell]).min().unwrap_or(0)
+    }
+
+    /// Number of values added in all.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+/// The most frequent values added, by a count-min sketch and a list of up
+/// to `capacity` candidates with the highest estimates so far.
+#[derive(Clone)]
+pub struct HeavyHitters {
+    sketch: CountMinSketch,
+    capacity: usize,
+    candidates: HashMap<String, u64>,
+    /// Lowest estimate among the candidates once the list is full.
+    floor: u64,
+}
+
+impl HeavyHitters {
+    pub fn new(width: usize, depth: usize, capacity: usize) -> Self {
+        HeavyHitters {
+            sketch: CountMinSketch::new(width, depth),
+            capacity: capacity.max(1),
+            candidates: HashMap::new(),
+            floor: 0,
+        }
+    }
+
+    pub fn add(&mut self, item: &str) {
+        self.sketch.add(item, 1);
+        let estimate = self.sketch.estimate(item);
+        if let Some(count) = self.candidates.get_mut(item) {
+            *count = estimate;
+            return;
+        }
+        if self.candidates.len() >= self.capacity {
+            if estimate <= self.floor {
+                return;
+            }
+            if let Some(lowest) = self.lowest() {
+                self.candidates.remove(&lowest);
+            }
+        }
+        self.candidates.insert(item.to_string(), estimate);
+        if self.candidates.len() >= self.capacity {
+            self.floor = self.lowest().map_or(0, |lowest| self.candidates[&lowest]);
+        }
+    }
+
+    fn lowest(&self) -> Option<String> {
+        self.candidates
+            .iter()
+            .min_by_key(|(_, count)| **count)
+            .map(|(item, _)| item.clone())
+    }
+
+    /// Up to `k` of the most frequent values with their estimated counts,
+    /// most frequent first.
+    pub fn top(&self, k: usize) -> Vec<(String, u64)> {
+        let mut top: Vec<(String, u64)> = self
+            .candidates
+            .keys()
+            .map(|item| (item.clone(), self.sketch.estimate(item)))
+            .collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(k);
+        top
+    }
+
+    pub fn sketch(&self) -> &CountMinSketch {
+        &self.sketch
+    }
+}
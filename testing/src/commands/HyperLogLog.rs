@@ -0,0 +1,72 @@
+//! HyperLogLog: an estimate of how many distinct values were added, in a
+//! fixed 4 KiB however many there are. The standard error is about 1.6%.
+
+/// Bits of the hash that pick a register; there are `1 << PRECISION` of them.
+const PRECISION: u32 = 12;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// FNV-1a of `item` run through the SplitMix64 finalizer, so every bit of the
+/// result depends on every byte. Not stable across versions; sketches are
+/// never saved.
+pub(crate) fn hash64(item: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in item.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+#[derive(Clone)]
+pub struct HyperLogLog {
+    /// Per register, the most leading zeros (plus one) seen in the rest of a hash.
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        HyperLogLog {
+            registers: vec![0; REGISTERS],
+        }
+    }
+
+    pub fn add(&mut self, item: &str) {
+        let hash = hash64(item);
+        let register = (hash >> (64 - PRECISION)) as usize;
+        // The low bit set stops the count at the end of the hash.
+        let rest = (hash << PRECISION) | (1 << (PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if rank > self.registers[register] {
+            self.registers[register] = rank;
+        }
+    }
+
+    /// Estimated number of distinct values added.
+    pub fn estimate(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&rank| 2f64.powi(-(rank as i32)))
+            .sum();
+        let raw = alpha * m * m / sum;
+        let empty = self.registers.iter().filter(|&&rank| rank == 0).count();
+        // Few values leave registers empty; counting those is more accurate then.
+        if raw <= 2.5 * m && empty > 0 {
+            (m * (m / empty as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+}
@@ -1,36 +1,89 @@
+use crate::commands::crypto::{self, ColumnCipher};
+use crate::commands::matching::MatchOptions;
+use crate::commands::storage::Storage;
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
-use std::fs;
 
 #[derive(Serialize, Deserialize)]
 pub struct Indexer {
     // Map a key (for example, a column value) to a list of row IDs
     pub index: HashMap<String, Vec<String>>,
+    // Keys are stored folded with these options, e.g. lowercased for case-insensitive lookups.
+    #[serde(default)]
+    pub options: MatchOptions,
+}
+
+impl Default for Indexer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Indexer {
     pub fn new() -> Self {
+        Self::with_options(MatchOptions::default())
+    }
+
+    pub fn with_options(options: MatchOptions) -> Self {
         Indexer {
             index: HashMap::new(),
+            options,
         }
     }
 
     pub fn add(&mut self, key: &str, row_id: &str) {
-        self.index.entry(key.to_string()).or_insert(Vec::new()).push(row_id.to_string());
+        let key = self.options.fold(key).into_owned();
+        self.index.entry(key).or_default().push(row_id.to_string());
     }
 
     pub fn get(&self, key: &str) -> Option<&Vec<String>> {
-        self.index.get(key)
+        self.index.get(self.options.fold(key).as_ref())
+    }
+
+    // An index over several tables keeps its keys scoped by table and column,
+    // so a value in one table or column never matches rows of another.
+    fn scope(table: &str, column: &str) -> String {
+        format!("{}\u{0}{}\u{0}", table, column)
+    }
+
+    pub fn add_scoped(&mut self, table: &str, column: &str, key: &str, row_id: &str) {
+        let key = Self::scope(table, column) + &self.options.fold(key);
+        self.index.entry(key).or_default().push(row_id.to_string());
+    }
+
+    pub fn get_scoped(&self, table: &str, column: &str, key: &str) -> Option<&Vec<String>> {
+        self.index.get(&(Self::scope(table, column) + &self.options.fold(key)))
+    }
+
+    // Folded keys of one table and column with their row ids.
+    pub fn scoped_entries<'a>(
+        &'a self,
+        table: &str,
+        column: &str,
+    ) -> impl Iterator<Item = (&'a str, &'a Vec<String>)> {
+        let scope = Self::scope(table, column);
+        self.index
+            .iter()
+            .filter_map(move |(key, row_ids)| Some((key.strip_prefix(scope.as_str())?, row_ids)))
+    }
+
+    // Split a scoped key into table, column and folded key.
+    pub fn split_scoped(key: &str) -> Option<(&str, &str, &str)> {
+        let mut parts = key.splitn(3, '\u{0}');
+        Some((parts.next()?, parts.next()?, parts.next()?))
     }
 
-    pub fn save_to_file(&self, file_path: &str) -> std::io::Result<()> {
-        let serialized = serde_json::to_string(self).unwrap();
-        fs::write(file_path, serialized)
+    // Index keys are column values, so the file is encrypted whenever a cipher is given.
+    pub fn save_to_file(&self, storage: &dyn Storage, file_path: &str, cipher: Option<&ColumnCipher>) -> std::io::Result<()> {
+        let serialized = serde_json::to_string(self)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        crypto::write_file(storage, file_path, &serialized, cipher)
     }
 
-    pub fn load_from_file(file_path: &str) -> std::io::Result<Self> {
-        let data = fs::read_to_string(file_path)?;
-        let indexer: Indexer = serde_json::from_str(&data).unwrap();
+    pub fn load_from_file(storage: &dyn Storage, file_path: &str, cipher: Option<&ColumnCipher>) -> std::io::Result<Self> {
+        let data = crypto::read_file(storage, file_path, cipher)?;
+        let indexer: Indexer = serde_json::from_str(&data)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
         Ok(indexer)
     }
 }
\ No newline at end of file
@@ -0,0 +1,108 @@
+//! Crash-safe file replacement: contents go to `<path>.tmp`, are synced, and
+//! only then renamed over `<path>`. A crash leaves either the old file or the
+//! new one, plus at worst a stray temp file that `remove_leftovers` deletes.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use tracing::{debug, warn};
+
+/// Suffix of files being written. Loaders only read `.csv`, `.json` and
+/// `.log` files, so they never pick up a half-written one.
+pub const TEMP_SUFFIX: &str = ".tmp";
+
+pub fn temp_path(path: &str) -> String {
+    format!("{}{}", path, TEMP_SUFFIX)
+}
+
+/// A file written under its temp name. `commit` puts it in place; dropping it
+/// uncommitted removes the temp file and leaves `path` untouched.
+pub struct AtomicFile {
+    path: String,
+    temp: String,
+    file: Option<File>,
+}
+
+impl AtomicFile {
+    pub fn create(path: &str) -> io::Result<Self> {
+        let temp = temp_path(path);
+        let file = File::create(&temp)?;
+        Ok(AtomicFile {
+            path: path.to_string(),
+            temp,
+            file: Some(file),
+        })
+    }
+
+    /// Sync the contents, rename them over the target and sync the directory
+    /// so the rename itself survives a crash.
+    pub fn commit(mut self) -> io::Result<()> {
+        let file = self.file.take().expect("file is only taken by commit");
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&self.temp, &self.path)?;
+        sync_dir(&self.path);
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file
+            .as_mut()
+            .expect("file is only taken by commit")
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file
+            .as_mut()
+            .expect("file is only taken by commit")
+            .flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// Best effort: not every platform can open or sync a directory.
+fn sync_dir(path: &str) {
+    let dir = match Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if let Err(e) = File::open(dir).and_then(|d| d.sync_all()) {
+        debug!("Could not sync directory '{}': {}", dir.display(), e);
+    }
+}
+
+/// Delete temp files left in `dir` by writes that never committed. Returns
+/// how many were removed.
+pub fn remove_leftovers(dir: &str) -> usize {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    let mut removed = 0;
+    for path in entries.flatten().map(|e| e.path()) {
+        let is_temp = path
+            .file_name()
+            .and_then(|f| f.to_str())
+            .is_some_and(|f| f.ends_with(TEMP_SUFFIX));
+        if !is_temp || !path.is_file() {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                warn!("Removed unfinished write '{}'.", path.display());
+                removed += 1;
+            }
+            Err(e) => warn!("Could not remove '{}': {}", path.display(), e),
+        }
+    }
+    removed
+}
@@ -0,0 +1,30 @@
+use crate::commands::db::Database;
+use crate::commands::scheduler::{MaintenanceJob, Priority};
+use std::time::Duration;
+
+/// How often the engine looks for columns added online.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Gives existing rows the default of columns added with
+/// `Database::add_column_online`. Each step fills one batch of
+/// `backfill_batch_rows` rows, so reads and writes get the lock between
+/// batches however large the table is.
+pub struct BackfillEngine;
+
+impl MaintenanceJob for BackfillEngine {
+    fn name(&self) -> &'static str {
+        "backfill_engine"
+    }
+
+    fn priority(&self) -> Priority {
+        Priority::Low
+    }
+
+    fn interval(&self) -> Duration {
+        POLL_INTERVAL
+    }
+
+    fn step(&mut self, db: &mut Database) -> bool {
+        db.backfill_step()
+    }
+}
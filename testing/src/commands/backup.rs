@@ -0,0 +1,119 @@
+//! Backups and the catalog `Database::recover_to` restores them from.
+//!
+//! A `BackupSnapshot` holds every table as of one WAL position. Only taking it
+//! needs the database; writing it does not, which is how `DbHandle::hot_backup`
+//! backs up a database that keeps serving writes.
+
+use crate::commands::crypto::ColumnCipher;
+use crate::commands::db::{Database, DatabaseError, Result};
+use crate::commands::stats::BackfillProgress;
+use crate::commands::storage::Storage;
+use crate::table::table::Table;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::info;
+
+/// One backup in the catalog, stored as a line of JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupEntry {
+    /// Sequence number of the last operation the backup holds.
+    pub lsn: u64,
+    /// When the snapshot was taken, in Unix milliseconds.
+    pub millis: u64,
+    pub dir: String,
+    /// Tables in the backup, each saved as `<dir>/<table>.csv`.
+    pub tables: Vec<String>,
+    /// Online column additions the tables were still being backfilled for.
+    #[serde(default)]
+    pub backfills: Vec<BackfillProgress>,
+}
+
+/// Every backup listed in the catalog at `path`, oldest first. Lines that do
+/// not parse are skipped.
+pub fn read_catalog(storage: &dyn Storage, path: &str) -> Vec<BackupEntry> {
+    storage
+        .read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// The tables of a database as of one WAL position, ready to be written out.
+pub struct BackupSnapshot<'a> {
+    pub(crate) tables: Cow<'a, HashMap<String, Table>>,
+    pub(crate) lsn: u64,
+    pub(crate) millis: u64,
+    pub(crate) backfills: Vec<BackfillProgress>,
+    pub(crate) cipher: Option<ColumnCipher>,
+    pub(crate) storage: Arc<dyn Storage>,
+    /// The WAL and WAL archive, copied into the backup for reference.
+    pub(crate) wal_files: Vec<String>,
+    pub(crate) catalog: String,
+}
+
+impl BackupSnapshot<'_> {
+    /// Sequence number of the last operation the snapshot holds.
+    pub fn lsn(&self) -> u64 {
+        self.lsn
+    }
+
+    /// Write every table plus copies of the WAL files into `dir`, then list
+    /// the backup in the catalog. Returns the paths written.
+    pub fn write(&self, dir: &str) -> Result<Vec<String>> {
+        self.storage
+            .create_dir_all(dir)
+            .map_err(|e| DatabaseError::FileCreationError(dir.to_string(), e.to_string()))?;
+        let mut tables: Vec<String> = self.tables.keys().cloned().collect();
+        tables.sort();
+        let mut written = Vec::new();
+        for table_name in &tables {
+            let file_name = Path::new(dir)
+                .join(format!("{}.csv", table_name))
+                .to_string_lossy()
+                .into_owned();
+            // Values are kept inline, so a backup doesn't need the blob store.
+            Database::write_table(
+                self.storage.as_ref(),
+                &self.cipher,
+                None,
+                &self.tables[table_name],
+                &file_name,
+            )?;
+            written.push(file_name);
+        }
+        for wal in &self.wal_files {
+            let base = Path::new(wal).file_name().unwrap_or_default();
+            let dest = Path::new(dir).join(base).to_string_lossy().into_owned();
+            // The archive may be rotated away while a hot backup runs.
+            match self.storage.copy(wal, &dest) {
+                Ok(()) => written.push(dest),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(DatabaseError::FileCreationError(dest, e.to_string())),
+            }
+        }
+        let entry = BackupEntry {
+            lsn: self.lsn,
+            millis: self.millis,
+            dir: dir.to_string(),
+            tables,
+            backfills: self.backfills.clone(),
+        };
+        let mut line = serde_json::to_string(&entry)
+            .map_err(|e| DatabaseError::FileCreationError(self.catalog.clone(), e.to_string()))?;
+        line.push('\n');
+        self.storage
+            .append(&self.catalog, line.as_bytes())
+            .map_err(|e| DatabaseError::FileCreationError(self.catalog.clone(), e.to_string()))?;
+        info!(
+            "Backup of {} files as of operation {} written to '{}'.",
+            written.len(),
+            self.lsn,
+            dir
+        );
+        Ok(written)
+    }
+}
@@ -0,0 +1,80 @@
+//! `bench`: time each call of an operation and summarise the timings, for the
+//! `--bench` run and for callers that want numbers without a harness. The
+//! criterion suite in `benches/` covers the same operations more rigorously.
+
+use crate::commands::clock::Instant;
+use std::fmt;
+use std::time::Duration;
+
+/// How long the calls of one operation took.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpTimings {
+    pub name: String,
+    pub ops: usize,
+    pub total: Duration,
+    pub min: Duration,
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl OpTimings {
+    /// Mean time per call.
+    pub fn per_op(&self) -> Duration {
+        if self.ops == 0 {
+            return Duration::ZERO;
+        }
+        self.total / self.ops as u32
+    }
+
+    pub fn ops_per_sec(&self) -> f64 {
+        if self.total.is_zero() {
+            return 0.0;
+        }
+        self.ops as f64 / self.total.as_secs_f64()
+    }
+}
+
+impl fmt::Display for OpTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<20} {:>8} ops in {:>10.2?} ({:.0} ops/s; min {:.2?}, p50 {:.2?}, p99 {:.2?}, max {:.2?})",
+            self.name,
+            self.ops,
+            self.total,
+            self.ops_per_sec(),
+            self.min,
+            self.p50,
+            self.p99,
+            self.max
+        )
+    }
+}
+
+/// Call `op` `ops` times, passing the call's index, and time every call.
+pub fn bench(name: &str, ops: usize, mut op: impl FnMut(usize)) -> OpTimings {
+    let mut times = Vec::with_capacity(ops);
+    for i in 0..ops {
+        let started = Instant::now();
+        op(i);
+        times.push(started.elapsed());
+    }
+    let total = times.iter().sum();
+    times.sort();
+    let percentile = |p: usize| {
+        times
+            .get((times.len() * p / 100).min(times.len().saturating_sub(1)))
+            .copied()
+            .unwrap_or_default()
+    };
+    OpTimings {
+        name: name.to_string(),
+        ops,
+        total,
+        min: times.first().copied().unwrap_or_default(),
+        p50: percentile(50),
+        p99: percentile(99),
+        max: times.last().copied().unwrap_or_default(),
+    }
+}
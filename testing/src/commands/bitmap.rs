@@ -0,0 +1,258 @@
+//! Bitmap indexes: for each value of a column, a compressed bitmap of the
+//! positions of the rows holding it. Filters on several such columns are
+//! answered by ANDing and ORing bitmaps instead of scanning rows.
+
+use crate::commands::matching::MatchOptions;
+use crate::table::table::Table;
+use std::collections::{BTreeMap, HashMap};
+
+/// Chunks holding more positions than this are stored as bitsets.
+const ARRAY_MAX: usize = 4096;
+const BITSET_WORDS: usize = 1 << 10;
+
+/// The low 16 bits of the positions in one chunk: a sorted array while
+/// sparse, a bitset once dense.
+#[derive(Debug, Clone, PartialEq)]
+enum Container {
+    Array(Vec<u16>),
+    Bits(Box<[u64; BITSET_WORDS]>),
+}
+
+impl Container {
+    fn insert(&mut self, low: u16) {
+        match self {
+            Container::Array(values) => {
+                if let Err(at) = values.binary_search(&low) {
+                    values.insert(at, low);
+                    if values.len() > ARRAY_MAX {
+                        *self = Container::bits(values.iter().copied());
+                    }
+                }
+            }
+            Container::Bits(words) => words[low as usize / 64] |= 1 << (low % 64),
+        }
+    }
+
+    fn contains(&self, low: u16) -> bool {
+        match self {
+            Container::Array(values) => values.binary_search(&low).is_ok(),
+            Container::Bits(words) => words[low as usize / 64] & (1 << (low % 64)) != 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Container::Array(values) => values.len(),
+            Container::Bits(words) => words.iter().map(|w| w.count_ones() as usize).sum(),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = u16> + '_> {
+        match self {
+            Container::Array(values) => Box::new(values.iter().copied()),
+            Container::Bits(words) => Box::new(
+                (0..=u16::MAX).filter(|&low| words[low as usize / 64] & (1 << (low % 64)) != 0),
+            ),
+        }
+    }
+
+    fn bits(values: impl Iterator<Item = u16>) -> Container {
+        let mut words = Box::new([0u64; BITSET_WORDS]);
+        for low in values {
+            words[low as usize / 64] |= 1 << (low % 64);
+        }
+        Container::Bits(words)
+    }
+
+    /// The same positions in whichever form suits their count.
+    fn normalized(self) -> Container {
+        match self {
+            Container::Bits(_) if self.len() <= ARRAY_MAX => {
+                Container::Array(self.iter().collect())
+            }
+            Container::Array(values) if values.len() > ARRAY_MAX => {
+                Container::bits(values.into_iter())
+            }
+            other => other,
+        }
+    }
+
+    fn and(&self, other: &Container) -> Container {
+        match (self, other) {
+            (Container::Bits(a), Container::Bits(b)) => {
+                let mut words = Box::new([0u64; BITSET_WORDS]);
+                for (i, word) in words.iter_mut().enumerate() {
+                    *word = a[i] & b[i];
+                }
+                Container::Bits(words).normalized()
+            }
+            (Container::Array(values), other) | (other, Container::Array(values)) => {
+                Container::Array(
+                    values
+                        .iter()
+                        .copied()
+                        .filter(|&low| other.contains(low))
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    fn or(&self, other: &Container) -> Container {
+        match (self, other) {
+            (Container::Bits(a), Container::Bits(b)) => {
+                let mut words = Box::new([0u64; BITSET_WORDS]);
+                for (i, word) in words.iter_mut().enumerate() {
+                    *word = a[i] | b[i];
+                }
+                Container::Bits(words)
+            }
+            (Container::Array(values), Container::Bits(words))
+            | (Container::Bits(words), Container::Array(values)) => {
+                let mut words = words.clone();
+                for &low in values {
+                    words[low as usize / 64] |= 1 << (low % 64);
+                }
+                Container::Bits(words)
+            }
+            (Container::Array(a), Container::Array(b)) => {
+                let mut values = Vec::with_capacity(a.len() + b.len());
+                let (mut i, mut j) = (0, 0);
+                while i < a.len() && j < b.len() {
+                    match a[i].cmp(&b[j]) {
+                        std::cmp::Ordering::Less => {
+                            values.push(a[i]);
+                            i += 1;
+                        }
+                        std::cmp::Ordering::Greater => {
+                            values.push(b[j]);
+                            j += 1;
+                        }
+                        std::cmp::Ordering::Equal => {
+                            values.push(a[i]);
+                            i += 1;
+                            j += 1;
+                        }
+                    }
+                }
+                values.extend_from_slice(&a[i..]);
+                values.extend_from_slice(&b[j..]);
+                Container::Array(values).normalized()
+            }
+        }
+    }
+}
+
+/// A set of row positions, split into 65536-position chunks by their high
+/// 16 bits in the manner of roaring bitmaps.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bitmap {
+    chunks: BTreeMap<u16, Container>,
+}
+
+impl Bitmap {
+    pub fn new() -> Self {
+        Bitmap::default()
+    }
+
+    pub fn insert(&mut self, position: u32) {
+        let (high, low) = ((position >> 16) as u16, position as u16);
+        self.chunks
+            .entry(high)
+            .or_insert_with(|| Container::Array(Vec::new()))
+            .insert(low);
+    }
+
+    pub fn contains(&self, position: u32) -> bool {
+        self.chunks
+            .get(&((position >> 16) as u16))
+            .is_some_and(|c| c.contains(position as u16))
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.values().map(Container::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Positions in increasing order.
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.chunks.iter().flat_map(|(&high, container)| {
+            container
+                .iter()
+                .map(move |low| ((high as u32) << 16) | low as u32)
+        })
+    }
+
+    pub fn and(&self, other: &Bitmap) -> Bitmap {
+        let chunks = self
+            .chunks
+            .iter()
+            .filter_map(|(high, a)| {
+                let chunk = a.and(other.chunks.get(high)?);
+                (chunk.len() > 0).then_some((*high, chunk))
+            })
+            .collect();
+        Bitmap { chunks }
+    }
+
+    pub fn or(&self, other: &Bitmap) -> Bitmap {
+        let mut chunks = self.chunks.clone();
+        for (high, b) in &other.chunks {
+            let chunk = match chunks.get(high) {
+                Some(a) => a.or(b),
+                None => b.clone(),
+            };
+            chunks.insert(*high, chunk);
+        }
+        Bitmap { chunks }
+    }
+}
+
+impl FromIterator<u32> for Bitmap {
+    fn from_iter<I: IntoIterator<Item = u32>>(positions: I) -> Self {
+        let mut bitmap = Bitmap::new();
+        for position in positions {
+            bitmap.insert(position);
+        }
+        bitmap
+    }
+}
+
+/// A bitmap per value of one column. Positions number the table's rows in
+/// row id order; values are keyed in their match-options form.
+#[derive(Debug, Clone)]
+pub struct BitmapIndex {
+    bitmaps: HashMap<String, Bitmap>,
+    options: MatchOptions,
+}
+
+impl BitmapIndex {
+    pub fn build(table: &Table, column: &str, options: MatchOptions) -> Self {
+        let mut bitmaps: HashMap<String, Bitmap> = HashMap::new();
+        for (position, row) in table.rows.values().enumerate() {
+            if let Some(value) = row.get(column) {
+                bitmaps
+                    .entry(options.fold(value).into_owned())
+                    .or_default()
+                    .insert(position as u32);
+            }
+        }
+        BitmapIndex { bitmaps, options }
+    }
+
+    /// Positions of the rows holding `value`.
+    pub fn get(&self, value: &str) -> Bitmap {
+        self.bitmaps
+            .get(self.options.fold(value).as_ref())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Number of distinct values.
+    pub fn keys(&self) -> usize {
+        self.bitmaps.len()
+    }
+}
@@ -0,0 +1,118 @@
+//! Content-addressed storage of large values. A value longer than
+//! `DatabaseConfig::blob_threshold` bytes is written once to the blobs
+//! directory, in a file named by its hash, and table files, delta files,
+//! cold segments and WAL records hold a short `blob:` reference in its
+//! place, so equal values share one file. Rows in memory keep the values
+//! themselves. Blobs are never removed, so a reference stays readable for
+//! as long as the data directory does.
+
+use crate::commands::storage::Storage;
+use std::borrow::Cow;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::error;
+
+/// Start of a reference: `blob:` then the blob's name.
+pub const BLOB_PREFIX: &str = "blob:";
+
+/// Whether `value` has the form of a reference: the prefix, 16 hex digits
+/// of hash, and a `-n` suffix if an earlier value had the same hash.
+pub fn is_reference(value: &str) -> bool {
+    let Some(name) = value.strip_prefix(BLOB_PREFIX) else {
+        return false;
+    };
+    let (hash, suffix) = name.split_once('-').unwrap_or((name, "0"));
+    hash.len() == 16
+        && hash
+            .bytes()
+            .all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase())
+        && !suffix.is_empty()
+        && suffix.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// FNV-1a of `value`. Names are only a starting point: a blob is compared
+/// with the value before being shared, so a collision costs a suffix.
+fn hash(value: &str) -> u64 {
+    value.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    storage: Arc<dyn Storage>,
+    dir: String,
+    threshold: usize,
+}
+
+impl BlobStore {
+    /// Blobs kept in `dir`. Values over `threshold` bytes are moved out of
+    /// rows; 0 keeps every value inline but still reads existing references.
+    pub fn new(storage: Arc<dyn Storage>, dir: &str, threshold: usize) -> Self {
+        BlobStore {
+            storage,
+            dir: dir.to_string(),
+            threshold,
+        }
+    }
+
+    /// The form of `value` to store: a reference to its blob if it is over
+    /// the threshold, or would itself read as a reference, otherwise the
+    /// value. A value whose blob can't be written is kept inline.
+    pub fn put<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        let large = self.threshold > 0 && value.len() > self.threshold;
+        if !large && !is_reference(value) {
+            return Cow::Borrowed(value);
+        }
+        match self.write(value) {
+            Ok(name) => Cow::Owned(format!("{}{}", BLOB_PREFIX, name)),
+            Err(e) => {
+                error!(
+                    "Failed to store a {} byte value as a blob: {}",
+                    value.len(),
+                    e
+                );
+                Cow::Borrowed(value)
+            }
+        }
+    }
+
+    /// The value `stored` stands for: the blob's contents if it is a
+    /// reference, otherwise `stored` itself.
+    pub fn get<'a>(&self, stored: &'a str) -> io::Result<Cow<'a, str>> {
+        if !is_reference(stored) {
+            return Ok(Cow::Borrowed(stored));
+        }
+        let path = self.path(&stored[BLOB_PREFIX.len()..]);
+        self.storage.read_to_string(&path).map(Cow::Owned)
+    }
+
+    /// Write `value` unless a blob holds it already. Returns the blob's name.
+    fn write(&self, value: &str) -> io::Result<String> {
+        let hash = format!("{:016x}", hash(value));
+        let mut name = hash.clone();
+        let mut n = 0;
+        loop {
+            let path = self.path(&name);
+            match self.storage.read(&path) {
+                Ok(stored) if stored == value.as_bytes() => return Ok(name),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    self.storage.write(&path, value.as_bytes())?;
+                    return Ok(name);
+                }
+                Err(e) => return Err(e),
+            }
+            n += 1;
+            name = format!("{}-{}", hash, n);
+        }
+    }
+
+    fn path(&self, name: &str) -> String {
+        Path::new(&self.dir)
+            .join(name)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
@@ -0,0 +1,168 @@
+//! `DatabaseBuilder`: configure a database in one place and open it, instead
+//! of adjusting a `Database` (or its `wal_writer`) after construction.
+
+use crate::commands::config::{DatabaseConfig, PersistencePolicy};
+use crate::commands::db::{Database, Result};
+use crate::commands::shards::ShardedDatabase;
+use crate::commands::storage::Storage;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+
+impl Database {
+    /// Start configuring a database, e.g.
+    /// `Database::builder().data_dir("data").save_policy(policy).open()?`.
+    pub fn builder() -> DatabaseBuilder {
+        DatabaseBuilder::default()
+    }
+
+    /// Open the database `config` describes, recovered and with its engines
+    /// running; see `DatabaseBuilder::open`. Use the builder to also give an
+    /// encryption key or another storage backend.
+    pub fn open(config: DatabaseConfig) -> Result<ShardedDatabase> {
+        Database::builder().config(config).open()
+    }
+}
+
+/// Settings for a database about to be opened. Unset values keep the
+/// `DatabaseConfig` defaults.
+#[derive(Default)]
+pub struct DatabaseBuilder {
+    config: DatabaseConfig,
+    encryption_key: Option<[u8; 32]>,
+    /// Unset starts the engines wherever there are threads to run them.
+    engines: Option<bool>,
+    storage: Option<Arc<dyn Storage>>,
+}
+
+impl DatabaseBuilder {
+    /// Replace every setting made so far with `config`, e.g. one read from
+    /// `rustdb.toml`; later calls adjust it.
+    pub fn config(mut self, config: DatabaseConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn data_dir(mut self, dir: &str) -> Self {
+        self.config = self.config.data_dir(dir);
+        self
+    }
+
+    /// Use this backend instead of a new one of the kind `config.storage`
+    /// names, e.g. a `MemoryStorage` a test inspects afterwards.
+    pub fn storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    fn backend(&self) -> Arc<dyn Storage> {
+        self.storage
+            .clone()
+            .unwrap_or_else(|| self.config.storage.open())
+    }
+
+    /// WAL file name, relative to the data directory.
+    pub fn wal(mut self, file: &str) -> Self {
+        self.config = self.config.wal_file(file);
+        self
+    }
+
+    /// When tables are saved to their files, unless overridden per table.
+    pub fn save_policy(mut self, policy: PersistencePolicy) -> Self {
+        self.config = self.config.persistence(policy);
+        self
+    }
+
+    pub fn table_save_policy(mut self, table_name: &str, policy: PersistencePolicy) -> Self {
+        self.config = self.config.table_persistence(table_name, policy);
+        self
+    }
+
+    /// Writes between saves under `PersistencePolicy::Threshold`.
+    pub fn save_threshold(mut self, threshold: usize) -> Self {
+        self.config = self.config.save_threshold(threshold);
+        self
+    }
+
+    pub fn table_save_threshold(mut self, table_name: &str, threshold: usize) -> Self {
+        self.config = self.config.table_save_threshold(table_name, threshold);
+        self
+    }
+
+    /// Column the index is built on. The index covers that column in every table.
+    pub fn index(mut self, column: &str) -> Self {
+        self.config = self.config.index_column(column);
+        self
+    }
+
+    pub fn bloom_filter(mut self, column: &str, size: usize) -> Self {
+        self.config = self.config.bloom_column(column, size);
+        self
+    }
+
+    pub fn checkpoint_interval(mut self, interval: Duration) -> Self {
+        self.config = self.config.checkpoint_interval(interval);
+        self
+    }
+
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.config = self.config.shards(shards);
+        self
+    }
+
+    /// Key for reading and writing encrypted columns and the WAL.
+    pub fn encryption_key(mut self, key: [u8; 32]) -> Self {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    /// Whether `open` starts the async WAL writer and background maintenance
+    /// (on by default). Without them the WAL stays in memory until flushed
+    /// or committed by hand. The engines run on threads, so this is ignored
+    /// without the `native` feature.
+    pub fn engines(mut self, engines: bool) -> Self {
+        self.engines = Some(engines);
+        self
+    }
+
+    /// A single `Database` with these settings, without recovery or engines.
+    pub fn build(self) -> Database {
+        let storage = self.backend();
+        let mut db = Database::with_storage(self.config, storage);
+        if let Some(key) = self.encryption_key {
+            db.set_encryption_key(key);
+        }
+        db
+    }
+
+    /// Open the database's shards ready for use, in the order recovery
+    /// needs: prepare the data directory (see `Database::prepare_data_dir`),
+    /// recover each shard from its files and WAL (see `Database::recover`),
+    /// catalog any table files from before there was a catalog, and only
+    /// then start the engines, unless they were turned off.
+    pub fn open(self) -> Result<ShardedDatabase> {
+        let storage = self.backend();
+        Database::prepare_data_dir(&self.config, storage.as_ref())?;
+        let database = ShardedDatabase::open(&self.config, self.encryption_key, storage);
+        for (i, db) in database.shards().iter().enumerate() {
+            let mut db = db.lock();
+            let cataloged = db.storage.exists(&db.config.catalog_path());
+            db.recover()?;
+            // Table files saved before there was a catalog join the shard that owns them.
+            if !cataloged {
+                db.adopt_table_files(|table_name| database.shard_index(table_name) == i)?;
+            }
+        }
+        let engines = self.engines.unwrap_or(true) && cfg!(feature = "native");
+        #[cfg(feature = "native")]
+        if engines {
+            database.start_engines();
+        }
+        info!(
+            "Database in '{}' opened ({} engines).",
+            self.config.data_dir,
+            if engines { "with" } else { "without" }
+        );
+        Ok(database)
+    }
+}
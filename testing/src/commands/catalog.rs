@@ -0,0 +1,98 @@
+//! The catalog: a file per shard listing its tables with their schemas,
+//! files, row counts and the WAL position of their last checkpoint, so the
+//! tables that exist are known without probing for table files. It is
+//! rewritten whole, in one step, after every change to the set of tables or
+//! their columns, and read when the database is constructed.
+
+use crate::commands::storage::Storage;
+use crate::table::table::Table;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+/// What the catalog records about one table.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    /// Columns in schema order with their datatypes, if declared.
+    pub schema: Vec<(String, Option<String>)>,
+    /// Name of the table's file in the tables directory.
+    pub file: String,
+    /// Rows, cold ones included, as of the last checkpoint.
+    pub rows: usize,
+    /// Sequence number of the last WAL record the table file held at the
+    /// last checkpoint; 0 before the first.
+    pub checkpoint_lsn: u64,
+}
+
+impl CatalogEntry {
+    /// Schema of `table`, kept in `file` (a name in the tables directory);
+    /// the checkpoint fields start at 0.
+    pub fn describe(table: &Table, file: &str) -> Self {
+        CatalogEntry {
+            schema: table
+                .columns
+                .iter()
+                .map(|c| (c.clone(), table.datatype(c).map(str::to_string)))
+                .collect(),
+            file: file.to_string(),
+            rows: 0,
+            checkpoint_lsn: 0,
+        }
+    }
+
+    /// An empty table with this schema, for a table that has no file yet.
+    pub fn empty_table(&self) -> Table {
+        let mut table = Table::new();
+        let mut datatypes = HashMap::new();
+        for (column, datatype) in &self.schema {
+            table.add_column(column);
+            if let Some(datatype) = datatype {
+                table.add_datatype(column, datatype);
+                datatypes.insert(column.clone(), datatype.clone());
+            }
+        }
+        if !datatypes.is_empty() {
+            table.rows.insert("datatypes".to_string(), datatypes);
+        }
+        table
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Catalog {
+    pub tables: BTreeMap<String, CatalogEntry>,
+}
+
+impl Catalog {
+    /// Read the catalog at `path`; `None` if there is none yet.
+    pub fn load(storage: &dyn Storage, path: &str) -> io::Result<Option<Self>> {
+        match storage.read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Replace the catalog at `path`. Readers see the old catalog or the new
+    /// one, never part of either.
+    pub fn save(&self, storage: &dyn Storage, path: &str) -> io::Result<()> {
+        let data = serde_json::to_vec_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        storage.write(path, &data)
+    }
+
+    pub fn get(&self, table_name: &str) -> Option<&CatalogEntry> {
+        self.tables.get(table_name)
+    }
+
+    pub fn contains(&self, table_name: &str) -> bool {
+        self.tables.contains_key(table_name)
+    }
+
+    /// Names of the tables, sorted.
+    pub fn table_names(&self) -> Vec<String> {
+        self.tables.keys().cloned().collect()
+    }
+}
@@ -0,0 +1,52 @@
+use crate::commands::db::Database;
+use crate::commands::scheduler::{MaintenanceJob, Priority};
+use std::time::{Duration, Instant};
+use tracing::{debug, error};
+
+/// How often the engine checks the WAL size.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Saves dirty tables and indexes and then truncates the WAL, either every
+/// `interval` or as soon as the WAL file passes `wal_bytes`. Saves between
+/// checkpoints are the `SaveEngine`'s.
+pub struct CheckpointEngine {
+    interval: Duration,
+    wal_bytes: u64,
+    last_checkpoint: Instant,
+}
+
+impl CheckpointEngine {
+    pub fn new(interval: Duration, wal_bytes: u64) -> Self {
+        CheckpointEngine {
+            interval,
+            wal_bytes,
+            last_checkpoint: Instant::now(),
+        }
+    }
+}
+
+impl MaintenanceJob for CheckpointEngine {
+    fn name(&self) -> &'static str {
+        "checkpoint_engine"
+    }
+
+    fn priority(&self) -> Priority {
+        Priority::Normal
+    }
+
+    fn interval(&self) -> Duration {
+        POLL_INTERVAL.min(self.interval)
+    }
+
+    fn step(&mut self, db: &mut Database) -> bool {
+        let wal_full = self.wal_bytes > 0 && db.wal_file_bytes() >= self.wal_bytes;
+        if wal_full || self.last_checkpoint.elapsed() >= self.interval {
+            match db.checkpoint() {
+                Ok(saved) => debug!("Checkpoint complete, saved {} tables.", saved.len()),
+                Err(e) => error!("Checkpoint failed: {}", e),
+            }
+            self.last_checkpoint = Instant::now();
+        }
+        false
+    }
+}
@@ -0,0 +1,118 @@
+use std::io::{self, Write};
+
+/// Separates a WAL record from its checksum. Records never contain tabs: values
+/// are JSON-encoded and commands are split on whitespace.
+const CHECKSUM_SEPARATOR: char = '\t';
+
+/// CRC-32 (IEEE), computed bitwise.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    crc32_extend(0, bytes)
+}
+
+/// The CRC-32 of some data followed by `bytes`, given the CRC-32 of the data.
+pub fn crc32_extend(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Append a checksum to a WAL record: `<record>\t<crc32 as 8 hex digits>`.
+pub fn frame(record: &str) -> String {
+    format!(
+        "{}{}{:08x}",
+        record,
+        CHECKSUM_SEPARATOR,
+        crc32(record.as_bytes())
+    )
+}
+
+/// When a WAL record was logged. `lsn` (log sequence number) counts records
+/// and only ever grows; `millis` is the wall-clock time in Unix milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Stamp {
+    pub lsn: u64,
+    pub millis: u64,
+}
+
+/// Append a checksum to a stamped WAL record:
+/// `<lsn>\t<millis>\t<record>\t<crc32 as 8 hex digits>`. The checksum covers
+/// the stamp too.
+pub fn frame_stamped(stamp: Stamp, record: &str) -> String {
+    frame(&format!(
+        "{}{sep}{}{sep}{}",
+        stamp.lsn,
+        stamp.millis,
+        record,
+        sep = CHECKSUM_SEPARATOR
+    ))
+}
+
+/// Split a WAL line into its record and checksum status: `Some(true)` if the
+/// checksum matches, `Some(false)` if it does not, `None` for lines written
+/// before checksums were added. Any stamp is dropped; see `unframe_stamped`.
+pub fn unframe(line: &str) -> (&str, Option<bool>) {
+    let (_, record, valid) = unframe_stamped(line);
+    (record, valid)
+}
+
+/// Like `unframe`, also returning the record's stamp. Lines written before
+/// records were stamped have none.
+pub fn unframe_stamped(line: &str) -> (Option<Stamp>, &str, Option<bool>) {
+    let (body, valid) = match line.rsplit_once(CHECKSUM_SEPARATOR) {
+        Some((body, sum)) if sum.len() == 8 => {
+            let ok = u32::from_str_radix(sum, 16)
+                .map(|sum| sum == crc32(body.as_bytes()))
+                .unwrap_or(false);
+            (body, Some(ok))
+        }
+        _ => (line, None),
+    };
+    let mut fields = body.splitn(3, CHECKSUM_SEPARATOR);
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some(lsn), Some(millis), Some(record)) => match (lsn.parse(), millis.parse()) {
+            (Ok(lsn), Ok(millis)) => (Some(Stamp { lsn, millis }), record, valid),
+            _ => (None, body, valid),
+        },
+        _ => (None, body, valid),
+    }
+}
+
+/// Where the checksum of a whole file is kept: `<file>.crc`, holding the
+/// file's CRC-32 as 8 hex digits.
+pub fn sidecar_path(file_name: &str) -> String {
+    format!("{}.crc", file_name)
+}
+
+/// A writer that keeps the CRC-32 of everything written through it.
+pub struct Crc32Writer<W> {
+    inner: W,
+    crc: u32,
+}
+
+impl<W> Crc32Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Crc32Writer { inner, crc: 0 }
+    }
+
+    pub fn into_parts(self) -> (W, u32) {
+        (self.inner, self.crc)
+    }
+}
+
+impl<W: Write> Write for Crc32Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc = crc32_extend(self.crc, &buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,61 @@
+//! Time for the engine. `std::time::Instant::now` and `SystemTime::now` panic
+//! on wasm32 in the browser, so there the clock is read from JavaScript's
+//! `Date.now()` instead.
+
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::Instant;
+
+/// Milliseconds since the Unix epoch, 0 if the clock is before it.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn unix_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn unix_millis() -> u64 {
+    js_sys::Date::now() as u64
+}
+
+/// Seconds since the Unix epoch.
+pub fn unix_secs() -> u64 {
+    Duration::from_millis(unix_millis()).as_secs()
+}
+
+/// A point in time with the subset of `std::time::Instant` the engine uses.
+/// Millisecond resolution, and not monotonic if the system clock is set back.
+#[cfg(target_arch = "wasm32")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(u64);
+
+#[cfg(target_arch = "wasm32")]
+impl Instant {
+    pub fn now() -> Self {
+        Instant(unix_millis())
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().saturating_duration_since(*self)
+    }
+
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+
+    pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_millis(self.0.saturating_sub(earlier.0))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl std::ops::Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant(self.0 + rhs.as_millis() as u64)
+    }
+}
@@ -0,0 +1,254 @@
+//! Cold storage for `Database` tables: rows flushed out of memory into a
+//! sorted binary segment, one per table, with a bloom filter and a sparse
+//! index so a point lookup reads only a short run of the file.
+//!
+//! Layout: `RCS1`, then `(row id, row)` entries sorted by row id, each a u32
+//! length-prefixed string with the row as JSON. The sparse index and the
+//! bloom filter follow, and a footer of the entries' end offset (u64), the
+//! row count (u32) and `RCS1` again.
+
+use crate::commands::storage::{Storage, StorageWriter};
+use crate::commands::BloomFilter::BloomFilter;
+use std::collections::HashMap;
+use std::io::{self, BufReader, Read, Write};
+
+const MAGIC: &[u8; 4] = b"RCS1";
+const FOOTER_LEN: u64 = 16;
+/// Every this many entries get a sparse index entry.
+const INDEX_INTERVAL: usize = 64;
+/// Bloom filter bits per row.
+const BLOOM_BITS_PER_ROW: usize = 10;
+
+fn invalid(path: &str, detail: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("cold segment '{}': {}", path, detail),
+    )
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+/// A u32 length-prefixed byte string, refusing lengths over `max`.
+fn read_bytes<R: Read>(reader: &mut R, max: u64) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as u64;
+    if len > max {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("length {} runs past the end of the file", len),
+        ));
+    }
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_string<R: Read>(reader: &mut R, max: u64) -> io::Result<String> {
+    String::from_utf8(read_bytes(reader, max)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Discard the next `n` bytes of `reader`.
+fn skip<R: Read>(reader: &mut R, n: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(n), &mut io::sink())?;
+    if skipped < n {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// A table's flushed rows on disk. Only the bloom filter and the sparse index
+/// are held in memory.
+pub struct ColdSegment {
+    path: String,
+    rows: usize,
+    /// End of the entries, where the sparse index starts.
+    data_end: u64,
+    bloom: BloomFilter,
+    /// Every `INDEX_INTERVAL`th row id with the offset of its entry.
+    index: Vec<(String, u64)>,
+}
+
+impl ColdSegment {
+    /// Read the footer, sparse index and bloom filter of the segment at `path`.
+    pub fn open(storage: &dyn Storage, path: &str) -> io::Result<Self> {
+        let len = storage.size(path)?;
+        if len < MAGIC.len() as u64 + FOOTER_LEN {
+            return Err(invalid(path, "too short"));
+        }
+        let mut reader = BufReader::new(storage.open(path)?);
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid(path, "bad magic"));
+        }
+        skip(&mut reader, len - FOOTER_LEN - MAGIC.len() as u64)?;
+        let mut footer = [0u8; FOOTER_LEN as usize];
+        reader.read_exact(&mut footer)?;
+        if &footer[12..] != MAGIC {
+            return Err(invalid(path, "bad footer"));
+        }
+        let data_end = u64::from_le_bytes(footer[..8].try_into().unwrap());
+        let rows = u32::from_le_bytes(footer[8..12].try_into().unwrap()) as usize;
+        if data_end < MAGIC.len() as u64 || data_end > len - FOOTER_LEN {
+            return Err(invalid(path, "footer points outside the file"));
+        }
+
+        let mut reader = BufReader::new(storage.open(path)?);
+        skip(&mut reader, data_end)?;
+        let mut count = [0u8; 4];
+        reader.read_exact(&mut count)?;
+        let count = u32::from_le_bytes(count) as usize;
+        if count > rows {
+            return Err(invalid(path, "more index entries than rows"));
+        }
+        let mut index = Vec::with_capacity(count);
+        for _ in 0..count {
+            let row_id = read_string(&mut reader, len)?;
+            let mut offset = [0u8; 8];
+            reader.read_exact(&mut offset)?;
+            index.push((row_id, u64::from_le_bytes(offset)));
+        }
+        let bloom = BloomFilter::from_bytes(&read_bytes(&mut reader, len)?)
+            .map_err(|e| invalid(path, &e.to_string()))?;
+        Ok(ColdSegment { path: path.to_string(), rows, data_end, bloom, index })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Number of rows in the segment.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// False if `row_id` is certainly not in the segment.
+    pub fn may_contain(&self, row_id: &str) -> bool {
+        self.bloom.contains(row_id)
+    }
+
+    /// The stored row `row_id`, reading from the sparse index entry before it.
+    pub fn get(
+        &self,
+        storage: &dyn Storage,
+        row_id: &str,
+    ) -> io::Result<Option<HashMap<String, String>>> {
+        if !self.may_contain(row_id) {
+            return Ok(None);
+        }
+        let mut found = None;
+        self.visit(storage, Some(row_id), |id, row| {
+            if id == row_id {
+                found = Some(row);
+            }
+            Ok(false)
+        })?;
+        Ok(found)
+    }
+
+    /// Pass the rows to `visit` in row id order, from the first id at least
+    /// `start`, until `visit` returns false or fails.
+    pub fn visit(
+        &self,
+        storage: &dyn Storage,
+        start: Option<&str>,
+        mut visit: impl FnMut(&str, HashMap<String, String>) -> io::Result<bool>,
+    ) -> io::Result<()> {
+        let from = start.map_or(MAGIC.len() as u64, |start| {
+            let at = self.index.partition_point(|(id, _)| id.as_str() <= start);
+            at.checked_sub(1)
+                .map_or(MAGIC.len() as u64, |i| self.index[i].1)
+        });
+        let mut reader = BufReader::new(storage.open(&self.path)?);
+        skip(&mut reader, from)?;
+        let mut pos = from;
+        while pos < self.data_end {
+            let row_id = read_string(&mut reader, self.data_end - pos)?;
+            let row = read_bytes(&mut reader, self.data_end - pos)?;
+            pos += 8 + row_id.len() as u64 + row.len() as u64;
+            if start.is_some_and(|start| row_id.as_str() < start) {
+                continue;
+            }
+            let row = serde_json::from_slice(&row).map_err(|e| invalid(&self.path, &e.to_string()))?;
+            if !visit(&row_id, row)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes a segment. Rows must be pushed in increasing row id order; the
+/// file replaces `path` when finished.
+pub struct SegmentWriter {
+    path: String,
+    out: Box<dyn StorageWriter>,
+    pos: u64,
+    rows: usize,
+    last: Option<String>,
+    bloom: BloomFilter,
+    index: Vec<(String, u64)>,
+}
+
+impl SegmentWriter {
+    /// Start a segment of at most `expected_rows` rows; the bloom filter is
+    /// sized for that many.
+    pub fn create(storage: &dyn Storage, path: &str, expected_rows: usize) -> io::Result<Self> {
+        let mut out = storage.create(path)?;
+        out.write_all(MAGIC)?;
+        Ok(SegmentWriter {
+            path: path.to_string(),
+            out,
+            pos: MAGIC.len() as u64,
+            rows: 0,
+            last: None,
+            bloom: BloomFilter::new((expected_rows * BLOOM_BITS_PER_ROW).max(64)),
+            index: Vec::new(),
+        })
+    }
+
+    pub fn push(&mut self, row_id: &str, row: &HashMap<String, String>) -> io::Result<()> {
+        if self.last.as_deref().is_some_and(|last| last >= row_id) {
+            return Err(invalid(&self.path, &format!("row '{}' pushed out of order", row_id)));
+        }
+        if self.rows.is_multiple_of(INDEX_INTERVAL) {
+            self.index.push((row_id.to_string(), self.pos));
+        }
+        let row = serde_json::to_vec(row)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_bytes(&mut self.out, row_id.as_bytes())?;
+        write_bytes(&mut self.out, &row)?;
+        self.pos += 8 + row_id.len() as u64 + row.len() as u64;
+        self.bloom.add(row_id);
+        self.rows += 1;
+        self.last = Some(row_id.to_string());
+        Ok(())
+    }
+
+    /// Write the index, bloom filter and footer and commit the file.
+    pub fn finish(mut self) -> io::Result<ColdSegment> {
+        let data_end = self.pos;
+        self.out.write_all(&(self.index.len() as u32).to_le_bytes())?;
+        for (row_id, offset) in &self.index {
+            write_bytes(&mut self.out, row_id.as_bytes())?;
+            self.out.write_all(&offset.to_le_bytes())?;
+        }
+        write_bytes(&mut self.out, &self.bloom.to_bytes()?)?;
+        self.out.write_all(&data_end.to_le_bytes())?;
+        self.out.write_all(&(self.rows as u32).to_le_bytes())?;
+        self.out.write_all(MAGIC)?;
+        self.out.commit()?;
+        Ok(ColdSegment {
+            path: self.path,
+            rows: self.rows,
+            data_end,
+            bloom: self.bloom,
+            index: self.index,
+        })
+    }
+}
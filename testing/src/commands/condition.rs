@@ -0,0 +1,345 @@
+use crate::commands::db::{DatabaseError, Result};
+use crate::commands::expr::{self, Expr};
+use crate::commands::functions::FunctionRegistry;
+use crate::commands::matching::{Collation, MatchOptions};
+use regex::{Regex, RegexBuilder};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+/// Longest `MATCHES` pattern accepted, in bytes.
+pub const MAX_PATTERN_LEN: usize = 1024;
+/// Cap on the compiled size of a `MATCHES` regex. The regex engine runs in
+/// time linear in the input, so bounding the program size bounds the work per row.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Eq,
+    Gt,
+    Lt,
+    Ge,
+    Le,
+    Like,
+    Matches,
+    /// `IN (a, b)` or `IN (SELECT column FROM table WHERE ...)`.
+    In,
+}
+
+impl Operator {
+    fn parse(op: &str) -> Option<Self> {
+        match op {
+            "==" => Some(Operator::Eq),
+            ">" => Some(Operator::Gt),
+            "<" => Some(Operator::Lt),
+            ">=" => Some(Operator::Ge),
+            "<=" => Some(Operator::Le),
+            op if op.eq_ignore_ascii_case("LIKE") => Some(Operator::Like),
+            op if op.eq_ignore_ascii_case("MATCHES") => Some(Operator::Matches),
+            op if op.eq_ignore_ascii_case("IN") => Some(Operator::In),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a `YYYY-MM-DD` date into a tuple that orders chronologically.
+pub fn parse_date(value: &str) -> Option<(i32, u32, u32)> {
+    let mut parts = value.splitn(3, '-');
+    let year = parts.next()?.parse::<i32>().ok()?;
+    let month = parts.next()?.parse::<u32>().ok()?;
+    let day = parts.next()?.parse::<u32>().ok()?;
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return None,
+    };
+    (1..=days_in_month)
+        .contains(&day)
+        .then_some((year, month, day))
+}
+
+/// A value parsed according to a column's declared datatype.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Typed {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Date((i32, u32, u32)),
+}
+
+impl Typed {
+    fn parse(value: &str, datatype: &str) -> Option<Self> {
+        match datatype {
+            "int" => value.trim().parse().ok().map(Typed::Int),
+            "float" => value.trim().parse().ok().map(Typed::Float),
+            "bool" => match value.trim().to_lowercase().as_str() {
+                "true" => Some(Typed::Bool(true)),
+                "false" => Some(Typed::Bool(false)),
+                _ => None,
+            },
+            "date" => parse_date(value.trim()).map(Typed::Date),
+            _ => None,
+        }
+    }
+}
+
+/// A parsed `column operator value` condition such as `age > 10`,
+/// `name LIKE A%` or `email MATCHES ^[a-z]+@example\.com$`. Everything after
+/// the operator is the value, so it may contain spaces. The left side may be
+/// an expression instead of a column, e.g. `lower(name) == alice`.
+#[derive(Debug, Clone)]
+pub struct Condition {
+    /// The left side as written.
+    pub column: String,
+    pub left: Expr,
+    pub operator: Operator,
+    pub value: String,
+    /// Compiled once when the condition is parsed, for `MATCHES`.
+    regex: Option<Regex>,
+    /// The value parsed as the column's declared datatype, set by `typed()`.
+    typed_value: Option<(&'static str, Typed)>,
+    /// Declared "string": order lexicographically even when values look numeric.
+    text: bool,
+    /// How text values are ordered and compared for equality.
+    collation: Collation,
+    /// Folded members of an `IN` list or subquery result.
+    set: Option<HashSet<String>>,
+    /// Fixed result, for `EXISTS (SELECT ...)`.
+    constant: Option<bool>,
+}
+
+impl Condition {
+    pub fn parse(
+        condition: &str,
+        options: &MatchOptions,
+        functions: &FunctionRegistry,
+    ) -> Result<Self> {
+        let invalid =
+            |msg: &str| DatabaseError::InvalidCondition(format!("'{}': {}", condition, msg));
+        let condition = condition.trim();
+        // The operator is the first word after the left side that is outside
+        // any parentheses or quotes.
+        let words = expr::top_level_words(condition);
+        if words.len() < 3 {
+            return Err(invalid("expected \"column operator value\""));
+        }
+        let (op_start, op_end, operator) = words[1..]
+            .iter()
+            .find_map(|&(start, end)| {
+                Operator::parse(&condition[start..end]).map(|op| (start, end, op))
+            })
+            .ok_or_else(|| {
+                let (start, end) = words[1];
+                invalid(&format!(
+                    "unsupported operator '{}'",
+                    &condition[start..end]
+                ))
+            })?;
+        let column = condition[..op_start].trim();
+        // A single word that isn't a valid expression, such as `e.mail`, is
+        // taken as a column name.
+        let left = match Expr::parse(column, functions) {
+            Ok(left) => left,
+            Err(_) if !column.contains(char::is_whitespace) => Expr::Column(column.to_string()),
+            Err(e) => return Err(e),
+        };
+        let value = condition[op_end..].trim().to_string();
+        if value.is_empty() {
+            return Err(invalid("expected \"column operator value\""));
+        }
+        let set = if operator == Operator::In {
+            let inner = value
+                .strip_prefix('(')
+                .and_then(|v| v.strip_suffix(')'))
+                .ok_or_else(|| invalid("IN needs a parenthesized list or subquery"))?;
+            // A subquery is filled in by the database with `with_values`.
+            (!is_subquery(inner)).then(|| {
+                expr::split_list(inner)
+                    .iter()
+                    .map(|item| options.fold(unquote(item)).into_owned())
+                    .collect()
+            })
+        } else {
+            None
+        };
+        let regex = if operator == Operator::Matches {
+            if value.len() > MAX_PATTERN_LEN {
+                return Err(invalid(&format!(
+                    "pattern is longer than {} bytes",
+                    MAX_PATTERN_LEN
+                )));
+            }
+            let regex = RegexBuilder::new(&value)
+                .case_insensitive(options.case_insensitive)
+                .size_limit(REGEX_SIZE_LIMIT)
+                .dfa_size_limit(REGEX_SIZE_LIMIT)
+                .build()
+                .map_err(|e| invalid(&e.to_string()))?;
+            Some(regex)
+        } else {
+            None
+        };
+        Ok(Condition {
+            column: column.to_string(),
+            left,
+            operator,
+            value,
+            regex,
+            typed_value: None,
+            text: false,
+            collation: Collation::Binary,
+            set,
+            constant: None,
+        })
+    }
+
+    /// A condition with a fixed result, e.g. for an uncorrelated `EXISTS`.
+    pub fn constant(result: bool) -> Self {
+        Condition {
+            column: String::new(),
+            left: Expr::Literal(String::new()),
+            operator: Operator::Eq,
+            value: String::new(),
+            regex: None,
+            typed_value: None,
+            text: false,
+            collation: Collation::Binary,
+            set: None,
+            constant: Some(result),
+        }
+    }
+
+    /// The query inside `IN (SELECT ...)`, which the caller must run and pass
+    /// to `with_values`.
+    pub fn subquery(&self) -> Option<&str> {
+        if self.operator != Operator::In {
+            return None;
+        }
+        let inner = self.value.strip_prefix('(')?.strip_suffix(')')?;
+        is_subquery(inner).then_some(inner)
+    }
+
+    /// Set the members of an `IN` subquery from its results.
+    pub fn with_values(mut self, values: Vec<String>, options: &MatchOptions) -> Self {
+        self.set = Some(
+            values
+                .iter()
+                .map(|v| options.fold(v).into_owned())
+                .collect(),
+        );
+        self
+    }
+
+    /// Compare text with the column's collation instead of byte order.
+    pub fn collated(mut self, collation: Collation) -> Self {
+        self.collation = collation;
+        self
+    }
+
+    /// Compare using the column's declared datatype ("int", "float", "bool",
+    /// "date" or "string") instead of guessing from the values. Fails if the
+    /// condition's value is not of that type.
+    pub fn typed(mut self, datatype: &str) -> Result<Self> {
+        // IN compares members as text.
+        if self.operator == Operator::In {
+            return Ok(self);
+        }
+        let datatype: &'static str = match datatype {
+            "int" => "int",
+            "float" => "float",
+            "bool" => "bool",
+            "date" => "date",
+            "string" => {
+                self.text = true;
+                return Ok(self);
+            }
+            _ => return Ok(self),
+        };
+        if matches!(self.operator, Operator::Like | Operator::Matches) {
+            return Err(DatabaseError::InvalidCondition(format!(
+                "on '{}': LIKE and MATCHES need a text column, but it is {}",
+                self.column, datatype
+            )));
+        }
+        let typed = Typed::parse(&self.value, datatype).ok_or_else(|| {
+            DatabaseError::InvalidCondition(format!(
+                "on '{}': '{}' is not a valid {}",
+                self.column, self.value, datatype
+            ))
+        })?;
+        self.typed_value = Some((datatype, typed));
+        Ok(self)
+    }
+
+    /// Evaluate the condition against one row. Typed columns compare by their
+    /// datatype; in untyped columns numbers compare numerically and everything
+    /// else as strings in the column's collation. `LIKE`, `MATCHES` and binary
+    /// `==` follow the match options. Rows without the column, or where the
+    /// left side can't be evaluated, never match.
+    pub fn matches(&self, row_data: &HashMap<String, String>, options: &MatchOptions) -> bool {
+        if let Some(result) = self.constant {
+            return result;
+        }
+        let Some(val) = self.left.eval(row_data) else {
+            return false;
+        };
+        let val = val.as_str();
+        if self.operator == Operator::In {
+            return self
+                .set
+                .as_ref()
+                .is_some_and(|set| set.contains(options.fold(val).as_ref()));
+        }
+        let cond_value = self.value.as_str();
+        let ordering = if let Some((datatype, typed)) = self.typed_value {
+            // Values that do not parse as the column's type never match.
+            Typed::parse(val, datatype).and_then(|v| v.partial_cmp(&typed))
+        } else {
+            match self.operator {
+                Operator::Eq if self.collation == Collation::Binary => {
+                    return options.eq(val, cond_value)
+                }
+                Operator::Like => return options.like(val, cond_value),
+                Operator::Matches => {
+                    return self
+                        .regex
+                        .as_ref()
+                        .is_some_and(|re| re.is_match(&options.fold(val)))
+                }
+                _ => {}
+            }
+            match (val.parse::<f64>(), cond_value.parse::<f64>()) {
+                (Ok(num_val), Ok(num_cond)) if !self.text => num_val.partial_cmp(&num_cond),
+                _ => Some(self.collation.compare(val, cond_value)),
+            }
+        };
+        let Some(ordering) = ordering else {
+            return false;
+        };
+        match self.operator {
+            Operator::Eq => ordering == Ordering::Equal,
+            Operator::Gt => ordering == Ordering::Greater,
+            Operator::Lt => ordering == Ordering::Less,
+            Operator::Ge => ordering != Ordering::Less,
+            Operator::Le => ordering != Ordering::Greater,
+            Operator::Like | Operator::Matches | Operator::In => false,
+        }
+    }
+}
+
+/// Whether the inside of `( ... )` is a `SELECT` rather than a list of values.
+pub fn is_subquery(inner: &str) -> bool {
+    inner
+        .trim_start()
+        .get(..7)
+        .is_some_and(|kw| kw.eq_ignore_ascii_case("SELECT "))
+}
+
+/// Strip one pair of single quotes from a list item, if present.
+fn unquote(item: &str) -> &str {
+    item.strip_prefix('\'')
+        .and_then(|i| i.strip_suffix('\''))
+        .unwrap_or(item)
+}
@@ -0,0 +1,621 @@
+use crate::commands::db::{DatabaseError, Result};
+use crate::commands::matching::MatchOptions;
+use crate::commands::storage::{Storage, StorageKind};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::Duration;
+
+/// Subdirectories of `data_dir`, one per kind of file.
+pub const TABLES_DIR: &str = "data";
+pub const WAL_DIR: &str = "wal";
+pub const INDEX_DIR: &str = "index";
+pub const BACKUPS_DIR: &str = "backups";
+pub const BLOBS_DIR: &str = "blobs";
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// Caps that turn unbounded growth into typed errors. `None` means unlimited.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResourceLimits {
+    pub max_tables: Option<usize>,
+    pub max_rows_per_table: Option<usize>,
+    /// Length in bytes of any one value.
+    pub max_cell_bytes: Option<usize>,
+    /// Approximate size of a row: its id plus every column name and value.
+    pub max_row_bytes: Option<usize>,
+    /// Uncommitted WAL entries held in memory.
+    pub max_wal_backlog: Option<usize>,
+}
+
+/// When the WAL archive is rotated into numbered segments, and which segments
+/// are kept. `None` means no limit.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArchiveRetention {
+    /// Start a new segment once the archive reaches this many bytes.
+    pub segment_bytes: Option<u64>,
+    /// Start a new segment once the archive's oldest record is this old.
+    pub segment_age_secs: Option<u64>,
+    /// Segments whose newest record is older than this may be deleted.
+    pub keep_days: Option<u64>,
+    /// The oldest segments may be deleted while all of them exceed this size.
+    pub keep_bytes: Option<u64>,
+}
+
+/// Per-table overrides of the global row limits.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TableLimits {
+    pub max_rows: Option<usize>,
+    pub max_cell_bytes: Option<usize>,
+    pub max_row_bytes: Option<usize>,
+}
+
+/// When a table's in-memory changes are written back to its file. The WAL
+/// records every write either way; this only decides how often table files
+/// are rewritten.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PersistencePolicy {
+    /// Save after every write.
+    WriteThrough,
+    /// Save once `save_threshold` writes to the table have accumulated.
+    #[default]
+    Threshold,
+    /// Save once the oldest unsaved write is this many seconds old. Checked
+    /// by the WAL engine, so saves happen on its tick.
+    Interval(u64),
+    /// Save only on an explicit checkpoint.
+    Checkpoint,
+}
+
+impl fmt::Display for PersistencePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PersistencePolicy::WriteThrough => write!(f, "write_through"),
+            PersistencePolicy::Threshold => write!(f, "threshold"),
+            PersistencePolicy::Interval(secs) => write!(f, "interval {}s", secs),
+            PersistencePolicy::Checkpoint => write!(f, "checkpoint"),
+        }
+    }
+}
+
+/// Autosave settings kept in a table's schema. Unset fields fall back to the
+/// config: `table_persistence` and `table_save_threshold`, then the defaults.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Autosave {
+    pub persistence: Option<PersistencePolicy>,
+    pub save_threshold: Option<usize>,
+}
+
+impl Autosave {
+    pub fn is_default(&self) -> bool {
+        *self == Autosave::default()
+    }
+}
+
+/// Settings that control where the database keeps its files and how often
+/// the background engines run. Every field has a default, so a TOML file only
+/// needs to list the values it wants to change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    /// Root of the data directory layout: tables in `data/`, WAL files in
+    /// `wal/`, persisted indexes in `index/`, backups in `backups/`, large
+    /// values in `blobs/`.
+    pub data_dir: String,
+    /// Where files live: `"local"` disk, or `"memory"` for nothing persisted.
+    pub storage: StorageKind,
+    pub wal_file: String,
+    pub wal_archive_file: String,
+    /// Number of writes to a table between automatic saves of it.
+    pub save_threshold: usize,
+    /// Keyed by table name, e.g. `[table_save_threshold] logs = 500`.
+    pub table_save_threshold: HashMap<String, usize>,
+    /// Default persistence policy, e.g. `persistence = { interval = 30 }`.
+    pub persistence: PersistencePolicy,
+    /// Keyed by table name, e.g. `[table_persistence] logs = "checkpoint"`.
+    pub table_persistence: HashMap<String, PersistencePolicy>,
+    /// How long the async WAL writer batches entries before flushing.
+    pub wal_batch_interval_ms: u64,
+    pub wal_engine_interval_secs: u64,
+    pub index_engine_interval_secs: u64,
+    /// How often the save engine looks for tables whose save has come due.
+    pub save_engine_interval_ms: u64,
+    /// How often the checkpoint engine saves dirty tables and indexes and
+    /// truncates the WAL.
+    pub checkpoint_interval_secs: u64,
+    /// Checkpoint early once the WAL file grows past this many bytes. 0 disables it.
+    pub checkpoint_wal_bytes: u64,
+    /// Most milliseconds per second the background engines may hold the
+    /// database lock. 0 removes the limit.
+    pub maintenance_budget_ms: u64,
+    pub index_column: String,
+    pub bloom_column: String,
+    pub bloom_filter_size: usize,
+    pub indexer_file: String,
+    pub bloom_filter_file: String,
+    /// The catalog of tables, kept with the table files.
+    pub catalog_file: String,
+    /// Number of independently locked shards tables are spread across. Each
+    /// shard has its own WAL files and indexes.
+    pub shards: usize,
+    /// Values sampled per column to infer datatypes when a CSV is imported
+    /// without a datatypes row. 0 leaves imported columns untyped.
+    pub import_sample_rows: usize,
+    /// Rows inserted, saved and logged together by `Database::copy_from`.
+    pub ingest_chunk_rows: usize,
+    /// Rows the backfill job visits per step of an online column addition.
+    pub backfill_batch_rows: usize,
+    /// Values longer than this many bytes are stored once in the blob store
+    /// and referred to by hash from table files and WAL records. 0 keeps
+    /// every value inline.
+    pub blob_threshold: usize,
+    /// Refuse to load a table file that fails validation, instead of loading
+    /// what can be read and logging the problems.
+    pub strict_loads: bool,
+    /// Text comparison used by `==` and `LIKE` conditions and by the index.
+    pub match_options: MatchOptions,
+    pub limits: ResourceLimits,
+    /// Rotation and retention of the WAL archive, e.g. `[archive] keep_days = 7`.
+    pub archive: ArchiveRetention,
+    /// Keyed by table name, e.g. `[table_limits.users]` in TOML.
+    pub table_limits: HashMap<String, TableLimits>,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            data_dir: ".".to_string(),
+            storage: StorageKind::default(),
+            wal_file: "wal.log".to_string(),
+            wal_archive_file: "wal_archive.log".to_string(),
+            save_threshold: 5,
+            table_save_threshold: HashMap::new(),
+            persistence: PersistencePolicy::default(),
+            table_persistence: HashMap::new(),
+            wal_batch_interval_ms: 1000,
+            wal_engine_interval_secs: 10,
+            index_engine_interval_secs: 15,
+            save_engine_interval_ms: 100,
+            checkpoint_interval_secs: 30,
+            checkpoint_wal_bytes: 1024 * 1024,
+            maintenance_budget_ms: 250,
+            index_column: "name".to_string(),
+            bloom_column: "email".to_string(),
+            bloom_filter_size: 1000,
+            indexer_file: "indexer.json".to_string(),
+            bloom_filter_file: "bloom_filter.json".to_string(),
+            catalog_file: "catalog.json".to_string(),
+            shards: 1,
+            import_sample_rows: 100,
+            ingest_chunk_rows: 1000,
+            backfill_batch_rows: 1000,
+            blob_threshold: 0,
+            strict_loads: false,
+            match_options: MatchOptions::default(),
+            limits: ResourceLimits::default(),
+            archive: ArchiveRetention::default(),
+            table_limits: HashMap::new(),
+        }
+    }
+}
+
+impl DatabaseConfig {
+    /// Load a config from a TOML file.
+    pub fn from_file(path: &str) -> Result<Self> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| DatabaseError::ConfigError(format!("{}: {}", path, e)))?;
+        Self::from_toml(&data)
+    }
+
+    pub fn from_toml(data: &str) -> Result<Self> {
+        let config: Self =
+            toml::from_str(data).map_err(|e| DatabaseError::ConfigError(e.to_string()))?;
+        if config.bloom_filter_size == 0 {
+            return Err(DatabaseError::ConfigError(
+                "bloom_filter_size must be at least 1".to_string(),
+            ));
+        }
+        Ok(config)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).map_err(|e| DatabaseError::ConfigError(e.to_string()))
+    }
+
+    pub fn data_dir(mut self, dir: &str) -> Self {
+        self.data_dir = dir.to_string();
+        self
+    }
+
+    pub fn storage(mut self, storage: StorageKind) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    pub fn wal_file(mut self, file: &str) -> Self {
+        self.wal_file = file.to_string();
+        self
+    }
+
+    pub fn archive_retention(mut self, retention: ArchiveRetention) -> Self {
+        self.archive = retention;
+        self
+    }
+
+    pub fn save_threshold(mut self, threshold: usize) -> Self {
+        self.save_threshold = threshold;
+        self
+    }
+
+    pub fn table_save_threshold(mut self, table_name: &str, threshold: usize) -> Self {
+        self.table_save_threshold
+            .insert(table_name.to_string(), threshold);
+        self
+    }
+
+    pub fn persistence(mut self, policy: PersistencePolicy) -> Self {
+        self.persistence = policy;
+        self
+    }
+
+    pub fn table_persistence(mut self, table_name: &str, policy: PersistencePolicy) -> Self {
+        self.table_persistence
+            .insert(table_name.to_string(), policy);
+        self
+    }
+
+    pub fn wal_batch_interval(mut self, interval: Duration) -> Self {
+        self.wal_batch_interval_ms = interval.as_millis() as u64;
+        self
+    }
+
+    pub fn wal_engine_interval(mut self, interval: Duration) -> Self {
+        self.wal_engine_interval_secs = interval.as_secs();
+        self
+    }
+
+    pub fn index_engine_interval(mut self, interval: Duration) -> Self {
+        self.index_engine_interval_secs = interval.as_secs();
+        self
+    }
+
+    pub fn save_engine_interval(mut self, interval: Duration) -> Self {
+        self.save_engine_interval_ms = interval.as_millis() as u64;
+        self
+    }
+
+    pub fn checkpoint_interval(mut self, interval: Duration) -> Self {
+        self.checkpoint_interval_secs = interval.as_secs();
+        self
+    }
+
+    pub fn checkpoint_wal_bytes(mut self, bytes: u64) -> Self {
+        self.checkpoint_wal_bytes = bytes;
+        self
+    }
+
+    pub fn maintenance_budget(mut self, budget: Duration) -> Self {
+        self.maintenance_budget_ms = budget.as_millis() as u64;
+        self
+    }
+
+    pub fn index_column(mut self, column: &str) -> Self {
+        self.index_column = column.to_string();
+        self
+    }
+
+    pub fn bloom_column(mut self, column: &str, size: usize) -> Self {
+        self.bloom_column = column.to_string();
+        self.bloom_filter_size = size.max(1);
+        self
+    }
+
+    pub fn blob_threshold(mut self, bytes: usize) -> Self {
+        self.blob_threshold = bytes;
+        self
+    }
+
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.shards = shards;
+        self
+    }
+
+    /// Config for shard `index`: shard 0 keeps the configured file names, the
+    /// others get the index added, e.g. `wal.log` -> `wal.2.log`.
+    pub fn shard(&self, index: usize) -> DatabaseConfig {
+        let mut config = self.clone();
+        if index > 0 {
+            let numbered = |file: &str| match file.rsplit_once('.') {
+                Some((stem, ext)) => format!("{}.{}.{}", stem, index, ext),
+                None => format!("{}.{}", file, index),
+            };
+            config.wal_file = numbered(&self.wal_file);
+            config.wal_archive_file = numbered(&self.wal_archive_file);
+            config.indexer_file = numbered(&self.indexer_file);
+            config.bloom_filter_file = numbered(&self.bloom_filter_file);
+            config.catalog_file = numbered(&self.catalog_file);
+        }
+        config
+    }
+
+    pub fn import_sample_rows(mut self, rows: usize) -> Self {
+        self.import_sample_rows = rows;
+        self
+    }
+
+    pub fn ingest_chunk_rows(mut self, rows: usize) -> Self {
+        self.ingest_chunk_rows = rows;
+        self
+    }
+
+    pub fn backfill_batch_rows(mut self, rows: usize) -> Self {
+        self.backfill_batch_rows = rows;
+        self
+    }
+
+    pub fn strict_loads(mut self, strict: bool) -> Self {
+        self.strict_loads = strict;
+        self
+    }
+
+    pub fn match_options(mut self, options: MatchOptions) -> Self {
+        self.match_options = options;
+        self
+    }
+
+    pub fn limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn table_limits(mut self, table_name: &str, limits: TableLimits) -> Self {
+        self.table_limits.insert(table_name.to_string(), limits);
+        self
+    }
+
+    /// Row limit for a table: its own override, else the global limit.
+    pub fn max_rows_for(&self, table_name: &str) -> Option<usize> {
+        self.table_limits
+            .get(table_name)
+            .and_then(|l| l.max_rows)
+            .or(self.limits.max_rows_per_table)
+    }
+
+    /// Persistence policy for a table: its own override, else the default.
+    pub fn persistence_for(&self, table_name: &str) -> PersistencePolicy {
+        self.table_persistence
+            .get(table_name)
+            .copied()
+            .unwrap_or(self.persistence)
+    }
+
+    /// Save threshold for a table: its own override, else the global one.
+    pub fn save_threshold_for(&self, table_name: &str) -> usize {
+        self.table_save_threshold
+            .get(table_name)
+            .copied()
+            .unwrap_or(self.save_threshold)
+    }
+
+    pub fn max_cell_bytes_for(&self, table_name: &str) -> Option<usize> {
+        self.table_limits
+            .get(table_name)
+            .and_then(|l| l.max_cell_bytes)
+            .or(self.limits.max_cell_bytes)
+    }
+
+    pub fn max_row_bytes_for(&self, table_name: &str) -> Option<usize> {
+        self.table_limits
+            .get(table_name)
+            .and_then(|l| l.max_row_bytes)
+            .or(self.limits.max_row_bytes)
+    }
+
+    /// Resolve a file name relative to the data directory.
+    pub fn path(&self, file_name: &str) -> String {
+        Path::new(&self.data_dir)
+            .join(file_name)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Resolve a file name inside one of the layout's subdirectories.
+    fn path_in(&self, dir: &str, file_name: &str) -> String {
+        Path::new(&self.data_dir)
+            .join(dir)
+            .join(file_name)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// The layout's subdirectories, e.g. `tables_dir()` is `<data_dir>/data`.
+    pub fn tables_dir(&self) -> String {
+        self.path(TABLES_DIR)
+    }
+
+    pub fn wal_dir(&self) -> String {
+        self.path(WAL_DIR)
+    }
+
+    pub fn index_dir(&self) -> String {
+        self.path(INDEX_DIR)
+    }
+
+    pub fn backups_dir(&self) -> String {
+        self.path(BACKUPS_DIR)
+    }
+
+    pub fn quarantine_dir(&self) -> String {
+        self.path(QUARANTINE_DIR)
+    }
+
+    pub fn table_path(&self, table_name: &str) -> String {
+        self.path_in(TABLES_DIR, &Self::table_file(table_name))
+    }
+
+    /// Name of a table's file in the tables directory.
+    pub fn table_file(table_name: &str) -> String {
+        format!("{}.csv", table_name)
+    }
+
+    /// Rows changed since the table file was last written whole.
+    pub fn delta_path(&self, table_name: &str) -> String {
+        self.path_in(TABLES_DIR, &format!("{}.delta", table_name))
+    }
+
+    /// Tables of the database; see `catalog::Catalog`.
+    pub fn catalog_path(&self) -> String {
+        self.path_in(TABLES_DIR, &self.catalog_file)
+    }
+
+    /// Rows flushed out of memory by `Database::flush_cold`.
+    pub fn cold_path(&self, table_name: &str) -> String {
+        self.path_in(TABLES_DIR, &format!("{}.cold", table_name))
+    }
+
+    /// Large values, shared by every shard; see `blobs::BlobStore`.
+    pub fn blobs_dir(&self) -> String {
+        self.path(BLOBS_DIR)
+    }
+
+    /// Commit decisions of two-phase transactions coordinated from here.
+    pub fn transaction_log_path(&self) -> String {
+        self.path_in(WAL_DIR, "transactions.log")
+    }
+
+    pub fn wal_path(&self) -> String {
+        self.path_in(WAL_DIR, &self.wal_file)
+    }
+
+    pub fn wal_archive_path(&self) -> String {
+        self.path_in(WAL_DIR, &self.wal_archive_file)
+    }
+
+    /// Numbered segment `n` of the WAL archive, e.g. `wal_archive.log.000001`.
+    pub fn archive_segment_path(&self, n: u64) -> String {
+        self.path_in(WAL_DIR, &format!("{}.{:06}", self.wal_archive_file, n))
+    }
+
+    /// Numbers and paths of the archive's segments on `storage`, oldest first.
+    pub fn archive_segments(&self, storage: &dyn Storage) -> Vec<(u64, String)> {
+        let prefix = format!("{}.", self.wal_archive_file);
+        let mut segments: Vec<(u64, String)> = storage
+            .list(&self.wal_dir())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|path| {
+                let name = Path::new(&path).file_name()?.to_str()?;
+                let n = name.strip_prefix(&prefix)?.parse().ok()?;
+                Some((n, path))
+            })
+            .collect();
+        segments.sort();
+        segments
+    }
+
+    pub fn indexer_path(&self) -> String {
+        self.path_in(INDEX_DIR, &self.indexer_file)
+    }
+
+    pub fn bloom_filter_path(&self) -> String {
+        self.path_in(INDEX_DIR, &self.bloom_filter_file)
+    }
+
+    /// A named backup, e.g. `backups/nightly`.
+    pub fn backup_path(&self, name: &str) -> String {
+        self.path_in(BACKUPS_DIR, name)
+    }
+
+    /// Lists every backup of this database (or shard) with the last operation
+    /// it holds, e.g. `backups/wal_archive.log.catalog`. Each shard has its own
+    /// because each numbers its operations separately.
+    pub fn backup_catalog_path(&self) -> String {
+        self.path_in(BACKUPS_DIR, &format!("{}.catalog", self.wal_archive_file))
+    }
+
+    /// Create `data_dir` and the subdirectories files are written to.
+    pub fn create_layout(&self, storage: &dyn Storage) -> io::Result<()> {
+        for dir in [TABLES_DIR, WAL_DIR, INDEX_DIR, BACKUPS_DIR, BLOBS_DIR] {
+            storage.create_dir_all(&self.path(dir))?;
+        }
+        Ok(())
+    }
+
+    /// Create the layout's directories. Files that older versions kept at the
+    /// top of `data_dir` are moved into them: every shard's WAL and index
+    /// files, and `.csv`/`.delta` files that start with a `row_id` header like
+    /// table files do. Returns the files moved.
+    pub fn init_layout(&self, storage: &dyn Storage) -> io::Result<Vec<String>> {
+        self.create_layout(storage)?;
+        let mut moves: Vec<(String, String)> = Vec::new();
+        for path in storage.list(&self.data_dir)? {
+            let path = Path::new(&path);
+            let is_table = matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("csv" | "delta")
+            ) && starts_with_row_id(storage, path);
+            if let (true, Some(name)) = (is_table, path.file_name().and_then(|f| f.to_str())) {
+                moves.push((self.path(name), self.path_in(TABLES_DIR, name)));
+            }
+        }
+        for i in 0..self.shards.max(1) {
+            let shard = self.shard(i);
+            for (dir, name) in [
+                (WAL_DIR, &shard.wal_file),
+                (WAL_DIR, &shard.wal_archive_file),
+                (INDEX_DIR, &shard.indexer_file),
+                (INDEX_DIR, &shard.bloom_filter_file),
+            ] {
+                moves.push((self.path(name), self.path_in(dir, name)));
+            }
+        }
+        let mut moved = Vec::new();
+        for (from, to) in moves {
+            if storage.exists(&from) && !storage.exists(&to) {
+                storage.rename(&from, &to)?;
+                moved.push(to);
+            }
+        }
+        Ok(moved)
+    }
+
+    pub fn wal_batch_interval_duration(&self) -> Duration {
+        Duration::from_millis(self.wal_batch_interval_ms)
+    }
+
+    pub fn wal_engine_interval_duration(&self) -> Duration {
+        Duration::from_secs(self.wal_engine_interval_secs)
+    }
+
+    pub fn index_engine_interval_duration(&self) -> Duration {
+        Duration::from_secs(self.index_engine_interval_secs)
+    }
+
+    pub fn save_engine_interval_duration(&self) -> Duration {
+        Duration::from_millis(self.save_engine_interval_ms)
+    }
+
+    pub fn checkpoint_interval_duration(&self) -> Duration {
+        Duration::from_secs(self.checkpoint_interval_secs)
+    }
+
+    pub fn maintenance_budget_duration(&self) -> Option<Duration> {
+        (self.maintenance_budget_ms > 0).then(|| Duration::from_millis(self.maintenance_budget_ms))
+    }
+}
+
+/// Whether the file's first line is a table header, `row_id,...`.
+fn starts_with_row_id(storage: &dyn Storage, path: &Path) -> bool {
+    let mut head = [0u8; 6];
+    storage
+        .open(&path.to_string_lossy())
+        .and_then(|mut f| f.read_exact(&mut head))
+        .is_ok_and(|_| &head == b"row_id")
+}
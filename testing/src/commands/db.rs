@@ -1,20 +1,56 @@
-//// filepath: c:\Users\srija\Documents\GitHub\Rust_DB\testing\src\commands\db.rs
+use crate::commands::backup;
+use crate::commands::bitmap::Bitmap;
+use crate::commands::blobs::BlobStore;
+use crate::commands::catalog::{Catalog, CatalogEntry};
+use crate::commands::checksum::{self, Stamp};
+use crate::commands::clock::{self, Instant};
+use crate::commands::cold::{ColdSegment, SegmentWriter};
+use crate::commands::condition::{self, Condition, Operator};
+use crate::commands::config::{Autosave, DatabaseConfig, PersistencePolicy};
+use crate::commands::crypto::{self, ColumnCipher};
+use crate::commands::expr::{self, Expr};
+use crate::commands::functions::{FunctionRegistry, FunctionResult};
+use crate::commands::geo::{GeoIndex, GeoPoint};
+use crate::commands::index::{Consistency, Filter, IndexKind, QueryHints, TableIndex};
+use crate::commands::index_rebuild::{ColumnSketch, IndexRebuild, IndexSnapshot};
+use crate::commands::information_schema;
+#[cfg(feature = "import")]
+use crate::commands::ingest::{self, IngestFormat, IngestProgress, RowReader};
+use crate::commands::integrity::{
+    IntegrityReport, LoadReport, ProblemKind, RepairReport, RepairedFile,
+};
+use crate::commands::mapping;
+use crate::commands::matching::{Collation, MatchOptions};
+use crate::commands::result::{Column, QueryResult, RowSet, SortKey, Value};
+use crate::commands::row_id::{RowIdGenerator, RowIdStrategy};
+use crate::commands::stats::{
+    BackfillProgress, ColumnStats, DatabaseStats, MergeReport, RecoveryReport, StartupReport,
+    TableStats, VacuumReport,
+};
+use crate::commands::storage::Storage;
+use crate::commands::transfer::{TableChunk, TransferPosition};
+use crate::commands::twophase::TxWrite;
+use crate::commands::walwriter;
 use crate::commands::BloomFilter;
 use crate::commands::Indexer;
-use crate::table::table::Table;
-use crate::walwriter;
-use log::{error, info};
+use crate::table::table::{self as table_schema, Table, DELETED_AT, TENANT_ID};
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json;
-use std::collections::HashMap;
-use std::fs;
-use std::fs::File;
-use std::fs::OpenOptions;
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::path::Path;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+#[cfg(feature = "import")]
+use std::io::Read;
+use std::io::{BufRead, BufReader, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
+use tracing::{debug, error, info, instrument, warn};
 
-use csv::{ReaderBuilder, WriterBuilder}; // ← new
-use std::error::Error;
+use csv::{ReaderBuilder, Writer, WriterBuilder}; // ← new
 
 #[derive(Error, Debug)]
 pub enum DatabaseError {
@@ -32,824 +68,6529 @@ pub enum DatabaseError {
     DataTypeError,
     #[error("Invalid datatype provided.")]
     InvalidDataType,
+    #[error("'{0}' is not an allowed value of column '{1}'.")]
+    NotInEnum(String, String),
+    #[error("Invalid configuration: {0}")]
+    ConfigError(String),
+    #[error("Database '{0}' already exists.")]
+    DatabaseAlreadyExists(String),
+    #[error("Database '{0}' does not exist.")]
+    DatabaseDoesNotExist(String),
+    #[error("Database '{0}' is in use.")]
+    DatabaseInUse(String),
+    #[error("Database is attached read-only.")]
+    ReadOnly,
+    #[error("Timed out waiting for the database: {0}")]
+    LockTimeout(String),
+    #[error("Column '{0}' does not exist in table '{1}'.")]
+    ColumnDoesNotExist(String, String),
+    #[error("No encryption key has been set.")]
+    EncryptionKeyMissing,
+    #[error("Table limit of {0} reached.")]
+    TooManyTables(usize),
+    #[error("Table '{0}' has reached its limit of {1} rows.")]
+    TableFull(String, usize),
+    #[error("Row '{0}' is {1} bytes, over the limit of {2}.")]
+    RowTooLarge(String, usize, usize),
+    #[error("Value of column '{1}' in row '{0}' is {2} bytes, over the limit of {3}.")]
+    CellTooLarge(String, String, usize, usize),
+    #[error("WAL backlog has reached its limit of {0} entries; commit before writing more.")]
+    WalBacklogFull(usize),
+    #[error("Row '{0}' already exists in table '{1}'.")]
+    RowAlreadyExists(String, String),
+    #[error("Invalid condition {0}")]
+    InvalidCondition(String),
+    #[error("Invalid expression {0}")]
+    InvalidExpression(String),
+    #[error("Set operation between queries of {0} and {1} columns.")]
+    IncompatibleQueries(usize, usize),
+    #[error("Ingest failed at record {0}: {1}")]
+    IngestError(usize, String),
+    #[error("Row mapping failed: {0}")]
+    RowMapping(String),
+    #[error("Table '{0}' needs an explicit row id; its row id strategy is 'caller'.")]
+    RowIdRequired(String),
+    #[error("No backup was taken at or before {0} (Unix milliseconds).")]
+    NoBackup(u64),
+    #[error("Table file '{0}' failed validation with {1} problems.")]
+    InvalidTableFile(String, usize),
+    #[error("Column '{0}' already exists in table '{1}'.")]
+    ColumnAlreadyExists(String, String),
+    #[error("Chunk {1} of table '{0}' does not match its checksum.")]
+    ChunkChecksum(String, u64),
+    #[error("Row '{0}' of table '{1}' is held by prepared transaction '{2}'.")]
+    RowLocked(String, String, String),
+    #[error("Table '{0}' does not have soft deletes enabled.")]
+    SoftDeleteDisabled(String),
+    #[error("Row '{0}' of table '{1}' belongs to another tenant.")]
+    TenantMismatch(String, String),
+    #[error("Query hint rejected: {0}")]
+    HintRejected(String),
+    #[error("Table '{0}' is temporary and is never logged or saved.")]
+    TemporaryTable(String),
 }
 
 pub type Result<T> = std::result::Result<T, DatabaseError>;
 
+/// What `Database::merge_tables` does when a row id exists in both tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the destination row.
+    Skip,
+    /// Replace the destination row with the source row.
+    Overwrite,
+    /// Fail the merge before anything is changed.
+    Error,
+}
+
+/// How `Database::query` combines the results of two queries. Without `ALL`
+/// the combined result holds each distinct row once; with it duplicates are
+/// kept (or counted, for INTERSECT and EXCEPT).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOperation {
+    /// Rows from either side.
+    Union,
+    /// Rows present on both sides.
+    Intersect,
+    /// Rows on the left side that are not on the right.
+    Except,
+}
+
+impl SetOperation {
+    fn parse(word: &str) -> Option<Self> {
+        match word.to_ascii_uppercase().as_str() {
+            "UNION" => Some(Self::Union),
+            "INTERSECT" => Some(Self::Intersect),
+            "EXCEPT" => Some(Self::Except),
+            _ => None,
+        }
+    }
+
+    /// Combine two result sets, keeping the order rows first appear in.
+    pub fn apply(
+        self,
+        all: bool,
+        left: Vec<Vec<String>>,
+        right: Vec<Vec<String>>,
+    ) -> Vec<Vec<String>> {
+        let mut counts: HashMap<Vec<String>, usize> = HashMap::new();
+        let combined: Vec<Vec<String>> = match self {
+            Self::Union => left.into_iter().chain(right).collect(),
+            Self::Intersect | Self::Except => {
+                for row in right {
+                    *counts.entry(row).or_default() += 1;
+                }
+                let keep_present = self == Self::Intersect;
+                left.into_iter()
+                    .filter(|row| match counts.get_mut(row) {
+                        // ALL pairs each left row with one right row.
+                        Some(count) if *count > 0 => {
+                            if all {
+                                *count -= 1;
+                            }
+                            keep_present
+                        }
+                        _ => !keep_present,
+                    })
+                    .collect()
+            }
+        };
+        if all {
+            return combined;
+        }
+        let mut seen = HashSet::new();
+        combined
+            .into_iter()
+            .filter(|row| seen.insert(row.clone()))
+            .collect()
+    }
+}
+
+/// A parsed `SELECT <items> FROM <table> [WHERE <condition>]`.
+struct Select<'a> {
+    /// `None` for `*`, which selects row ids.
+    items: Option<Vec<Expr>>,
+    table: Cow<'a, Table>,
+    filter: Option<Condition>,
+    /// `ORDER BY` keys, applied to the table's rows before the items are
+    /// evaluated, so they may name columns that are not selected.
+    order: Vec<SortKey>,
+}
+
+impl Select<'_> {
+    fn width(&self) -> usize {
+        self.items.as_ref().map_or(1, Vec::len)
+    }
+
+    /// The selected items as result columns; plain columns keep their datatype.
+    fn columns(&self) -> Vec<Column> {
+        match &self.items {
+            Some(items) => items
+                .iter()
+                .map(|item| Column {
+                    name: item.to_string(),
+                    datatype: item
+                        .as_column()
+                        .and_then(|c| self.table.row_datatypes.get(c).cloned()),
+                })
+                .collect(),
+            None => vec![Column {
+                name: "row_id".to_string(),
+                datatype: None,
+            }],
+        }
+    }
+
+    /// Order the rows by `keys`, each a table column or `row_id`.
+    fn order_by(&mut self, keys: Vec<SortKey>) -> Result<()> {
+        if let Some(key) = keys
+            .iter()
+            .find(|key| key.column != "row_id" && !self.table.columns.contains(&key.column))
+        {
+            return Err(DatabaseError::InvalidCondition(format!(
+                "ORDER BY: no column '{}'",
+                key.column
+            )));
+        }
+        self.order = keys;
+        Ok(())
+    }
+
+    /// A row's values for the `ORDER BY` keys, typed by their columns' datatypes.
+    fn sort_values(&self, row_id: &str, row: &HashMap<String, String>) -> Vec<Value> {
+        self.order
+            .iter()
+            .map(|key| match key.column.as_str() {
+                "row_id" => Value::Text(row_id.to_string()),
+                column => row.get(column).map_or(Value::Null, |text| {
+                    Value::parse(
+                        text,
+                        self.table.row_datatypes.get(column).map(String::as_str),
+                    )
+                }),
+            })
+            .collect()
+    }
+
+    /// Rows where every item has a value, in `ORDER BY` order if there is one.
+    fn rows(&self, options: &MatchOptions) -> Vec<Vec<String>> {
+        let mut matched: Vec<(&String, &HashMap<String, String>)> = self
+            .table
+            .rows
+            .iter()
+            .filter(|(row_id, _)| row_id.as_str() != "datatypes")
+            .filter(|(_, row)| self.filter.as_ref().is_none_or(|c| c.matches(row, options)))
+            .collect();
+        if !self.order.is_empty() {
+            let mut keyed: Vec<_> = matched
+                .into_iter()
+                .map(|(row_id, row)| (self.sort_values(row_id, row), (row_id, row)))
+                .collect();
+            keyed.sort_by(|(a, _), (b, _)| SortKey::compare(&self.order, a, b));
+            matched = keyed.into_iter().map(|(_, row)| row).collect();
+        }
+        matched
+            .into_iter()
+            .filter_map(|(row_id, row)| match &self.items {
+                Some(items) => items.iter().map(|item| item.eval(row)).collect(),
+                None => Some(vec![row_id.clone()]),
+            })
+            .collect()
+    }
+}
+
+/// Split a trailing `ORDER BY <column> [ASC|DESC], ...` off a query.
+fn split_order_by(text: &str) -> Result<(&str, Vec<SortKey>)> {
+    let words = expr::top_level_words(text);
+    let word = |i: usize| &text[words[i].0..words[i].1];
+    let Some(at) = (1..words.len())
+        .rev()
+        .find(|&i| word(i - 1).eq_ignore_ascii_case("ORDER") && word(i).eq_ignore_ascii_case("BY"))
+    else {
+        return Ok((text, Vec::new()));
+    };
+    let keys = SortKey::parse_list(&text[words[at].1..]).ok_or_else(|| {
+        DatabaseError::InvalidCondition(format!(
+            "query '{}': expected ORDER BY <column> [ASC|DESC], ...",
+            text
+        ))
+    })?;
+    Ok((text[..words[at - 1].0].trim_end(), keys))
+}
+
+/// Result of a multi-row lookup: rows that were found, in request order, and
+/// the ids that were not.
+#[derive(Debug, Default)]
+pub struct RowBatch {
+    pub found: Vec<(String, HashMap<String, String>)>,
+    pub missing: Vec<String>,
+}
+
+/// A scan over row ids: `start` inclusive, `end` exclusive, either left open
+/// with `None`. Descending scans walk the ids from the end of the range, so
+/// "latest N" reads touch only the rows they return.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanOptions {
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub descending: bool,
+    pub limit: Option<usize>,
+}
+
+/// Writes to a table that have not been saved to its file yet. Taken by
+/// the next successful save of the table.
+#[derive(Debug)]
+struct UnsavedWrites {
+    count: usize,
+    since: Instant,
+    /// Ids of the rows written.
+    rows: BTreeSet<String>,
+    /// Only inserts of new rows so far, so the save can append those rows
+    /// instead of rewriting the file.
+    append_only: bool,
+}
+
 pub struct Database {
     pub tables: HashMap<String, Table>,
-    pub operations_since_save: usize,
-    pub save_threshold: usize,
     pub wal: Vec<String>,
     pub wal_file: String,
     pub datatypes: Vec<String>,
-    pub saved_row_count: usize,
     pub wal_writer: Option<walwriter::WalWriter>,
 
     pub indexer: Option<Indexer::Indexer>,
     pub bloom_filter: Option<BloomFilter::BloomFilter>,
+    pub config: DatabaseConfig,
+    /// Backend every table, WAL and index file is read from and written to.
+    pub storage: Arc<dyn Storage>,
+    pub read_only: bool,
+    pub cipher: Option<ColumnCipher>,
+    /// Scalar functions usable in conditions and projections.
+    pub functions: FunctionRegistry,
+    /// Number of logged write operations, used to tell how stale the indexes are.
+    pub writes: u64,
+    index_built_at: Option<u64>,
+    bloom_built_at: Option<u64>,
+    /// Indexes made with `create_index`, keyed by table and column.
+    pub table_indexes: BTreeMap<(String, String), TableIndex>,
+    /// Sketches of the indexed columns, keyed by table and column, rebuilt
+    /// with the indexes; see `approx_distinct` and `top_k`.
+    sketches: BTreeMap<(String, String), ColumnSketch>,
+    unsaved: HashMap<String, UnsavedWrites>,
+    /// Due saves are left to the `SaveEngine` instead of made by the write.
+    background_saves: bool,
+    /// Entries of `wal` already appended to the WAL file.
+    wal_persisted: usize,
+    /// When each entry of `wal` was logged.
+    wal_stamps: Vec<Stamp>,
+    /// Sequence number the next logged operation gets.
+    next_lsn: u64,
+    /// Sequence number of the last WAL record each table in memory reflects,
+    /// so replaying a record twice changes nothing.
+    applied_lsn: HashMap<String, u64>,
+    /// Tables of this database, as kept in `config.catalog_path()`.
+    catalog: Catalog,
+    /// Where values over `config.blob_threshold` are stored.
+    blobs: BlobStore,
+    /// Tables made by `create_temp_table`, kept in memory only.
+    temp_tables: HashSet<String>,
+    /// Online column additions whose rows are still being backfilled, oldest first.
+    backfills: Vec<BackfillProgress>,
+    /// Rows flushed out of memory by `flush_cold`, keyed by table.
+    cold: HashMap<String, ColdSegment>,
+    /// Writes of two-phase transactions prepared here and not yet committed
+    /// or aborted, keyed by transaction id.
+    prepared: BTreeMap<String, Vec<TxWrite>>,
+    /// Reads return soft-deleted rows too; see `set_include_deleted`.
+    include_deleted: bool,
+    /// Tenant reads and writes are scoped to; see `set_tenant`.
+    tenant: Option<String>,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Database::new()
+    }
 }
 
 impl Database {
     pub fn new() -> Self {
+        Database::with_config(DatabaseConfig::default())
+    }
+
+    /// Create a database whose paths, thresholds and index settings come from
+    /// `config`, on a new backend of the kind `config.storage` names.
+    pub fn with_config(config: DatabaseConfig) -> Self {
+        let storage = config.storage.open();
+        Database::with_storage(config, storage)
+    }
+
+    /// Create a database like `with_config` whose files live on `storage`.
+    pub fn with_storage(config: DatabaseConfig, storage: Arc<dyn Storage>) -> Self {
+        if let Err(e) = config.create_layout(storage.as_ref()) {
+            error!(
+                "Failed to create data directory '{}': {}",
+                config.data_dir, e
+            );
+        }
+        let next_lsn = Self::last_lsn(storage.as_ref(), &config) + 1;
+        let blobs = BlobStore::new(storage.clone(), &config.blobs_dir(), config.blob_threshold);
+        let catalog_path = config.catalog_path();
+        let catalog = Catalog::load(storage.as_ref(), &catalog_path)
+            .unwrap_or_else(|e| {
+                error!("Failed to read catalog '{}': {}", catalog_path, e);
+                None
+            })
+            .unwrap_or_default();
         Database {
             tables: HashMap::new(),
-            operations_since_save: 0,
-            save_threshold: 5,
             wal: Vec::new(),
-            wal_file: "wal.log".to_string(),
+            wal_file: config.wal_path(),
             datatypes: vec![
                 "int".to_string(),
                 "float".to_string(),
                 "string".to_string(),
                 "bool".to_string(),
+                "date".to_string(),
+                "geo".to_string(),
             ],
             wal_writer: None,
-            saved_row_count: 0,
 
             indexer: None,
             bloom_filter: None,
+            config,
+            storage,
+            read_only: false,
+            cipher: None,
+            blobs,
+            functions: FunctionRegistry::default(),
+            writes: 0,
+            index_built_at: None,
+            bloom_built_at: None,
+            table_indexes: BTreeMap::new(),
+            sketches: BTreeMap::new(),
+            unsaved: HashMap::new(),
+            background_saves: false,
+            wal_persisted: 0,
+            wal_stamps: Vec::new(),
+            next_lsn,
+            applied_lsn: HashMap::new(),
+            catalog,
+            temp_tables: HashSet::new(),
+            backfills: Vec::new(),
+            cold: HashMap::new(),
+            prepared: BTreeMap::new(),
+            include_deleted: false,
+            tenant: None,
         }
     }
 
-    /// Build indexes (for example, index the "name" column of every row).
-    pub fn build_indexes(&mut self) {
-        // For simplicity, we build one global index on the "name" column.
-        let mut idx = Indexer::Indexer::new();
-        for (table_name, table) in self.tables.iter() {
-            for (row_id, row_data) in table.rows.iter() {
-                if let Some(value) = row_data.get("name") {
-                    // You could also include table_name in your key if needed.
-                    idx.add(value, row_id);
-                }
-            }
-        }
-        self.indexer = Some(idx);
-        info!("Indexes built.");
+    /// The highest sequence number logged so far: in the WAL file, else the
+    /// archive, else its newest segment, and never below the latest backup's.
+    fn last_lsn(storage: &dyn Storage, config: &DatabaseConfig) -> u64 {
+        let newest_segment = config.archive_segments(storage).pop().map(|(_, path)| path);
+        let logged = [
+            Some(config.wal_path()),
+            Some(config.wal_archive_path()),
+            newest_segment,
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|path| storage.read_to_string(&path).ok())
+        .find_map(|records| {
+            records
+                .lines()
+                .filter_map(|line| checksum::unframe_stamped(line).0)
+                .map(|stamp| stamp.lsn)
+                .max()
+        })
+        .unwrap_or(0);
+        let backed_up = backup::read_catalog(storage, &config.backup_catalog_path())
+            .iter()
+            .map(|entry| entry.lsn)
+            .max()
+            .unwrap_or(0);
+        logged.max(backed_up)
     }
 
-    /// Build bloom filter (for instance, for fast lookups on the "email" column).
-    pub fn build_bloom_filter(&mut self) {
-        // Create a bloom filter of fixed size.
-        let mut bf = crate::commands::BloomFilter::BloomFilter::new(1000);
-        for (_table_name, table) in self.tables.iter() {
-            for (_row_id, row_data) in table.rows.iter() {
-                if let Some(email) = row_data.get("email") {
-                    bf.add(email);
-                }
-            }
+    /// Stamp an operation and log it, through the background writer if one is
+    /// running.
+    fn log_op(&mut self, op: String) {
+        if self.names_temp_table(&op) {
+            return;
+        }
+        let stamp = Stamp {
+            lsn: self.next_lsn,
+            millis: clock::unix_millis(),
+        };
+        self.next_lsn += 1;
+        // The write path has changed the tables already.
+        self.mark_applied(stamp.lsn, &op);
+        if let Some(ref writer) = self.wal_writer {
+            writer.log(stamp, op);
+        } else {
+            self.wal.push(op);
+            self.wal_stamps.push(stamp);
         }
-        self.bloom_filter = Some(bf);
-        info!("Bloom filter built.");
     }
 
-    pub fn check_table(&self, table_name: &str) -> bool {
-        self.tables.contains_key(table_name)
+    /// Whether an operation is on a temporary table, which is never logged.
+    fn names_temp_table(&self, op: &str) -> bool {
+        !self.temp_tables.is_empty()
+            && Self::logged_tables(op).any(|table_name| self.temp_tables.contains(table_name))
     }
 
-    // Create table: update in-memory state and log to WAL.
-    pub fn create_table(&mut self, table_name: &str) -> Result<String> {
-        if self.check_table(table_name) {
-            error!("Table '{}' already exists.", table_name);
-            Err(DatabaseError::TableAlreadyExists(table_name.to_string()))
-        } else {
-            // Update in-memory table immediately.
-            self.tables.insert(table_name.to_string(), Table::new());
-            // Log the operation
-            let op = format!("create_table:{}", table_name);
-            self.wal.push(op.clone());
-            println!("Table '{}' created and logged to WAL", table_name);
-            Ok(table_name.to_string())
+    /// The tables a WAL record changes: its first field, and for a copy,
+    /// merge or rename also the target in the second. Transaction records
+    /// name none.
+    fn logged_tables(op: &str) -> impl Iterator<Item = &str> {
+        let mut fields = op.split(':');
+        let named = match fields.next().unwrap_or_default() {
+            "prepare_tx" | "commit_tx" | "abort_tx" => 0,
+            "copy_table" | "merge_tables" | "rename_table" => 2,
+            _ => 1,
+        };
+        fields.take(named)
+    }
+
+    /// Note that the tables `op` changes reflect every record up to `lsn`.
+    fn mark_applied(&mut self, lsn: u64, op: &str) {
+        for table_name in Self::logged_tables(op) {
+            let applied = self.applied_lsn.entry(table_name.to_string()).or_default();
+            *applied = (*applied).max(lsn);
         }
     }
 
-    // New helper function to load table from CSV file into memory.
-    pub fn load_table_from_file(&mut self, table_name: &str, file_name: &str) -> Result<()> {
-        let mut rdr = ReaderBuilder::new()
-            .has_headers(true)
-            .from_path(file_name)
-            .map_err(|e| DatabaseError::FileCreationError(file_name.to_string(), e.to_string()))?;
+    /// Whether every table record `lsn` changes reflects it already.
+    fn is_applied(&self, lsn: u64, op: &str) -> bool {
+        Self::logged_tables(op)
+            .all(|table_name| self.applied_lsn.get(table_name).is_some_and(|&a| a >= lsn))
+    }
 
-        let headers = rdr
-            .headers()
-            .map_err(|e| DatabaseError::FileCreationError(file_name.to_string(), e.to_string()))?
-            .clone();
+    /// Provide the key used for columns marked as encrypted. Must be set before
+    /// loading tables or replaying a WAL that contains encrypted cells.
+    pub fn set_encryption_key(&mut self, key: [u8; 32]) {
+        #[cfg(feature = "encryption")]
+        {
+            self.cipher = Some(ColumnCipher::new(key));
+        }
+        #[cfg(not(feature = "encryption"))]
+        {
+            let _ = key;
+            warn!("Built without the encryption feature; the encryption key is ignored.");
+        }
+    }
 
-        let mut table = Table::new();
-        // add columns
-        for hdr in headers.iter().skip(1) {
-            table.add_column(hdr);
+    /// Mark a column as encrypted: its cells are kept in plaintext in memory but
+    /// only ever written to CSV files and the WAL as ciphertext.
+    pub fn encrypt_column(&mut self, table_name: &str, column_name: &str) -> Result<()> {
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        if self.cipher.is_none() {
+            error!(
+                "Cannot encrypt column '{}': no encryption key set.",
+                column_name
+            );
+            return Err(DatabaseError::EncryptionKeyMissing);
+        }
+        let table = self
+            .tables
+            .get_mut(table_name)
+            .ok_or(DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+        if !table.columns.contains(column_name) {
+            return Err(DatabaseError::ColumnDoesNotExist(
+                column_name.to_string(),
+                table_name.to_string(),
+            ));
         }
+        table.encrypted_columns.insert(column_name.to_string());
+        let op = format!("encrypt_column:{}:{}", table_name, column_name);
+        self.log_op(op);
+        self.writes += 1;
+        info!(
+            "Column '{}' of table '{}' is now encrypted.",
+            column_name, table_name
+        );
+        Ok(())
+    }
 
-        for result in rdr.records() {
-            let record = result.map_err(|e| {
-                DatabaseError::FileCreationError(file_name.to_string(), e.to_string())
-            })?;
-            let row_id = &record[0];
-            let mut data = HashMap::new();
-            for (hdr, field) in headers.iter().skip(1).zip(record.iter().skip(1)) {
-                data.insert(hdr.to_string(), field.to_string());
-            }
-            table.insert_row(row_id, data);
+    /// Set the collation used to compare and order a column's text values.
+    pub fn set_collation(
+        &mut self,
+        table_name: &str,
+        column_name: &str,
+        collation: Collation,
+    ) -> Result<()> {
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        self.ensure_table_loaded(table_name)?;
+        let table = self.tables.get_mut(table_name).unwrap();
+        if !table.columns.contains(column_name) {
+            return Err(DatabaseError::ColumnDoesNotExist(
+                column_name.to_string(),
+                table_name.to_string(),
+            ));
         }
-        self.tables.insert(table_name.to_string(), table);
-        println!("Loaded table '{}' from '{}'", table_name, file_name);
+        table.collations.insert(column_name.to_string(), collation);
+        let op = format!("set_collation:{}:{}:{}", table_name, column_name, collation);
+        self.log_op(op);
+        self.writes += 1;
+        info!(
+            "Column '{}' of table '{}' now uses {} collation.",
+            column_name, table_name, collation
+        );
         Ok(())
     }
 
-    // Add a column: log and update in-memory.
-    pub fn add_column(&mut self, table_name: &str, column_name: &str) -> Result<Vec<String>> {
-        // Check if the table is in-memory.
-        if !self.check_table(table_name) {
-            // Table not found: try to load it from file.
-            let file_name = format!("{}.csv", table_name);
-            if fs::metadata(&file_name).is_ok() {
-                match self.load_table_from_file(table_name, &file_name) {
-                    Ok(_) => println!("Table '{}' loaded from file '{}'.", table_name, file_name),
-                    Err(e) => {
-                        error!("Failed to load table from file: {}", e);
-                        return Err(e);
+    /// Declare a column an enum: from now on it only takes one of `values`
+    /// (or no value), and table files store each value as its position in
+    /// `values`. The column's values, cold rows included, must already be
+    /// among them. Declaring it again replaces the allowed values.
+    pub fn set_enum(&mut self, table_name: &str, column_name: &str, values: &[&str]) -> Result<()> {
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        self.ensure_table_loaded(table_name)?;
+        let datatype = format!("enum({})", values.join("|"));
+        if values.iter().any(|v| v.contains('|')) || !self.known_datatype(&datatype) {
+            error!("Invalid enum values {:?}.", values);
+            return Err(DatabaseError::InvalidDataType);
+        }
+        let table = &self.tables[table_name];
+        if !table.columns.contains(column_name) {
+            return Err(DatabaseError::ColumnDoesNotExist(
+                column_name.to_string(),
+                table_name.to_string(),
+            ));
+        }
+        let allowed = |value: &str| value.is_empty() || values.contains(&value);
+        let mut invalid = table
+            .rows
+            .iter()
+            .filter(|(row_id, _)| row_id.as_str() != "datatypes")
+            .filter_map(|(_, row)| row.get(column_name))
+            .find(|value| !allowed(value))
+            .cloned();
+        let segment = self.cold.get(table_name).filter(|_| invalid.is_none());
+        if let Some(segment) = segment {
+            segment
+                .visit(self.storage.as_ref(), None, |_, row| {
+                    let value = row.get(column_name).map(|v| self.open_cell(v));
+                    match value {
+                        Some(value) if !allowed(&value) => {
+                            invalid = Some(value);
+                            Ok(false)
+                        }
+                        _ => Ok(true),
                     }
-                }
-            } else {
-                error!(
-                    "Table '{}' does not exist in memory or on disk.",
-                    table_name
-                );
-                return Err(DatabaseError::TableDoesNotExist(table_name.to_string()));
-            }
+                })
+                .map_err(|e| DatabaseError::FileCreationError(segment.path().to_string(), e.to_string()))?;
+        }
+        if let Some(value) = invalid {
+            return Err(DatabaseError::NotInEnum(value, column_name.to_string()));
         }
-        // At this point the table should be in memory.
         if let Some(table) = self.tables.get_mut(table_name) {
-            table.add_column(column_name);
-            let op = format!("add_column:{}:{}", table_name, column_name);
-            // self.wal.push(op);
-            if let Some(ref writer) = self.wal_writer {
-                writer.log(op);
-            } else {
-                self.wal.push(op);
+            table.row_datatypes.insert(column_name.to_string(), datatype.clone());
+        }
+        let data = HashMap::from([(column_name.to_string(), datatype)]);
+        self.insert_row(table_name, "datatypes", data)?;
+        self.sync_catalog(table_name)?;
+        info!(
+            "Column '{}' of table '{}' is now an enum of {} values.",
+            column_name,
+            table_name,
+            values.len()
+        );
+        Ok(())
+    }
+
+    /// Choose how `insert_generated` picks ids for rows of a table.
+    pub fn set_row_id_strategy(&mut self, table_name: &str, strategy: RowIdStrategy) -> Result<()> {
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        self.ensure_table_loaded(table_name)?;
+        let table = self.tables.get_mut(table_name).unwrap();
+        table.row_ids = RowIdGenerator::new(strategy);
+        let op = format!("set_row_id_strategy:{}:{}", table_name, strategy);
+        self.log_op(op);
+        self.writes += 1;
+        info!("Table '{}' now uses {} row ids.", table_name, strategy);
+        Ok(())
+    }
+
+    /// Set when a table is saved to its file, overriding the config for that
+    /// table. `Autosave::default()` goes back to the config's settings.
+    pub fn set_autosave(&mut self, table_name: &str, autosave: Autosave) -> Result<()> {
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        self.ensure_table_loaded(table_name)?;
+        let table = self.tables.get_mut(table_name).unwrap();
+        table.autosave = autosave;
+        let op = format!(
+            "set_autosave:{}:{}",
+            table_name,
+            serde_json::to_string(&autosave).unwrap()
+        );
+        self.log_op(op);
+        self.writes += 1;
+        info!(
+            "Table '{}' now saves by {} policy, threshold {}.",
+            table_name,
+            self.persistence_for(table_name),
+            self.save_threshold_for(table_name)
+        );
+        Ok(())
+    }
+
+    /// Insert a row under an id chosen by the table's row id strategy and
+    /// return the id. Fails for tables using the default `caller` strategy.
+    pub fn insert_generated(
+        &mut self,
+        table_name: &str,
+        data: HashMap<String, String>,
+    ) -> Result<String> {
+        self.ensure_table_loaded(table_name)?;
+        let table = self.tables.get_mut(table_name).unwrap();
+        // Ids passed by callers may already have taken the next one.
+        let row_id = loop {
+            let id = table
+                .row_ids
+                .next_id(table.rows.keys())
+                .ok_or_else(|| DatabaseError::RowIdRequired(table_name.to_string()))?;
+            if !table.rows.contains_key(&id) {
+                break id;
             }
-            println!(
-                "Column '{}' added to table '{}' and logged to WAL",
-                column_name, table_name
-            );
-            Ok(vec![column_name.to_string(), table_name.to_string()])
-        } else {
-            error!(
-                "Table '{}' is still not found after attempting to load.",
-                table_name
-            );
-            Err(DatabaseError::TableDoesNotExist(table_name.to_string()))
+        };
+        self.insert_row(table_name, &row_id, data)?;
+        Ok(row_id)
+    }
+
+    /// The on-disk form of a cell: ciphertext for encrypted columns, plaintext
+    /// otherwise, then a reference in place of a large value when `blobs` is given.
+    fn seal_cell(
+        cipher: &Option<ColumnCipher>,
+        blobs: Option<&BlobStore>,
+        table: &Table,
+        column: &str,
+        value: &str,
+    ) -> String {
+        let sealed = match cipher {
+            Some(c) if table.encrypted_columns.contains(column) => Cow::Owned(c.encrypt(value)),
+            _ => Cow::Borrowed(value),
+        };
+        match blobs {
+            Some(blobs) => blobs.put(&sealed).into_owned(),
+            None => sealed.into_owned(),
         }
     }
 
-    #[allow(dead_code)]
-    fn valid_datatype(dt: &str) -> bool {
-        match dt {
-            "int" | "float" | "string" => true,
-            _ => false,
+    /// The form of a cell in a table file: enum values as their codes, then
+    /// sealed like `seal_cell`. `open_cell` and `decode_enum` reverse it.
+    fn store_cell(
+        cipher: &Option<ColumnCipher>,
+        blobs: Option<&BlobStore>,
+        table: &Table,
+        column: &str,
+        value: &str,
+    ) -> String {
+        match table.datatype(column) {
+            Some(datatype) => Self::seal_cell(
+                cipher,
+                blobs,
+                table,
+                column,
+                &table_schema::encode_enum(datatype, value),
+            ),
+            None => Self::seal_cell(cipher, blobs, table, column, value),
         }
     }
-    #[allow(dead_code)]
-    fn check_value_matches(value: &str, dtype: &str) -> bool {
-        match dtype {
-            "int" => value.parse::<i64>().is_ok(),
-            "float" => value.parse::<f64>().is_ok(),
-            "bool" => {
-                let lower = value.to_lowercase();
-                lower == "true" || lower == "false"
+
+    /// The in-memory form of a cell read from disk. Values that cannot be
+    /// read from the blob store or decrypted are kept as-is.
+    fn open_cell(&self, value: &str) -> String {
+        let value = self.blobs.get(value).unwrap_or_else(|e| {
+            error!("Failed to read blob '{}': {}", value, e);
+            Cow::Borrowed(value)
+        });
+        match self.cipher {
+            Some(ref c) if crypto::is_encrypted(&value) => {
+                c.decrypt(&value).unwrap_or_else(|| value.into_owned())
             }
-            "string" => true,
-            _ => false,
+            _ => value.into_owned(),
         }
     }
-    #[allow(dead_code)]
-    fn is_subset_vec_str(&self, a: &Vec<&str>) -> bool {
-        a.iter().all(|&dt| self.datatypes.contains(&dt.to_string()))
+
+    fn open_row(&self, row: HashMap<String, String>) -> HashMap<String, String> {
+        row.into_iter()
+            .map(|(c, v)| {
+                let v = self.open_cell(&v);
+                (c, v)
+            })
+            .collect()
     }
-    pub fn add_columns(
-        &mut self,
+
+    /// A WAL entry as written to disk: one record per line, after its stamp,
+    /// encrypted when a key is set, followed by a checksum.
+    fn wal_record(&self, stamp: Stamp, entry: &str) -> String {
+        let record = match self.cipher {
+            Some(ref c) => c.encrypt(entry),
+            None => entry.to_string(),
+        };
+        checksum::frame_stamped(stamp, &record)
+    }
+
+    fn seal_row(
+        &self,
         table_name: &str,
-        column_names: Vec<&str>,
-        datatypes: Vec<&str>,
-    ) -> Result<Vec<Vec<String>>> {
-        if column_names.len() != datatypes.len() {
-            error!("Column names and datatypes must have the same length.");
-            return Err(DatabaseError::DataTypeError);
+        data: &HashMap<String, String>,
+    ) -> HashMap<String, String> {
+        match self.tables.get(table_name) {
+            Some(table) => data
+                .iter()
+                .map(|(c, v)| {
+                    let sealed = Self::seal_cell(&self.cipher, Some(&self.blobs), table, c, v);
+                    (c.clone(), sealed)
+                })
+                .collect(),
+            None => data.clone(),
         }
+    }
 
-        if !self.check_table(table_name) {
-            // Table not found: try to load it from file.
-            let file_name = format!("{}.csv", table_name);
-            if fs::metadata(&file_name).is_ok() {
-                match self.load_table_from_file(table_name, &file_name) {
-                    Ok(_) => println!("Table '{}' loaded from file '{}'.", table_name, file_name),
-                    Err(e) => {
-                        error!("Failed to load table from file: {}", e);
-                        return Err(e);
-                    }
-                }
-            } else {
-                error!(
-                    "Table '{}' does not exist in memory or on disk.",
-                    table_name
-                );
-                return Err(DatabaseError::TableDoesNotExist(table_name.to_string()));
-            }
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            error!("Write rejected: database is read-only.");
+            return Err(DatabaseError::ReadOnly);
         }
-        if Database::is_subset_vec_str(self, &datatypes) == false {
-            error!("Invalid datatypes provided.");
-            return Err(DatabaseError::InvalidDataType);
+        Ok(())
+    }
+
+    /// Reject writes once the in-memory WAL holds `max_wal_backlog` uncommitted entries.
+    fn check_wal_backlog(&self) -> Result<()> {
+        if let Some(max) = self.config.limits.max_wal_backlog {
+            if self.wal_writer.is_none() && self.wal.len() >= max {
+                error!("Write rejected: WAL backlog of {} entries is full.", max);
+                return Err(DatabaseError::WalBacklogFull(max));
+            }
         }
+        Ok(())
+    }
 
-        let mut results = Vec::new();
-
-        // Add the new columns.
-        for col in column_names.iter() {
-            match self.add_column(table_name, col) {
-                Ok(res) => results.push(res),
-                Err(e) => return Err(e),
+    fn check_table_count(&self) -> Result<()> {
+        if let Some(max) = self.config.limits.max_tables {
+            if self.tables.len() >= max {
+                error!("Write rejected: table limit of {} reached.", max);
+                return Err(DatabaseError::TooManyTables(max));
             }
         }
-
-        // Insert a single new row that contains the datatypes for each new column.
-        let mut data = HashMap::new();
-        let table = self
-            .tables
-            .get_mut(table_name)
-            .ok_or(DatabaseError::TableDoesNotExist(table_name.to_string()))?;
-        for (col, dt) in column_names.iter().zip(datatypes.iter()) {
-            data.insert(col.to_string(), dt.to_string());
-            table.add_datatype(col, dt);
-        }
-        match self.insert_row(table_name, "datatypes", data) {
-            Ok(res) => results.push(res),
-            Err(e) => return Err(e),
-        }
-
-        Ok(results)
+        Ok(())
     }
 
-    // Get row from table.
-    pub fn get_row(&mut self, table_name: &str, row_id: &str) -> Result<Vec<String>> {
-        // If the table isn't in memory, try to load it from file.
-        if !self.check_table(table_name) {
-            let file_name = format!("{}.csv", table_name);
-            if fs::metadata(&file_name).is_ok() {
-                match self.load_table_from_file(table_name, &file_name) {
-                    Ok(_) => println!("Table '{}' loaded from file '{}'.", table_name, file_name),
-                    Err(e) => {
-                        error!("Failed to load table from file: {}", e);
-                        return Err(e);
-                    }
-                }
-            } else {
+    /// Check the values of a row's enum columns are among their allowed values.
+    fn check_enum_values(
+        &self,
+        table_name: &str,
+        row_id: &str,
+        row: &HashMap<String, String>,
+    ) -> Result<()> {
+        let Some(table) = self.tables.get(table_name) else {
+            return Ok(());
+        };
+        if row_id == "datatypes" {
+            return Ok(());
+        }
+        for (column, value) in row.iter().filter(|(_, value)| !value.is_empty()) {
+            if table
+                .enum_values(column)
+                .is_some_and(|members| !members.contains(&value.as_str()))
+            {
                 error!(
-                    "Table '{}' does not exist in memory or on disk.",
-                    table_name
+                    "Write rejected: '{}' is not an allowed value of column '{}' in row '{}'.",
+                    value, column, row_id
                 );
-                return Err(DatabaseError::TableDoesNotExist(table_name.to_string()));
-            }
-        }
-        // Now the table must be in memory.
-        if let Some(table) = self.tables.get(table_name) {
-            if let Some(row) = table.get_row(row_id) {
-                println!("Row '{}': {:?}", row_id, row);
-                let row_string = format!("{:?}", row);
-                Ok(vec![row_id.to_string(), row_string])
-            } else {
-                error!("Row '{}' does not exist in '{}'.", row_id, table_name);
-                Err(DatabaseError::RowDoesNotExist(
-                    row_id.to_string(),
-                    table_name.to_string(),
-                ))
+                return Err(DatabaseError::NotInEnum(value.clone(), column.clone()));
             }
-        } else {
-            error!(
-                "Table '{}' is still not found after attempting to load.",
-                table_name
-            );
-            Err(DatabaseError::TableDoesNotExist(table_name.to_string()))
         }
+        Ok(())
     }
 
-    // Insert row: update in-memory table and log the operation.
-    pub fn insert_row(
-        &mut self,
+    /// Check a row against the table's cell and row size limits.
+    fn check_row_size(
+        &self,
         table_name: &str,
         row_id: &str,
-        data: HashMap<String, String>,
-    ) -> Result<Vec<String>> {
-        // If the table isn't in memory, try to load it from file.
-        if !self.check_table(table_name) {
-            let file_name = format!("{}.csv", table_name);
-            if fs::metadata(&file_name).is_ok() {
-                match self.load_table_from_file(table_name, &file_name) {
-                    Ok(_) => println!("Table '{}' loaded from file '{}'.", table_name, file_name),
-                    Err(e) => {
-                        error!("Failed to load table from file: {}", e);
-                        return Err(e);
-                    }
-                }
-            } else {
+        row: &HashMap<String, String>,
+    ) -> Result<()> {
+        if let Some(max) = self.config.max_cell_bytes_for(table_name) {
+            if let Some((column, value)) = row.iter().find(|(_, value)| value.len() > max) {
                 error!(
-                    "Table '{}' does not exist in memory or on disk.",
-                    table_name
+                    "Write rejected: column '{}' of row '{}' is {} bytes (limit {}).",
+                    column,
+                    row_id,
+                    value.len(),
+                    max
                 );
-                return Err(DatabaseError::TableDoesNotExist(table_name.to_string()));
+                return Err(DatabaseError::CellTooLarge(
+                    row_id.to_string(),
+                    column.clone(),
+                    value.len(),
+                    max,
+                ));
+            }
+        }
+        if let Some(max) = self.config.max_row_bytes_for(table_name) {
+            let size = Table::row_size(row_id, row);
+            if size > max {
+                error!(
+                    "Write rejected: row '{}' is {} bytes (limit {}).",
+                    row_id, size, max
+                );
+                return Err(DatabaseError::RowTooLarge(row_id.to_string(), size, max));
             }
         }
+        Ok(())
+    }
 
-        // //check for datatype
-        // for (col, val) in &data {
-        //     if let Some(table) = self.tables.get(table_name) {
-        //         if let Some(dt) = table.row_datatypes.get(col) {
-        //             if !Database::check_value_matches(val, dt) {
-        //                 error!("Value '{}' does not match datatype '{}' for column '{}'.", val, dt, col);
-        //                 return Err(DatabaseError::DataTypeError);
-        //             }
-        //         } else {
-        //             error!("Column '{}' not found in table '{}'.", col, table_name);
-        //             return Err(DatabaseError::RowDoesNotExist(row_id.to_string(), table_name.to_string()));
-        //         }
-        //     }
-        // }
+    /// Get `config.data_dir` ready to be opened for writing: create its
+    /// layout, move files from the flat layout of older versions into it, and
+    /// remove the temp files of writes a crash interrupted.
+    pub fn prepare_data_dir(config: &DatabaseConfig, storage: &dyn Storage) -> Result<()> {
+        let moved = config.init_layout(storage).map_err(|e| {
+            DatabaseError::FileCreationError(config.data_dir.clone(), e.to_string())
+        })?;
+        for file in &moved {
+            info!("Moved '{}' into the data directory layout.", file);
+        }
+        for dir in [config.tables_dir(), config.wal_dir(), config.index_dir()] {
+            storage.recover(&dir);
+        }
+        Ok(())
+    }
 
-        // Now perform the row insertion.
-        if let Some(table) = self.tables.get_mut(table_name) {
-            table.insert_row(row_id, data.clone());
-            let op = format!(
-                "insert_row:{}:{}:{}",
-                table_name,
-                row_id,
-                serde_json::to_string(&data).unwrap()
-            );
-            // self.wal.push(op);
-            if let Some(ref writer) = self.wal_writer {
-                writer.log(op);
-            } else {
-                self.wal.push(op);
+    /// Load every `*.csv` file in the tables directory as a table. Returns the table names loaded.
+    pub fn load_tables_from_data_dir(&mut self) -> Result<Vec<String>> {
+        let dir = self.config.tables_dir();
+        let files = self
+            .storage
+            .list(&dir)
+            .map_err(|e| DatabaseError::FileCreationError(dir.clone(), e.to_string()))?;
+        let mut loaded = Vec::new();
+        for file in files {
+            let path = Path::new(&file);
+            if path.extension() != Some(std::ffi::OsStr::new("csv")) {
+                continue;
             }
-            println!(
-                "Inserted row '{}' in table '{}' and logged to WAL",
-                row_id, table_name
-            );
-
-            self.operations_since_save += 1;
-            if self.operations_since_save >= self.save_threshold {
-                let file_name = format!("{}.csv", table_name);
-                if let Err(e) = self.save_table_for_insert(table_name, &file_name) {
-                    error!("Failed to save table '{}': {}", table_name, e);
-                }
-                self.operations_since_save = 0;
+            if let Some(table_name) = path.file_stem().and_then(|f| f.to_str()) {
+                self.load_table_from_file(table_name, &file)?;
+                loaded.push(table_name.to_string());
             }
-            Ok(vec![row_id.to_string(), table_name.to_string()])
-        } else {
-            error!(
-                "Table '{}' is still not found after attempting to load.",
-                table_name
-            );
-            Err(DatabaseError::TableDoesNotExist(table_name.to_string()))
         }
+        loaded.sort();
+        Ok(loaded)
     }
 
-    pub fn insert_row_with_datatype(
-        &mut self,
-        table_name: &str,
-        row_id: &str,
-        data: HashMap<String, String>,
-    ) -> Result<Vec<Vec<String>>> {
-        if !self.check_table(table_name) {
-            // Table not found: try to load it from file.
-            let file_name = format!("{}.csv", table_name);
-            if fs::metadata(&file_name).is_ok() {
-                match self.load_table_from_file(table_name, &file_name) {
-                    Ok(_) => println!("Table '{}' loaded from file '{}'.", table_name, file_name),
-                    Err(e) => {
-                        error!("Failed to load table from file: {}", e);
-                        return Err(e);
-                    }
+    /// Build indexes (for example, index the configured column of every row).
+    #[instrument(skip(self))]
+    pub fn build_indexes(&mut self) {
+        // For simplicity, we build one global index on the configured column ("name" by default).
+        let mut idx = Indexer::Indexer::with_options(self.config.match_options);
+        let column = &self.config.index_column;
+        for (table_name, table) in self.tables.iter() {
+            for (row_id, row_data) in table.rows.iter().filter(|(id, _)| *id != "datatypes") {
+                if let Some(value) = row_data.get(column) {
+                    idx.add_scoped(table_name, column, value, row_id);
                 }
-            } else {
-                error!(
-                    "Table '{}' does not exist in memory or on disk.",
-                    table_name
-                );
-                return Err(DatabaseError::TableDoesNotExist(table_name.to_string()));
             }
         }
-        let table = self
-            .tables
-            .get_mut(table_name)
-            .ok_or(DatabaseError::TableDoesNotExist(table_name.to_string()))?;
-        //check if the row_id already exists
-        if let Some(existing_row) = table.get_row(row_id) {
-            error!("Row '{}' already exists in table '{}'.", row_id, table_name);
-            return Err(DatabaseError::RowDoesNotExist(
-                row_id.to_string(),
+        self.indexer = Some(idx);
+        self.index_built_at = Some(self.writes);
+        let tables: BTreeSet<String> = self.table_indexes.keys().map(|(t, _)| t.clone()).collect();
+        for table_name in tables {
+            self.refresh_table_indexes(&table_name);
+        }
+        info!("Indexes built.");
+    }
+
+    /// Index `column` of a table. A bitmap index suits columns with few
+    /// distinct values and makes AND/OR filters over them fast; see `filter`.
+    pub fn create_index(&mut self, table_name: &str, column: &str, kind: IndexKind) -> Result<()> {
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        self.ensure_table_loaded(table_name)?;
+        if !self.tables[table_name].columns.contains(column) {
+            return Err(DatabaseError::ColumnDoesNotExist(
+                column.to_string(),
                 table_name.to_string(),
             ));
         }
+        let op = format!("create_index:{}:{}:{}", table_name, column, kind);
+        self.log_op(op);
+        self.writes += 1;
+        self.table_indexes.insert(
+            (table_name.to_string(), column.to_string()),
+            TableIndex::new(kind),
+        );
+        self.refresh_table_indexes(table_name);
+        info!("Created {} index on '{}.{}'.", kind, table_name, column);
+        Ok(())
+    }
 
-        //check for datatype
-        for (col, val) in &data {
-            if let Some(dt) = table.row_datatypes.get(col) {
-                if !Database::check_value_matches(val, dt) {
-                    error!(
-                        "Value '{}' does not match datatype '{}' for column '{}'.",
-                        val, dt, col
-                    );
-                    return Err(DatabaseError::DataTypeError);
-                }
-            } else {
-                error!("Column '{}' not found in table '{}'.", col, table_name);
-                return Err(DatabaseError::RowDoesNotExist(
-                    row_id.to_string(),
-                    table_name.to_string(),
-                ));
+    /// Rebuild the table's indexes that predate the latest write.
+    fn refresh_table_indexes(&mut self, table_name: &str) {
+        self.rebuild_stale_indexes(table_name, |_| true);
+    }
+
+    /// Rebuild the table's indexes that predate the latest write and `pick` selects.
+    fn rebuild_stale_indexes(&mut self, table_name: &str, pick: impl Fn(&TableIndex) -> bool) {
+        let Some(table) = self.tables.get(table_name) else {
+            return;
+        };
+        let key = (table_name.to_string(), String::new());
+        for ((_, column), index) in self
+            .table_indexes
+            .range_mut(key..)
+            .take_while(|((t, _), _)| t == table_name)
+        {
+            if !index.is_current(self.writes) && pick(index) {
+                index.build(table, column, self.config.match_options, self.writes);
+                debug!(
+                    "Rebuilt {} index on '{}.{}'.",
+                    index.kind, table_name, column
+                );
             }
         }
-        // Now perform the row insertion.
-        let result = self.insert_row(table_name, row_id, data)?;
-        Ok(vec![result])
     }
 
-    // Update a value in a row for a specific column.
-    pub fn update_row(
+    /// Rows matching every condition of an AND and any of an OR. Equality
+    /// conditions on indexed text columns are answered from the index, the
+    /// rest by a scan, and the results combined as bitmaps of row positions.
+    pub fn filter(
         &mut self,
         table_name: &str,
-        row_id: &str,
-        column_name: &str,
-        new_value: &str,
-    ) -> Result<Vec<String>> {
-        if !self.check_table(table_name) {
-            let file_name = format!("{}.csv", table_name);
-            if fs::metadata(&file_name).is_ok() {
-                match self.load_table_from_file(table_name, &file_name) {
-                    Ok(_) => println!("Table '{}' loaded from file '{}'.", table_name, file_name),
-                    Err(e) => {
-                        error!("Failed to load table '{}' from file: {}", table_name, e);
-                        return Err(e);
-                    }
-                }
-            } else {
-                error!(
-                    "Table '{}' does not exist in memory or on disk.",
-                    table_name
-                );
-                return Err(DatabaseError::TableDoesNotExist(table_name.to_string()));
+        filter: &Filter,
+        projection: Option<&[&str]>,
+    ) -> Result<QueryResult> {
+        self.filter_with_hints(table_name, filter, projection, &QueryHints::default())
+    }
+
+    /// `filter`, made to use an index, avoid scans or stop early as `hints`
+    /// say. A hint that can't be honored fails the query before any row is
+    /// returned.
+    pub fn filter_with_hints(
+        &mut self,
+        table_name: &str,
+        filter: &Filter,
+        projection: Option<&[&str]>,
+        hints: &QueryHints,
+    ) -> Result<QueryResult> {
+        let started = Instant::now();
+        self.ensure_table_loaded(table_name)?;
+        match hints.consistency {
+            Consistency::Strict => self.refresh_table_indexes(table_name),
+            // Bitmaps locate rows by position, which any write may shift.
+            Consistency::IndexOk => {
+                self.rebuild_stale_indexes(table_name, |index| index.kind == IndexKind::Bitmap)
             }
         }
-        // Now the table should be in memory.
-        if let Some(table) = self.tables.get_mut(table_name) {
-            // Ensure the column exists; add it if not.
-            if !table.columns.contains(&column_name.to_string()) {
-                table.add_column(column_name);
-                println!(
-                    "Column '{}' was added to table '{}'",
-                    column_name, table_name
-                );
+        if let Some(column) = &hints.force_index {
+            let key = (table_name.to_string(), column.clone());
+            if !self.table_indexes.contains_key(&key) {
+                return Err(DatabaseError::HintRejected(format!(
+                    "'{}.{}' has no index to force",
+                    table_name, column
+                )));
             }
-            if let Some(row) = table.rows.get_mut(row_id) {
-                // Update the row in place.
-                row.insert(column_name.to_string(), new_value.to_string());
+            if self.cold.contains_key(table_name) {
+                return Err(DatabaseError::HintRejected(format!(
+                    "'{}' has cold rows, which its indexes don't cover",
+                    table_name
+                )));
+            }
+        }
+        let items = projection;
+        let projection = self.parse_projection(table_name, projection)?;
+        let projection = projection.as_deref();
+        let table = self
+            .table_with_cold_rows(table_name)
+            .ok_or_else(|| DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+        let table = table.as_ref();
+        let row_ids: Vec<&String> = table.rows.keys().collect();
+        let mut examined = 0;
+        let matched =
+            self.filter_positions(table_name, table, &row_ids, filter, hints, &mut examined)?;
+        debug!(
+            "Filter on '{}' examined {} rows for {} matches.",
+            table_name,
+            examined,
+            matched.len()
+        );
+        let results = matched
+            .iter()
+            .map(|position| row_ids[position as usize])
+            .filter(|row_id| !self.hidden(table, &table.rows[*row_id]))
+            .map(|row_id| {
+                (
+                    row_id.clone(),
+                    Self::project(&table.rows[row_id], projection),
+                )
+            })
+            .collect();
+        Ok(QueryResult::read(
+            Self::row_set(Some(table), items, results),
+            started,
+        ))
+    }
 
-                // Log the update operation in the WAL.
-                let op = format!(
-                    "update_row:{}:{}:{}:{}",
-                    table_name,
-                    row_id,
-                    column_name,
-                    serde_json::to_string(new_value).unwrap()
-                );
-                // self.wal.push(op);
-                if let Some(ref writer) = self.wal_writer {
-                    writer.log(op);
-                } else {
-                    self.wal.push(op);
+    /// Positions of the rows matching `filter`, counting the rows examined
+    /// into `examined` and failing where `hints` can't be honored.
+    fn filter_positions(
+        &self,
+        table_name: &str,
+        table: &Table,
+        row_ids: &[&String],
+        filter: &Filter,
+        hints: &QueryHints,
+        examined: &mut usize,
+    ) -> Result<Bitmap> {
+        match filter {
+            Filter::Condition(text) => {
+                let condition = self.parse_condition(table_name, text)?;
+                let column = condition.left.as_column();
+                let lookup =
+                    self.index_lookup(table_name, table, row_ids, &condition, hints.consistency);
+                let (positions, cost) = match lookup {
+                    Some(positions) => {
+                        let cost = positions.len();
+                        (Some(positions), cost)
+                    }
+                    None => {
+                        if hints.force_index.as_deref().is_some_and(|c| Some(c) == column) {
+                            return Err(DatabaseError::HintRejected(format!(
+                                "the index on '{}' can't answer '{}'; only equality on \
+                                 untyped or string columns without a collation can",
+                                column.unwrap_or_default(),
+                                text
+                            )));
+                        }
+                        if hints.no_full_scan {
+                            return Err(DatabaseError::HintRejected(format!(
+                                "'{}' needs a full scan of '{}'",
+                                text, table_name
+                            )));
+                        }
+                        (None, table.rows.len())
+                    }
+                };
+                *examined += cost;
+                if let Some(max) = hints.max_rows_examined.filter(|max| *examined > *max) {
+                    return Err(DatabaseError::HintRejected(format!(
+                        "'{}' would examine {} rows of '{}', over the limit of {}",
+                        text, examined, table_name, max
+                    )));
                 }
-                println!(
-                    "Updated row '{}' in table '{}', column '{}' set to '{}'.",
-                    row_id, table_name, column_name, new_value
-                );
-                self.save_table(table_name, &format!("{}.csv", table_name))?;
-                self.operations_since_save += 1;
-                if self.operations_since_save >= self.save_threshold {
-                    let file_name = format!("{}.csv", table_name);
-                    if let Err(e) = self.save_table(table_name, &file_name) {
-                        error!("Failed to save table '{}': {}", table_name, e);
+                // The datatypes row holds type names, not data, and never matches.
+                let data_row = |position: usize| row_ids[position] != "datatypes";
+                if let Some(positions) = positions {
+                    return Ok(positions.iter().filter(|&p| data_row(p as usize)).collect());
+                }
+                Ok(table
+                    .rows
+                    .values()
+                    .enumerate()
+                    .filter(|(position, row)| {
+                        data_row(*position) && condition.matches(row, &self.config.match_options)
+                    })
+                    .map(|(position, _)| position as u32)
+                    .collect())
+            }
+            Filter::And(all) => {
+                let mut matched: Option<Bitmap> = None;
+                for filter in all {
+                    let positions =
+                        self.filter_positions(table_name, table, row_ids, filter, hints, examined)?;
+                    let positions = match matched {
+                        Some(matched) => matched.and(&positions),
+                        None => positions,
+                    };
+                    if positions.is_empty() {
+                        return Ok(positions);
                     }
-                    self.operations_since_save = 0;
+                    matched = Some(positions);
                 }
-                Ok(vec![
-                    row_id.to_string(),
-                    column_name.to_string(),
-                    new_value.to_string(),
-                ])
-            } else {
-                error!("Row '{}' does not exist in table '{}'.", row_id, table_name);
-                Err(DatabaseError::RowDoesNotExist(
-                    row_id.to_string(),
-                    table_name.to_string(),
-                ))
+                Ok(matched.unwrap_or_default())
             }
-        } else {
-            error!(
-                "Table '{}' is still not found after attempting to load.",
-                table_name
-            );
-            Err(DatabaseError::TableDoesNotExist(table_name.to_string()))
+            Filter::Or(any) => any.iter().try_fold(Bitmap::new(), |matched, filter| {
+                let positions =
+                    self.filter_positions(table_name, table, row_ids, filter, hints, examined)?;
+                Ok(matched.or(&positions))
+            }),
         }
     }
 
-    pub fn save_table_for_insert(
+    /// Positions matching `column == value` from an index `consistency`
+    /// allows, if the column has one and compares as plain text.
+    fn index_lookup(
+        &self,
+        table_name: &str,
+        table: &Table,
+        row_ids: &[&String],
+        condition: &Condition,
+        consistency: Consistency,
+    ) -> Option<Bitmap> {
+        if condition.operator != Operator::Eq {
+            return None;
+        }
+        // Indexes cover the rows in memory, so a table with cold rows is scanned.
+        if self.cold.contains_key(table_name) {
+            return None;
+        }
+        let column = condition.left.as_column()?;
+        // Typed columns and collations compare differently from the index keys.
+        let plain = table
+            .row_datatypes
+            .get(column)
+            .is_none_or(|datatype| datatype == "string")
+            && !table.collations.contains_key(column);
+        let current = |index: &TableIndex| index.is_current(self.writes);
+        let index = self
+            .table_indexes
+            .get(&(table_name.to_string(), column.to_string()))
+            .filter(|index| plain && (current(index) || consistency == Consistency::IndexOk))?;
+        let positions = index.lookup(&condition.value, row_ids)?;
+        if current(index) {
+            return Some(positions);
+        }
+        // An older entry may name a row whose value has changed since.
+        Some(
+            positions
+                .iter()
+                .filter(|&position| {
+                    let row = &table.rows[row_ids[position as usize]];
+                    condition.matches(row, &self.config.match_options)
+                })
+                .collect(),
+        )
+    }
+
+    /// Build bloom filter (for instance, for fast lookups on the "email" column).
+    #[instrument(skip(self))]
+    pub fn build_bloom_filter(&mut self) {
+        // Create a bloom filter of the configured size.
+        let mut bf = crate::commands::BloomFilter::BloomFilter::new(self.config.bloom_filter_size);
+        for (_table_name, table) in self.tables.iter() {
+            for (_row_id, row_data) in table.rows.iter() {
+                if let Some(email) = row_data.get(&self.config.bloom_column) {
+                    bf.add(email);
+                }
+            }
+        }
+        self.bloom_filter = Some(bf);
+        self.bloom_built_at = Some(self.writes);
+        info!("Bloom filter built.");
+    }
+
+    /// Copy the index and bloom columns of every table as they are now, so
+    /// the index and bloom filter can be rebuilt from the copy without the
+    /// lock; see `DbHandle::rebuild_indexes`.
+    pub fn begin_index_rebuild(&self) -> IndexRebuild {
+        let mut entries = Vec::new();
+        let mut bloom_values = Vec::new();
+        let mut sketch_columns = Vec::new();
+        let mut sketch_values = Vec::new();
+        for (table_name, table) in self.tables.iter() {
+            let data_rows = || table.rows.iter().filter(|(id, _)| *id != "datatypes");
+            for (row_id, row_data) in data_rows() {
+                if let Some(value) = row_data.get(&self.config.index_column) {
+                    entries.push((table_name.clone(), value.clone(), row_id.clone()));
+                }
+                if let Some(value) = row_data.get(&self.config.bloom_column) {
+                    bloom_values.push(value.clone());
+                }
+            }
+            // A sketch of only the hot rows would be misleading, so tables
+            // with cold rows are sketched on demand instead.
+            if self.cold.contains_key(table_name) {
+                continue;
+            }
+            let columns: BTreeSet<&String> = self
+                .table_indexes
+                .keys()
+                .filter(|(t, _)| t == table_name)
+                .map(|(_, c)| c)
+                .chain(table.columns.get(&self.config.index_column))
+                .collect();
+            for column in columns {
+                let sketch = sketch_columns.len();
+                sketch_columns.push((table_name.clone(), column.clone()));
+                for (_, row) in data_rows().filter(|(_, row)| !self.hidden(table, row)) {
+                    if let Some(value) = row.get(column) {
+                        let value = self.config.match_options.fold(value).into_owned();
+                        sketch_values.push((sketch, value));
+                    }
+                }
+            }
+        }
+        debug!(
+            "Index rebuild snapshot at write {}: {} index, {} bloom and {} sketch values.",
+            self.writes,
+            entries.len(),
+            bloom_values.len(),
+            sketch_values.len()
+        );
+        IndexRebuild::new(IndexSnapshot {
+            version: self.writes,
+            options: self.config.match_options,
+            bloom_size: self.config.bloom_filter_size,
+            column: self.config.index_column.clone(),
+            entries,
+            bloom_values,
+            sketch_columns,
+            sketch_values,
+        })
+    }
+
+    /// Finish `rebuild` and swap the new index and bloom filter in together.
+    /// They describe the tables as of the snapshot, so if anything was
+    /// written since, queries treat them as stale like any index built
+    /// before a write.
+    pub fn install_index_rebuild(&mut self, mut rebuild: IndexRebuild) {
+        rebuild.advance(usize::MAX);
+        self.indexer = Some(rebuild.indexer);
+        self.bloom_filter = Some(rebuild.bloom);
+        self.sketches = rebuild.sketches.into_iter().collect();
+        self.index_built_at = Some(rebuild.version);
+        self.bloom_built_at = Some(rebuild.version);
+        let tables: BTreeSet<String> = self.table_indexes.keys().map(|(t, _)| t.clone()).collect();
+        for table_name in tables {
+            self.refresh_table_indexes(&table_name);
+        }
+        if rebuild.version == self.writes {
+            info!("Indexes and bloom filter rebuilt.");
+        } else {
+            info!(
+                "Indexes and bloom filter rebuilt, {} writes behind.",
+                self.writes - rebuild.version
+            );
+        }
+    }
+
+    pub fn check_table(&self, table_name: &str) -> bool {
+        self.tables.contains_key(table_name)
+    }
+
+    // Create table: update in-memory state and log to WAL.
+    pub fn create_table(&mut self, table_name: &str) -> Result<String> {
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        if self.check_table(table_name) || self.catalog.contains(table_name) {
+            error!("Table '{}' already exists.", table_name);
+            Err(DatabaseError::TableAlreadyExists(table_name.to_string()))
+        } else {
+            self.check_table_count()?;
+            // Update in-memory table immediately.
+            self.tables.insert(table_name.to_string(), Table::new());
+            // Log the operation
+            let op = format!("create_table:{}", table_name);
+            self.log_op(op);
+            self.writes += 1;
+            self.sync_catalog(table_name)?;
+            debug!("Table '{}' created and logged to WAL", table_name);
+            Ok(table_name.to_string())
+        }
+    }
+
+    /// Create a table that lives in memory only: nothing done to it is
+    /// logged to the WAL or written to a file, it is left out of the catalog,
+    /// indexes saved to disk and backups, and it is gone when the database
+    /// is. `Session::create_temp_table` gives one a name private to the
+    /// session and drops it when the session ends.
+    pub fn create_temp_table(&mut self, table_name: &str) -> Result<String> {
+        if self.check_table(table_name) || self.catalog.contains(table_name) {
+            error!("Table '{}' already exists.", table_name);
+            return Err(DatabaseError::TableAlreadyExists(table_name.to_string()));
+        }
+        self.temp_tables.insert(table_name.to_string());
+        let created = self.create_table(table_name);
+        if created.is_err() {
+            self.temp_tables.remove(table_name);
+        }
+        created
+    }
+
+    pub fn is_temp_table(&self, table_name: &str) -> bool {
+        self.temp_tables.contains(table_name)
+    }
+
+    /// Refuse to write a temporary table anywhere but memory.
+    fn check_persistent(&self, table_name: &str) -> Result<()> {
+        if self.is_temp_table(table_name) {
+            error!("Table '{}' is temporary; it is never saved.", table_name);
+            return Err(DatabaseError::TemporaryTable(table_name.to_string()));
+        }
+        Ok(())
+    }
+
+    // New helper function to load table from CSV file into memory.
+    pub fn load_table_from_file(&mut self, table_name: &str, file_name: &str) -> Result<()> {
+        self.load_table(table_name, file_name).map(|_| ())
+    }
+
+    /// Load a table file, and its delta file if it is the table's own file,
+    /// validating them as they are read: the header against the table's
+    /// columns, each record's width, duplicate row ids, a last line cut short,
+    /// and the file against its checksum sidecar if it has one. Records of the
+    /// wrong width are skipped. With `strict_loads` any problem fails the load
+    /// and leaves the table as it was; otherwise the problems are logged.
+    #[instrument(skip(self))]
+    pub fn load_table(&mut self, table_name: &str, file_name: &str) -> Result<LoadReport> {
+        let mut report = LoadReport {
+            table: table_name.to_string(),
+            ..LoadReport::default()
+        };
+        let mut table = Table::new();
+        self.read_rows_into(&mut table, file_name, &mut report)?;
+        // A table's own file may have updates waiting in its delta file.
+        let delta_file = self.config.delta_path(table_name);
+        if file_name == self.config.table_path(table_name) && self.storage.exists(&delta_file) {
+            self.read_rows_into(&mut table, &delta_file, &mut report)?;
+            debug!("Applied delta file '{}'.", delta_file);
+        }
+        // A table reloaded from its file should come back with every column.
+        if let Some(loaded) = self.tables.get(table_name) {
+            for column in loaded.columns.iter() {
+                if !table.columns.contains(column) {
+                    report.add(
+                        ProblemKind::Schema,
+                        file_name,
+                        format!("column '{}' of table '{}' is missing", column, table_name),
+                    );
+                }
+            }
+        }
+        report.rows_loaded = table.rows.keys().filter(|id| *id != "datatypes").count();
+        if !report.is_ok() {
+            if self.config.strict_loads {
+                error!("Refusing to load '{}':\n{}", file_name, report);
+                return Err(DatabaseError::InvalidTableFile(
+                    file_name.to_string(),
+                    report.problems.len(),
+                ));
+            }
+            for p in &report.problems {
+                warn!("{:?} at {}: {}", p.kind, p.location, p.detail);
+            }
+        }
+        // Rows flushed by `flush_cold` stay on disk until they are read.
+        let cold_file = self.config.cold_path(table_name);
+        if file_name == self.config.table_path(table_name) && self.storage.exists(&cold_file) {
+            let segment = ColdSegment::open(self.storage.as_ref(), &cold_file)
+                .map_err(|e| DatabaseError::FileCreationError(cold_file.clone(), e.to_string()))?;
+            debug!("Opened {} cold rows in '{}'.", segment.rows(), cold_file);
+            self.cold.insert(table_name.to_string(), segment);
+        } else {
+            self.cold.remove(table_name);
+        }
+        self.tables.insert(table_name.to_string(), table);
+        info!("Loaded table '{}' from '{}'", table_name, file_name);
+        Ok(report)
+    }
+
+    /// Add the columns and rows of a table or delta file to `table`. Later
+    /// rows replace earlier ones with the same id. What looks wrong with the
+    /// file goes into `report`.
+    fn read_rows_into(
+        &self,
+        table: &mut Table,
+        file_name: &str,
+        report: &mut LoadReport,
+    ) -> Result<()> {
+        let bytes = self
+            .storage
+            .read(file_name)
+            .map_err(|e| DatabaseError::FileCreationError(file_name.to_string(), e.to_string()))?;
+        report.files.push(file_name.to_string());
+
+        if let Ok(sidecar) = self
+            .storage
+            .read_to_string(&checksum::sidecar_path(file_name))
+        {
+            let matches = u32::from_str_radix(sidecar.trim(), 16)
+                .is_ok_and(|sum| sum == checksum::crc32(&bytes));
+            if !matches {
+                report.add(
+                    ProblemKind::Checksum,
+                    file_name,
+                    "file does not match its checksum sidecar",
+                );
+            }
+            report.checksum_verified = Some(report.checksum_verified.unwrap_or(true) && matches);
+        }
+        // Every record is written with its line ending.
+        if !bytes.is_empty() && !bytes.ends_with(b"\n") {
+            report.add(
+                ProblemKind::Truncated,
+                file_name,
+                "last line has no line ending",
+            );
+        }
+
+        // Flexible so that records of the wrong width are reported, not fatal.
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .from_reader(&bytes[..]);
+        let headers = rdr
+            .headers()
+            .map_err(|e| DatabaseError::FileCreationError(file_name.to_string(), e.to_string()))?
+            .clone();
+        if headers.get(0) != Some("row_id") {
+            report.add(
+                ProblemKind::Schema,
+                file_name,
+                "first column is not 'row_id'",
+            );
+        }
+        let mut seen_columns = HashSet::new();
+        for hdr in headers.iter().skip(1) {
+            if hdr.is_empty() {
+                report.add(ProblemKind::Schema, file_name, "a column has no name");
+            } else if !seen_columns.insert(hdr) {
+                report.add(
+                    ProblemKind::Schema,
+                    file_name,
+                    format!("column '{}' appears more than once", hdr),
+                );
+            }
+        }
+        // A delta file is read after the table file and must have its columns.
+        for column in table.columns.iter() {
+            if !seen_columns.contains(column.as_str()) {
+                report.add(
+                    ProblemKind::Schema,
+                    file_name,
+                    format!("column '{}' is missing", column),
+                );
+            }
+        }
+
+        // add columns
+        for hdr in headers.iter().skip(1) {
+            table.add_column(hdr);
+        }
+
+        let mut seen_rows = HashSet::new();
+        for (i, result) in rdr.records().enumerate() {
+            // Line 1 is the header.
+            let location = format!("{}:{}", file_name, i + 2);
+            let record = match result {
+                Ok(record) => record,
+                Err(e) => {
+                    report.add(ProblemKind::Unreadable, location, e.to_string());
+                    report.rows_skipped += 1;
+                    continue;
+                }
+            };
+            if record.len() != headers.len() {
+                report.add(
+                    ProblemKind::Schema,
+                    location,
+                    format!("{} fields, expected {}", record.len(), headers.len()),
+                );
+                report.rows_skipped += 1;
+                continue;
+            }
+            let row_id = &record[0];
+            if !seen_rows.insert(row_id.to_string()) {
+                report.add(
+                    ProblemKind::DuplicateRow,
+                    location,
+                    format!("row '{}' appears more than once", row_id),
+                );
+            }
+            let mut data = HashMap::new();
+            for (hdr, field) in headers.iter().skip(1).zip(record.iter().skip(1)) {
+                if crypto::is_encrypted(field) {
+                    table.encrypted_columns.insert(hdr.to_string());
+                }
+                let mut value = self.open_cell(field);
+                // The datatypes row comes first, so enum columns are known by now.
+                if row_id != "datatypes" {
+                    if let Some(datatype) = table.datatype(hdr) {
+                        value = table_schema::decode_enum(datatype, &value).into_owned();
+                    }
+                }
+                data.insert(hdr.to_string(), value);
+            }
+            table.insert_row(row_id, data);
+        }
+        Ok(())
+    }
+
+    // Add a column: log and update in-memory.
+    pub fn add_column(&mut self, table_name: &str, column_name: &str) -> Result<Vec<String>> {
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        // Check if the table is in-memory.
+        if !self.check_table(table_name) {
+            // Table not found: try to load it from file.
+            let file_name = self.config.table_path(table_name);
+            if self.storage.exists(&file_name) {
+                match self.load_table_from_file(table_name, &file_name) {
+                    Ok(_) => debug!("Table '{}' loaded from file '{}'.", table_name, file_name),
+                    Err(e) => {
+                        error!("Failed to load table from file: {}", e);
+                        return Err(e);
+                    }
+                }
+            } else {
+                error!(
+                    "Table '{}' does not exist in memory or on disk.",
+                    table_name
+                );
+                return Err(DatabaseError::TableDoesNotExist(table_name.to_string()));
+            }
+        }
+        // At this point the table should be in memory.
+        if let Some(table) = self.tables.get_mut(table_name) {
+            table.add_column(column_name);
+            let op = format!("add_column:{}:{}", table_name, column_name);
+            // self.wal.push(op);
+            self.log_op(op);
+            self.writes += 1;
+            self.sync_catalog(table_name)?;
+            debug!(
+                "Column '{}' added to table '{}' and logged to WAL",
+                column_name, table_name
+            );
+            Ok(vec![column_name.to_string(), table_name.to_string()])
+        } else {
+            error!(
+                "Table '{}' is still not found after attempting to load.",
+                table_name
+            );
+            Err(DatabaseError::TableDoesNotExist(table_name.to_string()))
+        }
+    }
+
+    #[allow(dead_code)]
+    fn valid_datatype(dt: &str) -> bool {
+        matches!(dt, "int" | "float" | "string" | "bool" | "date" | "geo")
+    }
+    fn check_value_matches(value: &str, dtype: &str) -> bool {
+        match dtype {
+            "int" => value.parse::<i64>().is_ok(),
+            "float" => value.parse::<f64>().is_ok(),
+            "bool" => {
+                let lower = value.to_lowercase();
+                lower == "true" || lower == "false"
+            }
+            "date" => condition::parse_date(value).is_some(),
+            "geo" => GeoPoint::parse(value).is_some(),
+            "string" => true,
+            _ => table_schema::enum_members(dtype).is_some_and(|members| members.contains(&value)),
+        }
+    }
+
+    /// One of the built-in datatypes, or an enum, `enum(a|b|c)`, of
+    /// distinct, non-empty values.
+    fn known_datatype(&self, dtype: &str) -> bool {
+        self.datatypes.iter().any(|d| d == dtype)
+            || table_schema::enum_members(dtype).is_some_and(|members| {
+                let distinct: HashSet<&&str> = members.iter().collect();
+                !members.contains(&"") && distinct.len() == members.len()
+            })
+    }
+    #[allow(dead_code)]
+    fn is_subset_vec_str(&self, a: &Vec<&str>) -> bool {
+        a.iter().all(|&dt| self.known_datatype(dt))
+    }
+    pub fn add_columns(
+        &mut self,
+        table_name: &str,
+        column_names: Vec<&str>,
+        datatypes: Vec<&str>,
+    ) -> Result<Vec<Vec<String>>> {
+        if column_names.len() != datatypes.len() {
+            error!("Column names and datatypes must have the same length.");
+            return Err(DatabaseError::DataTypeError);
+        }
+
+        if !self.check_table(table_name) {
+            // Table not found: try to load it from file.
+            let file_name = self.config.table_path(table_name);
+            if self.storage.exists(&file_name) {
+                match self.load_table_from_file(table_name, &file_name) {
+                    Ok(_) => debug!("Table '{}' loaded from file '{}'.", table_name, file_name),
+                    Err(e) => {
+                        error!("Failed to load table from file: {}", e);
+                        return Err(e);
+                    }
+                }
+            } else {
+                error!(
+                    "Table '{}' does not exist in memory or on disk.",
+                    table_name
+                );
+                return Err(DatabaseError::TableDoesNotExist(table_name.to_string()));
+            }
+        }
+        if !Database::is_subset_vec_str(self, &datatypes) {
+            error!("Invalid datatypes provided.");
+            return Err(DatabaseError::InvalidDataType);
+        }
+
+        let mut results = Vec::new();
+
+        // Add the new columns.
+        for col in column_names.iter() {
+            match self.add_column(table_name, col) {
+                Ok(res) => results.push(res),
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Insert a single new row that contains the datatypes for each new column.
+        let mut data = HashMap::new();
+        let table = self
+            .tables
+            .get_mut(table_name)
+            .ok_or(DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+        for (col, dt) in column_names.iter().zip(datatypes.iter()) {
+            data.insert(col.to_string(), dt.to_string());
+            table.add_datatype(col, dt);
+        }
+        self.insert_row(table_name, "datatypes", data)?;
+        self.sync_catalog(table_name)?;
+        results.push(vec!["datatypes".to_string(), table_name.to_string()]);
+
+        Ok(results)
+    }
+
+    /// Add a column whose existing rows get `default`, without holding the
+    /// lock while every row is rewritten. The column is added at once and
+    /// rows written from now on can set it; the rows already there are given
+    /// the default in batches by `backfill_step`, which the maintenance
+    /// scheduler runs between other work. Until a row is reached it reads as
+    /// having no value for the column; `backfills` shows how far along it is.
+    pub fn add_column_online(
+        &mut self,
+        table_name: &str,
+        column_name: &str,
+        datatype: Option<&str>,
+        default: &str,
+    ) -> Result<BackfillProgress> {
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        self.ensure_table_loaded(table_name)?;
+        if self.tables[table_name].columns.contains(column_name) {
+            return Err(DatabaseError::ColumnAlreadyExists(
+                column_name.to_string(),
+                table_name.to_string(),
+            ));
+        }
+        if let Some(datatype) = datatype {
+            if !self.known_datatype(datatype) {
+                error!("Invalid datatype '{}'.", datatype);
+                return Err(DatabaseError::InvalidDataType);
+            }
+            if !Self::check_value_matches(default, datatype) {
+                error!(
+                    "Default '{}' does not match datatype '{}'.",
+                    default, datatype
+                );
+                return Err(DatabaseError::DataTypeError);
+            }
+        }
+        // The backfill writes the default into rows without checking them.
+        if let Some(max) = self.config.max_cell_bytes_for(table_name) {
+            if default.len() > max {
+                return Err(DatabaseError::CellTooLarge(
+                    "(default)".to_string(),
+                    column_name.to_string(),
+                    default.len(),
+                    max,
+                ));
+            }
+        }
+
+        self.add_column(table_name, column_name)?;
+        if let Some(datatype) = datatype {
+            if let Some(table) = self.tables.get_mut(table_name) {
+                table.add_datatype(column_name, datatype);
+            }
+            let data = HashMap::from([(column_name.to_string(), datatype.to_string())]);
+            self.insert_row(table_name, "datatypes", data)?;
+            self.sync_catalog(table_name)?;
+        }
+        let rows_total = self.tables[table_name]
+            .rows
+            .keys()
+            .filter(|row_id| row_id.as_str() != "datatypes")
+            .count();
+        let progress = BackfillProgress {
+            table: table_name.to_string(),
+            column: column_name.to_string(),
+            default: default.to_string(),
+            cursor: None,
+            rows_done: 0,
+            rows_total,
+            done: rows_total == 0,
+        };
+        if !progress.done {
+            self.log_backfill(&progress);
+            self.backfills.push(progress.clone());
+        }
+        info!(
+            "Column '{}' added to table '{}'; backfilling {} rows.",
+            column_name, table_name, rows_total
+        );
+        Ok(progress)
+    }
+
+    /// Online column additions still backfilling, oldest first.
+    pub fn backfills(&self) -> Vec<BackfillProgress> {
+        self.backfills.clone()
+    }
+
+    /// Give the default to the next `config.backfill_batch_rows` rows of the
+    /// oldest pending backfill that have no value for its column. Returns true
+    /// while backfills remain.
+    pub fn backfill_step(&mut self) -> bool {
+        let Some(mut progress) = self.backfills.first().cloned() else {
+            return false;
+        };
+        // Resumed after a restart, the table may not be loaded yet.
+        if let Err(e) = self.ensure_table_loaded(&progress.table) {
+            warn!(
+                "Abandoning backfill of '{}.{}': {}",
+                progress.table, progress.column, e
+            );
+            self.backfills.remove(0);
+            return !self.backfills.is_empty();
+        }
+        let Some(table) = self.tables.get_mut(&progress.table) else {
+            return false;
+        };
+        let batch = self.config.backfill_batch_rows.max(1);
+        let after = progress
+            .cursor
+            .as_deref()
+            .map_or(Bound::Unbounded, Bound::Excluded);
+        let (visited, last, filled) = Self::fill_missing(
+            table,
+            &progress.column,
+            &progress.default,
+            (after, Bound::Unbounded),
+            batch,
+        );
+        progress.rows_done += visited;
+        progress.cursor = last.or(progress.cursor);
+        progress.done = visited < batch;
+        self.log_backfill(&progress);
+        self.writes += 1;
+        if !filled.is_empty() {
+            self.record_write(&progress.table, filled.iter().map(String::as_str), false);
+        }
+        if progress.done {
+            info!(
+                "Backfill of '{}.{}' finished after {} rows.",
+                progress.table, progress.column, progress.rows_done
+            );
+            self.backfills.remove(0);
+        } else {
+            self.backfills[0] = progress;
+        }
+        !self.backfills.is_empty()
+    }
+
+    /// Give `default` to the rows in `range` (up to `limit` of them) that have
+    /// no value for `column`. Returns the rows visited, the last row id
+    /// visited and the rows changed.
+    fn fill_missing(
+        table: &mut Table,
+        column: &str,
+        default: &str,
+        range: (Bound<&str>, Bound<&str>),
+        limit: usize,
+    ) -> (usize, Option<String>, Vec<String>) {
+        let mut visited = 0;
+        let mut last = None;
+        let mut filled = Vec::new();
+        for (row_id, row) in table
+            .rows
+            .range_mut::<str, _>(range)
+            .filter(|(row_id, _)| row_id.as_str() != "datatypes")
+            .take(limit)
+        {
+            visited += 1;
+            last = Some(row_id.clone());
+            // Table files store a missing value as an empty one.
+            if row.get(column).is_none_or(String::is_empty) {
+                row.insert(column.to_string(), default.to_string());
+                filled.push(row_id.clone());
+            }
+        }
+        (visited, last, filled)
+    }
+
+    /// The progress logged by a `backfill_column` WAL entry.
+    fn logged_backfill(entry: &str) -> Option<BackfillProgress> {
+        let (_, json) = entry.strip_prefix("backfill_column:")?.split_once(':')?;
+        serde_json::from_str(json).ok()
+    }
+
+    fn log_backfill(&mut self, progress: &BackfillProgress) {
+        let op = format!(
+            "backfill_column:{}:{}",
+            progress.table,
+            serde_json::to_string(progress).unwrap()
+        );
+        self.log_op(op);
+    }
+
+    /// Replay a logged backfill step: fill the rows it visited, from the
+    /// cursor of the backfill as already replayed up to its own. A backfill
+    /// not seen yet only starts being tracked, as the tables already hold the
+    /// steps taken before the WAL was last committed.
+    fn replay_backfill(&mut self, progress: BackfillProgress) {
+        let known = self
+            .backfills
+            .iter()
+            .position(|b| b.table == progress.table && b.column == progress.column);
+        if let (Some(i), Some(table), Some(upto)) = (
+            known,
+            self.tables.get_mut(&progress.table),
+            progress.cursor.as_deref(),
+        ) {
+            let after = self.backfills[i]
+                .cursor
+                .as_deref()
+                .map_or(Bound::Unbounded, Bound::Excluded);
+            Self::fill_missing(
+                table,
+                &progress.column,
+                &progress.default,
+                (after, Bound::Included(upto)),
+                usize::MAX,
+            );
+        }
+        debug!(
+            "Replay: Backfill of '{}.{}' at {} rows.",
+            progress.table, progress.column, progress.rows_done
+        );
+        match (known, progress.done) {
+            (Some(i), true) => {
+                self.backfills.remove(i);
+            }
+            (Some(i), false) => self.backfills[i] = progress,
+            (None, true) => {}
+            (None, false) => self.backfills.push(progress),
+        }
+    }
+
+    // Get row from table.
+    pub fn get_row(&mut self, table_name: &str, row_id: &str) -> Result<QueryResult> {
+        let started = Instant::now();
+        // If the table isn't in memory, try to load it from file.
+        if !self.check_table(table_name) {
+            let file_name = self.config.table_path(table_name);
+            if self.storage.exists(&file_name) {
+                match self.load_table_from_file(table_name, &file_name) {
+                    Ok(_) => debug!("Table '{}' loaded from file '{}'.", table_name, file_name),
+                    Err(e) => {
+                        error!("Failed to load table from file: {}", e);
+                        return Err(e);
+                    }
+                }
+            } else {
+                error!(
+                    "Table '{}' does not exist in memory or on disk.",
+                    table_name
+                );
+                return Err(DatabaseError::TableDoesNotExist(table_name.to_string()));
+            }
+        }
+        // Now the table must be in memory, and the row too if it went cold.
+        self.warm_row(table_name, row_id)?;
+        if let Some(table) = self.tables.get(table_name) {
+            if let Some(row) = table.get_row(row_id).filter(|row| !self.hidden(table, row)) {
+                let rows = vec![(row_id.to_string(), row.clone())];
+                Ok(QueryResult::read(
+                    Self::row_set(Some(table), None, rows),
+                    started,
+                ))
+            } else {
+                error!("Row '{}' does not exist in '{}'.", row_id, table_name);
+                Err(DatabaseError::RowDoesNotExist(
+                    row_id.to_string(),
+                    table_name.to_string(),
+                ))
+            }
+        } else {
+            error!(
+                "Table '{}' is still not found after attempting to load.",
+                table_name
+            );
+            Err(DatabaseError::TableDoesNotExist(table_name.to_string()))
+        }
+    }
+
+    // Insert row: update in-memory table and log the operation.
+    pub fn insert_row(
+        &mut self,
+        table_name: &str,
+        row_id: &str,
+        data: HashMap<String, String>,
+    ) -> Result<QueryResult> {
+        let started = Instant::now();
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        self.check_unprepared(table_name, Some(row_id))?;
+        // If the table isn't in memory, try to load it from file.
+        if !self.check_table(table_name) {
+            let file_name = self.config.table_path(table_name);
+            if self.storage.exists(&file_name) {
+                match self.load_table_from_file(table_name, &file_name) {
+                    Ok(_) => debug!("Table '{}' loaded from file '{}'.", table_name, file_name),
+                    Err(e) => {
+                        error!("Failed to load table from file: {}", e);
+                        return Err(e);
+                    }
+                }
+            } else {
+                error!(
+                    "Table '{}' does not exist in memory or on disk.",
+                    table_name
+                );
+                return Err(DatabaseError::TableDoesNotExist(table_name.to_string()));
+            }
+        }
+        // An insert over a cold row merges into it like one over a row in memory.
+        self.warm_row(table_name, row_id)?;
+        let data = self.tenant_write(table_name, row_id, data)?;
+
+        // //check for datatype
+        // for (col, val) in &data {
+        //     if let Some(table) = self.tables.get(table_name) {
+        //         if let Some(dt) = table.row_datatypes.get(col) {
+        //             if !Database::check_value_matches(val, dt) {
+        //                 error!("Value '{}' does not match datatype '{}' for column '{}'.", val, dt, col);
+        //                 return Err(DatabaseError::DataTypeError);
+        //             }
+        //         } else {
+        //             error!("Column '{}' not found in table '{}'.", col, table_name);
+        //             return Err(DatabaseError::RowDoesNotExist(row_id.to_string(), table_name.to_string()));
+        //         }
+        //     }
+        // }
+
+        // Enforce row count and row size limits before touching the table.
+        if let Some(max) = self.config.max_rows_for(table_name) {
+            if let Some(table) = self.tables.get(table_name) {
+                if !table.rows.contains_key(row_id)
+                    && self.total_rows(table_name)? + self.prepared_new_rows(table_name) >= max
+                {
+                    error!(
+                        "Insert rejected: table '{}' is full ({} rows).",
+                        table_name, max
+                    );
+                    return Err(DatabaseError::TableFull(table_name.to_string(), max));
+                }
+            }
+        }
+        self.check_row_size(table_name, row_id, &data)?;
+        self.check_enum_values(table_name, row_id, &data)?;
+
+        // Now perform the row insertion.
+        let sealed = self.seal_row(table_name, &data);
+        if let Some(table) = self.tables.get_mut(table_name) {
+            table.insert_row(row_id, data.clone());
+            let op = format!(
+                "insert_row:{}:{}:{}",
+                table_name,
+                row_id,
+                serde_json::to_string(&sealed).unwrap()
+            );
+            // self.wal.push(op);
+            self.log_op(op);
+            self.writes += 1;
+            debug!(
+                "Inserted row '{}' in table '{}' and logged to WAL",
+                row_id, table_name
+            );
+
+            // The datatypes row is written in its own place, not appended.
+            self.record_write(table_name, [row_id], row_id != "datatypes");
+            Ok(QueryResult::write(1, started))
+        } else {
+            error!(
+                "Table '{}' is still not found after attempting to load.",
+                table_name
+            );
+            Err(DatabaseError::TableDoesNotExist(table_name.to_string()))
+        }
+    }
+
+    /// Insert a row, or merge `data` into it if `row_id` already exists: columns
+    /// named in `data` are replaced and the others keep their values. Logged as a
+    /// single `upsert_row` WAL op so replaying it is idempotent.
+    pub fn upsert_row(
+        &mut self,
+        table_name: &str,
+        row_id: &str,
+        data: HashMap<String, String>,
+    ) -> Result<QueryResult> {
+        let started = Instant::now();
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        self.check_unprepared(table_name, Some(row_id))?;
+        self.ensure_table_loaded(table_name)?;
+        self.warm_row(table_name, row_id)?;
+        let data = self.tenant_write(table_name, row_id, data)?;
+
+        let existing = self.tables[table_name].rows.get(row_id).cloned();
+        if existing.is_none() {
+            if let Some(max) = self.config.max_rows_for(table_name) {
+                let rows = self.total_rows(table_name)? + self.prepared_new_rows(table_name);
+                if rows >= max {
+                    error!(
+                        "Upsert rejected: table '{}' is full ({} rows).",
+                        table_name, max
+                    );
+                    return Err(DatabaseError::TableFull(table_name.to_string(), max));
+                }
+            }
+        }
+        let inserted = existing.is_none();
+        let mut merged = existing.unwrap_or_default();
+        merged.extend(data.clone());
+        self.check_row_size(table_name, row_id, &merged)?;
+        self.check_enum_values(table_name, row_id, &merged)?;
+
+        let sealed = self.seal_row(table_name, &data);
+        if let Some(table) = self.tables.get_mut(table_name) {
+            table.insert_row(row_id, merged);
+        }
+        let op = format!(
+            "upsert_row:{}:{}:{}",
+            table_name,
+            row_id,
+            serde_json::to_string(&sealed).unwrap()
+        );
+        self.log_op(op);
+        self.writes += 1;
+        debug!(
+            "Upserted row '{}' in table '{}' ({}).",
+            row_id,
+            table_name,
+            if inserted { "inserted" } else { "merged" }
+        );
+
+        // A merge changes a row already on disk, so save the whole table.
+        self.record_write(table_name, [row_id], inserted);
+        Ok(QueryResult::write(1, started))
+    }
+
+    /// Look up several rows at once. Missing ids are reported in
+    /// `RowBatch::missing` rather than failing the whole call.
+    pub fn get_rows<S: AsRef<str>>(&mut self, table_name: &str, row_ids: &[S]) -> Result<RowBatch> {
+        self.ensure_table_loaded(table_name)?;
+        let table = &self.tables[table_name];
+        let mut batch = RowBatch::default();
+        for row_id in row_ids {
+            let row_id = row_id.as_ref();
+            let row = match table.rows.get(row_id) {
+                Some(row) => Some(row.clone()),
+                None => self.cold_row(table_name, row_id)?,
+            };
+            match row.filter(|row| !self.hidden(table, row)) {
+                Some(row) => batch.found.push((row_id.to_string(), row)),
+                None => batch.missing.push(row_id.to_string()),
+            }
+        }
+        debug!(
+            "Fetched {} of {} rows from table '{}'.",
+            batch.found.len(),
+            row_ids.len(),
+            table_name
+        );
+        Ok(batch)
+    }
+
+    /// Check whether a row exists without cloning it.
+    pub fn row_exists(&mut self, table_name: &str, row_id: &str) -> Result<bool> {
+        self.ensure_table_loaded(table_name)?;
+        let table = &self.tables[table_name];
+        let row = match table.rows.get(row_id) {
+            Some(row) => Some(Cow::Borrowed(row)),
+            None => self.cold_row(table_name, row_id)?.map(Cow::Owned),
+        };
+        Ok(row.is_some_and(|row| !self.hidden(table, &row)))
+    }
+
+    /// Count rows, optionally only those matching `condition` ("column
+    /// operator value"). Equality on the indexed or bloom column is answered
+    /// from the index or bloom filter while they are up to date.
+    pub fn count_rows(&mut self, table_name: &str, condition: Option<&str>) -> Result<usize> {
+        self.count_rows_with(table_name, condition, Consistency::Strict)
+    }
+
+    /// `count_rows`, with the index and bloom filter also used while behind
+    /// the latest write if `consistency` allows.
+    pub fn count_rows_with(
+        &mut self,
+        table_name: &str,
+        condition: Option<&str>,
+        consistency: Consistency,
+    ) -> Result<usize> {
+        if !information_schema::is_virtual(table_name) {
+            self.ensure_table_loaded(table_name)?;
+        }
+        let table = self
+            .scannable_table(table_name)
+            .ok_or_else(|| DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+        let Some(condition) = condition else {
+            return Ok(Self::data_rows(&table));
+        };
+        let condition = self.parse_condition(table_name, condition)?;
+        let (col, value) = (condition.left.as_column(), condition.value.as_str());
+        if let (Operator::Eq, Some(col)) = (condition.operator, col) {
+            // The bloom filter holds raw values, so it only answers exact matches.
+            if col == self.config.bloom_column
+                && consistency.allows(self.bloom_built_at, self.writes)
+                && self.config.match_options.is_exact()
+            {
+                if let Some(ref bf) = self.bloom_filter {
+                    if !bf.contains(value) {
+                        return Ok(0);
+                    }
+                }
+            }
+            // Cold rows aren't indexed, so a table with any is scanned.
+            if col == self.config.index_column
+                && consistency.allows(self.index_built_at, self.writes)
+                && !self.cold.contains_key(table_name)
+            {
+                if let Some(ref indexer) = self.indexer {
+                    // Re-check each row's value so a bad entry is never counted.
+                    let count = indexer.get_scoped(table_name, col, value).map_or(0, |row_ids| {
+                        row_ids
+                            .iter()
+                            .filter(|id| {
+                                id.as_str() != "datatypes"
+                                    && table
+                                        .rows
+                                        .get(*id)
+                                        .and_then(|row| row.get(col))
+                                        .is_some_and(|v| indexer.options.eq(v, value))
+                            })
+                            .count()
+                    });
+                    return Ok(count);
+                }
+            }
+        }
+        Ok(table
+            .rows
+            .iter()
+            .filter(|(id, row)| {
+                id.as_str() != "datatypes" && condition.matches(row, &self.config.match_options)
+            })
+            .count())
+    }
+
+    /// Unique values of `column` with the number of rows holding each, sorted
+    /// by value. Values are grouped in their match-options form, so a
+    /// case-insensitive database reports "Bob" and "bob" together. Rows
+    /// without the column are skipped. Uses the index when it covers the
+    /// column and is up to date.
+    pub fn distinct_values(
+        &mut self,
+        table_name: &str,
+        column: &str,
+    ) -> Result<BTreeMap<String, usize>> {
+        self.ensure_table_loaded(table_name)?;
+        let table = &self.tables[table_name];
+        if !table.columns.contains(column) {
+            return Err(DatabaseError::ColumnDoesNotExist(
+                column.to_string(),
+                table_name.to_string(),
+            ));
+        }
+        let mut values = BTreeMap::new();
+        // The index covers the rows in memory, not those of a cold segment.
+        if column == self.config.index_column
+            && self.index_built_at == Some(self.writes)
+            && !self.cold.contains_key(table_name)
+        {
+            if let Some(ref indexer) = self.indexer {
+                for (value, row_ids) in indexer.scoped_entries(table_name, column) {
+                    // Re-check each row's value so a bad entry is never counted.
+                    let count = row_ids
+                        .iter()
+                        .filter(|id| *id != "datatypes")
+                        .filter(|id| {
+                            table
+                                .rows
+                                .get(*id)
+                                .filter(|row| !self.hidden(table, row))
+                                .and_then(|row| row.get(column))
+                                .is_some_and(|v| indexer.options.fold(v) == value)
+                        })
+                        .count();
+                    if count > 0 {
+                        values.insert(value.to_string(), count);
+                    }
+                }
+                return Ok(values);
+            }
+        }
+        let table = self
+            .scannable_table(table_name)
+            .ok_or_else(|| DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+        for (_, row) in table.rows.iter().filter(|(id, _)| *id != "datatypes") {
+            if let Some(value) = row.get(column) {
+                let value = self.config.match_options.fold(value).into_owned();
+                *values.entry(value).or_insert(0) += 1;
+            }
+        }
+        Ok(values)
+    }
+
+    /// Estimated number of distinct values in a column, from a HyperLogLog
+    /// sketch (about 1.6% error). Indexed columns are sketched by the
+    /// `IndexEngine` and answer without a scan, as of its last run; other
+    /// columns are sketched by scanning the table.
+    pub fn approx_distinct(&mut self, table_name: &str, column: &str) -> Result<u64> {
+        Ok(self.column_sketch(table_name, column)?.distinct.estimate())
+    }
+
+    /// Up to `k` of a column's most frequent values with their estimated
+    /// counts, most frequent first, from a count-min sketch. Counts are
+    /// never underestimated. At most `TOP_K_CAPACITY` values are tracked.
+    /// Sketched like `approx_distinct`.
+    pub fn top_k(&mut self, table_name: &str, column: &str, k: usize) -> Result<Vec<(String, u64)>> {
+        Ok(self.column_sketch(table_name, column)?.frequent.top(k))
+    }
+
+    /// The sketch of a column: the one kept for it if any, else one built
+    /// from the rows visible now. Under a tenant, a table scoped by tenant
+    /// is always sketched afresh, as the kept sketches cover every tenant.
+    fn column_sketch(&mut self, table_name: &str, column: &str) -> Result<Cow<'_, ColumnSketch>> {
+        self.ensure_table_loaded(table_name)?;
+        let table = &self.tables[table_name];
+        if !table.columns.contains(column) {
+            return Err(DatabaseError::ColumnDoesNotExist(
+                column.to_string(),
+                table_name.to_string(),
+            ));
+        }
+        let key = (table_name.to_string(), column.to_string());
+        if !(self.tenant.is_some() && table.tenant_scoped()) {
+            if let Some(sketch) = self.sketches.get(&key) {
+                return Ok(Cow::Borrowed(sketch));
+            }
+        }
+        let table = self
+            .scannable_table(table_name)
+            .ok_or_else(|| DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+        let mut sketch = ColumnSketch::default();
+        for (_, row) in table.rows.iter().filter(|(id, _)| *id != "datatypes") {
+            if let Some(value) = row.get(column) {
+                sketch.add(&self.config.match_options.fold(value));
+            }
+        }
+        Ok(Cow::Owned(sketch))
+    }
+
+    /// Rows whose `column` holds a point in the box from `min` (south-west)
+    /// to `max` (north-east), in row id order. A box can't cross the
+    /// antimeridian. Answered from a geo index on the column if it has one
+    /// (see `create_index`), else by a scan.
+    pub fn within_bbox(
+        &mut self,
+        table_name: &str,
+        column: &str,
+        min: GeoPoint,
+        max: GeoPoint,
+    ) -> Result<QueryResult> {
+        let started = Instant::now();
+        self.prepare_geo_query(table_name, column)?;
+        let table = &self.tables[table_name];
+        let rows: Vec<(String, HashMap<String, String>)> = match self.geo_index(table_name, column) {
+            Some(index) => {
+                let mut row_ids = index.within(&min, &max);
+                row_ids.sort_unstable();
+                row_ids
+                    .into_iter()
+                    .filter_map(|row_id| {
+                        let row = table.rows.get(row_id).filter(|row| !self.hidden(table, row))?;
+                        Some((row_id.to_string(), row.clone()))
+                    })
+                    .collect()
+            }
+            None => self
+                .scannable_table(table_name)
+                .ok_or_else(|| DatabaseError::TableDoesNotExist(table_name.to_string()))?
+                .rows
+                .iter()
+                .filter(|(row_id, row)| {
+                    row_id.as_str() != "datatypes"
+                        && row
+                            .get(column)
+                            .and_then(|value| GeoPoint::parse(value))
+                            .is_some_and(|point| point.within(&min, &max))
+                })
+                .map(|(row_id, row)| (row_id.clone(), row.clone()))
+                .collect(),
+        };
+        Ok(QueryResult::read(Self::row_set(Some(table), None, rows), started))
+    }
+
+    /// The `n` rows whose `column` holds the points nearest `origin`,
+    /// nearest first. Answered from a geo index like `within_bbox`.
+    pub fn nearest(
+        &mut self,
+        table_name: &str,
+        column: &str,
+        origin: GeoPoint,
+        n: usize,
+    ) -> Result<QueryResult> {
+        let started = Instant::now();
+        self.prepare_geo_query(table_name, column)?;
+        let table = &self.tables[table_name];
+        let rows: Vec<(String, HashMap<String, String>)> = match self.geo_index(table_name, column) {
+            Some(index) => index
+                .nearest(&origin, n, |row_id| {
+                    table.rows.get(row_id).is_some_and(|row| !self.hidden(table, row))
+                })
+                .into_iter()
+                .map(|(row_id, _)| (row_id.to_string(), table.rows[row_id].clone()))
+                .collect(),
+            None => {
+                let scanned = self
+                    .scannable_table(table_name)
+                    .ok_or_else(|| DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+                let mut by_distance: Vec<(f64, &String, &HashMap<String, String>)> = scanned
+                    .rows
+                    .iter()
+                    .filter(|(row_id, _)| row_id.as_str() != "datatypes")
+                    .filter_map(|(row_id, row)| {
+                        let point = GeoPoint::parse(row.get(column)?)?;
+                        Some((origin.distance_km(&point), row_id, row))
+                    })
+                    .collect();
+                by_distance.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(b.1)));
+                by_distance
+                    .into_iter()
+                    .take(n)
+                    .map(|(_, row_id, row)| (row_id.clone(), row.clone()))
+                    .collect()
+            }
+        };
+        Ok(QueryResult::read(Self::row_set(Some(table), None, rows), started))
+    }
+
+    /// Load the table of a geo query, check it has `column` and bring its
+    /// indexes up to date.
+    fn prepare_geo_query(&mut self, table_name: &str, column: &str) -> Result<()> {
+        self.ensure_table_loaded(table_name)?;
+        if !self.tables[table_name].columns.contains(column) {
+            return Err(DatabaseError::ColumnDoesNotExist(
+                column.to_string(),
+                table_name.to_string(),
+            ));
+        }
+        self.refresh_table_indexes(table_name);
+        Ok(())
+    }
+
+    /// The current geo index on a column. Cold rows aren't indexed, so a
+    /// table with any has none to use.
+    fn geo_index(&self, table_name: &str, column: &str) -> Option<&GeoIndex> {
+        if self.cold.contains_key(table_name) {
+            return None;
+        }
+        self.table_indexes
+            .get(&(table_name.to_string(), column.to_string()))
+            .filter(|index| index.is_current(self.writes))?
+            .geo()
+    }
+
+    /// Pick up to `n` rows uniformly at random in a single pass (reservoir
+    /// sampling) over the rows in memory and then the table's cold rows,
+    /// cloning only the rows in memory that end up in the sample.
+    pub fn sample_rows(&mut self, table_name: &str, n: usize) -> Result<QueryResult> {
+        let started = Instant::now();
+        self.ensure_table_loaded(table_name)?;
+        let mut rng = rand::thread_rng();
+        let mut seen = 0;
+        // The reservoir slot the next row takes, if any.
+        let mut slot = || {
+            seen += 1;
+            if seen <= n {
+                return Some(seen - 1);
+            }
+            Some(rng.gen_range(0..seen)).filter(|&slot| slot < n)
+        };
+        let mut reservoir = Vec::with_capacity(n);
+        let mut keep = |slot, row| match reservoir.get_mut(slot) {
+            Some(kept) => *kept = row,
+            None => reservoir.push(row),
+        };
+        let table = &self.tables[table_name];
+        let visible = table
+            .rows
+            .iter()
+            .filter(|(row_id, row)| row_id.as_str() != "datatypes" && !self.hidden(table, row));
+        for (row_id, row) in visible {
+            if let Some(slot) = slot() {
+                keep(slot, (Cow::Borrowed(row_id.as_str()), Cow::Borrowed(row)));
+            }
+        }
+        if let Some(segment) = self.cold.get(table_name) {
+            let read = segment.visit(self.storage.as_ref(), None, |row_id, row| {
+                // A row in memory is newer than its cold copy.
+                if table.rows.contains_key(row_id) {
+                    return Ok(true);
+                }
+                let row = self.open_row(row);
+                if !self.hidden(table, &row) {
+                    if let Some(slot) = slot() {
+                        keep(slot, (Cow::Owned(row_id.to_string()), Cow::Owned(row)));
+                    }
+                }
+                Ok(true)
+            });
+            if let Err(e) = read {
+                error!("Failed to read cold rows from '{}': {}", segment.path(), e);
+            }
+        }
+        let rows = reservoir
+            .into_iter()
+            .map(|(row_id, row)| (row_id.into_owned(), row.into_owned()))
+            .collect();
+        Ok(QueryResult::read(
+            Self::row_set(Some(table), None, rows),
+            started,
+        ))
+    }
+
+    /// Rows whose id starts with `prefix`, in id order. Rows are kept sorted
+    /// by id, so this seeks to the prefix rather than scanning the table.
+    pub fn scan_prefix(&mut self, table_name: &str, prefix: &str) -> Result<QueryResult> {
+        let started = Instant::now();
+        self.ensure_table_loaded(table_name)?;
+        let table = &self.tables[table_name];
+        let mut rows: BTreeMap<String, HashMap<String, String>> = table
+            .rows
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(row_id, _)| row_id.starts_with(prefix))
+            .filter(|(row_id, _)| row_id.as_str() != "datatypes")
+            .map(|(row_id, row)| (row_id.clone(), row.clone()))
+            .collect();
+        rows.extend(self.cold_range(table_name, Some(prefix), usize::MAX, |row_id| {
+            row_id.starts_with(prefix)
+        })?);
+        let rows = rows.into_iter().filter(|(_, row)| !self.hidden(table, row)).collect();
+        Ok(QueryResult::read(
+            Self::row_set(Some(table), None, rows),
+            started,
+        ))
+    }
+
+    /// Rows with ids in the range of `options`, in id order or, when
+    /// descending, reverse id order, up to its limit.
+    pub fn scan_range(&mut self, table_name: &str, options: &ScanOptions) -> Result<QueryResult> {
+        let started = Instant::now();
+        self.ensure_table_loaded(table_name)?;
+        let table = &self.tables[table_name];
+        let start = options.start.as_deref();
+        let end = options.end.as_deref();
+        // BTreeMap::range panics on an inverted range; it holds no rows anyway.
+        let rows = if matches!((start, end), (Some(start), Some(end)) if start > end) {
+            Vec::new()
+        } else {
+            let bounds = (
+                start.map_or(Bound::Unbounded, Bound::Included),
+                end.map_or(Bound::Unbounded, Bound::Excluded),
+            );
+            let range = table.rows.range::<str, _>(bounds);
+            let ids: Box<dyn Iterator<Item = (&String, &HashMap<String, String>)>> =
+                if options.descending {
+                    Box::new(range.rev())
+                } else {
+                    Box::new(range)
+                };
+            let limit = options.limit.unwrap_or(usize::MAX);
+            let mut rows: BTreeMap<String, HashMap<String, String>> = ids
+                .filter(|(row_id, row)| row_id.as_str() != "datatypes" && !self.hidden(table, row))
+                .take(limit)
+                .map(|(row_id, row)| (row_id.clone(), row.clone()))
+                .collect();
+            // Cold rows are read in id order, so a descending scan reads the whole range.
+            let cold_limit = if options.descending { usize::MAX } else { limit };
+            rows.extend(self.cold_range(table_name, start, cold_limit, |row_id| {
+                end.is_none_or(|end| row_id < end)
+            })?);
+            let rows = rows.into_iter().filter(|(_, row)| !self.hidden(table, row));
+            if options.descending {
+                rows.rev().take(limit).collect()
+            } else {
+                rows.take(limit).collect()
+            }
+        };
+        Ok(QueryResult::read(
+            Self::row_set(Some(table), None, rows),
+            started,
+        ))
+    }
+
+    /// Profile every column of a table (min/max, nulls, distinct count and an
+    /// equi-depth histogram) over the rows a scan reads, cold ones included.
+    /// The result is also kept on the table so later queries can use it; it
+    /// is not updated by subsequent writes.
+    pub fn analyze(&mut self, table_name: &str) -> Result<Vec<ColumnStats>> {
+        self.ensure_table_loaded(table_name)?;
+        let table = self
+            .scannable_table(table_name)
+            .ok_or_else(|| DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+        let stats: Vec<ColumnStats> = table
+            .columns
+            .iter()
+            .map(|column| {
+                let values = table
+                    .rows
+                    .iter()
+                    .filter(|(row_id, _)| row_id.as_str() != "datatypes")
+                    .map(|(_, row)| row.get(column).map(String::as_str));
+                ColumnStats::from_values(column, values)
+            })
+            .collect();
+        let table = self.tables.get_mut(table_name).unwrap();
+        table.column_stats = stats
+            .iter()
+            .map(|s| (s.column.clone(), s.clone()))
+            .collect();
+        info!(
+            "Analyzed {} columns of table '{}'.",
+            stats.len(),
+            table_name
+        );
+        Ok(stats)
+    }
+
+    /// Make sure a table is in memory, loading it from its file if needed.
+    fn ensure_table_loaded(&mut self, table_name: &str) -> Result<()> {
+        if self.check_table(table_name) || self.load_known_table(table_name)? {
+            return Ok(());
+        }
+        error!(
+            "Table '{}' does not exist in memory or on disk.",
+            table_name
+        );
+        Err(DatabaseError::TableDoesNotExist(table_name.to_string()))
+    }
+
+    /// Bring a table not in memory into it: from its file, or empty from its
+    /// catalog entry if it has no file yet. False if it has neither.
+    fn load_known_table(&mut self, table_name: &str) -> Result<bool> {
+        let file_name = self.config.table_path(table_name);
+        if self.storage.exists(&file_name) {
+            self.load_table_from_file(table_name, &file_name)?;
+            // The file holds every record up to its last checkpoint.
+            if let Some(entry) = self.catalog.get(table_name) {
+                let lsn = entry.checkpoint_lsn;
+                let applied = self.applied_lsn.entry(table_name.to_string()).or_default();
+                *applied = (*applied).max(lsn);
+            }
+            debug!("Table '{}' loaded from file '{}'.", table_name, file_name);
+            return Ok(true);
+        }
+        let Some(entry) = self.catalog.get(table_name) else {
+            return Ok(false);
+        };
+        if entry.rows > 0 {
+            warn!(
+                "Table '{}' had {} rows at the last checkpoint but its file is missing.",
+                table_name, entry.rows
+            );
+        }
+        let table = entry.empty_table();
+        self.tables.insert(table_name.to_string(), table);
+        debug!("Table '{}' made from its catalog entry.", table_name);
+        Ok(true)
+    }
+
+    /// A table to read from: one held in memory, or an `information_schema`
+    /// table built from the current state of the database.
+    fn readable_table(&self, table_name: &str) -> Option<Cow<'_, Table>> {
+        match self.tables.get(table_name) {
+            Some(table) => Some(Cow::Borrowed(table)),
+            None => information_schema::table(self, table_name).map(Cow::Owned),
+        }
+    }
+
+    /// Like `readable_table`, with the rows of the table's cold segment read
+    /// back in, for reads that scan every row.
+    /// Soft-deleted rows and rows of other tenants are left out; see `hidden`.
+    fn scannable_table(&self, table_name: &str) -> Option<Cow<'_, Table>> {
+        let table = self.table_with_cold_rows(table_name)?;
+        Some(self.visible_rows(table))
+    }
+
+    /// Like `scannable_table`, but with every row, for reads that check
+    /// `hidden` themselves.
+    fn table_with_cold_rows(&self, table_name: &str) -> Option<Cow<'_, Table>> {
+        let table = self.readable_table(table_name)?;
+        let Some(segment) = self.cold.get(table_name) else {
+            return Some(table);
+        };
+        let mut table = table.into_owned();
+        if let Err(e) = self.add_cold_rows(segment, &mut table) {
+            error!("Failed to read cold rows: {}", e);
+        }
+        Some(Cow::Owned(table))
+    }
+
+    /// `table` without the rows reads skip (see `hidden`), copied only if it
+    /// has any.
+    fn visible_rows<'a>(&self, table: Cow<'a, Table>) -> Cow<'a, Table> {
+        if !(table.soft_deletes() || self.tenant.is_some() && table.tenant_scoped()) {
+            return table;
+        }
+        let hidden: Vec<String> = table
+            .rows
+            .iter()
+            .filter(|(_, row)| self.hidden(&table, row))
+            .map(|(row_id, _)| row_id.clone())
+            .collect();
+        if hidden.is_empty() {
+            return table;
+        }
+        let mut table = table.into_owned();
+        for row_id in &hidden {
+            table.rows.remove(row_id);
+        }
+        Cow::Owned(table)
+    }
+
+    /// Move all but `keep_hot` of a table's rows out of memory into its cold
+    /// segment, a sorted binary file with a bloom filter and a sparse index,
+    /// so a table larger than memory stays queryable. Pending writes are saved
+    /// first and the table file is rewritten without the flushed rows. Rows go
+    /// cold in row id order, so with the sequential row id strategies the
+    /// oldest go first. Point reads fetch a cold row from the segment, writes
+    /// bring it back into memory, and queries and counts scan both. Indexes
+    /// made with `create_index` cover the rows in memory, so filters on a
+    /// table with cold rows scan it. Returns the rows flushed.
+    #[instrument(skip(self))]
+    pub fn flush_cold(&mut self, table_name: &str, keep_hot: usize) -> Result<usize> {
+        self.check_writable()?;
+        self.check_persistent(table_name)?;
+        self.ensure_table_loaded(table_name)?;
+        // A backfill only fills the rows it finds in memory.
+        if self.backfills.iter().any(|b| b.table == table_name) {
+            warn!(
+                "Not flushing '{}' while a column is being backfilled.",
+                table_name
+            );
+            return Ok(0);
+        }
+        self.save_unsaved(table_name)?;
+
+        let table = &self.tables[table_name];
+        let hot = table.rows.keys().filter(|id| *id != "datatypes").count();
+        let flushed: BTreeMap<String, HashMap<String, String>> = table
+            .rows
+            .iter()
+            .filter(|(id, _)| *id != "datatypes")
+            .take(hot.saturating_sub(keep_hot))
+            .map(|(id, row)| (id.clone(), self.seal_row(table_name, row)))
+            .collect();
+        if flushed.is_empty() {
+            return Ok(0);
+        }
+
+        // Merge the flushed rows into the existing segment. Its copies of rows
+        // still in memory are stale and dropped.
+        let path = self.config.cold_path(table_name);
+        let io_err = |e: std::io::Error| DatabaseError::FileCreationError(path.clone(), e.to_string());
+        let storage = self.storage.as_ref();
+        let old = self.cold.get(table_name);
+        let expected = old.map_or(0, ColdSegment::rows) + flushed.len();
+        let mut writer = SegmentWriter::create(storage, &path, expected).map_err(io_err)?;
+        let mut pending = flushed.iter().peekable();
+        if let Some(old) = old {
+            old.visit(storage, None, |row_id, row| {
+                while let Some((id, fresh)) = pending.next_if(|(id, _)| id.as_str() <= row_id) {
+                    writer.push(id, fresh)?;
+                }
+                if !flushed.contains_key(row_id) && !table.rows.contains_key(row_id) {
+                    writer.push(row_id, &row)?;
+                }
+                Ok(true)
+            })
+            .map_err(io_err)?;
+        }
+        for (id, fresh) in pending {
+            writer.push(id, fresh).map_err(io_err)?;
+        }
+        let segment = writer.finish().map_err(io_err)?;
+        info!(
+            "Flushed {} rows of '{}' to '{}' ({} cold rows).",
+            flushed.len(),
+            table_name,
+            path,
+            segment.rows()
+        );
+        self.cold.insert(table_name.to_string(), segment);
+
+        if let Some(table) = self.tables.get_mut(table_name) {
+            for row_id in flushed.keys() {
+                table.rows.remove(row_id);
+            }
+        }
+        // The rows left in memory have moved, so indexes built before are stale.
+        self.writes += 1;
+        self.write_table_file(table_name, &self.config.table_path(table_name))?;
+        self.remove_delta(table_name)?;
+        self.refresh_table_indexes(table_name);
+        Ok(flushed.len())
+    }
+
+    /// Rows of a table held in its cold segment, 0 if it has none.
+    pub fn cold_rows(&self, table_name: &str) -> usize {
+        self.cold.get(table_name).map_or(0, ColdSegment::rows)
+    }
+
+    /// Remove a table's cold segment and its file, if it has one.
+    fn remove_cold_segment(&mut self, table_name: &str) -> Result<()> {
+        self.cold.remove(table_name);
+        let cold_file = self.config.cold_path(table_name);
+        if self.storage.exists(&cold_file) {
+            self.storage
+                .remove(&cold_file)
+                .map_err(|e| DatabaseError::FileCreationError(cold_file, e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite a table's cold segment without `row_id`, if it holds the row.
+    fn remove_cold_row(&mut self, table_name: &str, row_id: &str) -> Result<()> {
+        let Some(old) = self.cold.get(table_name) else {
+            return Ok(());
+        };
+        let path = self.config.cold_path(table_name);
+        let io_err = |e: std::io::Error| DatabaseError::FileCreationError(path.clone(), e.to_string());
+        let storage = self.storage.as_ref();
+        if old.get(storage, row_id).map_err(io_err)?.is_none() {
+            return Ok(());
+        }
+        let mut writer = SegmentWriter::create(storage, &path, old.rows()).map_err(io_err)?;
+        old.visit(storage, None, |id, row| {
+            if id != row_id {
+                writer.push(id, &row)?;
+            }
+            Ok(true)
+        })
+        .map_err(io_err)?;
+        let segment = writer.finish().map_err(io_err)?;
+        self.cold.insert(table_name.to_string(), segment);
+        Ok(())
+    }
+
+    /// A row of a table's cold segment, with its cells decrypted. `None` if
+    /// the row is in memory, where the cold copy is stale, or not in the segment.
+    fn cold_row(&self, table_name: &str, row_id: &str) -> Result<Option<HashMap<String, String>>> {
+        let Some(segment) = self.cold.get(table_name) else {
+            return Ok(None);
+        };
+        if self.tables.get(table_name).is_some_and(|t| t.rows.contains_key(row_id)) {
+            return Ok(None);
+        }
+        let row = segment
+            .get(self.storage.as_ref(), row_id)
+            .map_err(|e| DatabaseError::FileCreationError(segment.path().to_string(), e.to_string()))?;
+        Ok(row.map(|row| self.open_row(row)))
+    }
+
+    /// Up to `limit` rows of a table's cold segment from `start` on, while
+    /// `keep` accepts their ids. Rows held in memory are skipped.
+    fn cold_range(
+        &self,
+        table_name: &str,
+        start: Option<&str>,
+        limit: usize,
+        mut keep: impl FnMut(&str) -> bool,
+    ) -> Result<Vec<(String, HashMap<String, String>)>> {
+        let Some(segment) = self.cold.get(table_name) else {
+            return Ok(Vec::new());
+        };
+        let hot = &self.tables[table_name].rows;
+        let mut rows = Vec::new();
+        segment
+            .visit(self.storage.as_ref(), start, |row_id, row| {
+                if !keep(row_id) || rows.len() >= limit {
+                    return Ok(false);
+                }
+                if !hot.contains_key(row_id) {
+                    rows.push((row_id.to_string(), self.open_row(row)));
+                }
+                Ok(true)
+            })
+            .map_err(|e| DatabaseError::FileCreationError(segment.path().to_string(), e.to_string()))?;
+        Ok(rows)
+    }
+
+    /// Bring a cold row back into memory so it can be changed in place.
+    fn warm_row(&mut self, table_name: &str, row_id: &str) -> Result<()> {
+        if let Some(row) = self.cold_row(table_name, row_id)? {
+            if let Some(table) = self.tables.get_mut(table_name) {
+                table.rows.insert(row_id.to_string(), row);
+                self.writes += 1;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn insert_row_with_datatype(
+        &mut self,
+        table_name: &str,
+        row_id: &str,
+        data: HashMap<String, String>,
+    ) -> Result<QueryResult> {
+        if !self.check_table(table_name) {
+            // Table not found: try to load it from file.
+            let file_name = self.config.table_path(table_name);
+            if self.storage.exists(&file_name) {
+                match self.load_table_from_file(table_name, &file_name) {
+                    Ok(_) => debug!("Table '{}' loaded from file '{}'.", table_name, file_name),
+                    Err(e) => {
+                        error!("Failed to load table from file: {}", e);
+                        return Err(e);
+                    }
+                }
+            } else {
+                error!(
+                    "Table '{}' does not exist in memory or on disk.",
+                    table_name
+                );
+                return Err(DatabaseError::TableDoesNotExist(table_name.to_string()));
+            }
+        }
+        let table = self
+            .tables
+            .get_mut(table_name)
+            .ok_or(DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+        //check if the row_id already exists
+        if table.get_row(row_id).is_some() {
+            error!("Row '{}' already exists in table '{}'.", row_id, table_name);
+            return Err(DatabaseError::RowDoesNotExist(
+                row_id.to_string(),
+                table_name.to_string(),
+            ));
+        }
+
+        //check for datatype
+        for (col, val) in &data {
+            if let Some(dt) = table.row_datatypes.get(col) {
+                if !Database::check_value_matches(val, dt) {
+                    error!(
+                        "Value '{}' does not match datatype '{}' for column '{}'.",
+                        val, dt, col
+                    );
+                    return Err(DatabaseError::DataTypeError);
+                }
+            } else {
+                error!("Column '{}' not found in table '{}'.", col, table_name);
+                return Err(DatabaseError::RowDoesNotExist(
+                    row_id.to_string(),
+                    table_name.to_string(),
+                ));
+            }
+        }
+        // Now perform the row insertion.
+        self.insert_row(table_name, row_id, data)
+    }
+
+    // Update a value in a row for a specific column.
+    pub fn update_row(
+        &mut self,
+        table_name: &str,
+        row_id: &str,
+        column_name: &str,
+        new_value: &str,
+    ) -> Result<QueryResult> {
+        let started = Instant::now();
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        self.check_unprepared(table_name, Some(row_id))?;
+        if !self.check_table(table_name) {
+            let file_name = self.config.table_path(table_name);
+            if self.storage.exists(&file_name) {
+                match self.load_table_from_file(table_name, &file_name) {
+                    Ok(_) => debug!("Table '{}' loaded from file '{}'.", table_name, file_name),
+                    Err(e) => {
+                        error!("Failed to load table '{}' from file: {}", table_name, e);
+                        return Err(e);
+                    }
+                }
+            } else {
+                error!(
+                    "Table '{}' does not exist in memory or on disk.",
+                    table_name
+                );
+                return Err(DatabaseError::TableDoesNotExist(table_name.to_string()));
+            }
+        }
+        self.warm_row(table_name, row_id)?;
+        // A tenant may only update its own rows, and not hand them to another.
+        self.tenant_write(table_name, row_id, HashMap::new())?;
+        if self.tenant.is_some() && column_name == TENANT_ID {
+            return Err(DatabaseError::TenantMismatch(
+                row_id.to_string(),
+                table_name.to_string(),
+            ));
+        }
+        // Check the updated row against the size limit.
+        if let Some(row) = self.tables.get(table_name).and_then(|t| t.rows.get(row_id)) {
+            let mut updated = row.clone();
+            updated.insert(column_name.to_string(), new_value.to_string());
+            self.check_row_size(table_name, row_id, &updated)?;
+            self.check_enum_values(table_name, row_id, &updated)?;
+        }
+        // Now the table should be in memory.
+        if let Some(table) = self.tables.get_mut(table_name) {
+            // Ensure the column exists; add it if not.
+            if !table.columns.contains(&column_name.to_string()) {
+                table.add_column(column_name);
+                debug!(
+                    "Column '{}' was added to table '{}'",
+                    column_name, table_name
+                );
+            }
+            if let Some(row) = table.rows.get_mut(row_id) {
+                // Update the row in place.
+                row.insert(column_name.to_string(), new_value.to_string());
+
+                // Log the update operation in the WAL.
+                let logged_value = Self::seal_cell(
+                    &self.cipher,
+                    Some(&self.blobs),
+                    table,
+                    column_name,
+                    new_value,
+                );
+                let op = format!(
+                    "update_row:{}:{}:{}:{}",
+                    table_name,
+                    row_id,
+                    column_name,
+                    serde_json::to_string(&logged_value).unwrap()
+                );
+                // self.wal.push(op);
+                self.log_op(op);
+                self.writes += 1;
+                // The value itself is not logged; the column may be encrypted.
+                debug!(
+                    "Updated row '{}' in table '{}', column '{}'.",
+                    row_id, table_name, column_name
+                );
+                self.record_write(table_name, [row_id], false);
+                Ok(QueryResult::write(1, started))
+            } else {
+                error!("Row '{}' does not exist in table '{}'.", row_id, table_name);
+                Err(DatabaseError::RowDoesNotExist(
+                    row_id.to_string(),
+                    table_name.to_string(),
+                ))
+            }
+        } else {
+            error!(
+                "Table '{}' is still not found after attempting to load.",
+                table_name
+            );
+            Err(DatabaseError::TableDoesNotExist(table_name.to_string()))
+        }
+    }
+
+    /// Remove a row for good and log it as a `delete_row` WAL record; the
+    /// table file loses it at the next save. See `soft_delete_row` for a
+    /// delete that can be undone.
+    pub fn delete_row(&mut self, table_name: &str, row_id: &str) -> Result<QueryResult> {
+        let started = Instant::now();
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        self.check_unprepared(table_name, Some(row_id))?;
+        self.ensure_table_loaded(table_name)?;
+        self.warm_row(table_name, row_id)?;
+        // A tenant may only delete its own rows.
+        self.tenant_write(table_name, row_id, HashMap::new())?;
+        if row_id == "datatypes" || !self.tables[table_name].rows.contains_key(row_id) {
+            error!("Row '{}' does not exist in table '{}'.", row_id, table_name);
+            return Err(DatabaseError::RowDoesNotExist(
+                row_id.to_string(),
+                table_name.to_string(),
+            ));
+        }
+        self.remove_cold_row(table_name, row_id)?;
+        if let Some(table) = self.tables.get_mut(table_name) {
+            table.delete_row(row_id);
+        }
+        let op = format!("delete_row:{}:{}", table_name, row_id);
+        self.log_op(op);
+        self.writes += 1;
+        debug!("Deleted row '{}' from table '{}'.", row_id, table_name);
+        self.record_write(table_name, [row_id], false);
+        Ok(QueryResult::write(1, started))
+    }
+
+    #[instrument(skip(self))]
+    pub fn save_table_for_insert(
+        &mut self,
+        table_name: &str,
+        file_name: &str,
+    ) -> Result<Vec<String>> {
+        self.check_writable()?;
+        self.check_persistent(table_name)?;
+        let table = self
+            .tables
+            .get(table_name)
+            .ok_or(DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+
+        let cols: Vec<_> = table.columns.iter().cloned().collect();
+        let mut header = vec!["row_id".to_string()];
+        header.extend(cols.clone());
+
+        // Rows can only be appended to a file with the same columns; a missing
+        // file, or one written before a column was added, is rewritten whole.
+        if self.file_header(file_name).as_ref() != Some(&header) {
+            let saved = self.save_table(table_name, file_name)?;
+            self.unsaved.remove(table_name);
+            return Ok(saved);
+        }
+        let mut wtr = WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(Vec::new());
+
+        let unsaved: Vec<_> = self
+            .unsaved
+            .get(table_name)
+            .map(|unsaved| &unsaved.rows)
+            .into_iter()
+            .flatten()
+            .filter(|rid| rid.as_str() != "datatypes")
+            .filter_map(|rid| table.rows.get_key_value(rid))
+            .collect();
+        let unsaved_count = unsaved.len();
+
+        for (row_id, row_data) in unsaved {
+            let mut rec = vec![row_id.clone()];
+            rec.extend(cols.iter().map(|c| {
+                let value = row_data.get(c).cloned().unwrap_or_default();
+                Self::store_cell(&self.cipher, Some(&self.blobs), table, c, &value)
+            }));
+            wtr.write_record(&rec).map_err(|e| {
+                DatabaseError::FileCreationError(file_name.to_string(), e.to_string())
+            })?;
+        }
+        self.append_records(wtr, file_name)?;
+
+        self.unsaved.remove(table_name);
+        info!(
+            "Table '{}' appended to '{}' ({} new rows).",
+            table_name, file_name, unsaved_count
+        );
+        Ok(vec![table_name.to_string(), file_name.to_string()])
+    }
+
+    /// Append the rows changed since the last save to the table's delta file
+    /// instead of rewriting the table file; the next full save folds them in.
+    /// Saves in full when the table file is missing or has other columns, a
+    /// changed row has since been removed, or the delta outgrows the table file.
+    #[instrument(skip(self))]
+    pub fn save_table_delta(&mut self, table_name: &str, file_name: &str) -> Result<Vec<String>> {
+        self.check_writable()?;
+        self.check_persistent(table_name)?;
+        let table = self
+            .tables
+            .get(table_name)
+            .ok_or(DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+
+        let cols: Vec<_> = table.columns.iter().cloned().collect();
+        let mut header = vec!["row_id".to_string()];
+        header.extend(cols.clone());
+
+        let delta_file = self.config.delta_path(table_name);
+        let delta_header = self.file_header(&delta_file);
+        let file_size = |path: &str| self.storage.size(path).unwrap_or(0);
+        let mut changed: Vec<&String> = self
+            .unsaved
+            .get(table_name)
+            .map(|unsaved| &unsaved.rows)
+            .into_iter()
+            .flatten()
+            .collect();
+        // Rows are decoded by the datatypes before them, so a new one goes first.
+        changed.sort_by_key(|rid| rid.as_str() != "datatypes");
+        let full = changed.iter().any(|rid| !table.rows.contains_key(*rid))
+            || self.file_header(file_name).as_ref() != Some(&header)
+            || delta_header.as_ref().is_some_and(|h| h != &header)
+            || file_size(&delta_file) > file_size(file_name);
+        if full {
+            let saved = self.save_table(table_name, file_name)?;
+            self.unsaved.remove(table_name);
+            return Ok(saved);
+        }
+
+        let mut wtr = WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(Vec::new());
+        if delta_header.is_none() {
+            wtr.write_record(&header)
+                .map_err(|e| DatabaseError::FileCreationError(delta_file.clone(), e.to_string()))?;
+        }
+        for row_id in &changed {
+            let row_data = &table.rows[*row_id];
+            let mut rec = vec![row_id.to_string()];
+            rec.extend(cols.iter().map(|c| {
+                let value = row_data.get(c).cloned().unwrap_or_default();
+                if row_id.as_str() == "datatypes" {
+                    value
+                } else {
+                    Self::store_cell(&self.cipher, Some(&self.blobs), table, c, &value)
+                }
+            }));
+            wtr.write_record(&rec)
+                .map_err(|e| DatabaseError::FileCreationError(delta_file.clone(), e.to_string()))?;
+        }
+        self.append_records(wtr, &delta_file)?;
+
+        let changed_count = changed.len();
+        self.unsaved.remove(table_name);
+        info!(
+            "Table '{}' delta appended to '{}' ({} changed rows).",
+            table_name, delta_file, changed_count
+        );
+        Ok(vec![table_name.to_string(), delta_file])
+    }
+
+    /// Delete a table's delta file once its table file holds every row.
+    fn remove_delta(&self, table_name: &str) -> Result<()> {
+        self.remove_with_sidecar(&self.config.delta_path(table_name))
+    }
+
+    /// The header row of a table file, or `None` if it can't be read.
+    fn file_header(&self, file_name: &str) -> Option<Vec<String>> {
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .from_reader(self.storage.open(file_name).ok()?);
+        let headers = rdr.headers().ok()?;
+        Some(headers.iter().map(str::to_string).collect())
+    }
+
+    /// Append the records written to `wtr` to `file_name`, extending its
+    /// checksum sidecar, or starting one for a new file.
+    fn append_records(&self, wtr: Writer<Vec<u8>>, file_name: &str) -> Result<()> {
+        let to_err = |e: String| DatabaseError::FileCreationError(file_name.to_string(), e);
+        let records = wtr.into_inner().map_err(|e| to_err(e.to_string()))?;
+        let previous = if self.storage.exists(file_name) {
+            self.storage
+                .read_to_string(&checksum::sidecar_path(file_name))
+                .ok()
+                .and_then(|sum| u32::from_str_radix(sum.trim(), 16).ok())
+        } else {
+            Some(0)
+        };
+        self.storage
+            .append(file_name, &records)
+            .map_err(|e| to_err(e.to_string()))?;
+        match previous {
+            Some(crc) => self.write_sidecar(file_name, checksum::crc32_extend(crc, &records)),
+            None => Ok(()),
+        }
+    }
+
+    /// Record the CRC-32 of a file in its sidecar, checked when it is loaded.
+    fn write_sidecar(&self, file_name: &str, crc: u32) -> Result<()> {
+        Self::store_sidecar(self.storage.as_ref(), file_name, crc)
+    }
+
+    fn store_sidecar(storage: &dyn Storage, file_name: &str, crc: u32) -> Result<()> {
+        let sidecar = checksum::sidecar_path(file_name);
+        storage
+            .write(&sidecar, format!("{:08x}\n", crc).as_bytes())
+            .map_err(|e| DatabaseError::FileCreationError(sidecar, e.to_string()))
+    }
+
+    /// Rename a file and its checksum sidecar, if they exist.
+    fn rename_with_sidecar(&self, from: &str, to: &str) -> Result<()> {
+        for (from, to) in [
+            (from.to_string(), to.to_string()),
+            (checksum::sidecar_path(from), checksum::sidecar_path(to)),
+        ] {
+            if self.storage.exists(&from) {
+                self.storage
+                    .rename(&from, &to)
+                    .map_err(|e| DatabaseError::FileCreationError(to.clone(), e.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete a file and its checksum sidecar, if they exist.
+    fn remove_with_sidecar(&self, file_name: &str) -> Result<()> {
+        for file in [file_name.to_string(), checksum::sidecar_path(file_name)] {
+            if self.storage.exists(&file) {
+                self.storage
+                    .remove(&file)
+                    .map_err(|e| DatabaseError::FileCreationError(file.clone(), e.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Save the table to a CSV file.
+    #[instrument(skip(self))]
+    pub fn save_table(&self, table_name: &str, file_name: &str) -> Result<Vec<String>> {
+        self.check_writable()?;
+        self.check_persistent(table_name)?;
+        let saved = self.write_table_file(table_name, file_name)?;
+        if file_name == self.config.table_path(table_name) {
+            self.remove_delta(table_name)?;
+        }
+        Ok(saved)
+    }
+
+    // Write the full table to a CSV file; also used for backups of read-only databases.
+    fn write_table_file(&self, table_name: &str, file_name: &str) -> Result<Vec<String>> {
+        let table = self
+            .tables
+            .get(table_name)
+            .ok_or(DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+        Self::write_table(
+            self.storage.as_ref(),
+            &self.cipher,
+            Some(&self.blobs),
+            table,
+            file_name,
+        )?;
+        info!("Table '{}' saved to '{}'.", table_name, file_name);
+        Ok(vec![table_name.to_string(), file_name.to_string()])
+    }
+
+    /// Write `table` and its checksum sidecar without the database, so a
+    /// `BackupSnapshot` can be written while the database serves writes.
+    pub(crate) fn write_table(
+        storage: &dyn Storage,
+        cipher: &Option<ColumnCipher>,
+        blobs: Option<&BlobStore>,
+        table: &Table,
+        file_name: &str,
+    ) -> Result<()> {
+        let cols: Vec<_> = table.columns.iter().cloned().collect();
+
+        // Written aside and renamed into place, so a crash never leaves half a table.
+        let file = storage
+            .create(file_name)
+            .map_err(|e| DatabaseError::FileCreationError(file_name.to_string(), e.to_string()))?;
+        let mut wtr = WriterBuilder::new()
+            .has_headers(true)
+            .from_writer(checksum::Crc32Writer::new(file));
+
+        // header
+        let mut hdr = vec!["row_id".to_string()];
+        hdr.extend(cols.clone());
+        wtr.write_record(&hdr)
+            .map_err(|e| DatabaseError::FileCreationError(file_name.to_string(), e.to_string()))?;
+
+        // optional datatypes row
+        if let Some(dt_row) = table.rows.get("datatypes") {
+            let mut rec = vec!["datatypes".to_string()];
+            rec.extend(
+                cols.iter()
+                    .map(|c| dt_row.get(c).cloned().unwrap_or_default()),
+            );
+            wtr.write_record(&rec).map_err(|e| {
+                DatabaseError::FileCreationError(file_name.to_string(), e.to_string())
+            })?;
+        }
+
+        // all other rows
+        let mut rows: Vec<_> = table
+            .rows
+            .iter()
+            .filter(|(rid, _)| rid.as_str() != "datatypes")
+            .collect();
+        rows.sort_by_key(|(rid, _)| *rid);
+
+        for (row_id, row_data) in rows {
+            let mut rec = vec![row_id.clone()];
+            rec.extend(cols.iter().map(|c| {
+                let value = row_data.get(c).cloned().unwrap_or_default();
+                Self::store_cell(cipher, blobs, table, c, &value)
+            }));
+            wtr.write_record(&rec).map_err(|e| {
+                DatabaseError::FileCreationError(file_name.to_string(), e.to_string())
+            })?;
+        }
+        let crc = wtr
+            .into_inner()
+            .map_err(|e| e.to_string())
+            .and_then(|writer| {
+                let (file, crc) = writer.into_parts();
+                file.commit().map(|_| crc).map_err(|e| e.to_string())
+            })
+            .map_err(|e| DatabaseError::FileCreationError(file_name.to_string(), e))?;
+        Self::store_sidecar(storage, file_name, crc)
+    }
+
+    pub fn get_table(&self, table_name: &str) -> Result<&Table> {
+        self.tables
+            .get(table_name)
+            .ok_or(DatabaseError::TableDoesNotExist(table_name.to_string()))
+    }
+
+    /// Read one chunk of a table dump: up to `max_rows` rows after
+    /// `position.after`, cold rows included, in row id order. The first chunk
+    /// also carries the schema. `chunk.next()` is the position of the next.
+    pub fn dump_chunk(
+        &mut self,
+        table_name: &str,
+        position: &TransferPosition,
+        max_rows: usize,
+    ) -> Result<TableChunk> {
+        self.ensure_table_loaded(table_name)?;
+        let max_rows = max_rows.max(1);
+        let after = position.after.as_deref();
+        let bounds = (
+            after.map_or(Bound::Unbounded, Bound::Excluded),
+            Bound::Unbounded,
+        );
+        // One row past the chunk tells whether another chunk follows.
+        let mut rows: BTreeMap<String, HashMap<String, String>> = self.tables[table_name]
+            .rows
+            .range::<str, _>(bounds)
+            .take(max_rows + 1)
+            .map(|(row_id, row)| (row_id.clone(), row.clone()))
+            .collect();
+        rows.extend(self.cold_range(table_name, after, max_rows + 2, |_| true)?);
+        if let Some(after) = after {
+            rows.remove(after);
+        }
+        let more = rows.len() > max_rows;
+        let rows: Vec<(String, BTreeMap<String, String>)> = rows
+            .into_iter()
+            .take(max_rows)
+            .map(|(row_id, row)| {
+                let sealed = self.seal_row(table_name, &row);
+                (row_id, sealed.into_iter().collect())
+            })
+            .collect();
+        let cursor = if more {
+            rows.last().map(|(row_id, _)| row_id.clone())
+        } else {
+            None
+        };
+        let schema = if position.seq == 0 {
+            Some(self.table_schema(table_name)?)
+        } else {
+            None
+        };
+        debug!(
+            "Dumped chunk {} of table '{}' ({} rows).",
+            position.seq,
+            table_name,
+            rows.len()
+        );
+        Ok(TableChunk::new(table_name, position.seq, schema, rows, cursor))
+    }
+
+    /// Apply a chunk from `dump_chunk`, after checking its checksum. The
+    /// first chunk creates the table and its columns if they are missing.
+    /// Rows are upserted and logged like any other write, so applying a chunk
+    /// again leaves the table the same. Returns where the transfer continues,
+    /// `None` after the last chunk.
+    pub fn restore_chunk(&mut self, chunk: &TableChunk) -> Result<Option<TransferPosition>> {
+        self.check_writable()?;
+        let table_name = chunk.table.as_str();
+        if !chunk.is_valid() {
+            error!(
+                "Chunk {} of table '{}' failed its checksum.",
+                chunk.seq, table_name
+            );
+            return Err(DatabaseError::ChunkChecksum(table_name.to_string(), chunk.seq));
+        }
+        if let Some(schema) = &chunk.schema {
+            if !self.check_table(table_name)
+                && !self.storage.exists(&self.config.table_path(table_name))
+            {
+                self.create_table(table_name)?;
+            }
+            self.ensure_table_loaded(table_name)?;
+            for (column, datatype) in schema {
+                if !self.tables[table_name].columns.contains(column) {
+                    self.add_column(table_name, column)?;
+                }
+                if let (Some(datatype), Some(table)) = (datatype, self.tables.get_mut(table_name)) {
+                    table.add_datatype(column, datatype);
+                }
+            }
+        }
+        for (row_id, row) in &chunk.rows {
+            let row: HashMap<String, String> = row
+                .iter()
+                .map(|(c, v)| (c.clone(), self.open_cell(v)))
+                .collect();
+            self.upsert_row(table_name, row_id, row)?;
+        }
+        debug!(
+            "Restored chunk {} of table '{}' ({} rows).",
+            chunk.seq,
+            table_name,
+            chunk.rows.len()
+        );
+        Ok(chunk.next())
+    }
+
+    /// First phase of a two-phase commit: check `writes`, log them to the WAL
+    /// as prepared, and hold their rows against other writes until
+    /// `commit_prepared` or `abort_prepared`. Every check the writes meet at
+    /// commit is made here, and rows are tagged with the tenant now, so a
+    /// transaction that prepares can always commit. Preparing a transaction
+    /// again does nothing. Transaction ids come from `twophase::new_txid`; see
+    /// `twophase::Coordinator`.
+    pub fn prepare(&mut self, txid: &str, mut writes: Vec<TxWrite>) -> Result<()> {
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        if self.prepared.contains_key(txid) {
+            return Ok(());
+        }
+        // Rows each table gains, checked against its row limit at the end.
+        let mut new_rows: HashMap<String, HashSet<String>> = HashMap::new();
+        for write in &mut writes {
+            let (table_name, row_id) = (write.table().to_string(), write.row_id().to_string());
+            let (table_name, row_id) = (table_name.as_str(), row_id.as_str());
+            self.ensure_table_loaded(table_name)?;
+            // A prepared transaction has to survive a restart.
+            self.check_persistent(table_name)?;
+            self.check_unprepared(table_name, Some(row_id))?;
+            self.warm_row(table_name, row_id)?;
+            let existing = self.tables[table_name].rows.get(row_id).cloned();
+            let upsert = matches!(write, TxWrite::Upsert { .. });
+            match write {
+                TxWrite::Insert { data, .. } | TxWrite::Upsert { data, .. } => {
+                    *data = self.tenant_write(table_name, row_id, std::mem::take(data))?;
+                    if existing.is_none() {
+                        new_rows
+                            .entry(table_name.to_string())
+                            .or_default()
+                            .insert(row_id.to_string());
+                    }
+                    // An upsert is checked as the row it merges into.
+                    let mut row = if upsert {
+                        existing.unwrap_or_default()
+                    } else {
+                        HashMap::new()
+                    };
+                    row.extend(data.clone());
+                    self.check_row_size(table_name, row_id, &row)?;
+                    self.check_enum_values(table_name, row_id, &row)?;
+                }
+                TxWrite::Update { column, value, .. } => {
+                    let Some(mut updated) = existing else {
+                        error!("Row '{}' does not exist in table '{}'.", row_id, table_name);
+                        return Err(DatabaseError::RowDoesNotExist(
+                            row_id.to_string(),
+                            table_name.to_string(),
+                        ));
+                    };
+                    self.tenant_write(table_name, row_id, HashMap::new())?;
+                    if self.tenant.is_some() && column == TENANT_ID {
+                        return Err(DatabaseError::TenantMismatch(
+                            row_id.to_string(),
+                            table_name.to_string(),
+                        ));
+                    }
+                    updated.insert(column.clone(), value.clone());
+                    self.check_row_size(table_name, row_id, &updated)?;
+                    self.check_enum_values(table_name, row_id, &updated)?;
+                }
+            }
+        }
+        for (table_name, row_ids) in &new_rows {
+            let Some(max) = self.config.max_rows_for(table_name) else {
+                continue;
+            };
+            let rows = self.total_rows(table_name)? + self.prepared_new_rows(table_name);
+            if rows + row_ids.len() > max {
+                error!(
+                    "Prepare rejected: table '{}' would exceed its limit of {} rows.",
+                    table_name, max
+                );
+                return Err(DatabaseError::TableFull(table_name.to_string(), max));
+            }
+        }
+        self.log_prepare(txid, &writes);
+        // The coordinator may only count this vote once it survives a crash.
+        self.sync_wal()?;
+        debug!("Prepared transaction {} ({} writes).", txid, writes.len());
+        self.prepared.insert(txid.to_string(), writes);
+        Ok(())
+    }
+
+    /// Second phase: apply the writes of a prepared transaction. If one
+    /// fails, the transaction stays prepared so the commit can be retried;
+    /// writes already applied are applied again harmlessly. Returns the
+    /// writes applied, 0 for a transaction not prepared here.
+    pub fn commit_prepared(&mut self, txid: &str) -> Result<usize> {
+        self.check_writable()?;
+        let Some(writes) = self.prepared.remove(txid) else {
+            return Ok(0);
+        };
+        for write in &writes {
+            let result = match write {
+                TxWrite::Insert { table, row_id, data } => {
+                    self.insert_row(table, row_id, data.clone()).map(|_| ())
+                }
+                TxWrite::Upsert { table, row_id, data } => {
+                    self.upsert_row(table, row_id, data.clone()).map(|_| ())
+                }
+                TxWrite::Update { table, row_id, column, value } => {
+                    self.update_row(table, row_id, column, value).map(|_| ())
+                }
+            };
+            if let Err(e) = result {
+                error!("Commit of transaction {} failed: {}", txid, e);
+                self.prepared.insert(txid.to_string(), writes);
+                return Err(e);
+            }
+        }
+        self.log_op(format!("commit_tx:{}", txid));
+        self.sync_wal()?;
+        debug!("Committed transaction {} ({} writes).", txid, writes.len());
+        Ok(writes.len())
+    }
+
+    /// Drop a prepared transaction without applying it.
+    pub fn abort_prepared(&mut self, txid: &str) -> Result<()> {
+        if self.prepared.remove(txid).is_some() {
+            self.log_op(format!("abort_tx:{}", txid));
+            self.sync_wal()?;
+            debug!("Aborted transaction {}.", txid);
+        }
+        Ok(())
+    }
+
+    /// Ids of the transactions prepared here and waiting on their coordinator.
+    pub fn prepared_transactions(&self) -> Vec<String> {
+        self.prepared.keys().cloned().collect()
+    }
+
+    /// Rows that prepared transactions will add to the table when they commit.
+    /// They count against its row limit, so other inserts can't take the room
+    /// a prepared transaction was promised.
+    fn prepared_new_rows(&self, table_name: &str) -> usize {
+        let Some(table) = self.tables.get(table_name) else {
+            return 0;
+        };
+        let row_ids: HashSet<&str> = self
+            .prepared
+            .values()
+            .flatten()
+            .filter(|w| matches!(w, TxWrite::Insert { .. } | TxWrite::Upsert { .. }))
+            .filter(|w| w.table() == table_name && !table.rows.contains_key(w.row_id()))
+            .map(TxWrite::row_id)
+            .collect();
+        row_ids.len()
+    }
+
+    /// Fail if a prepared transaction holds `row_id` of the table, or any of
+    /// its rows when `row_id` is `None`.
+    fn check_unprepared(&self, table_name: &str, row_id: Option<&str>) -> Result<()> {
+        let held = self.prepared.iter().find_map(|(txid, writes)| {
+            writes
+                .iter()
+                .find(|w| w.table() == table_name && row_id.is_none_or(|id| w.row_id() == id))
+                .map(|w| (txid, w.row_id()))
+        });
+        match held {
+            Some((txid, row_id)) => {
+                error!(
+                    "Write rejected: row '{}' of '{}' is held by transaction {}.",
+                    row_id, table_name, txid
+                );
+                Err(DatabaseError::RowLocked(
+                    row_id.to_string(),
+                    table_name.to_string(),
+                    txid.clone(),
+                ))
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn logged_prepare(entry: &str) -> Option<(String, Vec<TxWrite>)> {
+        let (txid, json) = entry.strip_prefix("prepare_tx:")?.split_once(':')?;
+        let writes = serde_json::from_str(json).ok()?;
+        Some((txid.to_string(), writes))
+    }
+
+    fn log_prepare(&mut self, txid: &str, writes: &[TxWrite]) {
+        let op = format!("prepare_tx:{}:{}", txid, serde_json::to_string(writes).unwrap());
+        self.log_op(op);
+    }
+
+    /// Give a table soft deletes: `soft_delete_row` then marks a row with the
+    /// time it was deleted instead of removing it, reads and index lookups
+    /// skip it unless `set_include_deleted` is on, `restore_row` brings it
+    /// back and `purge_deleted` removes it for good. The mark is kept in the
+    /// `_deleted_at` column, so it is saved and logged like any other value.
+    pub fn enable_soft_delete(&mut self, table_name: &str) -> Result<()> {
+        self.ensure_table_loaded(table_name)?;
+        if !self.tables[table_name].soft_deletes() {
+            self.add_column(table_name, DELETED_AT)?;
+            info!("Table '{}' now soft-deletes rows.", table_name);
+        }
+        Ok(())
+    }
+
+    /// Let reads return soft-deleted rows, e.g. to audit or undo deletes.
+    /// Returns the setting before. A `Session` that includes them sets it
+    /// around each of its calls, so it holds for that session only.
+    pub fn set_include_deleted(&mut self, include: bool) -> bool {
+        std::mem::replace(&mut self.include_deleted, include)
+    }
+
+    /// Whether reads should skip `row` of `table`: it is soft-deleted, or
+    /// belongs to a tenant other than the one set.
+    fn hidden(&self, table: &Table, row: &HashMap<String, String>) -> bool {
+        (!self.include_deleted && Table::deleted_at(row).is_some())
+            || self.tenant.as_ref().is_some_and(|tenant| {
+                table.tenant_scoped() && row.get(TENANT_ID) != Some(tenant)
+            })
+    }
+
+    /// Give a table tenants: rows written while a tenant is set (see
+    /// `set_tenant`) are tagged with it in the `_tenant_id` column, and reads
+    /// and writes under a tenant only see that tenant's rows.
+    pub fn enable_tenancy(&mut self, table_name: &str) -> Result<()> {
+        self.ensure_table_loaded(table_name)?;
+        if !self.tables[table_name].tenant_scoped() {
+            self.add_column(table_name, TENANT_ID)?;
+            info!("Table '{}' is now scoped by tenant.", table_name);
+        }
+        Ok(())
+    }
+
+    /// Scope reads and writes of tables with tenancy to `tenant`, or lift the
+    /// scope with `None`. Returns the tenant set before. A `Session` with a
+    /// tenant sets it around each of its calls. Maintenance such as
+    /// `purge_deleted`, saves and WAL replay is not scoped.
+    pub fn set_tenant(&mut self, tenant: Option<&str>) -> Option<String> {
+        std::mem::replace(&mut self.tenant, tenant.map(str::to_string))
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    /// With a tenant set, refuse a write to another tenant's row of a table
+    /// with tenancy, and tag the written `data` with the tenant.
+    fn tenant_write(
+        &self,
+        table_name: &str,
+        row_id: &str,
+        mut data: HashMap<String, String>,
+    ) -> Result<HashMap<String, String>> {
+        let (Some(tenant), Some(table)) = (&self.tenant, self.tables.get(table_name)) else {
+            return Ok(data);
+        };
+        if !table.tenant_scoped() {
+            return Ok(data);
+        }
+        if table.rows.get(row_id).is_some_and(|row| row.get(TENANT_ID) != Some(tenant)) {
+            warn!(
+                "Write rejected: row '{}' of '{}' belongs to another tenant.",
+                row_id, table_name
+            );
+            return Err(DatabaseError::TenantMismatch(
+                row_id.to_string(),
+                table_name.to_string(),
+            ));
+        }
+        data.insert(TENANT_ID.to_string(), tenant.clone());
+        Ok(data)
+    }
+
+    /// Mark a row of a soft-delete table as deleted now.
+    pub fn soft_delete_row(&mut self, table_name: &str, row_id: &str) -> Result<QueryResult> {
+        self.ensure_table_loaded(table_name)?;
+        if !self.tables[table_name].soft_deletes() {
+            return Err(DatabaseError::SoftDeleteDisabled(table_name.to_string()));
+        }
+        self.warm_row(table_name, row_id)?;
+        let deleted = self.tables[table_name].rows.get(row_id).map(Table::deleted_at);
+        match deleted {
+            None => Err(DatabaseError::RowDoesNotExist(
+                row_id.to_string(),
+                table_name.to_string(),
+            )),
+            // Deleting twice keeps the first time.
+            Some(Some(_)) => Ok(QueryResult::write(0, Instant::now())),
+            Some(None) => {
+                let now = clock::unix_millis().to_string();
+                self.update_row(table_name, row_id, DELETED_AT, &now)
+            }
+        }
+    }
+
+    /// Undo `soft_delete_row`.
+    pub fn restore_row(&mut self, table_name: &str, row_id: &str) -> Result<QueryResult> {
+        self.ensure_table_loaded(table_name)?;
+        if !self.tables[table_name].soft_deletes() {
+            return Err(DatabaseError::SoftDeleteDisabled(table_name.to_string()));
+        }
+        self.update_row(table_name, row_id, DELETED_AT, "")
+    }
+
+    /// Remove the rows of a table soft-deleted before `before` (Unix
+    /// milliseconds) for good. Rows in the cold segment are brought back
+    /// into memory by their next write, so only rows in memory are purged.
+    /// Returns the rows removed.
+    pub fn purge_deleted(&mut self, table_name: &str, before: u64) -> Result<usize> {
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        self.check_unprepared(table_name, None)?;
+        self.ensure_table_loaded(table_name)?;
+        if !self.tables[table_name].soft_deletes() {
+            return Err(DatabaseError::SoftDeleteDisabled(table_name.to_string()));
+        }
+        let purged = self.remove_deleted(table_name, before);
+        if purged.is_empty() {
+            return Ok(0);
+        }
+        self.log_op(format!("purge_deleted:{}:{}", table_name, before));
+        self.writes += 1;
+        self.record_write(table_name, purged.iter().map(String::as_str), false);
+        info!(
+            "Purged {} rows deleted before {} from '{}'.",
+            purged.len(),
+            before,
+            table_name
+        );
+        Ok(purged.len())
+    }
+
+    /// Drop the rows soft-deleted before `before`, returning their ids.
+    fn remove_deleted(&mut self, table_name: &str, before: u64) -> Vec<String> {
+        let Some(table) = self.tables.get_mut(table_name) else {
+            return Vec::new();
+        };
+        let purged: Vec<String> = table
+            .rows
+            .iter()
+            .filter(|(_, row)| Table::deleted_at(row).is_some_and(|at| at < before))
+            .map(|(row_id, _)| row_id.clone())
+            .collect();
+        for row_id in &purged {
+            table.delete_row(row_id);
+        }
+        purged
+    }
+
+    /// Names of all tables currently held in memory, sorted.
+    pub fn list_tables(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.tables.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Every table of the database, held in memory or not, with its schema,
+    /// file, and row count and WAL position as of the last checkpoint.
+    pub fn catalog(&self) -> &Catalog {
+        &self.catalog
+    }
+
+    /// Record a table in the catalog as it is in memory now, or remove it if
+    /// it is gone, and rewrite the catalog file. Called after each change to
+    /// the set of tables or their columns, once the change is logged: if the
+    /// file can't be written the error is returned, and WAL replay brings the
+    /// catalog up to date at the next open.
+    fn sync_catalog(&mut self, table_name: &str) -> Result<()> {
+        if self.is_temp_table(table_name) {
+            return Ok(());
+        }
+        match self.tables.get(table_name) {
+            Some(table) => {
+                let file = DatabaseConfig::table_file(table_name);
+                let mut entry = CatalogEntry::describe(table, &file);
+                if let Some(old) = self.catalog.get(table_name) {
+                    entry.rows = old.rows;
+                    entry.checkpoint_lsn = old.checkpoint_lsn;
+                }
+                self.catalog.tables.insert(table_name.to_string(), entry);
+            }
+            None => {
+                self.catalog.tables.remove(table_name);
+            }
+        }
+        self.save_catalog()
+    }
+
+    fn save_catalog(&self) -> Result<()> {
+        let path = self.config.catalog_path();
+        self.catalog
+            .save(self.storage.as_ref(), &path)
+            .map_err(|e| DatabaseError::FileCreationError(path, e.to_string()))
+    }
+
+    /// Add the table files in the tables directory that the catalog doesn't
+    /// list yet and `owns` accepts, e.g. those saved before there was a
+    /// catalog. Each is loaded to learn its schema. Returns the tables added.
+    pub fn adopt_table_files(&mut self, owns: impl Fn(&str) -> bool) -> Result<Vec<String>> {
+        let dir = self.config.tables_dir();
+        let files = self
+            .storage
+            .list(&dir)
+            .map_err(|e| DatabaseError::FileCreationError(dir.clone(), e.to_string()))?;
+        let mut adopted = Vec::new();
+        for file in files {
+            let path = Path::new(&file);
+            if path.extension() != Some(std::ffi::OsStr::new("csv")) {
+                continue;
+            }
+            let Some(table_name) = path.file_stem().and_then(|f| f.to_str()) else {
+                continue;
+            };
+            if self.catalog.contains(table_name) || !owns(table_name) {
+                continue;
+            }
+            self.ensure_table_loaded(table_name)?;
+            let table = &self.tables[table_name];
+            let mut entry = CatalogEntry::describe(table, &DatabaseConfig::table_file(table_name));
+            entry.rows = Self::data_rows(table) + self.cold_rows(table_name);
+            self.catalog.tables.insert(table_name.to_string(), entry);
+            adopted.push(table_name.to_string());
+        }
+        if !adopted.is_empty() {
+            self.save_catalog()?;
+            info!("Added {} table files to the catalog.", adopted.len());
+        }
+        Ok(adopted)
+    }
+
+    /// Rows of a table in memory, not counting its datatypes row.
+    fn data_rows(table: &Table) -> usize {
+        table.rows.len() - usize::from(table.rows.contains_key("datatypes"))
+    }
+
+    /// Data rows of a table in memory and in its cold segment, each counted
+    /// once: a row brought back into memory leaves a stale copy in the segment.
+    fn total_rows(&self, table_name: &str) -> Result<usize> {
+        let Some(table) = self.tables.get(table_name) else {
+            return Ok(0);
+        };
+        let hot = Self::data_rows(table);
+        let Some(segment) = self.cold.get(table_name) else {
+            return Ok(hot);
+        };
+        let mut warmed = 0;
+        for row_id in table.rows.keys().filter(|id| segment.may_contain(id)) {
+            let row = segment
+                .get(self.storage.as_ref(), row_id)
+                .map_err(|e| DatabaseError::FileCreationError(segment.path().to_string(), e.to_string()))?;
+            warmed += usize::from(row.is_some());
+        }
+        Ok(hot + segment.rows() - warmed)
+    }
+
+    /// Columns of a table in schema order, paired with their declared datatype, if any.
+    pub fn table_schema(&self, table_name: &str) -> Result<Vec<(String, Option<String>)>> {
+        let table = self.get_table(table_name)?;
+        Ok(table
+            .columns
+            .iter()
+            .map(|c| (c.clone(), table.row_datatypes.get(c).cloned()))
+            .collect())
+    }
+
+    /// Describe the indexes and bloom filters that are currently built.
+    pub fn list_indexes(&self) -> Vec<String> {
+        let mut indexes = Vec::new();
+        if let Some(ref indexer) = self.indexer {
+            indexes.push(format!(
+                "index on '{}' ({} keys)",
+                self.config.index_column,
+                indexer.index.len()
+            ));
+        }
+        if self.bloom_filter.is_some() {
+            indexes.push(format!("bloom filter on '{}'", self.config.bloom_column));
+        }
+        for ((table_name, column), index) in &self.table_indexes {
+            indexes.push(format!(
+                "{} index on '{}.{}' ({} keys)",
+                index.kind,
+                table_name,
+                column,
+                index.keys()
+            ));
+        }
+        indexes
+    }
+
+    /// Import a CSV file (row_id first, then one column per header) into a new table.
+    /// Returns the number of rows loaded.
+    #[cfg(feature = "import")]
+    pub fn import_csv(&mut self, table_name: &str, file_name: &str) -> Result<usize> {
+        self.import_csv_with_types(table_name, file_name, &HashMap::new())
+    }
+
+    /// Import a CSV file like `import_csv`, typing its columns. A `datatypes`
+    /// row in the file declares types; without one, each column's type is
+    /// inferred from a sample of its values (see `import_sample_rows`).
+    /// `overrides` (column -> datatype) win over both, for columns whose
+    /// values are ambiguous. Every value must match its column's type.
+    #[cfg(feature = "import")]
+    pub fn import_csv_with_types(
+        &mut self,
+        table_name: &str,
+        file_name: &str,
+        overrides: &HashMap<String, String>,
+    ) -> Result<usize> {
+        self.check_writable()?;
+        if self.check_table(table_name) {
+            error!("Table '{}' already exists.", table_name);
+            return Err(DatabaseError::TableAlreadyExists(table_name.to_string()));
+        }
+        self.check_wal_backlog()?;
+        self.check_table_count()?;
+        self.load_table_from_file(table_name, file_name)?;
+        let count = self.get_table(table_name)?.rows.len();
+        if let Some(max) = self.config.max_rows_for(table_name) {
+            if count > max {
+                self.tables.remove(table_name);
+                error!(
+                    "Import rejected: {} rows exceeds the limit of {}.",
+                    count, max
+                );
+                return Err(DatabaseError::TableFull(table_name.to_string(), max));
+            }
+        }
+        if let Err(e) = self.type_imported_columns(table_name, overrides) {
+            self.tables.remove(table_name);
+            return Err(e);
+        }
+        let op = format!("import_csv:{}:{}", table_name, file_name);
+        self.log_op(op);
+        self.writes += 1;
+        self.sync_catalog(table_name)?;
+        Ok(count)
+    }
+
+    /// Load rows from the CSV file `source` (row_id first, then one column per
+    /// header) into `table_name` for initial ingestion, creating the table if
+    /// it doesn't exist; a new table is typed like an imported one. Rather
+    /// than logging each row, the load is one `bulk_load` WAL record and the
+    /// table is saved once at the end, after which the index and bloom filter
+    /// are rebuilt once. Every row is checked against the table's columns,
+    /// datatypes and limits first, so a failed load changes nothing.
+    /// Returns the number of rows loaded.
+    #[cfg(feature = "import")]
+    #[instrument(skip(self))]
+    pub fn bulk_load(&mut self, table_name: &str, source: &str) -> Result<usize> {
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        let file_error =
+            |e: csv::Error| DatabaseError::FileCreationError(source.to_string(), e.to_string());
+        let created = !self.check_table(table_name)
+            && !self.storage.exists(&self.config.table_path(table_name));
+        if created {
+            self.check_table_count()?;
+        } else {
+            self.ensure_table_loaded(table_name)?;
+        }
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .from_path(source)
+            .map_err(file_error)?;
+        let columns: Vec<String> = rdr
+            .headers()
+            .map_err(file_error)?
+            .iter()
+            .skip(1)
+            .map(str::to_string)
+            .collect();
+        let mut rows = Vec::new();
+        let mut encrypted = HashSet::new();
+        for result in rdr.records() {
+            let record = result.map_err(file_error)?;
+            // An existing table keeps its own datatypes.
+            if &record[0] == "datatypes" && !created {
+                continue;
+            }
+            let mut data = HashMap::new();
+            for (column, field) in columns.iter().zip(record.iter().skip(1)) {
+                if crypto::is_encrypted(field) {
+                    encrypted.insert(column.clone());
+                }
+                data.insert(column.clone(), self.open_cell(field));
+            }
+            rows.push((record[0].to_string(), data));
+        }
+        self.check_bulk_rows(table_name, &rows)?;
+        let count = rows.iter().filter(|(id, _)| id != "datatypes").count();
+
+        let table = self
+            .tables
+            .entry(table_name.to_string())
+            .or_default();
+        if created {
+            for column in &columns {
+                table.add_column(column);
+            }
+            table.encrypted_columns = encrypted;
+        }
+        for (row_id, data) in rows {
+            table.insert_row(&row_id, data);
+        }
+        if created {
+            if let Err(e) = self.type_imported_columns(table_name, &HashMap::new()) {
+                self.tables.remove(table_name);
+                return Err(e);
+            }
+        }
+        let op = format!("bulk_load:{}:{}", table_name, source);
+        self.log_op(op);
+        self.writes += 1;
+        if !self.is_temp_table(table_name) {
+            self.save_table(table_name, &self.config.table_path(table_name))?;
+            self.unsaved.remove(table_name);
+            self.sync_catalog(table_name)?;
+        }
+        if self.indexer.is_some() {
+            self.build_indexes();
+        }
+        if self.bloom_filter.is_some() {
+            self.build_bloom_filter();
+        }
+        info!(
+            "Bulk loaded {} rows into '{}' from '{}'.",
+            count, table_name, source
+        );
+        Ok(count)
+    }
+
+    /// Stream rows from `reader` (see `IngestFormat`) into an existing table in
+    /// chunks of `ingest_chunk_rows`, so the source never has to be held in
+    /// memory at once. Each chunk is checked like a `bulk_load`, appended to
+    /// the table file, added to the table and logged as one `copy_from` WAL
+    /// record; `progress` is then called with the totals so far. A bad row
+    /// stops the copy, keeping the chunks before it. The index and bloom
+    /// filter are rebuilt once at the end. Returns the final totals.
+    #[cfg(feature = "import")]
+    #[instrument(skip(self, reader, progress))]
+    pub fn copy_from<R: Read>(
+        &mut self,
+        table_name: &str,
+        reader: R,
+        format: IngestFormat,
+        mut progress: impl FnMut(&IngestProgress),
+    ) -> Result<IngestProgress> {
+        self.check_writable()?;
+        self.ensure_table_loaded(table_name)?;
+        let mut rows = RowReader::new(reader, format)?;
+        // Start from a file matching the table, so chunks can be appended to it.
+        self.save_table(table_name, &self.config.table_path(table_name))?;
+        self.unsaved.remove(table_name);
+        let chunk_rows = self.config.ingest_chunk_rows.max(1);
+        let mut done = IngestProgress::default();
+        loop {
+            let chunk: Vec<ingest::Row> = rows.by_ref().take(chunk_rows).collect::<Result<_>>()?;
+            if chunk.is_empty() {
+                break;
+            }
+            self.check_wal_backlog()?;
+            self.check_bulk_rows(table_name, &chunk)?;
+            let count = chunk.len();
+            self.append_rows(table_name, chunk)?;
+            let op = format!("copy_from:{}:{}", table_name, count);
+            self.log_op(op);
+            self.writes += 1;
+            done.rows += count;
+            done.chunks += 1;
+            debug!(
+                "Copied chunk {} ({} rows) into '{}'.",
+                done.chunks, count, table_name
+            );
+            progress(&done);
+        }
+        if self.indexer.is_some() {
+            self.build_indexes();
+        }
+        if self.bloom_filter.is_some() {
+            self.build_bloom_filter();
+        }
+        info!(
+            "Copied {} rows into '{}' from a {} stream.",
+            done.rows, table_name, format
+        );
+        Ok(done)
+    }
+
+    /// Insert `value` as a row with one column per field (see
+    /// `mapping::to_row`). Fails if `T` has fields the table has no column for.
+    pub fn insert_struct<T: Serialize>(
+        &mut self,
+        table_name: &str,
+        row_id: &str,
+        value: &T,
+    ) -> Result<QueryResult> {
+        self.ensure_table_loaded(table_name)?;
+        let row = mapping::to_row(value).map_err(DatabaseError::RowMapping)?;
+        let table = &self.tables[table_name];
+        let mut extra: Vec<&str> = row
+            .keys()
+            .filter(|field| !table.columns.contains(*field))
+            .map(String::as_str)
+            .collect();
+        if !extra.is_empty() {
+            extra.sort();
+            return Err(DatabaseError::RowMapping(format!(
+                "{} has fields with no column in table '{}': {}",
+                mapping::type_name::<T>(),
+                table_name,
+                extra.join(", ")
+            )));
+        }
+        self.insert_row(table_name, row_id, row)
+    }
+
+    /// Rows of a table read as `T`, with their ids, optionally only those
+    /// matching `condition` ("column operator value"). Fails if a field of `T`
+    /// has no column, or if a value doesn't parse as its field's type.
+    pub fn query_as<T: DeserializeOwned>(
+        &mut self,
+        table_name: &str,
+        condition: Option<&str>,
+    ) -> Result<Vec<(String, T)>> {
+        self.check_struct_columns::<T>(table_name)?;
+        let condition = condition
+            .map(|c| self.parse_condition(table_name, c))
+            .transpose()?;
+        self.tables[table_name]
+            .rows
+            .iter()
+            .filter(|(row_id, _)| row_id.as_str() != "datatypes")
+            .filter(|(_, row)| {
+                condition
+                    .as_ref()
+                    .is_none_or(|c| c.matches(row, &self.config.match_options))
+            })
+            .map(|(row_id, row)| {
+                let value = mapping::from_row(row).map_err(|e| {
+                    DatabaseError::RowMapping(format!(
+                        "row '{}' of '{}' as {}: {}",
+                        row_id,
+                        table_name,
+                        mapping::type_name::<T>(),
+                        e
+                    ))
+                })?;
+                Ok((row_id.clone(), value))
+            })
+            .collect()
+    }
+
+    /// Check that every field of the struct `T` has a column in the table,
+    /// loading the table if needed. Types that don't deserialize as a struct
+    /// are not checked.
+    pub fn check_struct_columns<T: DeserializeOwned>(&mut self, table_name: &str) -> Result<()> {
+        self.ensure_table_loaded(table_name)?;
+        let table = &self.tables[table_name];
+        let Some(fields) = mapping::struct_fields::<T>() else {
+            return Ok(());
+        };
+        let missing: Vec<&str> = fields
+            .iter()
+            .copied()
+            .filter(|field| !table.columns.contains(*field))
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(DatabaseError::RowMapping(format!(
+            "table '{}' has no column for {} fields: {}",
+            table_name,
+            mapping::type_name::<T>(),
+            missing.join(", ")
+        )))
+    }
+
+    /// Check rows about to be added in bulk before any of them is: their ids
+    /// against the table and each other, the row size and count limits, and,
+    /// when the table exists, their columns and datatypes. Empty values are
+    /// missing and match any datatype.
+    #[cfg(feature = "import")]
+    fn check_bulk_rows(&self, table_name: &str, rows: &[ingest::Row]) -> Result<()> {
+        let table = self.tables.get(table_name);
+        let mut seen = HashSet::new();
+        for (row_id, data) in rows {
+            if table.is_some_and(|t| t.rows.contains_key(row_id)) || !seen.insert(row_id.as_str()) {
+                return Err(DatabaseError::RowAlreadyExists(
+                    row_id.clone(),
+                    table_name.to_string(),
+                ));
+            }
+            self.check_row_size(table_name, row_id, data)?;
+            self.check_enum_values(table_name, row_id, data)?;
+            let Some(table) = table else {
+                continue;
+            };
+            if let Some(column) = data.keys().find(|c| !table.columns.contains(*c)) {
+                return Err(DatabaseError::ColumnDoesNotExist(
+                    column.clone(),
+                    table_name.to_string(),
+                ));
+            }
+            let mismatch = data.iter().find(|(column, value)| {
+                !value.is_empty()
+                    && table
+                        .row_datatypes
+                        .get(*column)
+                        .is_some_and(|dt| !Self::check_value_matches(value, dt))
+            });
+            if let Some((column, value)) = mismatch {
+                error!(
+                    "Value '{}' in row '{}' does not match the datatype of column '{}'.",
+                    value, row_id, column
+                );
+                return Err(DatabaseError::DataTypeError);
+            }
+        }
+        let count = rows.iter().filter(|(id, _)| id != "datatypes").count();
+        if let Some(max) = self.config.max_rows_for(table_name) {
+            if table.map_or(0, |t| t.rows.len()) + count > max {
+                error!(
+                    "Bulk write rejected: {} more rows would exceed the limit of {}.",
+                    count, max
+                );
+                return Err(DatabaseError::TableFull(table_name.to_string(), max));
+            }
+        }
+        Ok(())
+    }
+
+    /// Append new rows to the table file, which must already have the table's
+    /// header, and then add them to the table.
+    #[cfg(feature = "import")]
+    fn append_rows(&mut self, table_name: &str, rows: Vec<ingest::Row>) -> Result<()> {
+        let file_name = self.config.table_path(table_name);
+        let file_error = |e: String| DatabaseError::FileCreationError(file_name.clone(), e);
+        let table = self.get_table(table_name)?;
+        let cols: Vec<_> = table.columns.iter().cloned().collect();
+        let mut wtr = WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(Vec::new());
+        for (row_id, row_data) in &rows {
+            let mut rec = vec![row_id.clone()];
+            rec.extend(cols.iter().map(|c| {
+                let value = row_data.get(c).cloned().unwrap_or_default();
+                Self::store_cell(&self.cipher, Some(&self.blobs), table, c, &value)
+            }));
+            wtr.write_record(&rec)
+                .map_err(|e| file_error(e.to_string()))?;
+        }
+        if !self.is_temp_table(table_name) {
+            self.append_records(wtr, &file_name)?;
+        }
+        let table = self
+            .tables
+            .get_mut(table_name)
+            .ok_or_else(|| DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+        for (row_id, data) in rows {
+            table.insert_row(&row_id, data);
+        }
+        Ok(())
+    }
+
+    /// Set the datatypes of a freshly imported table and record them in its
+    /// `datatypes` row, so they are saved with it.
+    #[cfg(feature = "import")]
+    fn type_imported_columns(
+        &mut self,
+        table_name: &str,
+        overrides: &HashMap<String, String>,
+    ) -> Result<()> {
+        if overrides.values().any(|dt| !self.datatypes.contains(dt)) {
+            error!("Invalid datatypes provided.");
+            return Err(DatabaseError::InvalidDataType);
+        }
+        let sample = self.config.import_sample_rows;
+        let table = self
+            .tables
+            .get_mut(table_name)
+            .ok_or_else(|| DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+        if let Some(column) = overrides.keys().find(|c| !table.columns.contains(*c)) {
+            return Err(DatabaseError::ColumnDoesNotExist(
+                column.clone(),
+                table_name.to_string(),
+            ));
+        }
+        let declared = table.rows.get("datatypes").cloned();
+        let columns: Vec<String> = table.columns.iter().cloned().collect();
+        for column in columns {
+            // Empty cells are missing values and match any type.
+            let values = || {
+                table
+                    .rows
+                    .iter()
+                    .filter(|(row_id, _)| row_id.as_str() != "datatypes")
+                    .filter_map(|(row_id, row)| Some((row_id, row.get(&column)?)))
+                    .filter(|(_, value)| !value.is_empty())
+            };
+            let declared = declared.as_ref().and_then(|row| row.get(&column));
+            let datatype = match overrides.get(&column).or(declared) {
+                Some(datatype) if !datatype.is_empty() => datatype.clone(),
+                // Ciphertext says nothing about the type of the value.
+                _ if declared.is_some() || table.encrypted_columns.contains(&column) => continue,
+                _ => {
+                    let sampled: Vec<&str> =
+                        values().take(sample).map(|(_, v)| v.as_str()).collect();
+                    match Self::infer_datatype(&sampled) {
+                        Some(datatype) => datatype.to_string(),
+                        None => continue,
+                    }
+                }
+            };
+            if let Some((row_id, value)) =
+                values().find(|(_, v)| !Self::check_value_matches(v, &datatype))
+            {
+                error!(
+                    "Value '{}' in row '{}' does not match datatype '{}' for column '{}'.",
+                    value, row_id, datatype, column
+                );
+                return Err(DatabaseError::DataTypeError);
+            }
+            debug!(
+                "Column '{}' of '{}' typed as {}.",
+                column, table_name, datatype
+            );
+            table.add_datatype(&column, &datatype);
+        }
+        if !table.row_datatypes.is_empty() {
+            let row = table.row_datatypes.clone();
+            table.rows.insert("datatypes".to_string(), row);
+        }
+        Ok(())
+    }
+
+    /// The narrowest datatype every sampled value fits, or `None` without
+    /// values. Whole numbers are `int` rather than `float`, and anything
+    /// that fits nothing narrower is a `string`.
+    #[cfg(feature = "import")]
+    fn infer_datatype(values: &[&str]) -> Option<&'static str> {
+        if values.is_empty() {
+            return None;
+        }
+        let datatype = ["int", "float", "bool", "date"]
+            .into_iter()
+            .find(|dt| values.iter().all(|v| Self::check_value_matches(v, dt)));
+        Some(datatype.unwrap_or("string"))
+    }
+
+    /// Create `dst_table` with the schema of `src_table` and a copy of its rows,
+    /// optionally only those matching `condition` ("column operator value").
+    /// The copy is logged as one `copy_table` WAL record and the new table is
+    /// saved right away. Returns the number of rows copied.
+    pub fn copy_table(
+        &mut self,
+        src_table: &str,
+        dst_table: &str,
+        condition: Option<&str>,
+    ) -> Result<usize> {
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        self.ensure_table_loaded(src_table)?;
+        // The copy is saved, so replaying its record needs the source.
+        self.check_persistent(src_table)?;
+        if self.check_table(dst_table) {
+            error!("Table '{}' already exists.", dst_table);
+            return Err(DatabaseError::TableAlreadyExists(dst_table.to_string()));
+        }
+        self.check_table_count()?;
+        let filter = condition
+            .map(|c| self.parse_condition(src_table, c))
+            .transpose()?;
+
+        let src = &self.tables[src_table];
+        let mut copy = Table::new();
+        copy.columns = src.columns.clone();
+        copy.row_datatypes = src.row_datatypes.clone();
+        copy.encrypted_columns = src.encrypted_columns.clone();
+        copy.collations = src.collations.clone();
+        copy.row_ids = RowIdGenerator::new(src.row_ids.strategy);
+        copy.autosave = src.autosave;
+        for (row_id, row) in &src.rows {
+            let matches = filter
+                .as_ref()
+                .is_none_or(|c| c.matches(row, &self.config.match_options));
+            if matches {
+                copy.rows.insert(row_id.clone(), row.clone());
+            }
+        }
+        if let Some(max) = self.config.max_rows_for(dst_table) {
+            if copy.rows.len() > max {
+                error!(
+                    "Copy rejected: {} rows exceeds the limit of {}.",
+                    copy.rows.len(),
+                    max
+                );
+                return Err(DatabaseError::TableFull(dst_table.to_string(), max));
+            }
+        }
+        for (row_id, row) in &copy.rows {
+            self.check_row_size(dst_table, row_id, row)?;
+        }
+
+        let count = copy.rows.len();
+        let row_ids: Vec<&String> = copy.rows.keys().collect();
+        let op = format!(
+            "copy_table:{}:{}:{}",
+            src_table,
+            dst_table,
+            serde_json::json!({ "rows": row_ids })
+        );
+        self.tables.insert(dst_table.to_string(), copy);
+        self.log_op(op);
+        self.writes += 1;
+        self.save_table(dst_table, &self.config.table_path(dst_table))?;
+        self.sync_catalog(dst_table)?;
+        info!(
+            "Copied {} rows from table '{}' into '{}'.",
+            count, src_table, dst_table
+        );
+        Ok(count)
+    }
+
+    /// Append the rows of `src_table` to `dst_table`, adding any columns the
+    /// destination lacks. Row id collisions are handled by `policy`. Logged as
+    /// one `merge_tables` WAL record; the destination is saved once the save
+    /// threshold is reached.
+    pub fn merge_tables(
+        &mut self,
+        src_table: &str,
+        dst_table: &str,
+        policy: ConflictPolicy,
+    ) -> Result<MergeReport> {
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        self.ensure_table_loaded(src_table)?;
+        self.ensure_table_loaded(dst_table)?;
+        if !self.is_temp_table(dst_table) {
+            self.check_persistent(src_table)?;
+        }
+        let src = &self.tables[src_table];
+        let dst = &self.tables[dst_table];
+
+        let mut report = MergeReport {
+            source: src_table.to_string(),
+            destination: dst_table.to_string(),
+            ..Default::default()
+        };
+        let mut rows = Vec::new();
+        for (row_id, row) in &src.rows {
+            if dst.rows.contains_key(row_id) {
+                match policy {
+                    ConflictPolicy::Skip => {
+                        report.skipped += 1;
+                        continue;
+                    }
+                    ConflictPolicy::Overwrite => report.overwritten += 1,
+                    ConflictPolicy::Error => {
+                        error!("Merge rejected: row '{}' is in both tables.", row_id);
+                        return Err(DatabaseError::RowAlreadyExists(
+                            row_id.clone(),
+                            dst_table.to_string(),
+                        ));
+                    }
+                }
+            } else {
+                report.added += 1;
+            }
+            self.check_row_size(dst_table, row_id, row)?;
+            rows.push((row_id.clone(), row.clone()));
+        }
+        if let Some(max) = self.config.max_rows_for(dst_table) {
+            if dst.rows.len() + report.added > max {
+                error!(
+                    "Merge rejected: {} rows would exceed the limit of {}.",
+                    dst.rows.len() + report.added,
+                    max
+                );
+                return Err(DatabaseError::TableFull(dst_table.to_string(), max));
+            }
+        }
+
+        let src = src.clone();
+        let row_ids: Vec<&String> = rows.iter().map(|(row_id, _)| row_id).collect();
+        let op = format!(
+            "merge_tables:{}:{}:{}",
+            src_table,
+            dst_table,
+            serde_json::json!({ "rows": row_ids })
+        );
+        let written: Vec<String> = rows.iter().map(|(row_id, _)| row_id.clone()).collect();
+        let dst = self.tables.get_mut(dst_table).unwrap();
+        Self::merge_schema(dst, &src);
+        dst.rows.extend(rows);
+        self.log_op(op);
+        self.writes += 1;
+        debug!("{}", report);
+
+        self.record_write(dst_table, written.iter().map(String::as_str), false);
+        self.sync_catalog(dst_table)?;
+        Ok(report)
+    }
+
+    /// Add the columns, datatypes, collations and encryption flags of `src` that `dst` lacks.
+    fn merge_schema(dst: &mut Table, src: &Table) {
+        dst.columns.extend(src.columns.iter().cloned());
+        dst.encrypted_columns
+            .extend(src.encrypted_columns.iter().cloned());
+        for (column, collation) in &src.collations {
+            dst.collations.entry(column.clone()).or_insert(*collation);
+        }
+        for (column, datatype) in &src.row_datatypes {
+            dst.row_datatypes
+                .entry(column.clone())
+                .or_insert_with(|| datatype.clone());
+        }
+    }
+
+    /// Remove a table from memory and delete its file, logging it to the WAL.
+    pub fn drop_table(&mut self, table_name: &str) -> Result<()> {
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        self.check_unprepared(table_name, None)?;
+        let file_name = self.config.table_path(table_name);
+        let on_disk = self.storage.exists(&file_name);
+        let cataloged = self.catalog.contains(table_name);
+        if self.tables.remove(table_name).is_none() && !on_disk && !cataloged {
+            return Err(DatabaseError::TableDoesNotExist(table_name.to_string()));
+        }
+        self.remove_with_sidecar(&file_name)?;
+        self.remove_delta(table_name)?;
+        self.remove_cold_segment(table_name)?;
+        self.unsaved.remove(table_name);
+        self.table_indexes.retain(|(t, _), _| t != table_name);
+        self.sketches.retain(|(t, _), _| t != table_name);
+        self.backfills.retain(|b| b.table != table_name);
+        let op = format!("drop_table:{}", table_name);
+        self.log_op(op);
+        self.writes += 1;
+        self.sync_catalog(table_name)?;
+        self.temp_tables.remove(table_name);
+        info!("Table '{}' dropped.", table_name);
+        Ok(())
+    }
+
+    /// Rename a table in memory and on disk and log it to the WAL. The file is
+    /// renamed first, so a failure leaves the table untouched under its old name.
+    pub fn rename_table(&mut self, old_name: &str, new_name: &str) -> Result<()> {
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        self.check_unprepared(old_name, None)?;
+        self.ensure_table_loaded(old_name)?;
+        self.check_persistent(old_name)?;
+        let new_file = self.config.table_path(new_name);
+        if self.check_table(new_name)
+            || self.catalog.contains(new_name)
+            || self.storage.exists(&new_file)
+        {
+            error!("Table '{}' already exists.", new_name);
+            return Err(DatabaseError::TableAlreadyExists(new_name.to_string()));
+        }
+        let old_file = self.config.table_path(old_name);
+        self.rename_with_sidecar(&old_file, &new_file)?;
+        self.rename_with_sidecar(
+            &self.config.delta_path(old_name),
+            &self.config.delta_path(new_name),
+        )?;
+        if self.cold.contains_key(old_name) {
+            let (old_cold, new_cold) = (self.config.cold_path(old_name), self.config.cold_path(new_name));
+            let segment = self
+                .storage
+                .rename(&old_cold, &new_cold)
+                .and_then(|_| ColdSegment::open(self.storage.as_ref(), &new_cold))
+                .map_err(|e| DatabaseError::FileCreationError(new_cold.clone(), e.to_string()))?;
+            self.cold.remove(old_name);
+            self.cold.insert(new_name.to_string(), segment);
+        }
+        if let Some(table) = self.tables.remove(old_name) {
+            self.tables.insert(new_name.to_string(), table);
+        }
+        if let Some(unsaved) = self.unsaved.remove(old_name) {
+            self.unsaved.insert(new_name.to_string(), unsaved);
+        }
+        Self::rename_table_indexes(&mut self.table_indexes, old_name, new_name);
+        self.sketches.retain(|(t, _), _| t != old_name);
+        Self::rename_backfills(&mut self.backfills, old_name, new_name);
+        let op = format!("rename_table:{}:{}", old_name, new_name);
+        self.log_op(op);
+        self.writes += 1;
+        if let Some(entry) = self.catalog.tables.remove(old_name) {
+            self.catalog.tables.insert(new_name.to_string(), entry);
+        }
+        self.sync_catalog(new_name)?;
+        info!("Table '{}' renamed to '{}'.", old_name, new_name);
+        Ok(())
+    }
+
+    /// Move the pending backfills of table `old_name` over to `new_name`.
+    fn rename_backfills(backfills: &mut [BackfillProgress], old_name: &str, new_name: &str) {
+        for progress in backfills.iter_mut().filter(|b| b.table == old_name) {
+            progress.table = new_name.to_string();
+        }
+    }
+
+    /// Move the indexes of table `old_name` over to `new_name`.
+    fn rename_table_indexes(
+        indexes: &mut BTreeMap<(String, String), TableIndex>,
+        old_name: &str,
+        new_name: &str,
+    ) {
+        let columns: Vec<String> = indexes
+            .keys()
+            .filter(|(t, _)| t == old_name)
+            .map(|(_, c)| c.clone())
+            .collect();
+        for column in columns {
+            if let Some(index) = indexes.remove(&(old_name.to_string(), column.clone())) {
+                indexes.insert((new_name.to_string(), column), index);
+            }
+        }
+    }
+
+    /// Snapshot every table as of the last operation logged, to be written
+    /// with `BackupSnapshot::write` after the lock is released.
+    pub fn backup_snapshot(&self) -> Result<backup::BackupSnapshot<'static>> {
+        let snapshot = self.borrowed_snapshot()?;
+        Ok(backup::BackupSnapshot {
+            tables: Cow::Owned(snapshot.tables.into_owned()),
+            ..snapshot
+        })
+    }
+
+    /// Every table of the catalog, less any temporary ones, with the rows of
+    /// its cold segment. Tables not in memory are read from their files,
+    /// without loading them.
+    fn persistent_tables(&self) -> Result<Cow<'_, HashMap<String, Table>>> {
+        let unloaded: Vec<&String> = self
+            .catalog
+            .tables
+            .keys()
+            .filter(|table_name| !self.tables.contains_key(*table_name))
+            .collect();
+        if self.temp_tables.is_empty() && unloaded.is_empty() && self.cold.is_empty() {
+            return Ok(Cow::Borrowed(&self.tables));
+        }
+        let mut tables = HashMap::new();
+        for (table_name, table) in &self.tables {
+            if self.is_temp_table(table_name) {
+                continue;
+            }
+            let mut table = table.clone();
+            if let Some(segment) = self.cold.get(table_name) {
+                self.add_cold_rows(segment, &mut table)?;
+            }
+            tables.insert(table_name.clone(), table);
+        }
+        for table_name in unloaded {
+            tables.insert(table_name.clone(), self.stored_table(table_name)?);
+        }
+        Ok(Cow::Owned(tables))
+    }
+
+    /// A table as its file, delta file and cold segment hold it, or empty
+    /// from its catalog entry if it has no file yet.
+    fn stored_table(&self, table_name: &str) -> Result<Table> {
+        let file_name = self.config.table_path(table_name);
+        if !self.storage.exists(&file_name) {
+            return self
+                .catalog
+                .get(table_name)
+                .map(CatalogEntry::empty_table)
+                .ok_or(DatabaseError::TableDoesNotExist(table_name.to_string()));
+        }
+        let mut report = LoadReport {
+            table: table_name.to_string(),
+            ..LoadReport::default()
+        };
+        let mut table = Table::new();
+        self.read_rows_into(&mut table, &file_name, &mut report)?;
+        let delta_file = self.config.delta_path(table_name);
+        if self.storage.exists(&delta_file) {
+            self.read_rows_into(&mut table, &delta_file, &mut report)?;
+        }
+        for p in &report.problems {
+            warn!("{:?} at {}: {}", p.kind, p.location, p.detail);
+        }
+        let cold_file = self.config.cold_path(table_name);
+        if self.storage.exists(&cold_file) {
+            let segment = ColdSegment::open(self.storage.as_ref(), &cold_file)
+                .map_err(|e| DatabaseError::FileCreationError(cold_file.clone(), e.to_string()))?;
+            self.add_cold_rows(&segment, &mut table)?;
+        }
+        Ok(table)
+    }
+
+    /// Add the rows of a cold segment that `table` does not hold; a row in
+    /// memory is newer than its cold copy.
+    fn add_cold_rows(&self, segment: &ColdSegment, table: &mut Table) -> Result<()> {
+        segment
+            .visit(self.storage.as_ref(), None, |row_id, row| {
+                if !table.rows.contains_key(row_id) {
+                    table.rows.insert(row_id.to_string(), self.open_row(row));
+                }
+                Ok(true)
+            })
+            .map_err(|e| DatabaseError::FileCreationError(segment.path().to_string(), e.to_string()))
+    }
+
+    fn borrowed_snapshot(&self) -> Result<backup::BackupSnapshot<'_>> {
+        Ok(backup::BackupSnapshot {
+            tables: self.persistent_tables()?,
+            lsn: self.next_lsn - 1,
+            millis: clock::unix_millis(),
+            backfills: self.backfills.clone(),
+            cipher: self.cipher.clone(),
+            storage: self.storage.clone(),
+            wal_files: vec![self.wal_file.clone(), self.config.wal_archive_path()],
+            catalog: self.config.backup_catalog_path(),
+        })
+    }
+
+    /// Save every table plus the WAL files into `dir`. Returns the paths written.
+    /// Tables not in memory are copied as their files hold them.
+    /// `DbHandle::hot_backup` does the same without blocking writes meanwhile.
+    pub fn backup(&self, dir: &str) -> Result<Vec<String>> {
+        let written = self.borrowed_snapshot()?.write(dir)?;
+        // The backup may have made older archive segments redundant.
+        if let Err(e) = self.prune_archive() {
+            warn!("Pruning the WAL archive after a backup failed: {}", e);
+        }
+        Ok(written)
+    }
+
+    /// The archive's numbered segments, oldest first, then the archive itself.
+    fn archive_files(&self) -> Vec<String> {
+        let mut files: Vec<String> = self
+            .config
+            .archive_segments(self.storage.as_ref())
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect();
+        files.push(self.config.wal_archive_path());
+        files
+    }
+
+    /// Move the WAL archive into the next numbered segment once it reaches
+    /// `archive.segment_bytes` or its oldest record `archive.segment_age_secs`.
+    /// Returns the segment written, if any.
+    pub fn rotate_archive(&self) -> Result<Option<String>> {
+        let retention = &self.config.archive;
+        let archive_file = self.config.wal_archive_path();
+        let size = self.storage.size(&archive_file).unwrap_or(0);
+        if size == 0 {
+            return Ok(None);
+        }
+        let full = retention.segment_bytes.is_some_and(|max| size >= max);
+        let old = retention.segment_age_secs.is_some_and(|secs| {
+            self.storage
+                .open(&archive_file)
+                .ok()
+                .and_then(|file| BufReader::new(file).lines().next()?.ok())
+                .and_then(|line| checksum::unframe_stamped(&line).0)
+                .is_some_and(|stamp| {
+                    clock::unix_millis().saturating_sub(stamp.millis) >= secs * 1000
+                })
+        });
+        if !full && !old {
+            return Ok(None);
+        }
+        let next = self
+            .config
+            .archive_segments(self.storage.as_ref())
+            .last()
+            .map_or(1, |(n, _)| n + 1);
+        let segment = self.config.archive_segment_path(next);
+        self.storage
+            .rename(&archive_file, &segment)
+            .map_err(|e| DatabaseError::FileCreationError(segment.clone(), e.to_string()))?;
+        info!("WAL archive rotated into '{}'.", segment);
+        Ok(Some(segment))
+    }
+
+    /// Delete the archive segments the retention policy no longer keeps: those
+    /// whose newest record is older than `archive.keep_days`, then the oldest
+    /// while all segments exceed `archive.keep_bytes`. Only segments the latest
+    /// backup already holds are deleted, so it can always be rolled forward
+    /// with `recover_to`. Returns the segments deleted.
+    pub fn prune_archive(&self) -> Result<Vec<String>> {
+        let retention = &self.config.archive;
+        if retention.keep_days.is_none() && retention.keep_bytes.is_none() {
+            return Ok(Vec::new());
+        }
+        let Some(backup::BackupEntry {
+            lsn: backup_lsn, ..
+        }) = self.backup_before(u64::MAX)
+        else {
+            return Ok(Vec::new());
+        };
+        let segments: Vec<(String, u64)> = self
+            .config
+            .archive_segments(self.storage.as_ref())
+            .into_iter()
+            .map(|(_, path)| {
+                let size = self.storage.size(&path).unwrap_or(0);
+                (path, size)
+            })
+            .collect();
+        let mut total: u64 = segments.iter().map(|(_, size)| size).sum();
+        let cutoff = retention
+            .keep_days
+            .map(|days| clock::unix_millis().saturating_sub(days * 24 * 60 * 60 * 1000));
+        let mut pruned = Vec::new();
+        for (path, size) in segments {
+            let records = self
+                .storage
+                .read_to_string(&path)
+                .map_err(|e| DatabaseError::FileCreationError(path.clone(), e.to_string()))?;
+            let newest = records
+                .lines()
+                .filter_map(|line| checksum::unframe_stamped(line).0)
+                .max();
+            // Unstamped segments predate backups that record their LSN.
+            let backed_up = newest.is_none_or(|stamp| stamp.lsn <= backup_lsn);
+            let expired = match (cutoff, newest) {
+                (Some(cutoff), Some(stamp)) => stamp.millis < cutoff,
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+            let over = retention.keep_bytes.is_some_and(|max| total > max);
+            if !backed_up || !(expired || over) {
+                break;
+            }
+            self.storage
+                .remove(&path)
+                .map_err(|e| DatabaseError::FileCreationError(path.clone(), e.to_string()))?;
+            total -= size;
+            pruned.push(path);
+        }
+        if !pruned.is_empty() {
+            info!("Pruned {} WAL archive segments.", pruned.len());
+        }
+        Ok(pruned)
+    }
+
+    /// The latest backup in the catalog taken at or before `millis`.
+    fn backup_before(&self, millis: u64) -> Option<backup::BackupEntry> {
+        backup::read_catalog(self.storage.as_ref(), &self.config.backup_catalog_path())
+            .into_iter()
+            .filter(|entry| entry.millis <= millis)
+            .max_by_key(|entry| (entry.millis, entry.lsn))
+    }
+
+    /// Restore the database as it was at `millis` (Unix milliseconds), e.g.
+    /// just before an accidental bulk delete: load the latest backup taken at
+    /// or before then and replay the archived WAL records logged after the
+    /// backup, up to `millis`. The result replaces the files of the tables in
+    /// the backup; tables it does not hold are kept unless a record replayed
+    /// dropped them.
+    ///
+    /// The WAL is committed first, so the records undone stay in the archive.
+    /// Records written before WAL records were stamped are not replayed.
+    #[instrument(skip(self))]
+    pub fn recover_to(&mut self, millis: u64) -> Result<RecoveryReport> {
+        self.check_writable()?;
+        self.commit_wal()?;
+        let backup::BackupEntry {
+            lsn: backup_lsn,
+            dir: backup,
+            tables: backed_up,
+            backfills,
+            ..
+        } = self
+            .backup_before(millis)
+            .ok_or(DatabaseError::NoBackup(millis))?;
+
+        let current = std::mem::take(&mut self.tables);
+        let running = std::mem::replace(&mut self.backfills, backfills);
+        for table_name in &backed_up {
+            let file = Path::new(&backup)
+                .join(format!("{}.csv", table_name))
+                .to_string_lossy()
+                .into_owned();
+            if let Err(e) = self.load_table_from_file(table_name, &file) {
+                self.tables = current;
+                self.backfills = running;
+                return Err(e);
+            }
+        }
+
+        let mut archive = String::new();
+        for archive_file in self.archive_files() {
+            match self.storage.read_to_string(&archive_file) {
+                Ok(records) => archive.push_str(&records),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    self.tables = current;
+                    self.backfills = running;
+                    return Err(DatabaseError::FileCreationError(
+                        archive_file,
+                        e.to_string(),
+                    ));
+                }
+            }
+        }
+        let mut records_replayed = 0;
+        let mut recovered_lsn = backup_lsn;
+        // Tables dropped by the records replayed; no others are removed.
+        let mut dropped = BTreeSet::new();
+        for line in archive.lines() {
+            let (stamp, record, valid) = checksum::unframe_stamped(line);
+            let Some(stamp) = stamp else { continue };
+            if stamp.lsn <= backup_lsn || stamp.millis > millis {
+                continue;
+            }
+            if valid == Some(false) {
+                warn!(
+                    "Skipping archived WAL record {} with a bad checksum.",
+                    stamp.lsn
+                );
+                continue;
+            }
+            let entry = self.open_cell(record);
+            // Tables made after the backup are not in it.
+            if let Some(table_name) = entry.strip_prefix("create_table:") {
+                self.tables
+                    .entry(table_name.to_string())
+                    .or_default();
+                dropped.remove(table_name);
+            } else if let Some(table_name) = entry.strip_prefix("drop_table:") {
+                dropped.insert(table_name.to_string());
+            } else if let Some(names) = entry.strip_prefix("rename_table:") {
+                if let Some((old_name, new_name)) = names.split_once(':') {
+                    dropped.insert(old_name.to_string());
+                    dropped.remove(new_name);
+                }
+            }
+            self.apply_wal_entries(std::slice::from_ref(&entry));
+            records_replayed += 1;
+            recovered_lsn = recovered_lsn.max(stamp.lsn);
+        }
+
+        // The backup holds the cold rows of the tables it restores.
+        let restored: Vec<String> = self.tables.keys().cloned().collect();
+        for table_name in restored.iter().chain(&dropped) {
+            self.remove_cold_segment(table_name)?;
+        }
+        // Tables in no backup and not dropped since are kept as they are.
+        for (table_name, table) in current {
+            if !self.tables.contains_key(&table_name)
+                && !dropped.contains(&table_name)
+                && !self.is_temp_table(&table_name)
+            {
+                self.tables.insert(table_name, table);
+            }
+        }
+        // Temporary tables are in no backup, so recovery drops them.
+        self.temp_tables.clear();
+        // Records logged since are undone rather than waiting to be replayed.
+        let lsn = self.next_lsn - 1;
+        self.applied_lsn = self.tables.keys().map(|t| (t.clone(), lsn)).collect();
+        for table_name in &dropped {
+            if self.tables.contains_key(table_name) {
+                continue;
+            }
+            self.remove_with_sidecar(&self.config.table_path(table_name))?;
+            self.remove_delta(table_name)?;
+            self.catalog.tables.remove(table_name);
+        }
+        let tables = self.list_tables();
+        for table_name in &tables {
+            self.save_table(table_name, &self.config.table_path(table_name))?;
+            let file = DatabaseConfig::table_file(table_name);
+            let entry = CatalogEntry::describe(&self.tables[table_name], &file);
+            self.catalog.tables.insert(table_name.clone(), entry);
+        }
+        self.save_catalog()?;
+        self.unsaved.clear();
+        self.table_indexes
+            .retain(|(t, _), _| self.tables.contains_key(t));
+        self.backfills
+            .retain(|b| self.tables.contains_key(&b.table));
+        // Backfills still running carry on from the recovered tables.
+        for progress in self.backfills.clone() {
+            self.log_backfill(&progress);
+        }
+        self.writes += 1;
+        info!(
+            "Recovered to {} from backup '{}': {} WAL records replayed.",
+            millis, backup, records_replayed
+        );
+        Ok(RecoveryReport {
+            backup,
+            backup_lsn,
+            records_replayed,
+            recovered_lsn,
+            tables,
+        })
+    }
+
+    /// Report table sizes, file sizes, WAL backlog and how many writes the
+    /// index and bloom filter are behind.
+    pub fn stats(&self) -> DatabaseStats {
+        let file_size = |path: &str| self.storage.size(path).ok();
+        let tables = self
+            .list_tables()
+            .into_iter()
+            .map(|name| {
+                let table = &self.tables[&name];
+                TableStats {
+                    rows: table.rows.len(),
+                    cold_rows: self.cold_rows(&name),
+                    columns: table.columns.len(),
+                    memory_bytes: table.approx_size(),
+                    file_bytes: file_size(&self.config.table_path(&name)),
+                    name,
+                }
+            })
+            .collect();
+        DatabaseStats {
+            tables,
+            wal_backlog: self.wal.len(),
+            wal_file_bytes: file_size(&self.wal_file),
+            wal_archive_bytes: file_size(&self.config.wal_archive_path()),
+            index_file_bytes: file_size(&self.config.indexer_path()),
+            bloom_file_bytes: file_size(&self.config.bloom_filter_path()),
+            index_stale_writes: self.index_built_at.map(|at| self.writes - at),
+            bloom_stale_writes: self.bloom_built_at.map(|at| self.writes - at),
+        }
+    }
+
+    /// Rewrite a table's file from its live in-memory rows, dropping the stale
+    /// copies left by appends and folding in its delta file, and remove the
+    /// table's entries from the WAL archive that the latest backup holds.
+    #[instrument(skip(self))]
+    pub fn vacuum(&mut self, table_name: &str) -> Result<VacuumReport> {
+        self.check_writable()?;
+        self.check_persistent(table_name)?;
+        self.get_table(table_name)?;
+        let file_size = |path: &str| self.storage.size(path).unwrap_or(0);
+
+        let table_file = self.config.table_path(table_name);
+        let table_bytes_before =
+            file_size(&table_file) + file_size(&self.config.delta_path(table_name));
+        self.write_table_file(table_name, &table_file)?;
+        self.remove_delta(table_name)?;
+        self.unsaved.remove(table_name);
+        let table_bytes_after = file_size(&table_file);
+
+        // Archived entries for this table that the latest backup already holds
+        // are not needed by `recover_to`; later ones are left to the retention
+        // policy, as are all of them when there is no backup.
+        let archive_file = self.config.wal_archive_path();
+        let archive_bytes_before = file_size(&archive_file);
+        let backup_lsn = self.backup_before(u64::MAX).map(|entry| entry.lsn);
+        let mut archive_entries_removed = 0;
+        if let Some(backup_lsn) = backup_lsn.filter(|_| archive_bytes_before > 0) {
+            let data = self.storage.read_to_string(&archive_file).map_err(|e| {
+                DatabaseError::FileCreationError(archive_file.clone(), e.to_string())
+            })?;
+            let mut kept = String::new();
+            for line in data.lines() {
+                let (stamp, body, _) = checksum::unframe_stamped(line);
+                let entry = self.open_cell(body);
+                // Unstamped entries predate backups that record their LSN.
+                let backed_up = stamp.is_none_or(|stamp| stamp.lsn <= backup_lsn);
+                if backed_up && entry.split(':').nth(1) == Some(table_name) {
+                    archive_entries_removed += 1;
+                } else {
+                    kept.push_str(line);
+                    kept.push('\n');
+                }
+            }
+            self.storage
+                .write(&archive_file, kept.as_bytes())
+                .map_err(|e| {
+                    DatabaseError::FileCreationError(archive_file.clone(), e.to_string())
+                })?;
+        }
+        let report = VacuumReport {
+            table: table_name.to_string(),
+            table_bytes_before,
+            table_bytes_after,
+            archive_bytes_before,
+            archive_bytes_after: file_size(&archive_file),
+            archive_entries_removed,
+        };
+        info!(
+            "Vacuumed '{}': {} bytes reclaimed.",
+            table_name,
+            report.reclaimed_bytes()
+        );
+        Ok(report)
+    }
+
+    /// Validate the on-disk state: table files against their schema and
+    /// datatypes, WAL and archive records against their checksums and format,
+    /// and the index and bloom filter against the rows they describe.
+    #[instrument(skip(self))]
+    pub fn check(&self) -> IntegrityReport {
+        let mut report = IntegrityReport::default();
+        // Every table file in the data directory, including ones that failed to load.
+        let mut table_names: Vec<String> = self
+            .storage
+            .list(&self.config.tables_dir())
+            .unwrap_or_default()
+            .iter()
+            .map(Path::new)
+            .filter(|p| p.extension() == Some(std::ffi::OsStr::new("csv")))
+            .filter_map(|p| p.file_stem().and_then(|f| f.to_str()).map(String::from))
+            .collect();
+        table_names.sort();
+        for table_name in table_names {
+            let file_name = self.config.table_path(&table_name);
+            self.check_table_file(&table_name, &file_name, &mut report);
+        }
+        let archive_file = self.config.wal_archive_path();
+        for wal in [self.wal_file.as_str(), archive_file.as_str()] {
+            if self.storage.exists(wal) {
+                self.check_wal_file(wal, &mut report);
+            }
+        }
+        self.check_indexes(&mut report);
+        info!("Integrity check found {} problems.", report.problems.len());
+        report
+    }
+
+    fn check_table_file(&self, table_name: &str, file_name: &str, report: &mut IntegrityReport) {
+        report.files_checked += 1;
+        if let Ok(sidecar) = self
+            .storage
+            .read_to_string(&checksum::sidecar_path(file_name))
+        {
+            let matches = self.storage.read(file_name).is_ok_and(|bytes| {
+                u32::from_str_radix(sidecar.trim(), 16)
+                    .is_ok_and(|sum| sum == checksum::crc32(&bytes))
+            });
+            if !matches {
+                report.add(
+                    ProblemKind::Checksum,
+                    file_name,
+                    "file does not match its checksum sidecar",
+                );
+            }
+        }
+        // Flexible so that short or long records are reported rather than aborting the scan.
+        let mut rdr = match self.storage.open(file_name) {
+            Ok(file) => ReaderBuilder::new()
+                .has_headers(true)
+                .flexible(true)
+                .from_reader(file),
+            Err(e) => {
+                report.add(ProblemKind::Unreadable, file_name, e.to_string());
+                return;
+            }
+        };
+        let headers = match rdr.headers() {
+            Ok(h) => h.clone(),
+            Err(e) => {
+                report.add(ProblemKind::Unreadable, file_name, e.to_string());
+                return;
+            }
+        };
+        if headers.get(0) != Some("row_id") {
+            report.add(
+                ProblemKind::Schema,
+                file_name,
+                "first column is not 'row_id'",
+            );
+        }
+        // Files that are not loaded are only checked against their own header.
+        if let Some(table) = self.tables.get(table_name) {
+            for column in headers.iter().skip(1) {
+                if !table.columns.contains(column) {
+                    report.add(
+                        ProblemKind::Schema,
+                        file_name,
+                        format!("column '{}' is not in table '{}'", column, table_name),
+                    );
+                }
+            }
+            for column in &table.columns {
+                if !headers.iter().any(|h| h == column) {
+                    report.add(
+                        ProblemKind::Schema,
+                        file_name,
+                        format!("column '{}' is missing", column),
+                    );
+                }
+            }
+        }
+
+        let mut datatypes: HashMap<String, String> = HashMap::new();
+        let mut seen = HashSet::new();
+        for (i, record) in rdr.records().enumerate() {
+            // Line 1 is the header.
+            let location = format!("{}:{}", file_name, i + 2);
+            let record = match record {
+                Ok(r) => r,
+                Err(e) => {
+                    report.add(ProblemKind::Unreadable, location, e.to_string());
+                    continue;
+                }
+            };
+            if record.len() != headers.len() {
+                report.add(
+                    ProblemKind::Schema,
+                    location,
+                    format!("{} fields, expected {}", record.len(), headers.len()),
+                );
+                continue;
+            }
+            let row_id = &record[0];
+            if row_id == "datatypes" {
+                for (column, dtype) in headers.iter().zip(record.iter()).skip(1) {
+                    if dtype.is_empty() {
+                        continue;
+                    }
+                    if !self.known_datatype(dtype) {
+                        report.add(
+                            ProblemKind::Datatype,
+                            location.clone(),
+                            format!("unknown datatype '{}' for column '{}'", dtype, column),
+                        );
+                    }
+                    datatypes.insert(column.to_string(), dtype.to_string());
+                }
+                continue;
+            }
+            if !seen.insert(row_id.to_string()) {
+                report.add(
+                    ProblemKind::DuplicateRow,
+                    location.clone(),
+                    format!("row '{}' appears more than once", row_id),
+                );
+            }
+            for (column, value) in headers.iter().zip(record.iter()).skip(1) {
+                let Some(dtype) = datatypes.get(column) else {
+                    continue;
+                };
+                if value.is_empty() || crypto::is_encrypted(value) {
+                    continue;
+                }
+                // Enum values are stored as their codes.
+                let value = table_schema::decode_enum(dtype, value);
+                if !Self::check_value_matches(&value, dtype) {
+                    report.add(
+                        ProblemKind::Datatype,
+                        location.clone(),
+                        format!(
+                            "'{}' in column '{}' is not a valid {}",
+                            value, column, dtype
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_wal_file(&self, file_name: &str, report: &mut IntegrityReport) {
+        report.files_checked += 1;
+        let data = match self.storage.read_to_string(file_name) {
+            Ok(d) => d,
+            Err(e) => {
+                report.add(ProblemKind::Unreadable, file_name, e.to_string());
+                return;
+            }
+        };
+        for (i, line) in data.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            report.wal_records_checked += 1;
+            if let Some((kind, detail)) = self.wal_line_problem(line) {
+                report.add(kind, format!("{}:{}", file_name, i + 1), detail);
+            }
+        }
+    }
+
+    /// What is wrong with one line of a WAL file, if anything.
+    fn wal_line_problem(&self, line: &str) -> Option<(ProblemKind, String)> {
+        let (record, valid) = checksum::unframe(line);
+        if valid == Some(false) {
+            return Some((ProblemKind::Checksum, "checksum mismatch".to_string()));
+        }
+        if crypto::is_encrypted(record) && self.cipher.is_none() {
+            return Some((
+                ProblemKind::Unreadable,
+                "encrypted record and no key set".to_string(),
+            ));
+        }
+        let entry = self.open_cell(record);
+        if crypto::is_encrypted(&entry) {
+            return Some((
+                ProblemKind::Unreadable,
+                "record cannot be decrypted".to_string(),
+            ));
+        }
+        if !Self::wal_entry_well_formed(&entry) {
+            let op = entry.split(':').next().unwrap_or_default();
+            return Some((
+                ProblemKind::MalformedWal,
+                format!("cannot parse '{}' record", op),
+            ));
+        }
+        None
+    }
+
+    /// True if a decoded WAL entry names a known operation with the fields it needs.
+    fn wal_entry_well_formed(entry: &str) -> bool {
+        match entry.split(':').next().unwrap_or_default() {
+            "create_table" | "drop_table" => entry.split(':').count() == 2,
+            "add_column" | "encrypt_column" | "rename_table" => entry.split(':').count() == 3,
+            "set_collation" => {
+                let fields: Vec<&str> = entry.split(':').collect();
+                fields.len() == 4 && fields[3].parse::<Collation>().is_ok()
+            }
+            "set_row_id_strategy" => {
+                let fields: Vec<&str> = entry.split(':').collect();
+                fields.len() == 3 && fields[2].parse::<RowIdStrategy>().is_ok()
+            }
+            "create_index" => {
+                let fields: Vec<&str> = entry.split(':').collect();
+                fields.len() == 4 && fields[3].parse::<IndexKind>().is_ok()
+            }
+            "set_autosave" => entry
+                .splitn(3, ':')
+                .nth(2)
+                .is_some_and(|json| serde_json::from_str::<Autosave>(json).is_ok()),
+            "backfill_column" => Self::logged_backfill(entry).is_some(),
+            "prepare_tx" => Self::logged_prepare(entry).is_some(),
+            "commit_tx" | "abort_tx" => entry.split(':').count() == 2,
+            "purge_deleted" => {
+                let fields: Vec<&str> = entry.split(':').collect();
+                fields.len() == 3 && fields[2].parse::<u64>().is_ok()
+            }
+            "import_csv" | "bulk_load" | "copy_from" => entry.splitn(3, ':').count() == 3,
+            "copy_table" | "merge_tables" => entry
+                .splitn(4, ':')
+                .nth(3)
+                .and_then(|json| serde_json::from_str::<serde_json::Value>(json).ok())
+                .is_some_and(|v| v["rows"].is_array()),
+            "update_rows" => entry
+                .splitn(3, ':')
+                .nth(2)
+                .and_then(|json| serde_json::from_str::<serde_json::Value>(json).ok())
+                .is_some_and(|v| v["rows"].is_array() && v["set"].is_object()),
+            "insert_row" | "upsert_row" => entry
+                .splitn(4, ':')
+                .nth(3)
+                .is_some_and(|json| serde_json::from_str::<HashMap<String, String>>(json).is_ok()),
+            "update_row" => entry
+                .splitn(5, ':')
+                .nth(4)
+                .is_some_and(|json| serde_json::from_str::<String>(json).is_ok()),
+            "delete_row" => entry.splitn(3, ':').count() == 3,
+            _ => false,
+        }
+    }
+
+    /// Salvage what can be read from damaged table files and WAL files. Each
+    /// damaged file is rewritten with only its good records and the original is
+    /// moved to the `quarantine` directory. Table files that could not be loaded
+    /// before are loaded afterwards.
+    #[instrument(skip(self))]
+    pub fn repair(&mut self) -> Result<RepairReport> {
+        self.check_writable()?;
+        let mut report = RepairReport::default();
+        let dir = self.config.tables_dir();
+        let table_files: Vec<PathBuf> = self
+            .storage
+            .list(&dir)
+            .map_err(|e| DatabaseError::FileCreationError(dir.clone(), e.to_string()))?
+            .into_iter()
+            .map(PathBuf::from)
+            .filter(|p| p.extension() == Some(std::ffi::OsStr::new("csv")))
+            .collect();
+        for path in &table_files {
+            let file_name = path.to_string_lossy().into_owned();
+            if let Some(repaired) = self.repair_table_file(&file_name)? {
+                report.files.push(repaired);
+            }
+        }
+        let archive_file = self.config.wal_archive_path();
+        for wal in [self.wal_file.clone(), archive_file] {
+            if self.storage.exists(&wal) {
+                if let Some(repaired) = self.repair_wal_file(&wal)? {
+                    report.files.push(repaired);
+                }
+            }
+        }
+        for path in &table_files {
+            let Some(table_name) = path.file_stem().and_then(|f| f.to_str()) else {
+                continue;
+            };
+            if !self.check_table(table_name) {
+                if let Err(e) = self.load_table_from_file(table_name, &path.to_string_lossy()) {
+                    error!("Table '{}' still cannot be loaded: {}", table_name, e);
+                }
+            }
+        }
+        info!("Repair rewrote {} files.", report.files.len());
+        Ok(report)
+    }
+
+    /// Keep the records of a CSV table file that have the header's width. Returns
+    /// None if nothing had to be dropped.
+    fn repair_table_file(&self, file_name: &str) -> Result<Option<RepairedFile>> {
+        let to_err = |e: &dyn std::fmt::Display| {
+            DatabaseError::FileCreationError(file_name.to_string(), e.to_string())
+        };
+        let file = self.storage.open(file_name).map_err(|e| to_err(&e))?;
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .from_reader(file);
+        let mut kept = Vec::new();
+        let mut dropped = 0;
+        let headers = match rdr.headers() {
+            Ok(h) => Some(h.clone()),
+            Err(_) => None,
+        };
+        if let Some(ref headers) = headers {
+            for record in rdr.records() {
+                match record {
+                    Ok(r) if r.len() == headers.len() => kept.push(r),
+                    _ => dropped += 1,
+                }
+            }
+        }
+        if headers.is_some() && dropped == 0 {
+            return Ok(None);
+        }
+
+        // The cleaned file takes the damaged one's place once that is quarantined.
+        let cleaned = match headers {
+            Some(ref headers) => {
+                let file = self.storage.create(file_name).map_err(|e| to_err(&e))?;
+                let mut wtr = WriterBuilder::new().from_writer(checksum::Crc32Writer::new(file));
+                wtr.write_record(headers).map_err(|e| to_err(&e))?;
+                for record in &kept {
+                    wtr.write_record(record).map_err(|e| to_err(&e))?;
+                }
+                Some(wtr.into_inner().map_err(|e| to_err(&e))?.into_parts())
+            }
+            None => None,
+        };
+        let quarantined_to = self.quarantine(file_name)?;
+        match cleaned {
+            Some((cleaned, crc)) => {
+                cleaned.commit().map_err(|e| to_err(&e))?;
+                self.write_sidecar(file_name, crc)?;
+            }
+            None => self.remove_with_sidecar(file_name)?,
+        }
+        warn!(
+            "Repaired '{}': kept {} records, dropped {}.",
+            file_name,
+            kept.len(),
+            dropped
+        );
+        Ok(Some(RepairedFile {
+            file: file_name.to_string(),
+            records_kept: kept.len(),
+            records_dropped: dropped,
+            quarantined_to,
+        }))
+    }
+
+    /// Keep the WAL lines that pass their checksum and parse. Returns None if
+    /// nothing had to be dropped.
+    fn repair_wal_file(&self, file_name: &str) -> Result<Option<RepairedFile>> {
+        let to_err = |e: std::io::Error| {
+            DatabaseError::FileCreationError(file_name.to_string(), e.to_string())
+        };
+        // Lossy so that a file with invalid UTF-8 still yields its readable lines.
+        let bytes = self.storage.read(file_name).map_err(to_err)?;
+        let data = String::from_utf8_lossy(&bytes);
+        let mut kept = String::new();
+        let mut records_kept = 0;
+        let mut dropped = 0;
+        for line in data.lines().filter(|l| !l.trim().is_empty()) {
+            if self.wal_line_problem(line).is_some() {
+                dropped += 1;
+            } else {
+                kept.push_str(line);
+                kept.push('\n');
+                records_kept += 1;
+            }
+        }
+        if dropped == 0 {
+            return Ok(None);
+        }
+        let mut cleaned = self.storage.create(file_name).map_err(to_err)?;
+        cleaned.write_all(kept.as_bytes()).map_err(to_err)?;
+        let quarantined_to = self.quarantine(file_name)?;
+        cleaned.commit().map_err(to_err)?;
+        warn!(
+            "Repaired '{}': kept {} records, dropped {}.",
+            file_name, records_kept, dropped
+        );
+        Ok(Some(RepairedFile {
+            file: file_name.to_string(),
+            records_kept,
+            records_dropped: dropped,
+            quarantined_to,
+        }))
+    }
+
+    /// Move a damaged file into `<data_dir>/quarantine`, suffixed with the current time.
+    fn quarantine(&self, file_name: &str) -> Result<String> {
+        let dir = self.config.quarantine_dir();
+        self.storage
+            .create_dir_all(&dir)
+            .map_err(|e| DatabaseError::FileCreationError(dir.clone(), e.to_string()))?;
+        let secs = clock::unix_secs();
+        let base = Path::new(file_name)
+            .file_name()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let dest = Path::new(&dir)
+            .join(format!("{}.{}", base, secs))
+            .to_string_lossy()
+            .into_owned();
+        self.storage
+            .rename(file_name, &dest)
+            .map_err(|e| DatabaseError::FileCreationError(dest.clone(), e.to_string()))?;
+        Ok(dest)
+    }
+
+    fn check_indexes(&self, report: &mut IntegrityReport) {
+        let column = &self.config.index_column;
+        if let Some(ref indexer) = self.indexer {
+            for (key, row_ids) in &indexer.index {
+                for row_id in row_ids {
+                    report.index_entries_checked += 1;
+                    let matches = Indexer::Indexer::split_scoped(key).is_some_and(
+                        |(table_name, key_column, value)| {
+                            key_column == column.as_str()
+                                && self
+                                    .tables
+                                    .get(table_name)
+                                    .and_then(|t| t.rows.get(row_id))
+                                    .and_then(|r| r.get(column))
+                                    .is_some_and(|v| indexer.options.fold(v) == value)
+                        },
+                    );
+                    if !matches {
+                        report.add(
+                            ProblemKind::DanglingIndex,
+                            format!("index on '{}'", column),
+                            format!("'{}' -> row '{}' has no matching row", key, row_id),
+                        );
+                    }
+                }
+            }
+        }
+        let column = &self.config.bloom_column;
+        if let Some(ref bf) = self.bloom_filter {
+            for (table_name, table) in &self.tables {
+                for (row_id, row) in &table.rows {
+                    if let Some(value) = row.get(column) {
+                        if !bf.contains(value) {
+                            report.add(
+                                ProblemKind::MissingBloomEntry,
+                                format!("bloom filter on '{}'", column),
+                                format!("row '{}' of '{}' is missing", row_id, table_name),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Register a scalar function for use in conditions and projections, e.g.
+    /// `db.register_function("reverse", |args| Ok(args[0].chars().rev().collect()))`.
+    /// Replaces any function, built-in or not, with the same name.
+    pub fn register_function(
         &mut self,
+        name: &str,
+        f: impl Fn(&[String]) -> FunctionResult + Send + Sync + 'static,
+    ) {
+        self.functions.register(name, Arc::new(f));
+        debug!("Function '{}' registered.", name);
+    }
+
+    /// Parse projection items (columns or expressions such as `lower(name)`).
+    fn parse_projection(
+        &self,
         table_name: &str,
-        file_name: &str,
-    ) -> Result<Vec<String>> {
-        let table = self
-            .tables
-            .get(table_name)
-            .ok_or(DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+        projection: Option<&[&str]>,
+    ) -> Result<Option<Vec<(String, Expr)>>> {
+        projection
+            .map(|items| {
+                items
+                    .iter()
+                    .map(|item| Ok((item.to_string(), self.parse_expr(table_name, item)?)))
+                    .collect()
+            })
+            .transpose()
+    }
+
+    /// Parse an expression, treating the name of one of the table's columns
+    /// as that column even if it would parse as arithmetic (`unit-price`).
+    fn parse_expr(&self, table_name: &str, text: &str) -> Result<Expr> {
+        let text = text.trim();
+        if self
+            .readable_table(table_name)
+            .is_some_and(|t| t.columns.contains(text))
+        {
+            return Ok(Expr::Column(text.to_string()));
+        }
+        Expr::parse(text, &self.functions)
+    }
+
+    /// Copy a row, keeping only the projected items when a projection is
+    /// given. Each item is keyed by its text; items that can't be evaluated
+    /// for the row, such as missing columns, are left out.
+    fn project(
+        row: &HashMap<String, String>,
+        projection: Option<&[(String, Expr)]>,
+    ) -> HashMap<String, String> {
+        match projection {
+            Some(items) => items
+                .iter()
+                .filter_map(|(name, expr)| expr.eval(row).map(|v| (name.clone(), v)))
+                .collect(),
+            None => row.clone(),
+        }
+    }
+
+    /// Rows read from `table` as a `RowSet`: the selected items in order, or
+    /// else the table's columns.
+    fn row_set(
+        table: Option<&Table>,
+        projection: Option<&[&str]>,
+        rows: Vec<(String, HashMap<String, String>)>,
+    ) -> RowSet {
+        let columns: Vec<&str> = match projection {
+            Some(items) => items.iter().map(|item| item.trim()).collect(),
+            None => table.map_or_else(Vec::new, |t| t.columns.iter().map(String::as_str).collect()),
+        };
+        RowSet::from_rows(table, &columns, rows)
+    }
 
-        // collect sorted columns
-        let mut cols: Vec<_> = table.columns.iter().cloned().collect();
-        cols.sort();
+    /// Finds rows by the given column having a specific value.
+    /// If `return_many` is false, stops at the first match.
+    /// With a `projection`, only those columns or expressions are copied into the results.
+    pub fn find_rows_by_value_in_table(
+        &self,
+        table_name: &str,
+        column: &str,
+        value: &str,
+        return_many: bool,
+        projection: Option<&[&str]>,
+    ) -> Result<QueryResult> {
+        self.find_rows_by_value_with(
+            table_name,
+            column,
+            value,
+            return_many,
+            projection,
+            Consistency::Strict,
+        )
+    }
 
-        // open CSV writer (append or new with header)
-        let path = Path::new(file_name);
-        let file = if path.exists() {
-            OpenOptions::new().append(true).open(file_name)
+    /// `find_rows_by_value_in_table`, with the index and bloom filter also
+    /// used while behind the latest write if `consistency` allows.
+    pub fn find_rows_by_value_with(
+        &self,
+        table_name: &str,
+        column: &str,
+        value: &str,
+        return_many: bool,
+        projection: Option<&[&str]>,
+        consistency: Consistency,
+    ) -> Result<QueryResult> {
+        let started = Instant::now();
+        let items = projection;
+        let projection = self.parse_projection(table_name, projection)?;
+        let projection = projection.as_deref();
+        // If we're searching on the indexed column (e.g., "name"), use the
+        // indexer instead of scanning every row. Only an index built since the
+        // last write is complete, and cold rows aren't in it, so otherwise the
+        // table is scanned below unless `consistency` accepts an older index.
+        let index_usable = column == self.config.index_column
+            && consistency.allows(self.index_built_at, self.writes)
+            && !self.cold.contains_key(table_name);
+        if let (true, Some(indexer)) = (index_usable, self.indexer.as_ref()) {
+            // information_schema tables aren't indexed; they are scanned below.
+            if let Some(table) = self.tables.get(table_name) {
+                let mut results = Vec::new();
+                for row_id in indexer.get_scoped(table_name, column, value).into_iter().flatten() {
+                    // Re-check the row's value, so a bad entry costs a lookup
+                    // instead of a wrong result.
+                    let Some(row) = table.rows.get(row_id).filter(|row| !self.hidden(table, row)) else {
+                        continue;
+                    };
+                    if !row.get(column).is_some_and(|v| indexer.options.eq(v, value)) {
+                        continue;
+                    }
+                    results.push((row_id.clone(), Self::project(row, projection)));
+                    if !return_many {
+                        break;
+                    }
+                }
+                return Ok(QueryResult::read(
+                    Self::row_set(Some(table), items, results),
+                    started,
+                ));
+            }
+        }
+        // For columns not indexed or when index miss occurs, use the full scan.
+        if let Some(table) = self.scannable_table(table_name) {
+            let mut results = Vec::new();
+            for (row_id, row_data) in &table.rows {
+                if let Some(v) = row_data.get(column) {
+                    // If a BloomFilter is available for this column,
+                    // check it to quickly rule out non-existent values. One
+                    // built before the latest write may lack the value.
+                    if column == self.config.bloom_column
+                        && consistency.allows(self.bloom_built_at, self.writes)
+                    {
+                        if let Some(ref bf) = self.bloom_filter {
+                            if !bf.contains(v) {
+                                continue;
+                            }
+                        }
+                    }
+                    if self.config.match_options.eq(v, value) {
+                        results.push((row_id.clone(), Self::project(row_data, projection)));
+                        if !return_many {
+                            break;
+                        }
+                    }
+                }
+            }
+            Ok(QueryResult::read(
+                Self::row_set(Some(&table), items, results),
+                started,
+            ))
         } else {
-            OpenOptions::new().write(true).create(true).open(file_name)
+            Err(DatabaseError::TableDoesNotExist(table_name.to_string()))
         }
-        .map_err(|e| DatabaseError::FileCreationError(file_name.to_string(), e.to_string()))?;
+    }
 
-        let mut wtr = if path.exists() {
-            WriterBuilder::new().has_headers(false).from_writer(file)
+    /// Searches rows by a simple condition.
+    /// The condition should be in the format "column operator value", e.g., "age > 10" or "name == Alice".
+    /// Supported operators: "==", ">", "<", ">=", "<=", "LIKE" and "MATCHES" (regex, text columns only).
+    /// With a `projection`, only those columns or expressions are copied into the results.
+    pub fn search_rows_by_condition_in_table(
+        &self,
+        table_name: &str,
+        condition: &str,
+        projection: Option<&[&str]>,
+    ) -> Result<QueryResult> {
+        let started = Instant::now();
+        let items = projection;
+        if let Some(table) = self.scannable_table(table_name) {
+            let condition = self.parse_condition(table_name, condition)?;
+            let projection = self.parse_projection(table_name, projection)?;
+            let projection = projection.as_deref();
+            let mut results = Vec::new();
+            for (row_id, row_data) in &table.rows {
+                if condition.matches(row_data, &self.config.match_options) {
+                    results.push((row_id.clone(), Self::project(row_data, projection)));
+                }
+            }
+            Ok(QueryResult::read(
+                Self::row_set(Some(&table), items, results),
+                started,
+            ))
         } else {
-            let mut w = WriterBuilder::new().has_headers(true).from_writer(file);
-            let mut header_rec = vec!["row_id".to_string()];
-            header_rec.extend(cols.clone());
-            w.write_record(&header_rec).map_err(|e| {
-                DatabaseError::FileCreationError(file_name.to_string(), e.to_string())
+            Err(DatabaseError::TableDoesNotExist(table_name.to_string()))
+        }
+    }
+
+    /// Parse a condition against a table's schema, so columns with a declared
+    /// datatype compare by that type and text columns by their collation.
+    /// Expressions on the left compare by their value alone.
+    /// Uncorrelated subqueries (`EXISTS (SELECT ...)`, `col IN (SELECT ...)`)
+    /// are run once here, against tables of this database.
+    fn parse_condition(&self, table_name: &str, condition: &str) -> Result<Condition> {
+        let trimmed = condition.trim();
+        if let Some(query) = trimmed
+            .get(..6)
+            .filter(|kw| kw.eq_ignore_ascii_case("EXISTS"))
+            .and_then(|_| trimmed[6..].trim().strip_prefix('('))
+            .and_then(|q| q.strip_suffix(')'))
+        {
+            return Ok(Condition::constant(!self.run_subquery(query)?.is_empty()));
+        }
+        let mut parsed = Condition::parse(condition, &self.config.match_options, &self.functions)?;
+        if let Some(query) = parsed.subquery() {
+            let values = self.run_subquery(query)?;
+            parsed = parsed.with_values(values, &self.config.match_options);
+        }
+        let table = self.readable_table(table_name);
+        let table = table.as_deref();
+        // A column whose name looks like arithmetic (`unit-price`) is still a column.
+        if table.is_some_and(|t| t.columns.contains(&parsed.column)) {
+            parsed.left = Expr::Column(parsed.column.clone());
+        }
+        let Some(column) = parsed.left.as_column().map(str::to_string) else {
+            return Ok(parsed);
+        };
+        if let Some(collation) = table.and_then(|t| t.collations.get(&column)) {
+            parsed = parsed.collated(*collation);
+        }
+        let datatype = table.and_then(|t| t.row_datatypes.get(&column));
+        match datatype {
+            Some(datatype) => parsed.typed(datatype),
+            None => Ok(parsed),
+        }
+    }
+
+    /// Run `SELECT <expression> FROM <table> [WHERE <condition>]` and return
+    /// the expression's value for each matching row (row ids for `*`).
+    fn run_subquery(&self, query: &str) -> Result<Vec<String>> {
+        let select = self.parse_select(query)?;
+        if select.width() != 1 {
+            return Err(DatabaseError::InvalidCondition(format!(
+                "subquery '{}': expected a single expression",
+                query
+            )));
+        }
+        let rows = select.rows(&self.config.match_options);
+        Ok(rows.into_iter().flatten().collect())
+    }
+
+    /// Run one or more `SELECT <items> FROM <table> [WHERE <condition>]`
+    /// queries joined by `UNION`, `INTERSECT` or `EXCEPT` (each optionally
+    /// followed by `ALL`), combined left to right. Every query must select
+    /// the same number of items; rows compare by their values in order.
+    /// Only tables already in memory and the `information_schema` tables
+    /// can be queried. Columns are named and typed after the first query.
+    /// A trailing `ORDER BY` sorts a single query by any of its table's
+    /// columns, and combined queries by their result columns; values compare
+    /// by their datatype and rows equal on every key keep their order.
+    pub fn query(&self, text: &str) -> Result<QueryResult> {
+        let started = Instant::now();
+        let (text, order) = split_order_by(text)?;
+        let words = expr::top_level_words(text);
+        let compound = words
+            .iter()
+            .any(|&(s, e)| SetOperation::parse(&text[s..e]).is_some());
+        let mut columns = Vec::new();
+        let mut result: Option<(usize, Vec<Vec<String>>)> = None;
+        let mut pending: Option<(SetOperation, bool)> = None;
+        let mut start = 0;
+        let mut i = 0;
+        while i <= words.len() {
+            let op = words
+                .get(i)
+                .and_then(|&(s, e)| SetOperation::parse(&text[s..e]));
+            if i < words.len() && op.is_none() {
+                i += 1;
+                continue;
+            }
+            let end = words.get(i).map_or(text.len(), |w| w.0);
+            let mut select = self.parse_select(text[start..end].trim())?;
+            if !compound && !order.is_empty() {
+                select.order_by(order.clone())?;
+            }
+            let rows = select.rows(&self.config.match_options);
+            result = Some(match (result, pending) {
+                (Some((width, left)), Some((op, all))) => {
+                    if select.width() != width {
+                        return Err(DatabaseError::IncompatibleQueries(width, select.width()));
+                    }
+                    (width, op.apply(all, left, rows))
+                }
+                _ => {
+                    columns = select.columns();
+                    (select.width(), rows)
+                }
+            });
+            let Some(op) = op else { break };
+            let all = words
+                .get(i + 1)
+                .is_some_and(|&(s, e)| text[s..e].eq_ignore_ascii_case("ALL"));
+            pending = Some((op, all));
+            i += 1 + usize::from(all);
+            start = words.get(i).map_or(text.len(), |w| w.0);
+        }
+        let rows = result.map(|(_, rows)| rows).unwrap_or_default();
+        let mut rows = RowSet::from_values(columns, rows);
+        if compound {
+            rows.sort_by_keys(&order).map_err(|key| {
+                DatabaseError::InvalidCondition(format!(
+                    "ORDER BY: '{}' is not a result column",
+                    key.column
+                ))
             })?;
-            w
+        }
+        Ok(QueryResult::read(rows, started))
+    }
+
+    fn parse_select<'a>(&'a self, query: &str) -> Result<Select<'a>> {
+        let invalid =
+            |msg: &str| DatabaseError::InvalidCondition(format!("query '{}': {}", query, msg));
+        let words = expr::top_level_words(query);
+        let keyword = |kw: &str| {
+            words
+                .iter()
+                .position(|&(start, end)| query[start..end].eq_ignore_ascii_case(kw))
         };
+        let from = keyword("FROM").ok_or_else(|| invalid("expected SELECT ... FROM <table>"))?;
+        let where_at = keyword("WHERE");
+        if from < 2
+            || !query[words[0].0..words[0].1].eq_ignore_ascii_case("SELECT")
+            || where_at.is_some_and(|w| w != from + 2)
+            || words.len() < from + 2
+            || (where_at.is_none() && words.len() > from + 2)
+        {
+            return Err(invalid("expected SELECT <items> FROM <table> [WHERE ...]"));
+        }
+        let items = query[words[1].0..words[from - 1].1].trim();
+        let (start, end) = words[from + 1];
+        let table_name = &query[start..end];
+        let table = self
+            .scannable_table(table_name)
+            .ok_or_else(|| DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+        let filter = where_at
+            .map(|w| {
+                let rest = words.get(w + 1).ok_or_else(|| invalid("empty WHERE"))?;
+                self.parse_condition(table_name, &query[rest.0..])
+            })
+            .transpose()?;
+        let items = (items != "*")
+            .then(|| {
+                expr::split_list(items)
+                    .into_iter()
+                    .map(|item| self.parse_expr(table_name, item.trim()))
+                    .collect::<Result<Vec<_>>>()
+            })
+            .transpose()?;
+        Ok(Select {
+            items,
+            table,
+            filter,
+            order: Vec::new(),
+        })
+    }
 
-        let unsaved: Vec<_> = table
+    /// Apply `assignments` to every row matching `condition` ("column operator
+    /// value") in one pass. All rows are validated before any is changed; the
+    /// change is logged as one `update_rows` WAL record and the table is saved
+    /// once the save threshold is reached. The result counts the rows updated.
+    pub fn update_rows_by_condition(
+        &mut self,
+        table_name: &str,
+        condition: &str,
+        assignments: &HashMap<String, String>,
+    ) -> Result<QueryResult> {
+        let started = Instant::now();
+        self.check_writable()?;
+        self.check_wal_backlog()?;
+        self.ensure_table_loaded(table_name)?;
+        let parsed = self.parse_condition(table_name, condition)?;
+        let table = &self.tables[table_name];
+        if let Some(missing) = assignments.keys().find(|c| !table.columns.contains(*c)) {
+            return Err(DatabaseError::ColumnDoesNotExist(
+                missing.clone(),
+                table_name.to_string(),
+            ));
+        }
+        if self.tenant.is_some() && assignments.contains_key(TENANT_ID) {
+            return Err(DatabaseError::TenantMismatch(
+                condition.to_string(),
+                table_name.to_string(),
+            ));
+        }
+        let matched: Vec<String> = table
             .rows
             .iter()
-            .skip(self.saved_row_count)
-            .filter(|(rid, _)| rid.as_str() != "datatypes")
+            .filter(|(_, row)| !self.hidden(table, row))
+            .filter(|(_, row)| parsed.matches(row, &self.config.match_options))
+            .map(|(row_id, _)| row_id.clone())
             .collect();
-        let unsaved_count = unsaved.len();
-
-        for (row_id, row_data) in unsaved {
-            let mut rec = vec![row_id.clone()];
-            rec.extend(
-                cols.iter()
-                    .map(|c| row_data.get(c).cloned().unwrap_or_default()),
-            );
-            wtr.write_record(&rec).map_err(|e| {
-                DatabaseError::FileCreationError(file_name.to_string(), e.to_string())
-            })?;
+        if matched.is_empty() {
+            return Ok(QueryResult::write(0, started));
+        }
+        for row_id in &matched {
+            let mut updated = table.rows[row_id].clone();
+            updated.extend(assignments.clone());
+            self.check_row_size(table_name, row_id, &updated)?;
+            self.check_enum_values(table_name, row_id, &updated)?;
         }
-        wtr.flush()
-            .map_err(|e| DatabaseError::FileCreationError(file_name.to_string(), e.to_string()))?;
 
-        self.saved_row_count = table.rows.len();
-        println!(
-            "Table '{}' appended to '{}' ({} new rows).",
-            table_name, file_name, unsaved_count
+        let sealed = self.seal_row(table_name, assignments);
+        if let Some(table) = self.tables.get_mut(table_name) {
+            for row_id in &matched {
+                if let Some(row) = table.rows.get_mut(row_id) {
+                    row.extend(assignments.clone());
+                }
+            }
+        }
+        let payload = serde_json::json!({ "rows": matched, "set": sealed });
+        let op = format!("update_rows:{}:{}", table_name, payload);
+        self.log_op(op);
+        self.writes += 1;
+        debug!(
+            "Updated {} rows in table '{}' where {}.",
+            matched.len(),
+            table_name,
+            condition
         );
-        Ok(vec![table_name.to_string(), file_name.to_string()])
+
+        self.record_write(table_name, matched.iter().map(String::as_str), false);
+        Ok(QueryResult::write(matched.len(), started))
     }
 
-    // Save the table to a CSV file.
-    pub fn save_table(&self, table_name: &str, file_name: &str) -> Result<Vec<String>> {
-        let table = self
-            .tables
-            .get(table_name)
-            .ok_or(DatabaseError::TableDoesNotExist(table_name.to_string()))?;
+    // --- Persistence policy ---
 
-        let mut cols: Vec<_> = table.columns.iter().cloned().collect();
-        cols.sort();
+    /// Note a write to `table_name` and save the table if its persistence
+    /// policy says it is due. Save failures are logged, not returned: the
+    /// write is already in the WAL, and the table stays marked unsaved.
+    /// `append_only` says whether the write only inserted new rows. With
+    /// background saves on, only write-through tables are saved here.
+    fn record_write<'a>(
+        &mut self,
+        table_name: &str,
+        rows: impl IntoIterator<Item = &'a str>,
+        append_only: bool,
+    ) {
+        // Nothing about a temporary table is ever saved.
+        if self.is_temp_table(table_name) {
+            return;
+        }
+        let entry = self
+            .unsaved
+            .entry(table_name.to_string())
+            .or_insert_with(|| UnsavedWrites {
+                count: 0,
+                since: Instant::now(),
+                rows: BTreeSet::new(),
+                append_only: true,
+            });
+        entry.count += 1;
+        entry.rows.extend(rows.into_iter().map(str::to_string));
+        entry.append_only &= append_only;
+        let inline = !self.background_saves
+            || self.persistence_for(table_name) == PersistencePolicy::WriteThrough;
+        if inline && self.save_due(table_name) {
+            if let Err(e) = self.save_unsaved(table_name) {
+                error!("Failed to save table '{}': {}", table_name, e);
+            }
+        }
+    }
 
-        let file = File::create(file_name)
-            .map_err(|e| DatabaseError::FileCreationError(file_name.to_string(), e.to_string()))?;
-        let mut wtr = WriterBuilder::new().has_headers(true).from_writer(file);
+    /// Persistence policy for a table: its schema's, else the config's.
+    pub fn persistence_for(&self, table_name: &str) -> PersistencePolicy {
+        self.tables
+            .get(table_name)
+            .and_then(|t| t.autosave.persistence)
+            .unwrap_or_else(|| self.config.persistence_for(table_name))
+    }
 
-        // header
-        let mut hdr = vec!["row_id".to_string()];
-        hdr.extend(cols.clone());
-        wtr.write_record(&hdr)
-            .map_err(|e| DatabaseError::FileCreationError(file_name.to_string(), e.to_string()))?;
+    /// Writes to a table between threshold saves: its schema's, else the config's.
+    pub fn save_threshold_for(&self, table_name: &str) -> usize {
+        self.tables
+            .get(table_name)
+            .and_then(|t| t.autosave.save_threshold)
+            .unwrap_or_else(|| self.config.save_threshold_for(table_name))
+    }
 
-        // optional datatypes row
-        if let Some(dt_row) = table.rows.get("datatypes") {
-            let mut rec = vec!["datatypes".to_string()];
-            rec.extend(
-                cols.iter()
-                    .map(|c| dt_row.get(c).cloned().unwrap_or_default()),
-            );
-            wtr.write_record(&rec).map_err(|e| {
-                DatabaseError::FileCreationError(file_name.to_string(), e.to_string())
-            })?;
+    fn save_due(&self, table_name: &str) -> bool {
+        let Some(unsaved) = self.unsaved.get(table_name) else {
+            return false;
+        };
+        match self.persistence_for(table_name) {
+            PersistencePolicy::WriteThrough => true,
+            PersistencePolicy::Threshold => unsaved.count >= self.save_threshold_for(table_name),
+            PersistencePolicy::Interval(secs) => {
+                unsaved.since.elapsed() >= Duration::from_secs(secs)
+            }
+            PersistencePolicy::Checkpoint => false,
         }
+    }
 
-        // all other rows
-        let mut rows: Vec<_> = table
-            .rows
-            .iter()
-            .filter(|(rid, _)| rid.as_str() != "datatypes")
-            .collect();
-        rows.sort_by_key(|(rid, _)| rid.clone());
+    /// Leave threshold and interval saves to a running `SaveEngine`, keeping
+    /// them off the write path.
+    pub fn set_background_saves(&mut self, enabled: bool) {
+        self.background_saves = enabled;
+    }
 
-        for (row_id, row_data) in rows {
-            let mut rec = vec![row_id.clone()];
-            rec.extend(
-                cols.iter()
-                    .map(|c| row_data.get(c).cloned().unwrap_or_default()),
-            );
-            wtr.write_record(&rec).map_err(|e| {
-                DatabaseError::FileCreationError(file_name.to_string(), e.to_string())
-            })?;
+    /// Save one table with pending writes: new rows are appended to the table
+    /// file and changed rows to its delta file.
+    pub fn save_unsaved(&mut self, table_name: &str) -> Result<()> {
+        let Some(unsaved) = self.unsaved.get(table_name) else {
+            return Ok(());
+        };
+        let file_name = self.config.table_path(table_name);
+        if unsaved.append_only {
+            self.save_table_for_insert(table_name, &file_name)?;
+        } else {
+            self.save_table_delta(table_name, &file_name)?;
         }
-        wtr.flush()
-            .map_err(|e| DatabaseError::FileCreationError(file_name.to_string(), e.to_string()))?;
+        self.unsaved.remove(table_name);
+        Ok(())
+    }
+
+    /// Tables with writes that have not been saved to their files yet.
+    pub fn unsaved_tables(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.unsaved.keys().cloned().collect();
+        names.sort();
+        names
+    }
 
-        println!("Table '{}' saved to '{}'.", table_name, file_name);
-        Ok(vec![table_name.to_string(), file_name.to_string()])
+    /// Tables whose persistence policy says they should be saved now.
+    pub fn due_tables(&self) -> Vec<String> {
+        self.unsaved_tables()
+            .into_iter()
+            .filter(|t| self.save_due(t))
+            .collect()
     }
 
-    pub fn get_table(&self, table_name: &str) -> Result<&Table> {
-        self.tables
-            .get(table_name)
-            .ok_or(DatabaseError::TableDoesNotExist(table_name.to_string()))
+    /// Save every table whose policy has come due, e.g. interval tables whose
+    /// oldest unsaved write has aged out. Returns the tables saved.
+    pub fn save_due_tables(&mut self) -> Vec<String> {
+        let mut saved = Vec::new();
+        for table_name in self.due_tables() {
+            match self.save_unsaved(&table_name) {
+                Ok(()) => saved.push(table_name),
+                Err(e) => error!("Failed to save table '{}': {}", table_name, e),
+            }
+        }
+        saved
     }
 
-    /// Finds rows by the given column having a specific value.
-    /// Returns a vector of tuples: (table_name, row_id, row_data).
-    /// If `return_many` is false, stops at the first match.
-    pub fn find_rows_by_value_in_table(
-        &self,
-        table_name: &str,
-        column: &str,
-        value: &str,
-        return_many: bool,
-    ) -> Result<Vec<(String, HashMap<String, String>)>> {
-        // If we're searching on a column that we index (e.g., "name"),
-        // use the indexer instead of scanning every row.
+    /// Save every table with unsaved writes, whatever its policy, then the
+    /// indexes and the catalog, with each table's row count and the WAL
+    /// position, and only then archive and truncate the WAL. Returns the tables
+    /// saved; stops at the first failure, leaving the WAL intact.
+    #[instrument(skip(self))]
+    pub fn checkpoint(&mut self) -> Result<Vec<String>> {
+        self.check_writable()?;
+        let mut saved = Vec::new();
+        // Full saves: the WAL is about to be truncated, so don't rely on
+        // incremental appends having picked up every row.
+        for table_name in self.unsaved_tables() {
+            self.save_table(&table_name, &self.config.table_path(&table_name))?;
+            self.unsaved.remove(&table_name);
+            saved.push(table_name);
+        }
+        self.save_indexes()?;
+        // Every table file now holds every record logged so far.
+        let lsn = self.next_lsn - 1;
+        for (table_name, table) in &self.tables {
+            if self.temp_tables.contains(table_name) {
+                continue;
+            }
+            let mut entry = CatalogEntry::describe(table, &DatabaseConfig::table_file(table_name));
+            entry.rows =
+                Self::data_rows(table) + self.cold.get(table_name).map_or(0, ColdSegment::rows);
+            self.catalog.tables.insert(table_name.clone(), entry);
+        }
+        for entry in self.catalog.tables.values_mut() {
+            entry.checkpoint_lsn = lsn;
+        }
+        self.save_catalog()?;
+        self.commit_wal()?;
+        info!("Checkpoint saved {} tables.", saved.len());
+        Ok(saved)
+    }
+
+    /// Write the index and bloom filter, if built, to their files. One
+    /// built before the latest write has its file removed instead, so a file
+    /// on disk always matched the tables when it was written; see
+    /// `restore_indexes`.
+    pub fn save_indexes(&self) -> Result<()> {
+        // Indexes covering temporary tables hold rows no restart will see.
+        let current =
+            |built_at: Option<u64>| built_at == Some(self.writes) && self.temp_tables.is_empty();
         if let Some(ref indexer) = self.indexer {
-            // Assume that our indexer indexes the column we're interested in.
-            if let Some(row_ids) = indexer.get(value) {
-                if let Some(table) = self.tables.get(table_name) {
-                    let mut results = Vec::new();
-                    for row_id in row_ids {
-                        if let Some(row) = table.rows.get(row_id) {
-                            results.push((row_id.clone(), row.clone()));
-                            if !return_many {
-                                break;
-                            }
-                        }
-                    }
-                    return Ok(results);
-                } else {
-                    return Err(DatabaseError::TableDoesNotExist(table_name.to_string()));
-                }
+            let path = self.config.indexer_path();
+            if current(self.index_built_at) {
+                indexer
+                    .save_to_file(self.storage.as_ref(), &path, self.cipher.as_ref())
+                    .map_err(|e| DatabaseError::FileCreationError(path, e.to_string()))?;
+            } else {
+                self.remove_stale_file(&path)?;
             }
         }
-        // For columns not indexed or when index miss occurs, use the full scan.
-        if let Some(table) = self.tables.get(table_name) {
-            let mut results = Vec::new();
-            for (row_id, row_data) in &table.rows {
-                if let Some(v) = row_data.get(column) {
-                    // If a BloomFilter is available for this column,
-                    // check it to quickly rule out non-existent values.
-                    if column == "email" {
-                        if let Some(ref bf) = self.bloom_filter {
-                            if !bf.contains(v) {
-                                continue;
-                            }
-                        }
-                    }
-                    if v == value {
-                        results.push((row_id.clone(), row_data.clone()));
-                        if !return_many {
-                            break;
-                        }
-                    }
-                }
+        if let Some(ref bf) = self.bloom_filter {
+            let path = self.config.bloom_filter_path();
+            if current(self.bloom_built_at) {
+                bf.save_to_file(self.storage.as_ref(), &path, self.cipher.as_ref())
+                    .map_err(|e| DatabaseError::FileCreationError(path, e.to_string()))?;
+            } else {
+                self.remove_stale_file(&path)?;
             }
-            Ok(results)
-        } else {
-            Err(DatabaseError::TableDoesNotExist(table_name.to_string()))
         }
+        Ok(())
     }
 
-    /// Searches rows by a simple condition.
-    /// The condition should be in the format "column operator value", e.g., "age > 10" or "name == Alice".
-    /// Supported operators: "==", ">", "<", ">=", "<=".
-    /// Returns a vector of tuples: (table_name, row_id, row_data) for rows matching the condition.
-    pub fn search_rows_by_condition_in_table(
-        &self,
-        table_name: &str,
-        condition: &str,
-    ) -> Result<Vec<(String, HashMap<String, String>)>> {
-        if let Some(table) = self.tables.get(table_name) {
-            let parts: Vec<&str> = condition.split_whitespace().collect();
-            if parts.len() != 3 {
-                println!("Condition format invalid. Expected format: \"column operator value\"");
-                return Ok(Vec::new());
-            }
-            let col = parts[0];
-            let operator = parts[1];
-            let cond_value = parts[2];
-            let mut results = Vec::new();
-            for (row_id, row_data) in &table.rows {
-                if let Some(val) = row_data.get(col) {
-                    let condition_met = match operator {
-                        "==" => val == cond_value,
-                        ">" => {
-                            if let (Ok(num_val), Ok(num_cond)) =
-                                (val.parse::<f64>(), cond_value.parse::<f64>())
-                            {
-                                num_val > num_cond
-                            } else {
-                                val.as_str() > cond_value
-                            }
-                        }
-                        "<" => {
-                            if let (Ok(num_val), Ok(num_cond)) =
-                                (val.parse::<f64>(), cond_value.parse::<f64>())
-                            {
-                                num_val < num_cond
-                            } else {
-                                val.as_str() < cond_value
-                            }
-                        }
-                        ">=" => {
-                            if let (Ok(num_val), Ok(num_cond)) =
-                                (val.parse::<f64>(), cond_value.parse::<f64>())
-                            {
-                                num_val >= num_cond
-                            } else {
-                                val.as_str() >= cond_value
-                            }
-                        }
-                        "<=" => {
-                            if let (Ok(num_val), Ok(num_cond)) =
-                                (val.parse::<f64>(), cond_value.parse::<f64>())
-                            {
-                                num_val <= num_cond
-                            } else {
-                                val.as_str() <= cond_value
-                            }
-                        }
-                        _ => {
-                            println!("Unsupported operator: {}", operator);
-                            false
-                        }
-                    };
-                    if condition_met {
-                        results.push((row_id.clone(), row_data.clone()));
-                    }
-                }
-            }
-            Ok(results)
+    fn remove_stale_file(&self, path: &str) -> Result<()> {
+        if self.storage.exists(path) {
+            self.storage
+                .remove(path)
+                .map_err(|e| DatabaseError::FileCreationError(path.to_string(), e.to_string()))?;
+            debug!("Removed out-of-date '{}'.", path);
+        }
+        Ok(())
+    }
+
+    /// Load the index and bloom filter from the files `save_indexes` wrote,
+    /// as the current ones. Only call it while the tables are as they were
+    /// last saved, before any write or WAL replay; `recover` does. Files
+    /// built on another column or with other match options are ignored.
+    /// Returns whether each of the two was restored.
+    pub fn restore_indexes(&mut self) -> (bool, bool) {
+        let storage = self.storage.as_ref();
+        let path = self.config.indexer_path();
+        let indexer = storage
+            .exists(&path)
+            .then(|| Indexer::Indexer::load_from_file(storage, &path, self.cipher.as_ref()))
+            .and_then(|loaded| {
+                loaded
+                    .map_err(|e| warn!("Ignoring index file '{}': {}", path, e))
+                    .ok()
+            })
+            .filter(|indexer| {
+                indexer.options == self.config.match_options
+                    && indexer.index.keys().all(|key| {
+                        Indexer::Indexer::split_scoped(key)
+                            .is_some_and(|(_, column, _)| column == self.config.index_column)
+                    })
+            });
+        let path = self.config.bloom_filter_path();
+        let bloom = storage
+            .exists(&path)
+            .then(|| BloomFilter::BloomFilter::load_from_file(storage, &path, self.cipher.as_ref()))
+            .and_then(|loaded| {
+                loaded
+                    .map_err(|e| warn!("Ignoring bloom filter file '{}': {}", path, e))
+                    .ok()
+            })
+            .filter(|bf| bf.size() == self.config.bloom_filter_size);
+        let restored = (indexer.is_some(), bloom.is_some());
+        if let Some(indexer) = indexer {
+            self.indexer = Some(indexer);
+            self.index_built_at = Some(self.writes);
+        }
+        if let Some(bf) = bloom {
+            self.bloom_filter = Some(bf);
+            self.bloom_built_at = Some(self.writes);
+        }
+        restored
+    }
+
+    /// Bring a database just made with `with_storage` up to date with its
+    /// data directory: restore the saved index and bloom filter, then replay
+    /// the WAL file, loading the tables it names. An index or bloom filter a
+    /// replayed record made out of date is left for the index engine to
+    /// rebuild. Run it once, before any other call; `Database::open` does.
+    #[instrument(skip(self))]
+    pub fn recover(&mut self) -> Result<StartupReport> {
+        let (index_restored, bloom_restored) = self.restore_indexes();
+        let wal_records_replayed = if self.storage.exists(&self.wal_file) {
+            self.replay_wal_file()?
         } else {
-            Err(DatabaseError::TableDoesNotExist(table_name.to_string()))
+            0
+        };
+        if wal_records_replayed > 0 {
+            // The restored index and bloom filter predate the replayed records.
+            self.writes += 1;
         }
+        let mut tables: Vec<String> = self.tables.keys().cloned().collect();
+        tables.sort();
+        let report = StartupReport {
+            tables,
+            wal_records_replayed,
+            prepared_transactions: self.prepared.len(),
+            index_restored: index_restored && wal_records_replayed == 0,
+            bloom_restored: bloom_restored && wal_records_replayed == 0,
+        };
+        info!("{}", report);
+        Ok(report)
+    }
+
+    /// Size of the WAL file on disk, 0 if it does not exist yet.
+    pub fn wal_file_bytes(&self) -> u64 {
+        self.storage.size(&self.wal_file).unwrap_or(0)
     }
 
     // --- WAL functions ---
-    // flush_wal() replays all in‑memory operations.
+    // flush_wal() replays the in‑memory operations the tables don't reflect yet.
+    #[instrument(skip(self), fields(entries = self.wal.len()))]
     pub fn flush_wal(&mut self) -> Result<()> {
-        for entry in &self.wal {
+        let entries = std::mem::take(&mut self.wal);
+        let stamps = std::mem::take(&mut self.wal_stamps);
+        let mut skipped = 0;
+        for (entry, stamp) in entries.iter().zip(&stamps) {
+            if self.is_applied(stamp.lsn, entry) {
+                skipped += 1;
+                continue;
+            }
+            self.apply_wal_entries(std::slice::from_ref(entry));
+            self.mark_applied(stamp.lsn, entry);
+        }
+        debug!("Replay skipped {} WAL entries already applied.", skipped);
+        self.wal = entries;
+        self.wal_stamps = stamps;
+        Ok(())
+    }
+
+    /// Apply logged operations to the in-memory tables, skipping malformed ones.
+    fn apply_wal_entries(&mut self, entries: &[String]) {
+        for entry in entries {
+            if !Self::wal_entry_well_formed(entry) {
+                warn!("Skipping malformed WAL entry: {}", entry);
+                continue;
+            }
             let parts: Vec<&str> = entry.split(':').collect();
+            // A row flushed to cold storage is changed in memory, like on the write path.
+            if matches!(parts[0], "insert_row" | "upsert_row" | "update_row") {
+                if let Err(e) = self.warm_row(parts[1], parts[2]) {
+                    error!("Replay: could not read cold row '{}': {}", parts[2], e);
+                }
+            }
             match parts[0] {
                 "create_table" => {
                     // Already applied during create_table.
-                    println!("Replay: Table '{}' exists.", parts[1]);
+                    debug!("Replay: Table '{}' exists.", parts[1]);
+                }
+                "encrypt_column" => {
+                    if let Some(table) = self.tables.get_mut(parts[1]) {
+                        table.encrypted_columns.insert(parts[2].to_string());
+                        debug!(
+                            "Replay: Column '{}' of table '{}' marked encrypted.",
+                            parts[2], parts[1]
+                        );
+                    }
+                }
+                "drop_table" => {
+                    self.table_indexes.retain(|(t, _), _| t != parts[1]);
+                    self.backfills.retain(|b| b.table != parts[1]);
+                    if self.tables.remove(parts[1]).is_some() {
+                        debug!("Replay: Table '{}' dropped.", parts[1]);
+                    }
+                }
+                "set_collation" => {
+                    if let (Some(table), Ok(collation)) =
+                        (self.tables.get_mut(parts[1]), parts[3].parse::<Collation>())
+                    {
+                        table.collations.insert(parts[2].to_string(), collation);
+                        debug!(
+                            "Replay: Column '{}' of table '{}' uses {} collation.",
+                            parts[2], parts[1], collation
+                        );
+                    }
+                }
+                "set_row_id_strategy" => {
+                    if let (Some(table), Ok(strategy)) = (
+                        self.tables.get_mut(parts[1]),
+                        parts[2].parse::<RowIdStrategy>(),
+                    ) {
+                        table.row_ids = RowIdGenerator::new(strategy);
+                        debug!("Replay: Table '{}' uses {} row ids.", parts[1], strategy);
+                    }
+                }
+                "create_index" => {
+                    if let Ok(kind) = parts[3].parse::<IndexKind>() {
+                        // Built on first use, once the tables are in place.
+                        self.table_indexes
+                            .entry((parts[1].to_string(), parts[2].to_string()))
+                            .or_insert_with(|| TableIndex::new(kind));
+                        debug!("Replay: {} index on '{}.{}'.", kind, parts[1], parts[2]);
+                    }
+                }
+                "set_autosave" => {
+                    let payload = entry.splitn(3, ':').nth(2).unwrap_or_default();
+                    if let (Some(table), Ok(autosave)) = (
+                        self.tables.get_mut(parts[1]),
+                        serde_json::from_str::<Autosave>(payload),
+                    ) {
+                        table.autosave = autosave;
+                        debug!("Replay: Table '{}' autosave is {:?}.", parts[1], autosave);
+                    }
+                }
+                "import_csv" => {
+                    // Already applied during import_csv.
+                    debug!("Replay: Table '{}' imported from '{}'.", parts[1], parts[2]);
+                }
+                "bulk_load" => {
+                    // The table was saved when the load finished.
+                    debug!(
+                        "Replay: Table '{}' bulk loaded from '{}'.",
+                        parts[1], parts[2]
+                    );
+                }
+                "copy_from" => {
+                    // Each chunk was appended to the table file as it was copied.
+                    debug!("Replay: {} rows copied into '{}'.", parts[2], parts[1]);
                 }
                 "add_column" => {
                     if let Some(table) = self.tables.get_mut(parts[1]) {
                         table.add_column(parts[2]);
-                        println!(
+                        debug!(
                             "Replay: Column '{}' added to table '{}'.",
                             parts[2], parts[1]
                         );
                     }
                 }
+                "backfill_column" => {
+                    if let Some(progress) = Self::logged_backfill(entry) {
+                        self.replay_backfill(progress);
+                    }
+                }
+                "prepare_tx" => {
+                    if let Some((txid, writes)) = Self::logged_prepare(entry) {
+                        self.prepared.entry(txid).or_insert(writes);
+                    }
+                }
+                "commit_tx" | "abort_tx" => {
+                    self.prepared.remove(parts[1]);
+                }
+                "purge_deleted" => {
+                    if let Ok(before) = parts[2].parse() {
+                        let purged = self.remove_deleted(parts[1], before);
+                        debug!("Replay: {} rows purged from '{}'.", purged.len(), parts[1]);
+                    }
+                }
                 "insert_row" => {
                     let table_name = parts[1];
                     let row_id = parts[2];
-                    match serde_json::from_str::<HashMap<String, String>>(parts[3]) {
+                    // The JSON payload may itself contain ':'.
+                    let payload = entry.splitn(4, ':').nth(3).unwrap_or_default();
+                    match serde_json::from_str::<HashMap<String, String>>(payload) {
                         Ok(data) => {
+                            let data: HashMap<String, String> = data
+                                .into_iter()
+                                .map(|(c, v)| {
+                                    let v = self.open_cell(&v);
+                                    (c, v)
+                                })
+                                .collect();
                             if let Some(table) = self.tables.get_mut(table_name) {
                                 table.insert_row(row_id, data);
-                                println!(
+                                debug!(
                                     "Replay: Row '{}' inserted into table '{}'.",
                                     row_id, table_name
                                 );
@@ -863,6 +6604,119 @@ impl Database {
                         }
                     }
                 }
+                "merge_tables" => {
+                    // Expected format: merge_tables:{src}:{dst}:{"rows":[...]}
+                    let fields: Vec<&str> = entry.splitn(4, ':').collect();
+                    let payload: serde_json::Value =
+                        serde_json::from_str(fields[3]).unwrap_or_default();
+                    let row_ids: Vec<String> =
+                        serde_json::from_value(payload["rows"].clone()).unwrap_or_default();
+                    if let Some(src) = self.tables.get(fields[1]).cloned() {
+                        if let Some(dst) = self.tables.get_mut(fields[2]) {
+                            Self::merge_schema(dst, &src);
+                            for row_id in &row_ids {
+                                if let Some(row) = src.rows.get(row_id) {
+                                    dst.rows.insert(row_id.clone(), row.clone());
+                                }
+                            }
+                            debug!(
+                                "Replay: Merged {} rows from table '{}' into '{}'.",
+                                row_ids.len(),
+                                fields[1],
+                                fields[2]
+                            );
+                        }
+                    }
+                }
+                "rename_table" => {
+                    if !self.tables.contains_key(parts[2]) {
+                        if let Some(table) = self.tables.remove(parts[1]) {
+                            self.tables.insert(parts[2].to_string(), table);
+                            Self::rename_table_indexes(&mut self.table_indexes, parts[1], parts[2]);
+                            Self::rename_backfills(&mut self.backfills, parts[1], parts[2]);
+                            debug!("Replay: Table '{}' renamed to '{}'.", parts[1], parts[2]);
+                        }
+                    }
+                }
+                "copy_table" => {
+                    // Expected format: copy_table:{src}:{dst}:{"rows":[...]}
+                    let fields: Vec<&str> = entry.splitn(4, ':').collect();
+                    let payload: serde_json::Value =
+                        serde_json::from_str(fields[3]).unwrap_or_default();
+                    let row_ids: Vec<String> =
+                        serde_json::from_value(payload["rows"].clone()).unwrap_or_default();
+                    if let Some(src) = self.tables.get(fields[1]).cloned() {
+                        let dst = self.tables.entry(fields[2].to_string()).or_insert_with(|| {
+                            let mut table = Table::new();
+                            table.columns = src.columns.clone();
+                            table.row_datatypes = src.row_datatypes.clone();
+                            table.encrypted_columns = src.encrypted_columns.clone();
+                            table.collations = src.collations.clone();
+                            table.row_ids = RowIdGenerator::new(src.row_ids.strategy);
+                            table.autosave = src.autosave;
+                            table
+                        });
+                        for row_id in &row_ids {
+                            if let Some(row) = src.rows.get(row_id) {
+                                dst.rows
+                                    .entry(row_id.clone())
+                                    .or_insert_with(|| row.clone());
+                            }
+                        }
+                        debug!(
+                            "Replay: Copied {} rows from table '{}' into '{}'.",
+                            row_ids.len(),
+                            fields[1],
+                            fields[2]
+                        );
+                    }
+                }
+                "update_rows" => {
+                    // Expected format: update_rows:{table_name}:{"rows":[...],"set":{...}}
+                    let fields: Vec<&str> = entry.splitn(3, ':').collect();
+                    let payload: serde_json::Value =
+                        serde_json::from_str(fields[2]).unwrap_or_default();
+                    let set: HashMap<String, String> =
+                        serde_json::from_value(payload["set"].clone()).unwrap_or_default();
+                    let set: HashMap<String, String> = set
+                        .into_iter()
+                        .map(|(c, v)| (c, self.open_cell(&v)))
+                        .collect();
+                    let row_ids: Vec<String> =
+                        serde_json::from_value(payload["rows"].clone()).unwrap_or_default();
+                    if let Some(table) = self.tables.get_mut(fields[1]) {
+                        for row_id in &row_ids {
+                            if let Some(row) = table.rows.get_mut(row_id) {
+                                row.extend(set.clone());
+                            }
+                        }
+                        debug!(
+                            "Replay: Updated {} rows in table '{}'.",
+                            row_ids.len(),
+                            fields[1]
+                        );
+                    }
+                }
+                "upsert_row" => {
+                    // The JSON payload may itself contain ':'.
+                    let fields: Vec<&str> = entry.splitn(4, ':').collect();
+                    let (table_name, row_id) = (fields[1], fields[2]);
+                    let data: HashMap<String, String> =
+                        serde_json::from_str(fields[3]).unwrap_or_default();
+                    let data: HashMap<String, String> = data
+                        .into_iter()
+                        .map(|(c, v)| (c, self.open_cell(&v)))
+                        .collect();
+                    if let Some(table) = self.tables.get_mut(table_name) {
+                        let mut merged = table.rows.get(row_id).cloned().unwrap_or_default();
+                        merged.extend(data);
+                        table.insert_row(row_id, merged);
+                        debug!(
+                            "Replay: Row '{}' upserted into table '{}'.",
+                            row_id, table_name
+                        );
+                    }
+                }
                 "update_row" => {
                     // Expected format: update_row:{table_name}:{row_id}:{column_name}:{new_value_json}
                     if parts.len() < 5 {
@@ -872,15 +6726,17 @@ impl Database {
                     let table_name = parts[1];
                     let row_id = parts[2];
                     let column_name = parts[3];
-                    // Deserialize the new_value
+                    // Deserialize the new_value, which may itself contain ':'.
+                    let payload = entry.splitn(5, ':').nth(4).unwrap_or_default();
                     let new_value: String =
-                        serde_json::from_str(parts[4]).unwrap_or_else(|_| parts[4].to_string());
+                        serde_json::from_str(payload).unwrap_or_else(|_| payload.to_string());
+                    let new_value = self.open_cell(&new_value);
                     if let Some(table) = self.tables.get_mut(table_name) {
                         if let Some(row) = table.rows.get_mut(row_id) {
                             row.insert(column_name.to_string(), new_value.clone());
-                            println!(
-                                "Replay: Row '{}' in table '{}' updated column '{}' to '{}'.",
-                                row_id, table_name, column_name, new_value
+                            debug!(
+                                "Replay: Row '{}' in table '{}' updated column '{}'.",
+                                row_id, table_name, column_name
                             );
                         } else {
                             error!(
@@ -892,67 +6748,141 @@ impl Database {
                         error!("Replay: Table '{}' not found.", table_name);
                     }
                 }
+                "delete_row" => {
+                    // Expected format: delete_row:{table_name}:{row_id}
+                    let table_name = parts[1];
+                    let row_id = entry.splitn(3, ':').nth(2).unwrap_or_default();
+                    if let Err(e) = self.remove_cold_row(table_name, row_id) {
+                        error!("Replay: could not delete cold row '{}': {}", row_id, e);
+                    }
+                    if let Some(table) = self.tables.get_mut(table_name) {
+                        if table.delete_row(row_id) {
+                            debug!(
+                                "Replay: Row '{}' deleted from table '{}'.",
+                                row_id, table_name
+                            );
+                        }
+                    } else {
+                        error!("Replay: Table '{}' not found.", table_name);
+                    }
+                }
                 _ => {
-                    println!("Unknown WAL entry: {}", entry);
+                    warn!("Unknown WAL entry: {}", entry);
                 }
             }
         }
-        Ok(())
     }
 
-    // Call this after a set of operations has been committed.
+    /// Move the WAL file into the archive and truncate it. Only call this once
+    /// every change it records is in the table files; `checkpoint` saves first.
+    #[instrument(skip(self), fields(entries = self.wal.len()))]
     pub fn commit_wal(&mut self) -> Result<()> {
-        // Append the current in‑memory WAL entries to the archive file.
-        let archive_file = "wal_archive.log".to_string();
-        let archive = OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(&archive_file)
+        // Logged entries go to the WAL file first so the archive gets them too.
+        self.sync_wal()?;
+        let records = match self.storage.read_to_string(&self.wal_file) {
+            Ok(records) => records,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(DatabaseError::FileCreationError(
+                    self.wal_file.clone(),
+                    e.to_string(),
+                ))
+            }
+        };
+        let archive_file = self.config.wal_archive_path();
+        // The archive must hold the records before the WAL forgets them.
+        self.storage
+            .append(&archive_file, records.as_bytes())
+            .and_then(|_| self.storage.sync(&archive_file))
             .map_err(|err| {
                 DatabaseError::FileCreationError(archive_file.clone(), err.to_string())
             })?;
-        let mut archive_writer = BufWriter::new(archive);
-        for entry in &self.wal {
-            writeln!(archive_writer, "{}", entry).map_err(|err| {
-                DatabaseError::FileCreationError(archive_file.clone(), err.to_string())
-            })?;
-        }
-        archive_writer.flush().unwrap();
-        println!("WAL entries committed to archive '{}'.", archive_file);
+        debug!("WAL entries committed to archive '{}'.", archive_file);
 
         // Now clear the persistent WAL:
         self.wal.clear();
-        // Truncate the working persistent WAL file by creating a new file.
-        File::create(&self.wal_file).map_err(|err| {
+        self.wal_stamps.clear();
+        self.wal_persisted = 0;
+        // Truncate the working persistent WAL file by replacing it with an empty one.
+        self.storage.write(&self.wal_file, b"").map_err(|err| {
             DatabaseError::FileCreationError(self.wal_file.clone(), err.to_string())
         })?;
-        println!("Persistent WAL '{}' cleared.", self.wal_file);
+        debug!("Persistent WAL '{}' cleared.", self.wal_file);
+        // Backfills still running are logged again, so replaying the new WAL
+        // over the saved tables resumes them.
+        for progress in self.backfills.clone() {
+            self.log_backfill(&progress);
+        }
+        // So are prepared transactions, which still wait on their coordinator.
+        for (txid, writes) in self.prepared.clone() {
+            self.log_prepare(&txid, &writes);
+        }
+        if !self.backfills.is_empty() || !self.prepared.is_empty() {
+            self.sync_wal()?;
+        }
+        // The commit is complete; a failed rotation is retried on the next one.
+        if let Err(e) = self.rotate_archive() {
+            warn!("Rotating the WAL archive failed: {}", e);
+        }
         Ok(())
     }
 
-    // persist_wal() writes the in‑memory WAL to disk in append mode.
-    pub fn persist_wal(&self) -> Result<()> {
-        let file = OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(&self.wal_file)
+    // persist_wal() appends in‑memory WAL entries not yet on disk.
+    #[instrument(skip(self), fields(entries = self.wal.len()))]
+    pub fn persist_wal(&mut self) -> Result<()> {
+        if self.wal_persisted >= self.wal.len() {
+            return Ok(());
+        }
+        let mut records = String::new();
+        let pending = self.wal[self.wal_persisted..]
+            .iter()
+            .zip(&self.wal_stamps[self.wal_persisted..]);
+        for (entry, stamp) in pending {
+            records.push_str(&self.wal_record(*stamp, entry));
+            records.push('\n');
+        }
+        self.storage
+            .append(&self.wal_file, records.as_bytes())
             .map_err(|err| {
                 DatabaseError::FileCreationError(self.wal_file.to_string(), err.to_string())
             })?;
-        let mut writer = BufWriter::new(file);
-        for entry in &self.wal {
-            writeln!(writer, "{}", entry).map_err(|err| {
-                DatabaseError::FileCreationError(self.wal_file.to_string(), err.to_string())
-            })?;
-        }
-        writer.flush().unwrap();
-        println!("WAL persisted to {}", self.wal_file);
+        self.wal_persisted = self.wal.len();
+        debug!("WAL persisted to {}", self.wal_file);
         Ok(())
     }
 
-    // load_wal() reads existing WAL operations from disk.
+    /// Make every logged operation durable in the WAL file, through the
+    /// background writer if one is running.
+    pub fn sync_wal(&mut self) -> Result<()> {
+        match self.wal_writer {
+            Some(ref writer) => {
+                if writer.sync() {
+                    Ok(())
+                } else {
+                    Err(DatabaseError::FileCreationError(
+                        self.wal_file.clone(),
+                        "WAL writer has stopped".to_string(),
+                    ))
+                }
+            }
+            None => self.persist_wal(),
+        }
+    }
+
+    // load_wal() reads existing WAL operations from disk and replays them.
     pub fn load_wal(&mut self) -> Result<()> {
-        let file = File::open(&self.wal_file)
+        self.replay_wal_file().map(|_| ())
+    }
+
+    /// Replay the WAL file onto the tables, loading each table a record
+    /// names from its file first. Tables the replay changed are saved whole,
+    /// so their files hold every logged change before the WAL is next
+    /// committed. Returns the number of records replayed.
+    fn replay_wal_file(&mut self) -> Result<usize> {
+        let mut entries = Vec::new();
+        let file = self
+            .storage
+            .open(&self.wal_file)
             .map_err(|e| DatabaseError::FileCreationError(self.wal_file.clone(), e.to_string()))?;
         let reader = BufReader::new(file);
         for line in reader.lines() {
@@ -960,18 +6890,90 @@ impl Database {
                 DatabaseError::FileCreationError(self.wal_file.clone(), e.to_string())
             })?;
             if !ln.trim().is_empty() {
-                match serde_json::from_str::<HashMap<String, String>>(&ln) {
-                    Ok(row_data) => {
-                        // Process the row_data.
-                    }
-                    Err(e) => {
-                        error!(
-                            "Failed to deserialize row data for table 'test_table': {}",
-                            e
-                        );
+                let (stamp, record, valid) = checksum::unframe_stamped(&ln);
+                if valid == Some(false) {
+                    error!("Skipping WAL record with a bad checksum: {}", record);
+                    continue;
+                }
+                let ln = self.open_cell(record);
+                // Transactions prepared and not settled before the crash are
+                // held again until their coordinator settles them.
+                if let Some((txid, writes)) = Self::logged_prepare(&ln) {
+                    self.prepared.entry(txid).or_insert(writes);
+                    continue;
+                }
+                if let Some(txid) = ln
+                    .strip_prefix("commit_tx:")
+                    .or_else(|| ln.strip_prefix("abort_tx:"))
+                {
+                    self.prepared.remove(txid);
+                    continue;
+                }
+                // A backfill's first record since the last commit matches the
+                // saved tables, so it resumes from there.
+                if let Some(progress) = Self::logged_backfill(&ln) {
+                    let known = self
+                        .backfills
+                        .iter()
+                        .any(|b| b.table == progress.table && b.column == progress.column);
+                    if !known && !progress.done {
+                        self.backfills.push(progress);
                     }
+                    continue;
                 }
+                entries.push((stamp, ln));
+            }
+        }
+
+        let mut changed = BTreeSet::new();
+        let mut replayed = 0;
+        for (stamp, entry) in &entries {
+            let named: Vec<&str> = Self::logged_tables(entry).collect();
+            for table_name in &named {
+                self.load_for_replay(table_name)?;
+            }
+            // Records from before stamps were added are always replayed.
+            if stamp.is_some_and(|stamp| self.is_applied(stamp.lsn, entry)) {
+                continue;
+            }
+            // A table made since the last save has no file yet.
+            if let Some(table_name) = entry.strip_prefix("create_table:") {
+                self.tables
+                    .entry(table_name.to_string())
+                    .or_default();
+            }
+            self.apply_wal_entries(std::slice::from_ref(entry));
+            if let Some(stamp) = stamp {
+                self.mark_applied(stamp.lsn, entry);
             }
+            changed.extend(named.iter().map(|t| t.to_string()));
+            replayed += 1;
+        }
+        for table_name in changed {
+            if self.check_table(&table_name) {
+                self.save_table(&table_name, &self.config.table_path(&table_name))?;
+                self.unsaved.remove(&table_name);
+            }
+            if self.check_table(&table_name) || self.catalog.contains(&table_name) {
+                self.sync_catalog(&table_name)?;
+            }
+        }
+        if replayed > 0 {
+            info!(
+                "Replayed {} WAL records from '{}', {} already applied.",
+                replayed,
+                self.wal_file,
+                entries.len() - replayed
+            );
+        }
+        Ok(replayed)
+    }
+
+    /// Load a table a WAL record names, unless it is in memory already or
+    /// unknown.
+    fn load_for_replay(&mut self, table_name: &str) -> Result<()> {
+        if !self.check_table(table_name) {
+            self.load_known_table(table_name)?;
         }
         Ok(())
     }
@@ -979,16 +6981,317 @@ impl Database {
     // clear_wal() clears both the in‑memory WAL and truncates the WAL file.
     pub fn clear_wal(&mut self) -> Result<()> {
         self.wal.clear();
-        File::create(&self.wal_file).map_err(|err| {
+        self.wal_stamps.clear();
+        self.wal_persisted = 0;
+        self.storage.write(&self.wal_file, b"").map_err(|err| {
             DatabaseError::FileCreationError(self.wal_file.to_string(), err.to_string())
         })?;
-        println!("WAL cleared.");
+        info!("WAL cleared.");
         Ok(())
     }
 
-    // replay_wal() simply flushes the WAL to replay its operations.
+    // replay_wal() flushes the WAL to replay its operations. Records already
+    // applied are skipped, so it is safe to run any number of times.
     pub fn replay_wal(&mut self) -> Result<()> {
         self.flush_wal()?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::config::TableLimits;
+    use crate::commands::storage::MemoryStorage;
+
+    /// A database on `storage` without engines, as a restart would find it.
+    fn open(storage: &Arc<dyn Storage>) -> Database {
+        Database::builder()
+            .data_dir("db")
+            .storage(storage.clone())
+            .engines(false)
+            .build()
+    }
+
+    fn row(value: &str) -> HashMap<String, String> {
+        HashMap::from([("a".to_string(), value.to_string())])
+    }
+
+    /// The row ids a read returned, in order.
+    fn row_ids(result: &QueryResult) -> Vec<String> {
+        (0..result.rows.len())
+            .filter_map(|i| match result.rows.get(i, "row_id") {
+                Some(Value::Text(row_id)) => Some(row_id.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_wal_replayed_twice_leaves_tables_unchanged() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let mut db = open(&storage);
+        db.create_table("t").unwrap();
+        db.add_column("t", "a").unwrap();
+        db.insert_row("t", "r1", row("1")).unwrap();
+        db.update_row("t", "r1", "a", "2").unwrap();
+        db.insert_row("t", "r2", row("3")).unwrap();
+        db.delete_row("t", "r2").unwrap();
+        db.sync_wal().unwrap();
+
+        let mut db = open(&storage);
+        assert!(db.recover().unwrap().wal_records_replayed > 0);
+        let rows = db.tables["t"].rows.clone();
+        assert_eq!(rows["r1"]["a"], "2");
+        assert!(!rows.contains_key("r2"));
+
+        assert_eq!(db.replay_wal_file().unwrap(), 0);
+        db.replay_wal().unwrap();
+        assert_eq!(db.tables["t"].rows, rows);
+    }
+
+    #[test]
+    fn test_checkpointed_table_skips_records_before_checkpoint() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let mut db = open(&storage);
+        db.create_table("t").unwrap();
+        db.add_column("t", "a").unwrap();
+        db.insert_row("t", "r1", row("1")).unwrap();
+        db.sync_wal().unwrap();
+        // The WAL as it stood before the update, as if a crash had kept it
+        // from being truncated after the checkpoint below.
+        let stale_wal = storage.read(&db.wal_file).unwrap();
+        db.update_row("t", "r1", "a", "2").unwrap();
+        db.checkpoint().unwrap();
+        storage.write(&db.wal_file, &stale_wal).unwrap();
+
+        let mut db = open(&storage);
+        assert_eq!(db.recover().unwrap().wal_records_replayed, 0);
+        db.ensure_table_loaded("t").unwrap();
+        assert_eq!(db.tables["t"].rows["r1"]["a"], "2");
+    }
+
+    #[test]
+    fn test_recover_to_keeps_tables_not_loaded_at_backup() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let mut db = open(&storage);
+        for table_name in ["a", "b"] {
+            db.create_table(table_name).unwrap();
+            db.add_column(table_name, "a").unwrap();
+            db.insert_row(table_name, "r1", row(table_name)).unwrap();
+        }
+        db.checkpoint().unwrap();
+
+        let mut db = open(&storage);
+        db.recover().unwrap();
+        db.backup("backup").unwrap();
+        assert!(storage.exists("backup/a.csv") && storage.exists("backup/b.csv"));
+        db.recover_to(clock::unix_millis()).unwrap();
+        for table_name in ["a", "b"] {
+            assert!(db.get_row(table_name, "r1").is_ok());
+        }
+    }
+
+    #[test]
+    fn test_recover_to_removes_only_tables_dropped_since_backup() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let mut db = open(&storage);
+        for table_name in ["a", "b"] {
+            db.create_table(table_name).unwrap();
+            db.add_column(table_name, "a").unwrap();
+            db.insert_row(table_name, "r1", row(table_name)).unwrap();
+        }
+        db.backup("backup").unwrap();
+        db.drop_table("b").unwrap();
+        db.recover_to(clock::unix_millis()).unwrap();
+        assert!(db.get_row("a", "r1").is_ok());
+        assert!(matches!(
+            db.get_row("b", "r1"),
+            Err(DatabaseError::TableDoesNotExist(_))
+        ));
+        assert!(!db.catalog().contains("b"));
+    }
+
+    #[test]
+    fn test_backup_holds_cold_rows() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let mut db = open(&storage);
+        db.create_table("t").unwrap();
+        db.add_column("t", "a").unwrap();
+        for row_id in ["r1", "r2", "r3"] {
+            db.insert_row("t", row_id, row(row_id)).unwrap();
+        }
+        assert_eq!(db.flush_cold("t", 1).unwrap(), 2);
+        db.backup("loaded").unwrap();
+        let backed_up = storage.read_to_string("loaded/t.csv").unwrap();
+        assert!(backed_up.contains("r1") && backed_up.contains("r2"));
+        db.checkpoint().unwrap();
+
+        let mut db = open(&storage);
+        db.recover().unwrap();
+        db.backup("unloaded").unwrap();
+        db.recover_to(clock::unix_millis()).unwrap();
+        assert_eq!(db.cold_rows("t"), 0);
+        for row_id in ["r1", "r2", "r3"] {
+            assert_eq!(db.tables["t"].rows[row_id]["a"], row_id);
+        }
+    }
+
+    #[test]
+    fn test_table_indexes_follow_rows_between_tiers() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let mut db = open(&storage);
+        db.create_table("t").unwrap();
+        db.add_column("t", "a").unwrap();
+        for row_id in ["r1", "r2", "r3"] {
+            db.insert_row("t", row_id, row(row_id)).unwrap();
+        }
+        db.create_index("t", "a", IndexKind::Bitmap).unwrap();
+        db.flush_cold("t", 1).unwrap();
+        let found = db.filter("t", &Filter::condition("a == r3"), None).unwrap();
+        assert_eq!(row_ids(&found), ["r3"]);
+
+        // Reading a cold row brings it back into memory.
+        db.get_row("t", "r1").unwrap();
+        let found = db.filter("t", &Filter::condition("a == r3"), None).unwrap();
+        assert_eq!(row_ids(&found), ["r3"]);
+    }
+
+    #[test]
+    fn test_scans_read_cold_rows() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let mut db = open(&storage);
+        db.create_table("t").unwrap();
+        db.add_column("t", "a").unwrap();
+        for row_id in ["r1", "r2", "r3"] {
+            db.insert_row("t", row_id, row(row_id)).unwrap();
+        }
+        db.create_index("t", "a", IndexKind::Hash).unwrap();
+        db.flush_cold("t", 1).unwrap();
+
+        let found = db.filter("t", &Filter::condition("a == r1"), None).unwrap();
+        assert_eq!(row_ids(&found), ["r1"]);
+        let either = Filter::condition("a == r2").or(Filter::condition("a == r3"));
+        let found = db.filter("t", &either, None).unwrap();
+        assert_eq!(row_ids(&found), ["r2", "r3"]);
+        let distinct = db.distinct_values("t", "a").unwrap();
+        for row_id in ["r1", "r2", "r3"] {
+            assert_eq!(distinct[row_id], 1);
+        }
+        let stats = db.analyze("t").unwrap();
+        assert_eq!(stats[0].min.as_deref(), Some("r1"));
+    }
+
+    #[test]
+    fn test_distinct_values_skip_datatypes_row() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let mut db = open(&storage);
+        db.create_table("t").unwrap();
+        db.add_columns("t", vec!["name"], vec!["string"]).unwrap();
+        for row_id in ["r1", "r2"] {
+            let data = HashMap::from([("name".to_string(), "bob".to_string())]);
+            db.insert_row("t", row_id, data).unwrap();
+        }
+        let expected = BTreeMap::from([("bob".to_string(), 2)]);
+        assert_eq!(db.distinct_values("t", "name").unwrap(), expected);
+        // The same from the index on the configured column.
+        db.build_indexes();
+        assert_eq!(db.distinct_values("t", "name").unwrap(), expected);
+        let indexed = db.indexer.as_ref().unwrap().scoped_entries("t", "name");
+        assert!(indexed.flat_map(|(_, row_ids)| row_ids).all(|id| id != "datatypes"));
+    }
+
+    #[test]
+    fn test_analyze_profiles_visible_data_rows() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let mut db = open(&storage);
+        db.create_table("t").unwrap();
+        db.add_columns("t", vec!["n"], vec!["int"]).unwrap();
+        db.enable_soft_delete("t").unwrap();
+        for (row_id, n) in [("r1", "1"), ("r2", "5"), ("r3", "3"), ("r4", "9")] {
+            let data = HashMap::from([("n".to_string(), n.to_string())]);
+            db.insert_row("t", row_id, data).unwrap();
+        }
+        db.soft_delete_row("t", "r4").unwrap();
+        db.flush_cold("t", 2).unwrap();
+
+        let stats = db.analyze("t").unwrap();
+        let n = stats.iter().find(|s| s.column == "n").unwrap();
+        assert!(n.numeric);
+        assert_eq!((n.min.as_deref(), n.max.as_deref()), (Some("1"), Some("5")));
+        assert_eq!((n.distinct, n.null_count), (3, 0));
+        assert!(!n.histogram.iter().any(|bound| bound == "int"));
+    }
+
+    #[test]
+    fn test_sketches_skip_datatypes_row() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let mut db = open(&storage);
+        db.create_table("t").unwrap();
+        db.add_columns("t", vec!["name"], vec!["string"]).unwrap();
+        for row_id in ["r1", "r2", "r3", "r4", "r5"] {
+            let data = HashMap::from([("name".to_string(), "bob".to_string())]);
+            db.insert_row("t", row_id, data).unwrap();
+        }
+        let expected = vec![("bob".to_string(), 5)];
+        assert_eq!(db.top_k("t", "name", 2).unwrap(), expected);
+        assert_eq!(db.approx_distinct("t", "name").unwrap(), 1);
+        // The same from the sketches kept by an index rebuild.
+        let rebuild = db.begin_index_rebuild();
+        db.install_index_rebuild(rebuild);
+        assert!(db.sketches.contains_key(&("t".to_string(), "name".to_string())));
+        assert_eq!(db.top_k("t", "name", 2).unwrap(), expected);
+        assert_eq!(db.approx_distinct("t", "name").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_filter_skips_datatypes_row() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let mut db = open(&storage);
+        db.create_table("t").unwrap();
+        db.add_columns("t", vec!["name"], vec!["string"]).unwrap();
+        let data = HashMap::from([("name".to_string(), "string".to_string())]);
+        db.insert_row("t", "r1", data).unwrap();
+        let condition = Filter::condition("name == string");
+        assert_eq!(row_ids(&db.filter("t", &condition, None).unwrap()), ["r1"]);
+        db.create_index("t", "name", IndexKind::Bitmap).unwrap();
+        assert_eq!(row_ids(&db.filter("t", &condition, None).unwrap()), ["r1"]);
+    }
+
+    #[test]
+    fn test_row_limit_counts_data_rows_in_both_tiers() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let mut db = open(&storage);
+        let limits = TableLimits {
+            max_rows: Some(3),
+            ..TableLimits::default()
+        };
+        db.config.table_limits.insert("t".to_string(), limits);
+        db.create_table("t").unwrap();
+        // The datatypes row is not one of the three.
+        db.add_columns("t", vec!["a"], vec!["string"]).unwrap();
+        for row_id in ["r1", "r2"] {
+            db.insert_row("t", row_id, row(row_id)).unwrap();
+        }
+        db.flush_cold("t", 0).unwrap();
+        db.insert_row("t", "r3", row("r3")).unwrap();
+        let full = |result: Result<QueryResult>| matches!(result, Err(DatabaseError::TableFull(_, 3)));
+        assert!(full(db.insert_row("t", "r4", row("r4"))));
+        assert!(full(db.upsert_row("t", "r4", row("r4"))));
+        let insert = TxWrite::Insert {
+            table: "t".to_string(),
+            row_id: "r4".to_string(),
+            data: row("r4"),
+        };
+        assert!(matches!(
+            db.prepare("tx", vec![insert]),
+            Err(DatabaseError::TableFull(_, 3))
+        ));
+
+        // A cold row brought back into memory is still one row.
+        db.get_row("t", "r1").unwrap();
+        db.delete_row("t", "r3").unwrap();
+        db.insert_row("t", "r4", row("r4")).unwrap();
+        assert!(full(db.insert_row("t", "r5", row("r5"))));
+    }
+}
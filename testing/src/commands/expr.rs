@@ -0,0 +1,351 @@
+use crate::commands::db::{DatabaseError, Result};
+use crate::commands::functions::{Function, FunctionRegistry};
+use std::collections::HashMap;
+use std::fmt;
+use tracing::debug;
+
+/// A value computed from a row: a column, a literal, a function call such as
+/// `lower(name)`, or arithmetic such as `price * quantity`. Functions are
+/// resolved when the expression is parsed, so unknown names are reported up
+/// front rather than per row.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Column(String),
+    /// A quoted string (`'a b'`) or a number.
+    Literal(String),
+    Call(Function, Vec<Expr>),
+    Binary(ArithOp, Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl ArithOp {
+    fn symbol(self) -> char {
+        match self {
+            ArithOp::Add => '+',
+            ArithOp::Sub => '-',
+            ArithOp::Mul => '*',
+            ArithOp::Div => '/',
+        }
+    }
+
+    /// Integers stay integers unless the result overflows or doesn't divide
+    /// evenly, in which case it is computed as a float like any other numbers.
+    /// Non-numeric operands and division by zero have no value.
+    fn apply(self, left: &str, right: &str) -> Option<String> {
+        if let (Ok(l), Ok(r)) = (left.trim().parse::<i64>(), right.trim().parse::<i64>()) {
+            let exact = match self {
+                ArithOp::Add => l.checked_add(r),
+                ArithOp::Sub => l.checked_sub(r),
+                ArithOp::Mul => l.checked_mul(r),
+                ArithOp::Div if r != 0 && l % r == 0 => Some(l / r),
+                ArithOp::Div => None,
+            };
+            if let Some(value) = exact {
+                return Some(value.to_string());
+            }
+        }
+        let l = left.trim().parse::<f64>().ok()?;
+        let r = right.trim().parse::<f64>().ok()?;
+        let value = match self {
+            ArithOp::Add => l + r,
+            ArithOp::Sub => l - r,
+            ArithOp::Mul => l * r,
+            ArithOp::Div if r == 0.0 => return None,
+            ArithOp::Div => l / r,
+        };
+        value.is_finite().then(|| value.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Number(String),
+    LParen,
+    RParen,
+    Comma,
+    Op(ArithOp),
+}
+
+fn tokenize(text: &str) -> std::result::Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' | ',' | '+' | '-' | '*' | '/' => {
+                chars.next();
+                tokens.push(match c {
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    ',' => Token::Comma,
+                    '+' => Token::Op(ArithOp::Add),
+                    '-' => Token::Op(ArithOp::Sub),
+                    '*' => Token::Op(ArithOp::Mul),
+                    _ => Token::Op(ArithOp::Div),
+                });
+            }
+            '\'' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        // A doubled quote inside a string is a literal quote.
+                        Some('\'') if chars.peek() == Some(&'\'') => {
+                            chars.next();
+                            s.push('\'');
+                        }
+                        Some('\'') => break,
+                        Some(c) => s.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut s = String::new();
+                s.push(c);
+                chars.next();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_digit() || c == '.') {
+                        break;
+                    }
+                    s.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Number(s));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut s = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    s.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Ident(s));
+            }
+            c => return Err(format!("unexpected '{}'", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    functions: &'a FunctionRegistry,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    /// expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> std::result::Result<Expr, String> {
+        let mut left = self.term()?;
+        while let Some(&Token::Op(op @ (ArithOp::Add | ArithOp::Sub))) = self.peek() {
+            self.next();
+            left = Expr::Binary(op, Box::new(left), Box::new(self.term()?));
+        }
+        Ok(left)
+    }
+
+    /// term := unary (('*' | '/') unary)*
+    fn term(&mut self) -> std::result::Result<Expr, String> {
+        let mut left = self.unary()?;
+        while let Some(&Token::Op(op @ (ArithOp::Mul | ArithOp::Div))) = self.peek() {
+            self.next();
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    /// unary := '-' unary | primary
+    fn unary(&mut self) -> std::result::Result<Expr, String> {
+        if self.peek() == Some(&Token::Op(ArithOp::Sub)) {
+            self.next();
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    /// primary := literal | column | function '(' args ')' | '(' expr ')'
+    fn primary(&mut self) -> std::result::Result<Expr, String> {
+        match self.next() {
+            Some(Token::Str(s)) | Some(Token::Number(s)) => Ok(Expr::Literal(s)),
+            Some(Token::LParen) => {
+                let inner = self.expr()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(inner),
+                    _ => Err("expected ')'".to_string()),
+                }
+            }
+            Some(Token::Ident(name)) if self.peek() == Some(&Token::LParen) => {
+                self.next();
+                let function = self
+                    .functions
+                    .get(&name)
+                    .ok_or_else(|| format!("unknown function '{}'", name))?;
+                let mut args = Vec::new();
+                if self.peek() == Some(&Token::RParen) {
+                    self.next();
+                } else {
+                    loop {
+                        args.push(self.expr()?);
+                        match self.next() {
+                            Some(Token::Comma) => continue,
+                            Some(Token::RParen) => break,
+                            _ => return Err(format!("expected ',' or ')' in {}()", name)),
+                        }
+                    }
+                }
+                Ok(Expr::Call(function, args))
+            }
+            Some(Token::Ident(name)) => Ok(Expr::Column(name)),
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("expected an expression".to_string()),
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(text: &str, functions: &FunctionRegistry) -> Result<Self> {
+        let invalid =
+            |msg: String| DatabaseError::InvalidExpression(format!("'{}': {}", text, msg));
+        let mut parser = Parser {
+            tokens: tokenize(text).map_err(invalid)?,
+            pos: 0,
+            functions,
+        };
+        let expr = parser.expr().map_err(invalid)?;
+        if let Some(token) = parser.peek() {
+            return Err(invalid(format!("unexpected {:?}", token)));
+        }
+        Ok(expr)
+    }
+
+    /// The column name, if the expression is just a column.
+    pub fn as_column(&self) -> Option<&str> {
+        match self {
+            Expr::Column(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Evaluate against one row. `None` when a column is missing from the row
+    /// or a function rejects its arguments.
+    pub fn eval(&self, row: &HashMap<String, String>) -> Option<String> {
+        match self {
+            Expr::Column(name) => row.get(name).cloned(),
+            Expr::Literal(value) => Some(value.clone()),
+            Expr::Call(function, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(row))
+                    .collect::<Option<Vec<String>>>()?;
+                function.call(&args).map_err(|e| debug!("{}", e)).ok()
+            }
+            Expr::Binary(op, left, right) => op.apply(&left.eval(row)?, &right.eval(row)?),
+            Expr::Negate(inner) => ArithOp::Sub.apply("0", &inner.eval(row)?),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Column(name) => write!(f, "{}", name),
+            Expr::Literal(value) if value.parse::<f64>().is_ok() => write!(f, "{}", value),
+            Expr::Literal(value) => write!(f, "'{}'", value.replace('\'', "''")),
+            Expr::Call(function, args) => {
+                write!(f, "{}(", function.name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Expr::Binary(op, left, right) => write!(f, "({} {} {})", left, op.symbol(), right),
+            Expr::Negate(inner) => write!(f, "-{}", inner),
+        }
+    }
+}
+
+/// Split `text` at commas that are not inside parentheses or quotes, e.g. a
+/// projection list like `name, lower(email)`. Items are trimmed.
+pub fn split_list(text: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0usize;
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '\'' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth = depth.saturating_sub(1),
+            ',' if !quoted && depth == 0 => {
+                items.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() {
+        items.push(current.trim().to_string());
+    }
+    items
+}
+
+/// Byte ranges of the whitespace-separated words of `text` that are outside
+/// parentheses and quotes.
+pub fn top_level_words(text: &str) -> Vec<(usize, usize)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut depth = 0usize;
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        if c.is_whitespace() && depth == 0 && !quoted {
+            if let Some(s) = start.take() {
+                words.push((s, i));
+            }
+            continue;
+        }
+        if start.is_none() {
+            start = Some(i);
+        }
+        match c {
+            '\'' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        words.push((s, text.len()));
+    }
+    words
+}
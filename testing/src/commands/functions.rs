@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Result of a scalar function: the value, or a message saying why the
+/// arguments were rejected.
+pub type FunctionResult = std::result::Result<String, String>;
+
+/// A scalar function callable from conditions and projections, e.g. `lower(name)`.
+pub type ScalarFn = Arc<dyn Fn(&[String]) -> FunctionResult + Send + Sync>;
+
+/// A function looked up by name, kept with the name for error messages.
+#[derive(Clone)]
+pub struct Function {
+    pub name: String,
+    f: ScalarFn,
+}
+
+impl Function {
+    pub fn call(&self, args: &[String]) -> FunctionResult {
+        (self.f)(args)
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Function({})", self.name)
+    }
+}
+
+/// Scalar functions known to a database. Names are case-insensitive, and a
+/// registered function replaces a built-in of the same name.
+#[derive(Clone)]
+pub struct FunctionRegistry {
+    functions: HashMap<String, ScalarFn>,
+}
+
+impl Default for FunctionRegistry {
+    /// A registry holding the built-ins: `lower`, `upper`, `trim`, `length` and `concat`.
+    fn default() -> Self {
+        let mut registry = FunctionRegistry {
+            functions: HashMap::new(),
+        };
+        registry.register(
+            "lower",
+            Arc::new(|args| unary("lower", args, str::to_lowercase)),
+        );
+        registry.register(
+            "upper",
+            Arc::new(|args| unary("upper", args, str::to_uppercase)),
+        );
+        registry.register(
+            "trim",
+            Arc::new(|args| unary("trim", args, |s| s.trim().to_string())),
+        );
+        registry.register(
+            "length",
+            Arc::new(|args| unary("length", args, |s| s.chars().count().to_string())),
+        );
+        registry.register("concat", Arc::new(|args| Ok(args.concat())));
+        registry
+    }
+}
+
+impl FunctionRegistry {
+    pub fn register(&mut self, name: &str, f: ScalarFn) {
+        self.functions.insert(name.to_lowercase(), f);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Function> {
+        let name = name.to_lowercase();
+        self.functions
+            .get(&name)
+            .map(|f| Function { name, f: f.clone() })
+    }
+
+    /// Registered function names, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.functions.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+/// Apply `f` to the single argument of a one-argument function.
+fn unary(name: &str, args: &[String], f: impl Fn(&str) -> String) -> FunctionResult {
+    match args {
+        [arg] => Ok(f(arg)),
+        _ => Err(format!("{}() takes 1 argument, got {}", name, args.len())),
+    }
+}
@@ -0,0 +1,189 @@
+//! Locations: `GeoPoint` values, stored as `lat,lon` text in columns of the
+//! `geo` datatype, and `GeoIndex`, a grid over them answering bounding-box
+//! and nearest-point queries. Distances are great-circle kilometres.
+
+use crate::table::table::Table;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Mean radius of the Earth in kilometres.
+const EARTH_RADIUS_KM: f64 = 6371.0088;
+/// Size of a grid cell in degrees of latitude and longitude.
+const CELL_DEGREES: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl GeoPoint {
+    /// `None` unless `lat` is in -90..=90 and `lon` in -180..=180.
+    pub fn new(lat: f64, lon: f64) -> Option<Self> {
+        ((-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon))
+            .then_some(GeoPoint { lat, lon })
+    }
+
+    /// Parse `lat,lon`, e.g. `51.5072,-0.1276`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (lat, lon) = text.split_once(',')?;
+        Self::new(lat.trim().parse().ok()?, lon.trim().parse().ok()?)
+    }
+
+    /// Great-circle distance to `other` in kilometres, by the haversine formula.
+    pub fn distance_km(&self, other: &GeoPoint) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+    }
+
+    /// True if the point lies in the box with corners `min` (south-west)
+    /// and `max` (north-east), edges included.
+    pub fn within(&self, min: &GeoPoint, max: &GeoPoint) -> bool {
+        (min.lat..=max.lat).contains(&self.lat) && (min.lon..=max.lon).contains(&self.lon)
+    }
+
+    fn cell(&self) -> (i32, i32) {
+        (
+            (self.lat / CELL_DEGREES).floor() as i32,
+            (self.lon / CELL_DEGREES).floor() as i32,
+        )
+    }
+}
+
+impl fmt::Display for GeoPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.lat, self.lon)
+    }
+}
+
+/// The points of a column on a grid of `CELL_DEGREES` cells. Values that
+/// aren't points are left out.
+pub struct GeoIndex {
+    cells: HashMap<(i32, i32), Vec<(String, GeoPoint)>>,
+    points: usize,
+}
+
+impl GeoIndex {
+    pub fn build(table: &Table, column: &str) -> Self {
+        let mut cells: HashMap<(i32, i32), Vec<(String, GeoPoint)>> = HashMap::new();
+        let mut points = 0;
+        for (row_id, row) in &table.rows {
+            if let Some(point) = row.get(column).and_then(|value| GeoPoint::parse(value)) {
+                cells.entry(point.cell()).or_default().push((row_id.clone(), point));
+                points += 1;
+            }
+        }
+        GeoIndex { cells, points }
+    }
+
+    /// Number of points indexed.
+    pub fn len(&self) -> usize {
+        self.points
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points == 0
+    }
+
+    /// Ids of the rows with a point in the box from `min` (south-west) to
+    /// `max` (north-east). A box can't cross the antimeridian.
+    pub fn within(&self, min: &GeoPoint, max: &GeoPoint) -> Vec<&str> {
+        self.points_in(min, max)
+            .into_iter()
+            .map(|(row_id, _)| row_id.as_str())
+            .collect()
+    }
+
+    fn points_in(&self, min: &GeoPoint, max: &GeoPoint) -> Vec<&(String, GeoPoint)> {
+        let (lo, hi) = (min.cell(), max.cell());
+        if lo.0 > hi.0 || lo.1 > hi.1 {
+            return Vec::new();
+        }
+        let span = (hi.0 - lo.0 + 1) as usize * (hi.1 - lo.1 + 1) as usize;
+        let in_range = |cell: &(i32, i32)| (lo.0..=hi.0).contains(&cell.0) && (lo.1..=hi.1).contains(&cell.1);
+        // A box larger than the populated area is cheaper to check cell by cell.
+        let cells: Box<dyn Iterator<Item = &Vec<(String, GeoPoint)>>> = if span > self.cells.len() {
+            Box::new(self.cells.iter().filter(|(cell, _)| in_range(cell)).map(|(_, points)| points))
+        } else {
+            Box::new(
+                (lo.0..=hi.0)
+                    .flat_map(|lat| (lo.1..=hi.1).map(move |lon| (lat, lon)))
+                    .filter_map(|cell| self.cells.get(&cell)),
+            )
+        };
+        cells.flatten().filter(|(_, point)| point.within(min, max)).collect()
+    }
+
+    /// Up to `n` of the rows nearest `origin` for which `keep` is true, with
+    /// their distances in kilometres, nearest first.
+    pub fn nearest(&self, origin: &GeoPoint, n: usize, keep: impl Fn(&str) -> bool) -> Vec<(&str, f64)> {
+        if n == 0 {
+            return Vec::new();
+        }
+        // Search rings of cells around the origin until n rows are found.
+        // Cells aren't square on the globe, so a nearer row may still lie
+        // outside the rings: the answer comes from a second search of the
+        // box holding everything as close as the n-th row found.
+        let center = origin.cell();
+        let mut found: Vec<f64> = Vec::new();
+        let mut ring = 0;
+        while found.len() < n && ((2 * ring + 1) as usize).pow(2) <= self.cells.len() {
+            for lat in center.0 - ring..=center.0 + ring {
+                for lon in center.1 - ring..=center.1 + ring {
+                    if (lat - center.0).abs() != ring && (lon - center.1).abs() != ring {
+                        continue;
+                    }
+                    for (row_id, point) in self.cells.get(&(lat, lon)).into_iter().flatten() {
+                        if keep(row_id) {
+                            found.push(origin.distance_km(point));
+                        }
+                    }
+                }
+            }
+            ring += 1;
+        }
+        let candidates: Vec<&(String, GeoPoint)> = if found.len() >= n {
+            found.sort_by(f64::total_cmp);
+            // A little wider, so rounding can't leave the n-th row out.
+            let (min, max) = Self::around(origin, found[n - 1] * (1.0 + 1e-9) + 1e-9);
+            self.points_in(&min, &max)
+        } else {
+            self.cells.values().flatten().collect()
+        };
+        let mut nearest: Vec<(&str, f64)> = candidates
+            .into_iter()
+            .filter(|(row_id, _)| keep(row_id))
+            .map(|(row_id, point)| (row_id.as_str(), origin.distance_km(point)))
+            .collect();
+        nearest.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(b.0)));
+        nearest.truncate(n);
+        nearest
+    }
+
+    /// A box holding every point within `km` of `origin`. Near a pole or
+    /// the antimeridian it spans every longitude.
+    fn around(origin: &GeoPoint, km: f64) -> (GeoPoint, GeoPoint) {
+        let angle = km / EARTH_RADIUS_KM;
+        let dlat = angle.to_degrees();
+        let (south, north) = (origin.lat - dlat, origin.lat + dlat);
+        // The widest point of the circle in longitude, from spherical trigonometry.
+        let spread = angle.sin() / origin.lat.to_radians().cos();
+        let (west, east) = if south <= -90.0 || north >= 90.0 || spread >= 1.0 {
+            (-180.0, 180.0)
+        } else {
+            let dlon = spread.asin().to_degrees();
+            if origin.lon - dlon < -180.0 || origin.lon + dlon > 180.0 {
+                (-180.0, 180.0)
+            } else {
+                (origin.lon - dlon, origin.lon + dlon)
+            }
+        };
+        (
+            GeoPoint { lat: south.max(-90.0), lon: west },
+            GeoPoint { lat: north.min(90.0), lon: east },
+        )
+    }
+}
@@ -0,0 +1,299 @@
+use crate::commands::catalog::Catalog;
+use crate::commands::config::{Autosave, PersistencePolicy};
+use crate::commands::db::{ConflictPolicy, Database, DatabaseError, Result, RowBatch, ScanOptions};
+use crate::commands::functions::FunctionResult;
+use crate::commands::geo::GeoPoint;
+use crate::commands::index::{Consistency, Filter, IndexKind, QueryHints};
+#[cfg(feature = "import")]
+use crate::commands::ingest::{IngestFormat, IngestProgress};
+use crate::commands::integrity::{IntegrityReport, LoadReport, RepairReport};
+use crate::commands::locking::{Holder, TrackedGuard, TrackedMutex};
+use crate::commands::matching::Collation;
+use crate::commands::result::QueryResult;
+use crate::commands::row_id::RowIdStrategy;
+use crate::commands::stats::{
+    BackfillProgress, ColumnStats, DatabaseStats, MergeReport, RecoveryReport, VacuumReport,
+};
+use crate::commands::typed::TypedTable;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "import")]
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+/// Define `DbHandle` methods that lock the database for exactly one call.
+macro_rules! forward {
+    ($($(#[$meta:meta])* fn $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty;)*) => {
+        $(
+            $(#[$meta])*
+            #[track_caller]
+            pub fn $name(&self, $($arg: $ty),*) -> $ret {
+                self.lock().$name($($arg),*)
+            }
+        )*
+    };
+}
+
+/// A cloneable, thread-safe handle to a `Database`. Every clone shares the
+/// same database; each API call takes and releases the lock itself, so callers
+/// never hold it across calls by accident.
+#[derive(Clone)]
+pub struct DbHandle {
+    inner: Arc<TrackedMutex<Database>>,
+}
+
+impl DbHandle {
+    pub fn new(db: Database) -> Self {
+        DbHandle {
+            inner: Arc::new(TrackedMutex::new("database", db)),
+        }
+    }
+
+    /// Lock the database for a sequence of calls that must not interleave with
+    /// other threads. Recovers the database if a previous holder panicked.
+    /// Do not call other `DbHandle` methods while holding the guard: that
+    /// panics with where the guard was taken rather than deadlocking.
+    #[track_caller]
+    pub fn lock(&self) -> TrackedGuard<'_, Database> {
+        self.inner.lock()
+    }
+
+    /// Like `lock`, but give up with `DatabaseError::LockTimeout` after
+    /// `timeout`, naming the thread holding the lock and where it took it.
+    #[track_caller]
+    pub fn lock_timeout(&self, timeout: Duration) -> Result<TrackedGuard<'_, Database>> {
+        self.inner
+            .lock_timeout(timeout)
+            .map_err(|e| DatabaseError::LockTimeout(e.to_string()))
+    }
+
+    /// Who holds the database lock now, for diagnosing a stuck caller.
+    pub fn lock_holder(&self) -> Option<Holder> {
+        self.inner.holder()
+    }
+
+    /// Run `f` with the database locked; the lock is released when it returns.
+    #[track_caller]
+    pub fn with<R>(&self, f: impl FnOnce(&mut Database) -> R) -> R {
+        f(&mut self.lock())
+    }
+
+    /// Whether both handles refer to the same database.
+    pub fn same_database(&self, other: &DbHandle) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    /// A copy of the database's catalog of tables.
+    pub fn catalog(&self) -> Catalog {
+        self.lock().catalog().clone()
+    }
+
+    pub fn register_function(
+        &self,
+        name: &str,
+        f: impl Fn(&[String]) -> FunctionResult + Send + Sync + 'static,
+    ) {
+        self.lock().register_function(name, f)
+    }
+
+    /// Holds the lock for the whole copy.
+    #[cfg(feature = "import")]
+    pub fn copy_from<R: Read>(
+        &self,
+        table_name: &str,
+        reader: R,
+        format: IngestFormat,
+        progress: impl FnMut(&IngestProgress),
+    ) -> Result<IngestProgress> {
+        self.lock().copy_from(table_name, reader, format, progress)
+    }
+
+    /// Back up into `dir` like `Database::backup`, but hold the lock only to
+    /// snapshot the tables, not while writing files, so other threads keep
+    /// writing. The backup restores to the last operation before the snapshot;
+    /// `recover_to` replays the ones logged since from the WAL archive.
+    pub fn hot_backup(&self, dir: &str) -> Result<Vec<String>> {
+        let snapshot = self.lock().backup_snapshot()?;
+        let written = snapshot.write(dir)?;
+        if let Err(e) = self.lock().prune_archive() {
+            warn!("Pruning the WAL archive after a backup failed: {}", e);
+        }
+        Ok(written)
+    }
+
+    /// Rebuild the index and bloom filter like `Database::build_indexes`,
+    /// but from a snapshot, holding the lock only to take the snapshot and
+    /// to swap the results in.
+    pub fn rebuild_indexes(&self) {
+        let mut rebuild = self.lock().begin_index_rebuild();
+        rebuild.advance(usize::MAX);
+        self.lock().install_index_rebuild(rebuild);
+    }
+
+    pub fn insert_struct<T: Serialize>(
+        &self,
+        table_name: &str,
+        row_id: &str,
+        value: &T,
+    ) -> Result<QueryResult> {
+        self.lock().insert_struct(table_name, row_id, value)
+    }
+
+    pub fn query_as<T: DeserializeOwned>(
+        &self,
+        table_name: &str,
+        condition: Option<&str>,
+    ) -> Result<Vec<(String, T)>> {
+        self.lock().query_as(table_name, condition)
+    }
+
+    /// Open a table as a `TypedTable<T>`; see `TypedTable::open`.
+    pub fn typed_table<T: Serialize + DeserializeOwned>(
+        &self,
+        table_name: &str,
+    ) -> Result<TypedTable<T>> {
+        TypedTable::open(self, table_name)
+    }
+
+    pub fn get_rows(&self, table_name: &str, row_ids: &[&str]) -> Result<RowBatch> {
+        self.lock().get_rows(table_name, row_ids)
+    }
+
+    forward! {
+        fn create_table(table_name: &str) -> Result<String>;
+        fn create_temp_table(table_name: &str) -> Result<String>;
+        fn add_column(table_name: &str, column_name: &str) -> Result<Vec<String>>;
+        fn add_columns(
+            table_name: &str,
+            column_names: Vec<&str>,
+            datatypes: Vec<&str>
+        ) -> Result<Vec<Vec<String>>>;
+        fn add_column_online(
+            table_name: &str,
+            column_name: &str,
+            datatype: Option<&str>,
+            default: &str
+        ) -> Result<BackfillProgress>;
+        fn backfills() -> Vec<BackfillProgress>;
+        fn encrypt_column(table_name: &str, column_name: &str) -> Result<()>;
+        fn set_collation(table_name: &str, column_name: &str, collation: Collation) -> Result<()>;
+        fn set_enum(table_name: &str, column_name: &str, values: &[&str]) -> Result<()>;
+        fn insert_row(
+            table_name: &str,
+            row_id: &str,
+            data: HashMap<String, String>
+        ) -> Result<QueryResult>;
+        fn upsert_row(
+            table_name: &str,
+            row_id: &str,
+            data: HashMap<String, String>
+        ) -> Result<QueryResult>;
+        fn insert_generated(table_name: &str, data: HashMap<String, String>) -> Result<String>;
+        fn set_row_id_strategy(table_name: &str, strategy: RowIdStrategy) -> Result<()>;
+        fn set_autosave(table_name: &str, autosave: Autosave) -> Result<()>;
+        fn persistence_for(table_name: &str) -> PersistencePolicy;
+        fn save_threshold_for(table_name: &str) -> usize;
+        fn create_index(table_name: &str, column: &str, kind: IndexKind) -> Result<()>;
+        fn get_row(table_name: &str, row_id: &str) -> Result<QueryResult>;
+        fn update_row(
+            table_name: &str,
+            row_id: &str,
+            column_name: &str,
+            new_value: &str
+        ) -> Result<QueryResult>;
+        fn update_rows_by_condition(
+            table_name: &str,
+            condition: &str,
+            assignments: &HashMap<String, String>
+        ) -> Result<QueryResult>;
+        fn delete_row(table_name: &str, row_id: &str) -> Result<QueryResult>;
+        fn enable_soft_delete(table_name: &str) -> Result<()>;
+        fn enable_tenancy(table_name: &str) -> Result<()>;
+        fn soft_delete_row(table_name: &str, row_id: &str) -> Result<QueryResult>;
+        fn restore_row(table_name: &str, row_id: &str) -> Result<QueryResult>;
+        fn purge_deleted(table_name: &str, before: u64) -> Result<usize>;
+        fn row_exists(table_name: &str, row_id: &str) -> Result<bool>;
+        fn count_rows(table_name: &str, condition: Option<&str>) -> Result<usize>;
+        fn count_rows_with(
+            table_name: &str,
+            condition: Option<&str>,
+            consistency: Consistency
+        ) -> Result<usize>;
+        fn distinct_values(table_name: &str, column: &str) -> Result<BTreeMap<String, usize>>;
+        fn approx_distinct(table_name: &str, column: &str) -> Result<u64>;
+        fn top_k(table_name: &str, column: &str, k: usize) -> Result<Vec<(String, u64)>>;
+        fn within_bbox(table_name: &str, column: &str, min: GeoPoint, max: GeoPoint) -> Result<QueryResult>;
+        fn nearest(table_name: &str, column: &str, origin: GeoPoint, n: usize) -> Result<QueryResult>;
+        fn sample_rows(table_name: &str, n: usize) -> Result<QueryResult>;
+        fn scan_prefix(table_name: &str, prefix: &str) -> Result<QueryResult>;
+        fn scan_range(table_name: &str, options: &ScanOptions) -> Result<QueryResult>;
+        fn analyze(table_name: &str) -> Result<Vec<ColumnStats>>;
+        fn find_rows_by_value_in_table(
+            table_name: &str,
+            column: &str,
+            value: &str,
+            return_many: bool,
+            projection: Option<&[&str]>
+        ) -> Result<QueryResult>;
+        fn find_rows_by_value_with(
+            table_name: &str,
+            column: &str,
+            value: &str,
+            return_many: bool,
+            projection: Option<&[&str]>,
+            consistency: Consistency
+        ) -> Result<QueryResult>;
+        fn search_rows_by_condition_in_table(
+            table_name: &str,
+            condition: &str,
+            projection: Option<&[&str]>
+        ) -> Result<QueryResult>;
+        fn filter(
+            table_name: &str,
+            filter: &Filter,
+            projection: Option<&[&str]>
+        ) -> Result<QueryResult>;
+        fn filter_with_hints(
+            table_name: &str,
+            filter: &Filter,
+            projection: Option<&[&str]>,
+            hints: &QueryHints
+        ) -> Result<QueryResult>;
+        fn query(text: &str) -> Result<QueryResult>;
+        fn copy_table(src_table: &str, dst_table: &str, condition: Option<&str>) -> Result<usize>;
+        fn merge_tables(
+            src_table: &str,
+            dst_table: &str,
+            policy: ConflictPolicy
+        ) -> Result<MergeReport>;
+        fn rename_table(old_name: &str, new_name: &str) -> Result<()>;
+        fn drop_table(table_name: &str) -> Result<()>;
+        #[cfg(feature = "import")]
+        fn import_csv(table_name: &str, file_name: &str) -> Result<usize>;
+        #[cfg(feature = "import")]
+        fn bulk_load(table_name: &str, source: &str) -> Result<usize>;
+        fn check_table(table_name: &str) -> bool;
+        fn list_tables() -> Vec<String>;
+        fn table_schema(table_name: &str) -> Result<Vec<(String, Option<String>)>>;
+        fn list_indexes() -> Vec<String>;
+        fn load_table(table_name: &str, file_name: &str) -> Result<LoadReport>;
+        fn save_table(table_name: &str, file_name: &str) -> Result<Vec<String>>;
+        fn backup(dir: &str) -> Result<Vec<String>>;
+        fn checkpoint() -> Result<Vec<String>>;
+        fn unsaved_tables() -> Vec<String>;
+        fn stats() -> DatabaseStats;
+        fn vacuum(table_name: &str) -> Result<VacuumReport>;
+        fn recover_to(millis: u64) -> Result<RecoveryReport>;
+        fn check() -> IntegrityReport;
+        fn repair() -> Result<RepairReport>;
+        fn build_indexes() -> ();
+        fn build_bloom_filter() -> ();
+        fn flush_wal() -> Result<()>;
+        fn commit_wal() -> Result<()>;
+        fn persist_wal() -> Result<()>;
+        fn replay_wal() -> Result<()>;
+    }
+}
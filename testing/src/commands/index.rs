@@ -0,0 +1,264 @@
+//! Indexes on one column of one table, created with `Database::create_index`,
+//! the AND/OR filters they answer, hints overriding how a filter is run, and
+//! how fresh the indexes a query uses must be.
+
+use crate::commands::bitmap::{Bitmap, BitmapIndex};
+use crate::commands::geo::GeoIndex;
+use crate::commands::matching::MatchOptions;
+use crate::commands::Indexer::Indexer;
+use crate::table::table::Table;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexKind {
+    /// Value -> row ids; suits columns with many distinct values.
+    Hash,
+    /// Value -> bitmap of row positions; suits enum-like columns, and
+    /// combines cheaply across columns in AND/OR filters.
+    Bitmap,
+    /// Grid of `lat,lon` points; answers `Database::within_bbox` and
+    /// `Database::nearest`, not equality.
+    Geo,
+}
+
+impl FromStr for IndexKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hash" => Ok(IndexKind::Hash),
+            "bitmap" => Ok(IndexKind::Bitmap),
+            "geo" => Ok(IndexKind::Geo),
+            _ => Err(format!("unknown index kind '{}'; use hash, bitmap or geo", s)),
+        }
+    }
+}
+
+impl fmt::Display for IndexKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            IndexKind::Hash => "hash",
+            IndexKind::Bitmap => "bitmap",
+            IndexKind::Geo => "geo",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+enum IndexData {
+    Hash(Indexer),
+    Bitmap(BitmapIndex),
+    Geo(GeoIndex),
+}
+
+/// An index on a table column. It is rebuilt whenever the database has been
+/// written to since it was built.
+pub struct TableIndex {
+    pub kind: IndexKind,
+    data: Option<IndexData>,
+    /// `Database::writes` when the index was built.
+    built_at: Option<u64>,
+}
+
+impl TableIndex {
+    /// An index that has not been built yet.
+    pub fn new(kind: IndexKind) -> Self {
+        TableIndex {
+            kind,
+            data: None,
+            built_at: None,
+        }
+    }
+
+    pub fn is_current(&self, writes: u64) -> bool {
+        self.built_at == Some(writes)
+    }
+
+    pub fn build(&mut self, table: &Table, column: &str, options: MatchOptions, writes: u64) {
+        self.data = Some(match self.kind {
+            IndexKind::Hash => {
+                let mut indexer = Indexer::with_options(options);
+                for (row_id, row) in &table.rows {
+                    if let Some(value) = row.get(column) {
+                        indexer.add(value, row_id);
+                    }
+                }
+                IndexData::Hash(indexer)
+            }
+            IndexKind::Bitmap => IndexData::Bitmap(BitmapIndex::build(table, column, options)),
+            IndexKind::Geo => IndexData::Geo(GeoIndex::build(table, column)),
+        });
+        self.built_at = Some(writes);
+    }
+
+    /// Positions (in row id order) of the rows holding `value`. `row_ids` are
+    /// the table's ids in order. `None` if the index has not been built, or
+    /// is a geo index.
+    pub fn lookup(&self, value: &str, row_ids: &[&String]) -> Option<Bitmap> {
+        match self.data.as_ref()? {
+            IndexData::Geo(_) => None,
+            IndexData::Bitmap(index) => Some(index.get(value)),
+            IndexData::Hash(indexer) => Some(
+                indexer
+                    .get(value)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|id| row_ids.binary_search(&id).ok())
+                    .map(|position| position as u32)
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The grid of a built geo index.
+    pub fn geo(&self) -> Option<&GeoIndex> {
+        match self.data {
+            Some(IndexData::Geo(ref index)) => Some(index),
+            _ => None,
+        }
+    }
+
+    /// Number of distinct values (points, for a geo index), 0 if not built.
+    pub fn keys(&self) -> usize {
+        match self.data {
+            Some(IndexData::Hash(ref indexer)) => indexer.index.len(),
+            Some(IndexData::Bitmap(ref index)) => index.keys(),
+            Some(IndexData::Geo(ref index)) => index.len(),
+            None => 0,
+        }
+    }
+}
+
+/// How up to date the indexes and bloom filter answering a query must be.
+/// The global index and bloom filter are rebuilt by the `IndexEngine`, and
+/// table indexes when a filter finds them behind, so between rebuilds they
+/// miss the latest writes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Consistency {
+    /// Use them as last built, even if rows were written since: fast, but
+    /// rows written since may be missing from the results. Rows found are
+    /// still checked against their current values.
+    IndexOk,
+    /// Reflect every write: an index or bloom filter behind the latest
+    /// write is rebuilt or passed over for a scan.
+    #[default]
+    Strict,
+}
+
+impl Consistency {
+    /// Whether an index or filter built at write `built_at` (`None` if never)
+    /// may answer a query now, `writes` writes in.
+    pub fn allows(self, built_at: Option<u64>, writes: u64) -> bool {
+        match self {
+            Consistency::IndexOk => built_at.is_some(),
+            Consistency::Strict => built_at == Some(writes),
+        }
+    }
+}
+
+impl FromStr for Consistency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "index-ok" => Ok(Consistency::IndexOk),
+            "strict" => Ok(Consistency::Strict),
+            _ => Err(format!(
+                "unknown consistency '{}'; use index-ok or strict",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Consistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Consistency::IndexOk => "index-ok",
+            Consistency::Strict => "strict",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Hints given with a filter for when the automatic choice between index
+/// lookups and scans is wrong; see `Database::filter_with_hints`. A hint
+/// that can't be honored fails the query with `DatabaseError::HintRejected`
+/// saying why.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryHints {
+    /// Conditions on this column must be answered from its index.
+    pub force_index: Option<String>,
+    /// Fail instead of scanning the table for any condition.
+    pub no_full_scan: bool,
+    /// Fail instead of examining more rows than this, counting every row
+    /// of a scan and every match of an index lookup.
+    pub max_rows_examined: Option<usize>,
+    /// With `IndexOk`, hash indexes behind the latest write are used as
+    /// they are instead of rebuilt first. Bitmap indexes locate rows by
+    /// position, which writes shift, so they are always rebuilt.
+    pub consistency: Consistency,
+}
+
+/// Conditions combined with AND and OR, e.g.
+/// `status == active AND region == eu OR status == trial`. AND binds tighter.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Condition(String),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+}
+
+impl Filter {
+    pub fn condition(condition: &str) -> Self {
+        Filter::Condition(condition.to_string())
+    }
+
+    pub fn and(self, other: Filter) -> Self {
+        match self {
+            Filter::And(mut all) => {
+                all.push(other);
+                Filter::And(all)
+            }
+            first => Filter::And(vec![first, other]),
+        }
+    }
+
+    pub fn or(self, other: Filter) -> Self {
+        match self {
+            Filter::Or(mut any) => {
+                any.push(other);
+                Filter::Or(any)
+            }
+            first => Filter::Or(vec![first, other]),
+        }
+    }
+
+    /// Split a filter on the words AND and OR. A value containing either word
+    /// on its own is split too, so use a plain condition for those.
+    pub fn parse(text: &str) -> Self {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let mut any = Vec::new();
+        for group in words.split(|w| w.eq_ignore_ascii_case("OR")) {
+            let all: Vec<Filter> = group
+                .split(|w| w.eq_ignore_ascii_case("AND"))
+                .map(|condition| Filter::Condition(condition.join(" ")))
+                .collect();
+            any.push(match all.len() {
+                1 => all.into_iter().next().unwrap(),
+                _ => Filter::And(all),
+            });
+        }
+        match any.len() {
+            1 => any.into_iter().next().unwrap(),
+            _ => Filter::Or(any),
+        }
+    }
+
+    /// True if the text has more than one condition.
+    pub fn is_compound(text: &str) -> bool {
+        text.split_whitespace()
+            .any(|w| w.eq_ignore_ascii_case("AND") || w.eq_ignore_ascii_case("OR"))
+    }
+}
@@ -0,0 +1,129 @@
+//! Rebuilding the global index and bloom filter from a frozen copy of the
+//! columns they cover, so the work can run while writers keep changing the
+//! tables. Queries keep using the old index and bloom filter until
+//! `Database::install_index_rebuild` swaps both new ones in at once. The
+//! sketches of the indexed columns are rebuilt with them.
+
+use crate::commands::matching::MatchOptions;
+use crate::commands::BloomFilter::BloomFilter;
+use crate::commands::CountMinSketch::HeavyHitters;
+use crate::commands::HyperLogLog::HyperLogLog;
+use crate::commands::Indexer::Indexer;
+
+/// Counters per row of a column's count-min sketch.
+const SKETCH_WIDTH: usize = 2048;
+/// Rows of a column's count-min sketch.
+const SKETCH_DEPTH: usize = 4;
+/// Most frequent values a column's sketch keeps track of, and so the
+/// largest `k` `Database::top_k` answers in full.
+pub const TOP_K_CAPACITY: usize = 64;
+
+/// Approximate statistics of one column: its number of distinct values and
+/// its most frequent ones.
+#[derive(Clone)]
+pub struct ColumnSketch {
+    pub distinct: HyperLogLog,
+    pub frequent: HeavyHitters,
+}
+
+impl Default for ColumnSketch {
+    fn default() -> Self {
+        ColumnSketch {
+            distinct: HyperLogLog::new(),
+            frequent: HeavyHitters::new(SKETCH_WIDTH, SKETCH_DEPTH, TOP_K_CAPACITY),
+        }
+    }
+}
+
+impl ColumnSketch {
+    pub fn add(&mut self, value: &str) {
+        self.distinct.add(value);
+        self.frequent.add(value);
+    }
+}
+
+/// A rebuild in progress, taken by `Database::begin_index_rebuild`.
+pub struct IndexRebuild {
+    /// `Database::writes` when the snapshot was taken; the new index and
+    /// bloom filter describe the tables as of that write.
+    pub(crate) version: u64,
+    /// Column the index covers.
+    column: String,
+    /// `(table, value, row id)` of the index column, still to be indexed.
+    entries: Vec<(String, String, String)>,
+    /// Values of the bloom column, still to be added.
+    bloom_values: Vec<String>,
+    /// Values of the sketched columns, still to be added, each with the
+    /// position of its sketch in `sketches`.
+    sketch_values: Vec<(usize, String)>,
+    pub(crate) indexer: Indexer,
+    pub(crate) bloom: BloomFilter,
+    /// Sketches keyed by table and column.
+    pub(crate) sketches: Vec<((String, String), ColumnSketch)>,
+}
+
+/// What `Database::begin_index_rebuild` copies out of the tables to start
+/// an `IndexRebuild`.
+pub(crate) struct IndexSnapshot {
+    /// `Database::writes` when the snapshot was taken.
+    pub version: u64,
+    pub options: MatchOptions,
+    pub bloom_size: usize,
+    /// Column the index covers.
+    pub column: String,
+    /// `(table, value, row id)` of the index column.
+    pub entries: Vec<(String, String, String)>,
+    /// Values of the bloom column.
+    pub bloom_values: Vec<String>,
+    /// `(table, column)` of each sketched column.
+    pub sketch_columns: Vec<(String, String)>,
+    /// Values of the sketched columns, each with the position of its column
+    /// in `sketch_columns`.
+    pub sketch_values: Vec<(usize, String)>,
+}
+
+impl IndexRebuild {
+    pub(crate) fn new(snapshot: IndexSnapshot) -> Self {
+        IndexRebuild {
+            version: snapshot.version,
+            column: snapshot.column,
+            entries: snapshot.entries,
+            bloom_values: snapshot.bloom_values,
+            sketch_values: snapshot.sketch_values,
+            indexer: Indexer::with_options(snapshot.options),
+            bloom: BloomFilter::new(snapshot.bloom_size),
+            sketches: snapshot
+                .sketch_columns
+                .into_iter()
+                .map(|key| (key, ColumnSketch::default()))
+                .collect(),
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Number of snapshot values not indexed yet.
+    pub fn remaining(&self) -> usize {
+        self.entries.len() + self.bloom_values.len() + self.sketch_values.len()
+    }
+
+    /// Index up to `batch` more values. Returns true once all are done.
+    pub fn advance(&mut self, batch: usize) -> bool {
+        let mut left = batch;
+        while left > 0 {
+            if let Some((table, value, row_id)) = self.entries.pop() {
+                self.indexer.add_scoped(&table, &self.column, &value, &row_id);
+            } else if let Some(value) = self.bloom_values.pop() {
+                self.bloom.add(&value);
+            } else if let Some((sketch, value)) = self.sketch_values.pop() {
+                self.sketches[sketch].1.add(&value);
+            } else {
+                break;
+            }
+            left -= 1;
+        }
+        self.remaining() == 0
+    }
+}
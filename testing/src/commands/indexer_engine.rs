@@ -1,46 +1,72 @@
-use crate::commands::BloomFilter;
-use crate::commands::Indexer;
-use crate::db::Database;
-use log::{error, info};
-use std::sync::{Arc, Mutex};
-use std::thread;
+use crate::commands::db::Database;
+use crate::commands::index_rebuild::IndexRebuild;
+use crate::commands::scheduler::{MaintenanceJob, Priority};
 use std::time::Duration;
+use tracing::{debug, error};
 
+/// Values indexed per step of a rebuild.
+const BUILD_BATCH: usize = 10_000;
+
+/// The steps of one index run; the database lock is released between them.
+enum Stage {
+    Snapshot,
+    Build(Box<IndexRebuild>),
+    Save,
+}
+
+/// Rebuilds the index and bloom filter and saves them so they can be loaded
+/// later. The rebuild works from a snapshot taken in the first step, so
+/// writes between steps don't leave it half old and half new, and the
+/// results replace the old index and bloom filter together.
 pub struct IndexEngine {
-    db: Arc<Mutex<Database>>,
     interval: Duration,
+    stage: Stage,
 }
 
 impl IndexEngine {
-    pub fn new(db: Arc<Mutex<Database>>, interval: Duration) -> Self {
-        IndexEngine { db, interval }
+    pub fn new(interval: Duration) -> Self {
+        IndexEngine {
+            interval,
+            stage: Stage::Snapshot,
+        }
     }
+}
 
-    pub fn start(self) {
-        let db_clone = Arc::clone(&self.db);
-        let interval = self.interval;
-        thread::spawn(move || {
-            loop {
-                {
-                    let mut db = db_clone.lock().unwrap();
-                    db.build_indexes();
-                    db.build_bloom_filter();
-
-                    // Save indexes and bloom filter to file so they can be loaded later.
-                    if let Some(ref indexer) = db.indexer {
-                        if let Err(e) = indexer.save_to_file("indexer.json") {
-                            error!("Failed to save indexer: {}", e);
-                        }
-                    }
-                    if let Some(ref bf) = db.bloom_filter {
-                        if let Err(e) = bf.save_to_file("bloom_filter.json") {
-                            error!("Failed to save bloom filter: {}", e);
-                        }
-                    }
-                    info!("Indexes and bloom filter rebuilt and saved.");
+impl MaintenanceJob for IndexEngine {
+    fn name(&self) -> &'static str {
+        "index_engine"
+    }
+
+    fn priority(&self) -> Priority {
+        Priority::Low
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    fn step(&mut self, db: &mut Database) -> bool {
+        match std::mem::replace(&mut self.stage, Stage::Save) {
+            Stage::Snapshot => {
+                self.stage = Stage::Build(Box::new(db.begin_index_rebuild()));
+                true
+            }
+            Stage::Build(mut rebuild) => {
+                if rebuild.advance(BUILD_BATCH) {
+                    db.install_index_rebuild(*rebuild);
+                } else {
+                    self.stage = Stage::Build(rebuild);
+                }
+                true
+            }
+            Stage::Save => {
+                if let Err(e) = db.save_indexes() {
+                    error!("Failed to save indexes: {}", e);
                 }
-                thread::sleep(interval);
+                debug!("Indexes and bloom filter rebuilt and saved.");
+                self.stage = Stage::Snapshot;
+                false
             }
-        });
+        }
     }
 }
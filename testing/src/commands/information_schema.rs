@@ -0,0 +1,155 @@
+//! Read-only `information_schema.*` tables describing a database. They are
+//! built on demand from its current state, so metadata can be searched,
+//! counted and selected from like any other table.
+
+use crate::commands::db::Database;
+use crate::table::table::Table;
+use std::collections::HashMap;
+
+/// Name every virtual table is qualified with.
+pub const SCHEMA: &str = "information_schema";
+
+/// The virtual tables, without the `information_schema.` prefix.
+pub const TABLES: &[&str] = &["tables", "columns", "indexes"];
+
+/// Whether `name` is one of the `information_schema.<table>` names.
+pub fn is_virtual(name: &str) -> bool {
+    view(name).is_some()
+}
+
+/// Build the virtual table `name` from the current state of `db`, or `None`
+/// when `name` isn't one.
+pub fn table(db: &Database, name: &str) -> Option<Table> {
+    let mut out = Table::new();
+    match view(name)? {
+        "tables" => {
+            let columns = ["table_name", "column_count", "row_count", "bytes"];
+            define(&mut out, &columns);
+            for name in db.list_tables() {
+                let table = &db.tables[&name];
+                let rows = table.rows.keys().filter(|id| *id != "datatypes").count();
+                insert(
+                    &mut out,
+                    &columns,
+                    &name,
+                    &[
+                        &name,
+                        &table.columns.len().to_string(),
+                        &rows.to_string(),
+                        &table.approx_size().to_string(),
+                    ],
+                );
+            }
+            typed(
+                &mut out,
+                &[
+                    ("column_count", "int"),
+                    ("row_count", "int"),
+                    ("bytes", "int"),
+                ],
+            );
+        }
+        "columns" => {
+            let columns = [
+                "table_name",
+                "column_name",
+                "ordinal_position",
+                "datatype",
+                "collation",
+                "encrypted",
+            ];
+            define(&mut out, &columns);
+            for name in db.list_tables() {
+                let table = &db.tables[&name];
+                for (position, column) in table.columns.iter().enumerate() {
+                    let datatype = table.row_datatypes.get(column).map_or("", String::as_str);
+                    let collation = table.collations.get(column).copied().unwrap_or_default();
+                    insert(
+                        &mut out,
+                        &columns,
+                        &format!("{}.{}", name, column),
+                        &[
+                            &name,
+                            column,
+                            &(position + 1).to_string(),
+                            datatype,
+                            &collation.to_string(),
+                            &table.encrypted_columns.contains(column).to_string(),
+                        ],
+                    );
+                }
+            }
+            typed(
+                &mut out,
+                &[("ordinal_position", "int"), ("encrypted", "bool")],
+            );
+        }
+        "indexes" => {
+            let columns = ["kind", "column_name", "keys", "table_name"];
+            define(&mut out, &columns);
+            if let Some(ref indexer) = db.indexer {
+                let keys = indexer.index.len().to_string();
+                insert(
+                    &mut out,
+                    &columns,
+                    "index",
+                    &["index", &db.config.index_column, &keys],
+                );
+            }
+            if db.bloom_filter.is_some() {
+                insert(
+                    &mut out,
+                    &columns,
+                    "bloom",
+                    &["bloom", &db.config.bloom_column],
+                );
+            }
+            for ((table_name, column), index) in &db.table_indexes {
+                let kind = index.kind.to_string();
+                insert(
+                    &mut out,
+                    &columns,
+                    &format!("{}.{}", table_name, column),
+                    &[&kind, column, &index.keys().to_string(), table_name],
+                );
+            }
+            typed(&mut out, &[("keys", "int")]);
+        }
+        _ => return None,
+    }
+    Some(out)
+}
+
+/// The lowercase table part of an `information_schema.<table>` name.
+fn view(name: &str) -> Option<&'static str> {
+    let (schema, view) = name.split_once('.')?;
+    if !schema.eq_ignore_ascii_case(SCHEMA) {
+        return None;
+    }
+    TABLES
+        .iter()
+        .copied()
+        .find(|t| t.eq_ignore_ascii_case(view))
+}
+
+fn define(table: &mut Table, columns: &[&str]) {
+    for column in columns {
+        table.add_column(column);
+    }
+}
+
+fn typed(table: &mut Table, datatypes: &[(&str, &str)]) {
+    for (column, datatype) in datatypes {
+        table.add_datatype(column, datatype);
+    }
+}
+
+/// Add a row holding `values` for `columns`, in order.
+fn insert(table: &mut Table, columns: &[&str], row_id: &str, values: &[&str]) {
+    let row: HashMap<String, String> = columns
+        .iter()
+        .zip(values)
+        .map(|(c, v)| (c.to_string(), v.to_string()))
+        .collect();
+    table.insert_row(row_id, row);
+}
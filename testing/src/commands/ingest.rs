@@ -0,0 +1,153 @@
+//! Row sources for `Database::copy_from`. Rows are parsed one at a time from
+//! any reader, so only the chunk being inserted is held in memory.
+
+use crate::commands::db::{DatabaseError, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Lines, Read};
+use std::str::FromStr;
+
+/// A row id and its column values.
+pub type Row = (String, HashMap<String, String>);
+
+/// Layout of a stream passed to `Database::copy_from`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IngestFormat {
+    /// A header of `row_id` then column names, then one row per record.
+    Csv,
+    /// One JSON object per line holding a `row_id` key; every other key is a
+    /// column. Nulls are left out and other non-string values are stored as
+    /// their JSON text.
+    Ndjson,
+}
+
+impl FromStr for IngestFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(IngestFormat::Csv),
+            "ndjson" | "jsonl" => Ok(IngestFormat::Ndjson),
+            other => Err(format!(
+                "unknown format '{}', expected csv or ndjson",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for IngestFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IngestFormat::Csv => write!(f, "csv"),
+            IngestFormat::Ndjson => write!(f, "ndjson"),
+        }
+    }
+}
+
+/// Totals reported to the progress callback after each chunk.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IngestProgress {
+    pub rows: usize,
+    pub chunks: usize,
+}
+
+/// Iterator over the rows of a CSV or NDJSON stream. Errors name the record
+/// (1-based, not counting a CSV header) they were found in.
+pub struct RowReader<R: Read> {
+    source: Source<R>,
+    record: usize,
+}
+
+enum Source<R: Read> {
+    Csv {
+        reader: csv::Reader<R>,
+        columns: Vec<String>,
+    },
+    Ndjson(Lines<BufReader<R>>),
+}
+
+impl<R: Read> RowReader<R> {
+    /// Start reading `reader`; a CSV header is read right away.
+    pub fn new(reader: R, format: IngestFormat) -> Result<Self> {
+        let source = match format {
+            IngestFormat::Csv => {
+                let mut reader = csv::ReaderBuilder::new()
+                    .has_headers(true)
+                    .from_reader(reader);
+                let headers = reader
+                    .headers()
+                    .map_err(|e| DatabaseError::IngestError(0, e.to_string()))?;
+                if headers.get(0) != Some("row_id") {
+                    return Err(DatabaseError::IngestError(
+                        0,
+                        "the first CSV column must be row_id".to_string(),
+                    ));
+                }
+                let columns = headers.iter().skip(1).map(str::to_string).collect();
+                Source::Csv { reader, columns }
+            }
+            IngestFormat::Ndjson => Source::Ndjson(BufReader::new(reader).lines()),
+        };
+        Ok(RowReader { source, record: 0 })
+    }
+
+    fn parse_json(line: &str) -> std::result::Result<Row, String> {
+        let object: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(line).map_err(|e| e.to_string())?;
+        let mut row_id = None;
+        let mut data = HashMap::new();
+        for (key, value) in object {
+            let value = match value {
+                serde_json::Value::Null => continue,
+                serde_json::Value::String(s) => s,
+                other => other.to_string(),
+            };
+            if key == "row_id" {
+                row_id = Some(value);
+            } else {
+                data.insert(key, value);
+            }
+        }
+        let row_id = row_id.ok_or_else(|| "missing row_id".to_string())?;
+        Ok((row_id, data))
+    }
+}
+
+impl<R: Read> Iterator for RowReader<R> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = match &mut self.source {
+            Source::Csv { reader, columns } => {
+                let mut record = csv::StringRecord::new();
+                self.record += 1;
+                match reader.read_record(&mut record) {
+                    Ok(false) => return None,
+                    Ok(true) => Ok((
+                        record[0].to_string(),
+                        columns
+                            .iter()
+                            .zip(record.iter().skip(1))
+                            .map(|(c, v)| (c.clone(), v.to_string()))
+                            .collect(),
+                    )),
+                    Err(e) => Err(e.to_string()),
+                }
+            }
+            Source::Ndjson(lines) => loop {
+                let line = match lines.next()? {
+                    Ok(line) => line,
+                    Err(e) => break Err(e.to_string()),
+                };
+                // Blank lines separate nothing; skip them without counting.
+                if line.trim().is_empty() {
+                    continue;
+                }
+                self.record += 1;
+                break Self::parse_json(&line);
+            },
+        };
+        Some(row.map_err(|e| DatabaseError::IngestError(self.record, e)))
+    }
+}
@@ -0,0 +1,159 @@
+use std::fmt;
+
+/// What kind of problem `Database::check()` found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProblemKind {
+    /// A file could not be read, parsed or decrypted.
+    Unreadable,
+    /// Table file header or record width does not match the table's columns.
+    Schema,
+    /// A value does not match its column's declared datatype.
+    Datatype,
+    DuplicateRow,
+    /// A WAL record or file whose checksum does not match its contents.
+    Checksum,
+    /// A file that ends partway through a record.
+    Truncated,
+    /// A WAL record with an unknown operation or the wrong number of fields.
+    MalformedWal,
+    /// An index entry that points at a missing row or a row with a different value.
+    DanglingIndex,
+    /// A value that is in a table but not in the bloom filter.
+    MissingBloomEntry,
+}
+
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    /// File and line, or the in-memory structure, where the problem was found.
+    pub location: String,
+    pub detail: String,
+}
+
+/// Structured result of `Database::check()`.
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityReport {
+    pub problems: Vec<Problem>,
+    pub files_checked: usize,
+    pub wal_records_checked: usize,
+    pub index_entries_checked: usize,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn add(
+        &mut self,
+        kind: ProblemKind,
+        location: impl Into<String>,
+        detail: impl Into<String>,
+    ) {
+        self.problems.push(Problem {
+            kind,
+            location: location.into(),
+            detail: detail.into(),
+        });
+    }
+}
+
+impl fmt::Display for IntegrityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for p in &self.problems {
+            writeln!(f, "{:?} at {}: {}", p.kind, p.location, p.detail)?;
+        }
+        writeln!(
+            f,
+            "Checked {} files, {} WAL records, {} index entries: {} problems.",
+            self.files_checked,
+            self.wal_records_checked,
+            self.index_entries_checked,
+            self.problems.len()
+        )
+    }
+}
+
+/// One file rewritten by `Database::repair()`.
+#[derive(Debug, Clone)]
+pub struct RepairedFile {
+    pub file: String,
+    pub records_kept: usize,
+    pub records_dropped: usize,
+    /// Where the damaged original was moved.
+    pub quarantined_to: String,
+}
+
+/// Structured result of `Database::repair()`.
+#[derive(Debug, Clone, Default)]
+pub struct RepairReport {
+    pub files: Vec<RepairedFile>,
+}
+
+impl fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for r in &self.files {
+            writeln!(
+                f,
+                "{}: kept {} records, dropped {}; original moved to {}",
+                r.file, r.records_kept, r.records_dropped, r.quarantined_to
+            )?;
+        }
+        writeln!(f, "Repaired {} files.", self.files.len())
+    }
+}
+
+/// Structured result of `Database::load_table()`.
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    pub table: String,
+    /// The table file, then its delta file if one was applied.
+    pub files: Vec<String>,
+    pub rows_loaded: usize,
+    /// Records left out because they could not be read or had the wrong width.
+    pub rows_skipped: usize,
+    /// Whether every file with a checksum sidecar matched it; `None` if none had one.
+    pub checksum_verified: Option<bool>,
+    pub problems: Vec<Problem>,
+}
+
+impl LoadReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn add(
+        &mut self,
+        kind: ProblemKind,
+        location: impl Into<String>,
+        detail: impl Into<String>,
+    ) {
+        self.problems.push(Problem {
+            kind,
+            location: location.into(),
+            detail: detail.into(),
+        });
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for p in &self.problems {
+            writeln!(f, "{:?} at {}: {}", p.kind, p.location, p.detail)?;
+        }
+        let checksum = match self.checksum_verified {
+            Some(true) => "checksum verified",
+            Some(false) => "checksum mismatch",
+            None => "no checksum",
+        };
+        writeln!(
+            f,
+            "Loaded {} rows into '{}' ({} skipped, {}): {} problems.",
+            self.rows_loaded,
+            self.table,
+            self.rows_skipped,
+            checksum,
+            self.problems.len()
+        )
+    }
+}
@@ -0,0 +1,229 @@
+//! Taking locks the same way everywhere. A lock whose holder panicked is
+//! recovered instead of failing every later caller, and `TrackedMutex`
+//! remembers who holds it, so a thread asking again for a lock it already
+//! holds panics naming where it took it instead of hanging, and a caller
+//! that gives up waiting (`lock_timeout`) can say what it was waiting on.
+
+use crate::commands::clock::Instant;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::panic::Location;
+use std::sync::{
+    Condvar, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+use tracing::warn;
+
+/// Longest pause between attempts of `TrackedMutex::lock_timeout`.
+const MAX_BACKOFF: Duration = Duration::from_millis(5);
+
+/// Lock `mutex`, recovering it if a holder panicked. The data is whatever
+/// the panicking holder left, which every caller here keeps consistent
+/// between statements.
+pub fn recover<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        warn!("Recovering a lock poisoned by a panic.");
+        mutex.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Read-lock `lock`, recovering it like `recover`.
+pub fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|poisoned| {
+        warn!("Recovering a lock poisoned by a panic.");
+        lock.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Write-lock `lock`, recovering it like `recover`.
+pub fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|poisoned| {
+        warn!("Recovering a lock poisoned by a panic.");
+        lock.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Wait on `condvar`, getting the lock back even if it was poisoned meanwhile.
+pub fn wait<'a, T>(condvar: &Condvar, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+    condvar.wait(guard).unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Who holds a `TrackedMutex`.
+#[derive(Debug, Clone)]
+pub struct Holder {
+    /// Name of the holding thread, or its id if it has none.
+    pub thread: String,
+    thread_id: ThreadId,
+    /// Where the lock was taken.
+    pub at: &'static Location<'static>,
+    pub since: Instant,
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "thread '{}', which took it at {} {:?} ago",
+            self.thread,
+            self.at,
+            self.since.elapsed()
+        )
+    }
+}
+
+/// `TrackedMutex::lock_timeout` gave up.
+#[derive(Debug, Clone)]
+pub struct LockTimeout {
+    pub name: &'static str,
+    pub waited: Duration,
+    /// The holder when it gave up, if any still did.
+    pub holder: Option<Holder>,
+}
+
+impl fmt::Display for LockTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "gave up on lock '{}' after {:?}", self.name, self.waited)?;
+        if let Some(holder) = &self.holder {
+            write!(f, "; held by {}", holder)?;
+        }
+        Ok(())
+    }
+}
+
+/// A mutex that recovers from poisoning and records its holder.
+pub struct TrackedMutex<T> {
+    name: &'static str,
+    inner: Mutex<T>,
+    holder: Mutex<Option<Holder>>,
+}
+
+impl<T> TrackedMutex<T> {
+    pub fn new(name: &'static str, value: T) -> Self {
+        TrackedMutex {
+            name,
+            inner: Mutex::new(value),
+            holder: Mutex::new(None),
+        }
+    }
+
+    /// Lock, waiting as long as it takes.
+    ///
+    /// # Panics
+    /// If this thread already holds the lock, which would never return.
+    #[track_caller]
+    pub fn lock(&self) -> TrackedGuard<'_, T> {
+        let guard = match self.inner.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(poisoned)) => self.recovered(poisoned),
+            Err(TryLockError::WouldBlock) => {
+                self.check_reentry();
+                self.inner
+                    .lock()
+                    .unwrap_or_else(|poisoned| self.recovered(poisoned))
+            }
+        };
+        self.acquired(guard)
+    }
+
+    /// Lock, or give up after `timeout` saying who holds it.
+    ///
+    /// # Panics
+    /// Like `lock`.
+    #[track_caller]
+    pub fn lock_timeout(&self, timeout: Duration) -> Result<TrackedGuard<'_, T>, LockTimeout> {
+        let started = Instant::now();
+        let mut backoff = Duration::from_micros(50);
+        loop {
+            match self.inner.try_lock() {
+                Ok(guard) => return Ok(self.acquired(guard)),
+                Err(TryLockError::Poisoned(poisoned)) => {
+                    return Ok(self.acquired(self.recovered(poisoned)))
+                }
+                Err(TryLockError::WouldBlock) => self.check_reentry(),
+            }
+            let waited = started.elapsed();
+            if waited >= timeout {
+                return Err(LockTimeout {
+                    name: self.name,
+                    waited,
+                    holder: self.holder(),
+                });
+            }
+            thread::sleep(backoff.min(timeout - waited));
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Who holds the lock now, if anyone.
+    pub fn holder(&self) -> Option<Holder> {
+        recover(&self.holder).clone()
+    }
+
+    fn recovered<'a>(&self, poisoned: PoisonError<MutexGuard<'a, T>>) -> MutexGuard<'a, T> {
+        warn!(
+            "Lock '{}' was poisoned by a panic while held; recovering it.",
+            self.name
+        );
+        self.inner.clear_poison();
+        poisoned.into_inner()
+    }
+
+    #[track_caller]
+    fn check_reentry(&self) {
+        if let Some(holder) = self.holder() {
+            if holder.thread_id == thread::current().id() {
+                panic!(
+                    "deadlock: lock '{}' taken again at {} by {}",
+                    self.name,
+                    Location::caller(),
+                    holder
+                );
+            }
+        }
+    }
+
+    #[track_caller]
+    fn acquired<'a>(&'a self, guard: MutexGuard<'a, T>) -> TrackedGuard<'a, T> {
+        let current = thread::current();
+        *recover(&self.holder) = Some(Holder {
+            thread: current
+                .name()
+                .map_or_else(|| format!("{:?}", current.id()), str::to_string),
+            thread_id: current.id(),
+            at: Location::caller(),
+            since: Instant::now(),
+        });
+        TrackedGuard { guard, lock: self }
+    }
+}
+
+/// A held `TrackedMutex`; it is released when this is dropped.
+pub struct TrackedGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+    lock: &'a TrackedMutex<T>,
+}
+
+impl<T> Drop for TrackedGuard<'_, T> {
+    fn drop(&mut self) {
+        // Cleared before the guard field drops and lets the next holder in.
+        *recover(&self.lock.holder) = None;
+    }
+}
+
+impl<T> Deref for TrackedGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for TrackedGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
@@ -0,0 +1,242 @@
+use crate::commands::config::DatabaseConfig;
+use crate::commands::db::{Database, DatabaseError, Result};
+use crate::commands::handle::DbHandle;
+use crate::commands::shards::ShardedDatabase;
+use crate::commands::storage::Storage;
+use crate::commands::twophase::{Coordinator, TxWrite};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use tracing::info;
+
+pub const DEFAULT_DATABASE: &str = "main";
+
+/// Owns several logical databases, each with its own directory, WAL, tables and
+/// background engines. Tables are addressed as `dbname.table`, or just `table`
+/// for the current database, and resolve to the shard that owns them.
+pub struct DatabaseManager {
+    root_dir: String,
+    base_config: DatabaseConfig,
+    start_engines: bool,
+    databases: HashMap<String, ShardedDatabase>,
+    attached: HashSet<String>,
+    current: String,
+    encryption_key: Option<[u8; 32]>,
+    /// Where `transactions.log` lives, whichever databases a transaction spans.
+    storage: Arc<dyn Storage>,
+}
+
+impl DatabaseManager {
+    /// `base_config` is used as a template for new databases; each one gets its
+    /// own `data_dir` under `root_dir`.
+    pub fn new(root_dir: &str, base_config: DatabaseConfig, start_engines: bool) -> Self {
+        DatabaseManager {
+            root_dir: root_dir.to_string(),
+            storage: base_config.storage.open(),
+            base_config,
+            start_engines,
+            databases: HashMap::new(),
+            attached: HashSet::new(),
+            current: DEFAULT_DATABASE.to_string(),
+            encryption_key: None,
+        }
+    }
+
+    /// Key given to every database created or attached from now on.
+    pub fn set_encryption_key(&mut self, key: [u8; 32]) {
+        self.encryption_key = Some(key);
+    }
+
+    /// Keep `transactions.log` on `storage` instead of a new backend of the
+    /// template's kind. Set it before any transaction runs: decisions logged
+    /// on the old backend are not read from the new one.
+    pub fn set_storage(&mut self, storage: Arc<dyn Storage>) {
+        self.storage = storage;
+    }
+
+    fn open_database(&self, config: DatabaseConfig) -> Database {
+        let mut db = Database::with_config(config);
+        if let Some(key) = self.encryption_key {
+            db.set_encryption_key(key);
+        }
+        db
+    }
+
+    /// Register an already running database under `name`.
+    pub fn register(&mut self, name: &str, db: impl Into<ShardedDatabase>) {
+        self.databases.insert(name.to_string(), db.into());
+    }
+
+    /// Create a database with the template's shard count.
+    pub fn create_database(&mut self, name: &str) -> Result<ShardedDatabase> {
+        if self.databases.contains_key(name) {
+            return Err(DatabaseError::DatabaseAlreadyExists(name.to_string()));
+        }
+        let data_dir = Path::new(&self.root_dir)
+            .join(name)
+            .to_string_lossy()
+            .into_owned();
+        let mut builder = Database::builder()
+            .config(self.base_config.clone())
+            .data_dir(&data_dir)
+            .engines(self.start_engines);
+        if let Some(key) = self.encryption_key {
+            builder = builder.encryption_key(key);
+        }
+        let db = builder.open()?;
+
+        self.databases.insert(name.to_string(), db.clone());
+        info!("Database '{}' created in '{}'.", name, data_dir);
+        Ok(db)
+    }
+
+    pub fn get(&self, name: &str) -> Result<ShardedDatabase> {
+        self.databases
+            .get(name)
+            .cloned()
+            .ok_or(DatabaseError::DatabaseDoesNotExist(name.to_string()))
+    }
+
+    /// Forget a database after saving it and stopping its engines (see
+    /// `ShardedDatabase::close`). Its files stay on disk.
+    pub fn drop_database(&mut self, name: &str) -> Result<()> {
+        if name == self.current {
+            return Err(DatabaseError::DatabaseInUse(name.to_string()));
+        }
+        self.get(name)?.close()?;
+        self.databases.remove(name);
+        Ok(())
+    }
+
+    /// Attach an existing database directory under `alias`, loading every table
+    /// file it contains. Read-only attachments reject all writes and saves.
+    pub fn attach(&mut self, path: &str, alias: &str, read_only: bool) -> Result<Vec<String>> {
+        if self.databases.contains_key(alias) {
+            return Err(DatabaseError::DatabaseAlreadyExists(alias.to_string()));
+        }
+        if !Path::new(path).is_dir() {
+            return Err(DatabaseError::DatabaseDoesNotExist(path.to_string()));
+        }
+        let mut db = self.open_database(self.base_config.clone().data_dir(path));
+        if !read_only {
+            Database::prepare_data_dir(&db.config, db.storage.as_ref())?;
+        }
+        let tables = db.load_tables_from_data_dir()?;
+        db.read_only = read_only;
+
+        self.databases
+            .insert(alias.to_string(), DbHandle::new(db).into());
+        self.attached.insert(alias.to_string());
+        info!(
+            "Attached '{}' as '{}' ({} tables, {}).",
+            path,
+            alias,
+            tables.len(),
+            if read_only { "read-only" } else { "read-write" }
+        );
+        Ok(tables)
+    }
+
+    /// Detach a database previously added with `attach`.
+    pub fn detach(&mut self, alias: &str) -> Result<()> {
+        if !self.attached.contains(alias) {
+            return Err(DatabaseError::DatabaseDoesNotExist(alias.to_string()));
+        }
+        self.drop_database(alias)?;
+        self.attached.remove(alias);
+        info!("Detached '{}'.", alias);
+        Ok(())
+    }
+
+    /// Copy a table (schema and rows) from one database to another, e.g.
+    /// `archive.users` -> `main.users`. Returns the number of rows copied.
+    pub fn copy_table(&self, src: &str, dst: &str) -> Result<usize> {
+        let (src_db, src_table) = self.resolve(src)?;
+        let (dst_db, dst_table) = self.resolve(dst)?;
+
+        if src_db.same_database(&dst_db) {
+            return src_db.copy_table(src_table, dst_table, None);
+        }
+
+        // Clone under the source lock only, then write under the destination lock.
+        let table = src_db.lock().get_table(src_table)?.clone();
+
+        let mut dst_db = dst_db.lock();
+        dst_db.create_table(dst_table)?;
+        for col in &table.columns {
+            dst_db.add_column(dst_table, col)?;
+        }
+        if let Some(dst) = dst_db.tables.get_mut(dst_table) {
+            dst.row_datatypes = table.row_datatypes.clone();
+        }
+        for (row_id, row) in &table.rows {
+            dst_db.insert_row(dst_table, row_id, row.clone())?;
+        }
+        Ok(table.rows.len())
+    }
+
+    /// Apply writes to tables of several databases atomically, by two-phase
+    /// commit. Table names may be qualified as in `resolve`. Decisions are
+    /// logged to `transactions.log` under `root_dir`. Returns the transaction id.
+    pub fn write_atomically(&self, writes: Vec<TxWrite>) -> Result<String> {
+        let mut parts: Vec<(DbHandle, Vec<TxWrite>)> = Vec::new();
+        for write in writes {
+            let (db, table) = self.resolve(write.table())?;
+            let table = table.to_string();
+            parts.push((db, vec![write.with_table(&table)]));
+        }
+        if parts.is_empty() {
+            return Ok(String::new());
+        }
+        self.coordinator().execute(parts)
+    }
+
+    /// Settle transactions a crash left prepared on any database. Returns how
+    /// many were settled.
+    pub fn recover_transactions(&self) -> Result<usize> {
+        let shards: Vec<DbHandle> = self
+            .databases
+            .values()
+            .flat_map(|db| db.shards().iter().cloned())
+            .collect();
+        if shards.is_empty() {
+            return Ok(0);
+        }
+        self.coordinator().recover(&shards)
+    }
+
+    fn coordinator(&self) -> Coordinator {
+        let log_path = Path::new(&self.root_dir).join("transactions.log");
+        Coordinator::new(self.storage.clone(), &log_path.to_string_lossy())
+    }
+
+    pub fn list_databases(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.databases.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn current(&self) -> &str {
+        &self.current
+    }
+
+    /// Switch the database used for unqualified table names.
+    pub fn use_database(&mut self, name: &str) -> Result<()> {
+        self.get(name)?;
+        self.current = name.to_string();
+        Ok(())
+    }
+
+    /// Split `dbname.table` into the handle of the shard owning the table and
+    /// the table name. Names without a known database prefix resolve against
+    /// the current database.
+    pub fn resolve<'a>(&self, qualified: &'a str) -> Result<(DbHandle, &'a str)> {
+        if let Some((db_name, table)) = qualified.split_once('.') {
+            if let Some(db) = self.databases.get(db_name) {
+                return Ok((db.shard_for(table).clone(), table));
+            }
+        }
+        let db = self.get(&self.current)?;
+        Ok((db.shard_for(qualified).clone(), qualified))
+    }
+}
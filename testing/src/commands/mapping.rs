@@ -0,0 +1,163 @@
+//! Serde mapping between Rust types and rows, for `Database::insert_struct`
+//! and `Database::query_as`. Every field is a column; values are stored as
+//! text and parsed back into the field's type when a row is read.
+
+use serde::de::value::{Error as ValueError, MapDeserializer, StringDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::{forward_to_deserialize_any, Deserializer, Serialize};
+use std::cell::Cell;
+use std::collections::HashMap;
+
+/// Short name of `T` for messages, e.g. `Order` rather than `app::model::Order`.
+pub fn type_name<T>() -> &'static str {
+    let full = std::any::type_name::<T>();
+    let base = full.split('<').next().unwrap_or(full);
+    base.rsplit("::").next().unwrap_or(base)
+}
+
+/// Turn a value that serializes as a struct or map into a row. `None` fields
+/// are left out; strings are stored as they are and anything else as JSON.
+pub fn to_row<T: Serialize>(value: &T) -> Result<HashMap<String, String>, String> {
+    let serde_json::Value::Object(fields) =
+        serde_json::to_value(value).map_err(|e| e.to_string())?
+    else {
+        return Err(format!(
+            "{} does not serialize as a struct",
+            type_name::<T>()
+        ));
+    };
+    Ok(fields
+        .into_iter()
+        .filter_map(|(field, value)| match value {
+            serde_json::Value::Null => None,
+            serde_json::Value::String(s) => Some((field, s)),
+            other => Some((field, other.to_string())),
+        })
+        .collect())
+}
+
+/// Build a `T` from a row. Columns `T` has no field for are ignored; an
+/// empty value reads as `None` for optional fields.
+pub fn from_row<T: DeserializeOwned>(row: &HashMap<String, String>) -> Result<T, String> {
+    let cells = row
+        .iter()
+        .map(|(column, value)| (column.as_str(), CellDeserializer(value.clone())));
+    T::deserialize(MapDeserializer::<_, ValueError>::new(cells)).map_err(|e| e.to_string())
+}
+
+/// Field names of a struct type, read from its `Deserialize` impl; `None`
+/// for types that don't deserialize as a struct.
+pub fn struct_fields<T: DeserializeOwned>() -> Option<&'static [&'static str]> {
+    let probe = FieldProbe(Cell::new(None));
+    let _ = T::deserialize(&probe);
+    probe.0.get()
+}
+
+/// One stored value, parsed on demand into whatever type the field asks for.
+struct CellDeserializer(String);
+
+macro_rules! parse_cell {
+    ($($method:ident => $visit:ident: $ty:ty,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+                match self.0.trim().parse::<$ty>() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(_) => Err(de::Error::custom(format!(
+                        "'{}' is not a valid {}",
+                        self.0,
+                        stringify!($ty)
+                    ))),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for CellDeserializer {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        visitor.visit_string(self.0)
+    }
+
+    parse_cell! {
+        deserialize_bool => visit_bool: bool,
+        deserialize_i8 => visit_i8: i8,
+        deserialize_i16 => visit_i16: i16,
+        deserialize_i32 => visit_i32: i32,
+        deserialize_i64 => visit_i64: i64,
+        deserialize_u8 => visit_u8: u8,
+        deserialize_u16 => visit_u16: u16,
+        deserialize_u32 => visit_u32: u32,
+        deserialize_u64 => visit_u64: u64,
+        deserialize_f32 => visit_f32: f32,
+        deserialize_f64 => visit_f64: f64,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        if self.0.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Unit variants are stored by name.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        let name: StringDeserializer<ValueError> = self.0.into_deserializer();
+        visitor.visit_enum(name)
+    }
+
+    forward_to_deserialize_any! {
+        char str string bytes byte_buf unit unit_struct seq tuple tuple_struct
+        map struct identifier ignored_any i128 u128
+    }
+}
+
+impl<'de> IntoDeserializer<'de, ValueError> for CellDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// Records the field list a struct asks for, then stops deserializing.
+struct FieldProbe(Cell<Option<&'static [&'static str]>>);
+
+impl<'de> Deserializer<'de> for &FieldProbe {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ValueError> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        self.0.set(Some(fields));
+        Err(de::Error::custom("probed"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
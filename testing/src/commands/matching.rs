@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// How text is compared by equality and `LIKE` conditions and keyed in the
+/// index. The default is an exact, byte-for-byte comparison.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MatchOptions {
+    pub case_insensitive: bool,
+    /// Ignore leading and trailing whitespace.
+    pub trim: bool,
+    /// Compare in Unicode NFC form, so composed and decomposed accents match.
+    pub normalize_unicode: bool,
+}
+
+impl MatchOptions {
+    pub fn is_exact(&self) -> bool {
+        *self == MatchOptions::default()
+    }
+
+    /// The form a value is compared (and indexed) in.
+    pub fn fold<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        if self.is_exact() {
+            return Cow::Borrowed(value);
+        }
+        let mut folded = if self.trim { value.trim() } else { value }.to_string();
+        if self.normalize_unicode {
+            folded = folded.nfc().collect();
+        }
+        if self.case_insensitive {
+            folded = folded.to_lowercase();
+        }
+        Cow::Owned(folded)
+    }
+
+    pub fn eq(&self, value: &str, other: &str) -> bool {
+        self.fold(value) == self.fold(other)
+    }
+
+    /// SQL `LIKE`: `%` matches any run of characters, `_` exactly one.
+    pub fn like(&self, value: &str, pattern: &str) -> bool {
+        let value: Vec<char> = self.fold(value).chars().collect();
+        let pattern: Vec<char> = self.fold(pattern).chars().collect();
+        // matched[j]: the value prefix seen so far matches pattern[..j].
+        let mut matched = vec![false; pattern.len() + 1];
+        matched[0] = true;
+        for j in 1..=pattern.len() {
+            matched[j] = matched[j - 1] && pattern[j - 1] == '%';
+        }
+        for c in value {
+            let mut next = vec![false; pattern.len() + 1];
+            for j in 1..=pattern.len() {
+                next[j] = match pattern[j - 1] {
+                    '%' => next[j - 1] || matched[j],
+                    '_' => matched[j - 1],
+                    p => matched[j - 1] && p == c,
+                };
+            }
+            matched = next;
+        }
+        matched[pattern.len()]
+    }
+}
+
+/// Ordering used for a text column's comparisons, chosen per column.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Collation {
+    /// Byte order.
+    #[default]
+    Binary,
+    /// Unicode lowercase, then byte order.
+    NoCase,
+    /// Dictionary-style: accents and case are ignored first ("e" < "É" < "f"),
+    /// then used only to break ties. An approximation of the Unicode Collation
+    /// Algorithm without locale tailoring.
+    Unicode,
+}
+
+impl Collation {
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            Collation::Binary => a.cmp(b),
+            Collation::NoCase => a.to_lowercase().cmp(&b.to_lowercase()),
+            Collation::Unicode => Self::base_letters(a)
+                .cmp(&Self::base_letters(b))
+                .then_with(|| a.to_lowercase().cmp(&b.to_lowercase()))
+                .then_with(|| a.cmp(b)),
+        }
+    }
+
+    /// Lowercase with accents removed: decompose, then drop combining marks.
+    fn base_letters(value: &str) -> String {
+        value
+            .nfd()
+            .filter(|c| !is_combining_mark(*c))
+            .collect::<String>()
+            .to_lowercase()
+    }
+}
+
+impl FromStr for Collation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "binary" => Ok(Collation::Binary),
+            "nocase" => Ok(Collation::NoCase),
+            "unicode" => Ok(Collation::Unicode),
+            _ => Err(format!(
+                "unknown collation '{}'; use binary, nocase or unicode",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Collation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Collation::Binary => "binary",
+            Collation::NoCase => "nocase",
+            Collation::Unicode => "unicode",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -1,6 +1,57 @@
 pub mod BloomFilter;
+pub mod CountMinSketch;
+pub mod HyperLogLog;
 pub mod Indexer;
+#[cfg(feature = "native")]
+pub mod atomic;
+#[cfg(feature = "native")]
+pub mod backfill_engine;
+pub mod backup;
+pub mod bench;
+pub mod bitmap;
+pub mod blobs;
+pub mod builder;
+pub mod catalog;
+#[cfg(feature = "native")]
+pub mod checkpoint_engine;
+pub mod checksum;
+pub mod clock;
+pub mod cold;
+pub mod condition;
+pub mod config;
+pub mod crypto;
 pub mod db;
+pub mod expr;
+pub mod functions;
+pub mod geo;
+pub mod handle;
+pub mod index;
+pub mod index_rebuild;
+#[cfg(feature = "index-engine")]
 pub mod indexer_engine;
+pub mod information_schema;
+#[cfg(feature = "import")]
+pub mod ingest;
+pub mod integrity;
+pub mod locking;
+pub mod manager;
+pub mod mapping;
+pub mod matching;
+#[cfg(feature = "native")]
+pub mod repl;
+pub mod result;
+pub mod row_id;
+#[cfg(feature = "native")]
+pub mod save_engine;
+#[cfg(feature = "native")]
+pub mod scheduler;
+pub mod session;
+pub mod shards;
+pub mod stats;
+pub mod storage;
+pub mod transfer;
+pub mod twophase;
+pub mod typed;
+#[cfg(feature = "native")]
 pub mod walengine;
 pub mod walwriter;
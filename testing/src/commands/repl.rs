@@ -0,0 +1,947 @@
+use crate::commands::config::{Autosave, PersistencePolicy};
+use crate::commands::crypto;
+use crate::commands::db::{ConflictPolicy, Database, ScanOptions};
+use crate::commands::expr;
+use crate::commands::handle::DbHandle;
+use crate::commands::index::{Filter, IndexKind};
+use crate::commands::information_schema;
+use crate::commands::locking;
+#[cfg(feature = "import")]
+use crate::commands::ingest::{IngestFormat, IngestProgress};
+use crate::commands::manager::DatabaseManager;
+use crate::commands::matching::Collation;
+use crate::commands::row_id::RowIdStrategy;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::collections::HashMap;
+#[cfg(feature = "import")]
+use std::fs::File;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+const HISTORY_FILE: &str = ".rustdb_history";
+
+/// Keywords offered by tab completion, in the order they are listed by HELP.
+#[rustfmt::skip]
+const KEYWORDS: &[&str] = &[
+    "CREATE", "TABLE", "INDEX", "ADD", "COLUMN", "DEFAULT", "INSERT", "UPSERT", "GET", "UPDATE", "FIND", "SEARCH",
+    "EXISTS", "COUNT", "DISTINCT", "ANALYZE", "SAMPLE", "SCAN", "RANGE", "DESC", "LIMIT", "CLONE", "RENAME", "MERGE", "COLLATE",
+    "ENUM", "ROWID", "AUTOSAVE", "SHOW", "SAVE", "DATABASE", "USE", "DROP", "ATTACH", "DETACH", "AS", "READONLY", "COPY",
+    "ENCRYPT", "VACUUM", "COLD", "WHERE", "AND", "OR", "SET", "SELECT", "LIKE", "MATCHES", "IN", "FROM", "UNION",
+    "INTERSECT", "EXCEPT", "ALL", "HELP", "EXIT",
+];
+
+/// Dot-commands for introspection and maintenance.
+const META_COMMANDS: &[&str] = &[
+    ".tables",
+    ".schema",
+    ".indexes",
+    ".import",
+    ".load",
+    ".copy",
+    ".backup",
+    ".recover",
+    ".databases",
+    ".stats",
+    ".check",
+    ".repair",
+    ".checkpoint",
+    ".backfills",
+    ".functions",
+];
+
+/// Completes keywords, table names and column names pulled live from the database schema.
+pub struct SchemaHelper {
+    manager: Arc<Mutex<DatabaseManager>>,
+}
+
+impl SchemaHelper {
+    fn candidates(&self, line: &str, word: &str) -> Vec<String> {
+        let manager = locking::recover(&self.manager);
+        let upper = word.to_uppercase();
+        let mut out: Vec<String> = KEYWORDS
+            .iter()
+            .filter(|kw| kw.starts_with(&upper))
+            .chain(META_COMMANDS.iter().filter(|cmd| cmd.starts_with(word)))
+            .map(|kw| kw.to_string())
+            .collect();
+
+        // Tables of the current database complete bare; every database's tables
+        // also complete in their qualified `dbname.table` form.
+        for db_name in manager.list_databases() {
+            let Ok(db) = manager.get(&db_name) else {
+                continue;
+            };
+            for table in db.list_tables() {
+                let qualified = format!("{}.{}", db_name, table);
+                if db_name == manager.current() && table.starts_with(word) {
+                    out.push(table.clone());
+                }
+                if qualified.starts_with(word) {
+                    out.push(qualified);
+                }
+            }
+        }
+
+        for view in information_schema::TABLES {
+            let name = format!("{}.{}", information_schema::SCHEMA, view);
+            if name.starts_with(word) {
+                out.push(name);
+            }
+        }
+
+        // Offer columns of every table already mentioned on the line.
+        for token in line.split_whitespace() {
+            let Ok((db, table_name)) = manager.resolve(token) else {
+                continue;
+            };
+            let db = db.lock();
+            if let Some(table) = db.tables.get(table_name) {
+                let mut cols: Vec<&String> = table.columns.iter().collect();
+                cols.sort();
+                for col in cols {
+                    if col.starts_with(word) && !out.contains(col) {
+                        out.push(col.clone());
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+impl Completer for SchemaHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos]
+            .rfind(char::is_whitespace)
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let word = &line[start..pos];
+        let pairs = self
+            .candidates(&line[..start], word)
+            .into_iter()
+            .map(|c| Pair {
+                display: c.clone(),
+                replacement: c,
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for SchemaHelper {
+    type Hint = String;
+}
+
+impl Highlighter for SchemaHelper {}
+
+impl Validator for SchemaHelper {}
+
+impl Helper for SchemaHelper {}
+
+/// Interactive shell over a set of named databases with history and tab completion.
+pub struct Repl {
+    manager: Arc<Mutex<DatabaseManager>>,
+    history_file: String,
+}
+
+impl Repl {
+    pub fn new(manager: Arc<Mutex<DatabaseManager>>) -> Self {
+        Repl {
+            manager,
+            history_file: HISTORY_FILE.to_string(),
+        }
+    }
+
+    pub fn run(&self) -> rustyline::Result<()> {
+        let mut rl: Editor<SchemaHelper, DefaultHistory> = Editor::new()?;
+        rl.set_helper(Some(SchemaHelper {
+            manager: Arc::clone(&self.manager),
+        }));
+        // A missing history file just means this is the first session.
+        let _ = rl.load_history(&self.history_file);
+
+        println!("Welcome to RustDB! Type HELP for a list of commands.");
+        loop {
+            match rl.readline("> ") {
+                Ok(line) => {
+                    let line = line.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    rl.add_history_entry(line)?;
+                    if !self.execute(line) {
+                        break;
+                    }
+                }
+                Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+                Err(e) => {
+                    error!("Readline error: {}", e);
+                    break;
+                }
+            }
+        }
+
+        if let Err(e) = rl.save_history(&self.history_file) {
+            error!("Failed to save history to '{}': {}", self.history_file, e);
+        }
+        println!("Bye!");
+        Ok(())
+    }
+
+    /// Run a single command line. Returns false when the shell should exit.
+    pub fn execute(&self, line: &str) -> bool {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        // A blank line does nothing.
+        let Some(first) = parts.first() else {
+            return true;
+        };
+        if first.starts_with('.') {
+            self.execute_meta(&parts);
+            return true;
+        }
+        let command = first.to_uppercase();
+        let mut manager = locking::recover(&self.manager);
+
+        // Commands that act on the set of databases rather than on a table.
+        let result = match (command.as_str(), &parts[1..]) {
+            ("CREATE", [kw, name]) if kw.eq_ignore_ascii_case("DATABASE") => {
+                Some(manager.create_database(name).map(|_| ()))
+            }
+            ("DROP", [kw, name]) if kw.eq_ignore_ascii_case("DATABASE") => {
+                Some(manager.drop_database(name))
+            }
+            ("USE", [name]) => Some(manager.use_database(name)),
+            ("ATTACH", [path, kw, alias, mode @ ..])
+                if kw.eq_ignore_ascii_case("AS") && mode.len() <= 1 =>
+            {
+                let read_only = mode
+                    .first()
+                    .is_some_and(|m| m.eq_ignore_ascii_case("READONLY"));
+                Some(manager.attach(path, alias, read_only).map(|tables| {
+                    println!("Attached '{}' with tables: {}", alias, tables.join(", "));
+                }))
+            }
+            ("DETACH", [alias]) => Some(manager.detach(alias)),
+            ("COPY", [src, dst]) => Some(
+                manager
+                    .copy_table(src, dst)
+                    .map(|count| println!("Copied {} rows from '{}' to '{}'.", count, src, dst)),
+            ),
+            // Runs on the database holding the first table queried.
+            ("SELECT", args) => {
+                let from = args
+                    .iter()
+                    .position(|a| a.eq_ignore_ascii_case("FROM"))
+                    .and_then(|i| args.get(i + 1))
+                    .map_or("", |table| *table);
+                Some(
+                    manager
+                        .resolve(from)
+                        .and_then(|(db, _)| db.lock().query(line))
+                        .map(|result| println!("{}", result)),
+                )
+            }
+            ("HELP", []) => {
+                print_help();
+                Some(Ok(()))
+            }
+            ("EXIT", []) | ("QUIT", []) => return false,
+            _ => None,
+        };
+        if let Some(result) = result {
+            if let Err(e) = result {
+                println!("Error: {}", e);
+            }
+            return true;
+        }
+
+        // Everything else names a (possibly qualified) table as its first argument,
+        // optionally preceded by TABLE, COLUMN or INDEX.
+        let mut args = &parts[1..];
+        let mut index = false;
+        if let Some(first) = args.first() {
+            index = first.eq_ignore_ascii_case("INDEX");
+            if index || first.eq_ignore_ascii_case("TABLE") || first.eq_ignore_ascii_case("COLUMN")
+            {
+                args = &args[1..];
+            }
+        }
+        let Some(qualified) = args.first() else {
+            println!("Unknown command. Type HELP for a list of commands.");
+            return true;
+        };
+        let (db, table) = match manager.resolve(qualified) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                println!("Error: {}", e);
+                return true;
+            }
+        };
+        drop(manager);
+        let mut db = db.lock();
+
+        match (command.as_str(), &args[1..]) {
+            ("CREATE", [column, kind @ ..]) if index && kind.len() <= 1 => {
+                let kind = match kind.first().map_or(Ok(IndexKind::Hash), |k| k.parse()) {
+                    Ok(kind) => kind,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        return true;
+                    }
+                };
+                if let Err(e) = db.create_index(table, column, kind) {
+                    println!("Error: {}", e);
+                }
+            }
+            ("CREATE", []) if !index => {
+                if let Err(e) = db.create_table(table) {
+                    println!("Error: {}", e);
+                }
+            }
+            // Existing rows get the default in the background; see `.backfills`.
+            ("ADD", [column, datatype @ .., keyword, default])
+                if datatype.len() <= 1 && keyword.eq_ignore_ascii_case("DEFAULT") =>
+            {
+                match db.add_column_online(table, column, datatype.first().copied(), default) {
+                    Ok(progress) => print!("{}", progress),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            ("ADD", [column]) => {
+                if let Err(e) = db.add_column(table, column) {
+                    println!("Error: {}", e);
+                }
+            }
+            ("ADD", [column, datatype]) => {
+                if let Err(e) = db.add_columns(table, vec![column], vec![datatype]) {
+                    println!("Error: {}", e);
+                }
+            }
+            // Without a row id, the table's row id strategy picks one.
+            ("INSERT", assignments) if assignments.first().is_some_and(|a| a.contains('=')) => {
+                let Some(data) = parse_assignments(assignments) else {
+                    return true;
+                };
+                match db.insert_generated(table, data) {
+                    Ok(row_id) => println!("Inserted row '{}'.", row_id),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            ("INSERT", [row_id, assignments @ ..]) if !assignments.is_empty() => {
+                let Some(data) = parse_assignments(assignments) else {
+                    return true;
+                };
+                if let Err(e) = db.insert_row(table, row_id, data) {
+                    println!("Error: {}", e);
+                }
+            }
+            ("UPSERT", [row_id, assignments @ ..]) if !assignments.is_empty() => {
+                let Some(data) = parse_assignments(assignments) else {
+                    return true;
+                };
+                if let Err(e) = db.upsert_row(table, row_id, data) {
+                    println!("Error: {}", e);
+                }
+            }
+            ("GET", [row_id]) => match db.get_row(table, row_id) {
+                Ok(result) => println!("{}", result),
+                Err(e) => println!("Error: {}", e),
+            },
+            ("GET", row_ids) if row_ids.len() > 1 => match db.get_rows(table, row_ids) {
+                Ok(batch) => {
+                    for (row_id, row) in &batch.found {
+                        println!("{}: {:?}", row_id, row);
+                    }
+                    if !batch.missing.is_empty() {
+                        println!("Missing: {}", batch.missing.join(", "));
+                    }
+                }
+                Err(e) => println!("Error: {}", e),
+            },
+            ("UPDATE", [kw, column, operator, value, set, assignments @ ..])
+                if kw.eq_ignore_ascii_case("WHERE")
+                    && set.eq_ignore_ascii_case("SET")
+                    && !assignments.is_empty() =>
+            {
+                let Some(data) = parse_assignments(assignments) else {
+                    return true;
+                };
+                let condition = format!("{} {} {}", column, operator, value);
+                match db.update_rows_by_condition(table, &condition, &data) {
+                    Ok(result) => println!("{}", result),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            ("UPDATE", [row_id, column, value]) => {
+                if let Err(e) = db.update_row(table, row_id, column, value) {
+                    println!("Error: {}", e);
+                }
+            }
+            ("FIND", [column, value, select @ ..]) => {
+                let Some(projection) = parse_projection(select) else {
+                    return true;
+                };
+                let projection: Option<Vec<&str>> = projection
+                    .as_ref()
+                    .map(|items| items.iter().map(String::as_str).collect());
+                match db.find_rows_by_value_in_table(
+                    table,
+                    column,
+                    value,
+                    true,
+                    projection.as_deref(),
+                ) {
+                    Ok(result) => println!("{}", result),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            ("SEARCH", args) if !args.is_empty() => {
+                let split = args
+                    .iter()
+                    .position(|a| a.eq_ignore_ascii_case("SELECT"))
+                    .unwrap_or(args.len());
+                let (condition, select) = args.split_at(split);
+                let Some(projection) = parse_projection(select) else {
+                    return true;
+                };
+                let projection: Option<Vec<&str>> = projection
+                    .as_ref()
+                    .map(|items| items.iter().map(String::as_str).collect());
+                let condition = condition.join(" ");
+                let result = if Filter::is_compound(&condition) {
+                    db.filter(table, &Filter::parse(&condition), projection.as_deref())
+                } else {
+                    db.search_rows_by_condition_in_table(table, &condition, projection.as_deref())
+                };
+                match result {
+                    Ok(result) => println!("{}", result),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            ("EXISTS", [row_id]) => match db.row_exists(table, row_id) {
+                Ok(exists) => println!("{}", exists),
+                Err(e) => println!("Error: {}", e),
+            },
+            ("COUNT", condition) => {
+                let condition = condition.join(" ");
+                let condition = (!condition.is_empty()).then_some(condition.as_str());
+                match db.count_rows(table, condition) {
+                    Ok(count) => println!("{}", count),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            ("DISTINCT", [column]) => match db.distinct_values(table, column) {
+                Ok(values) => {
+                    for (value, count) in values {
+                        println!("{} ({})", value, count);
+                    }
+                }
+                Err(e) => println!("Error: {}", e),
+            },
+            ("SAMPLE", [n]) => {
+                let Ok(n) = n.parse::<usize>() else {
+                    println!("Expected a row count, got '{}'", n);
+                    return true;
+                };
+                match db.sample_rows(table, n) {
+                    Ok(result) => println!("{}", result),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            ("SCAN", [prefix]) => match db.scan_prefix(table, prefix) {
+                Ok(result) => println!("{}", result),
+                Err(e) => println!("Error: {}", e),
+            },
+            // `*` leaves a bound open.
+            ("RANGE", [start, end, rest @ ..]) => {
+                let bound = |id: &str| (id != "*").then(|| id.to_string());
+                let mut options = ScanOptions {
+                    start: bound(start),
+                    end: bound(end),
+                    ..ScanOptions::default()
+                };
+                let mut rest = rest.iter();
+                while let Some(word) = rest.next() {
+                    match word.to_uppercase().as_str() {
+                        "DESC" => options.descending = true,
+                        "LIMIT" => match rest.next().and_then(|n| n.parse().ok()) {
+                            Some(n) => options.limit = Some(n),
+                            None => {
+                                println!("Expected a row count after LIMIT");
+                                return true;
+                            }
+                        },
+                        _ => {
+                            println!("Expected DESC or LIMIT <n>, got '{}'", word);
+                            return true;
+                        }
+                    }
+                }
+                match db.scan_range(table, &options) {
+                    Ok(result) => println!("{}", result),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            ("CLONE", [dst, rest @ ..]) => {
+                let condition = match rest {
+                    [] => None,
+                    [kw, column, operator, value] if kw.eq_ignore_ascii_case("WHERE") => {
+                        Some(format!("{} {} {}", column, operator, value))
+                    }
+                    _ => {
+                        println!("Expected CLONE <table> <new_table> [WHERE <column> <operator> <value>]");
+                        return true;
+                    }
+                };
+                match db.copy_table(table, dst, condition.as_deref()) {
+                    Ok(count) => println!("Copied {} rows.", count),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            ("MERGE", [dst, policy @ ..]) if policy.len() <= 1 => {
+                let policy = match policy.first().map(|p| p.to_uppercase()).as_deref() {
+                    None | Some("ERROR") => ConflictPolicy::Error,
+                    Some("SKIP") => ConflictPolicy::Skip,
+                    Some("OVERWRITE") => ConflictPolicy::Overwrite,
+                    Some(other) => {
+                        println!(
+                            "Unknown conflict policy '{}'; use SKIP, OVERWRITE or ERROR",
+                            other
+                        );
+                        return true;
+                    }
+                };
+                match db.merge_tables(table, dst, policy) {
+                    Ok(report) => print!("{}", report),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            ("COLLATE", [column, collation]) => match collation.parse::<Collation>() {
+                Ok(collation) => {
+                    if let Err(e) = db.set_collation(table, column, collation) {
+                        println!("Error: {}", e);
+                    }
+                }
+                Err(e) => println!("Error: {}", e),
+            },
+            ("ENUM", [column, values @ ..]) if !values.is_empty() => {
+                if let Err(e) = db.set_enum(table, column, values) {
+                    println!("Error: {}", e);
+                }
+            }
+            ("ROWID", [strategy]) => match strategy.parse::<RowIdStrategy>() {
+                Ok(strategy) => {
+                    if let Err(e) = db.set_row_id_strategy(table, strategy) {
+                        println!("Error: {}", e);
+                    }
+                }
+                Err(e) => println!("Error: {}", e),
+            },
+            ("AUTOSAVE", args) => {
+                if let Some(autosave) = parse_autosave(args) {
+                    if let Err(e) = db.set_autosave(table, autosave) {
+                        println!("Error: {}", e);
+                    }
+                }
+            }
+            ("RENAME", [new_name]) => {
+                if let Err(e) = db.rename_table(table, new_name) {
+                    println!("Error: {}", e);
+                }
+            }
+            ("ANALYZE", []) => match db.analyze(table) {
+                Ok(stats) => {
+                    for column in stats {
+                        print!("{}", column);
+                    }
+                }
+                Err(e) => println!("Error: {}", e),
+            },
+            ("SHOW", []) => match db.get_table(table) {
+                Ok(t) => print!("{}", t),
+                Err(e) => println!("Error: {}", e),
+            },
+            ("ENCRYPT", [column]) => {
+                if let Err(e) = db.encrypt_column(table, column) {
+                    println!("Error: {}", e);
+                }
+            }
+            ("VACUUM", []) => match db.vacuum(table) {
+                Ok(report) => print!("{}", report),
+                Err(e) => println!("Error: {}", e),
+            },
+            ("COLD", [keep]) => {
+                let Ok(keep) = keep.parse::<usize>() else {
+                    println!("Expected a row count, got '{}'", keep);
+                    return true;
+                };
+                match db.flush_cold(table, keep) {
+                    Ok(n) => println!("{} rows flushed to cold storage.", n),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            ("SAVE", []) => {
+                if let Err(e) = db.save_table(table, &db.config.table_path(table)) {
+                    println!("Error: {}", e);
+                }
+            }
+            _ => println!("Unknown command. Type HELP for a list of commands."),
+        }
+        true
+    }
+
+    fn execute_meta(&self, parts: &[&str]) {
+        let manager = locking::recover(&self.manager);
+        if parts == [".databases"] {
+            for name in manager.list_databases() {
+                let marker = if name == manager.current() { "*" } else { " " };
+                println!("{} {}", marker, name);
+            }
+            return;
+        }
+
+        if !META_COMMANDS.contains(&parts[0]) {
+            println!("Unknown meta command. Type HELP for a list of commands.");
+            return;
+        }
+
+        // `.schema`, `.import`, `.load` and `.copy` take a table name that may be qualified; the
+        // other commands act on every shard of the current database.
+        let resolved = match parts {
+            [".schema", table]
+            | [".import", _, table, ..]
+            | [".load", _, table]
+            | [".copy", _, table, ..] => manager.resolve(table).map(|target| vec![target]),
+            // Every shard has the same functions.
+            [".functions"] => manager
+                .get(manager.current())
+                .map(|db| vec![(db.shards()[0].clone(), "")]),
+            _ => manager.get(manager.current()).map(|db| {
+                db.shards()
+                    .iter()
+                    .map(|shard| (shard.clone(), ""))
+                    .collect()
+            }),
+        };
+        let targets: Vec<(DbHandle, &str)> = match resolved {
+            Ok(targets) => targets,
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            }
+        };
+        drop(manager);
+
+        // Backups only lock each shard to snapshot it, so writes from other
+        // sessions carry on while files are written.
+        if let [".backup"] | [".backup", _] = parts {
+            for (db, _) in targets {
+                Self::backup_shard(&db, parts.get(1).copied());
+            }
+            return;
+        }
+
+        for (db, table) in targets {
+            let mut db = db.lock();
+            self.execute_meta_on(&mut db, table, parts);
+        }
+    }
+
+    fn backup_shard(db: &DbHandle, dir: Option<&str>) {
+        let dir = match dir {
+            Some(dir) => dir.to_string(),
+            None => {
+                let secs = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs());
+                db.lock().config.backup_path(&secs.to_string())
+            }
+        };
+        match db.hot_backup(&dir) {
+            Ok(files) => println!("Backed up {} files to '{}'.", files.len(), dir),
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+
+    fn execute_meta_on(&self, db: &mut Database, table: &str, parts: &[&str]) {
+        match (parts[0], &parts[1..]) {
+            (".tables", []) => {
+                for table in db.list_tables() {
+                    println!("{}", table);
+                }
+            }
+            (".schema", [_]) => match db.table_schema(table) {
+                Ok(columns) => {
+                    let collations = &db.tables[table].collations;
+                    for (column, datatype) in columns {
+                        match collations.get(&column) {
+                            Some(collation) => println!(
+                                "{:<15} {:<8} collate {}",
+                                column,
+                                datatype.unwrap_or_default(),
+                                collation
+                            ),
+                            None => println!("{:<15} {}", column, datatype.unwrap_or_default()),
+                        }
+                    }
+                    let strategy = db.tables[table].row_ids.strategy;
+                    if strategy != RowIdStrategy::Caller {
+                        println!("(row ids: {})", strategy);
+                    }
+                    if !db.tables[table].autosave.is_default() {
+                        println!(
+                            "(autosave: {}, threshold {})",
+                            db.persistence_for(table),
+                            db.save_threshold_for(table)
+                        );
+                    }
+                }
+                Err(e) => println!("Error: {}", e),
+            },
+            (".indexes", []) => {
+                for index in db.list_indexes() {
+                    println!("{}", index);
+                }
+            }
+            #[cfg(feature = "import")]
+            (".import", [file_name, _, overrides @ ..]) => {
+                let Some(overrides) = parse_assignments(overrides) else {
+                    return;
+                };
+                match db.import_csv_with_types(table, file_name, &overrides) {
+                    Ok(count) => println!("Imported {} rows into '{}'.", count, table),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            #[cfg(feature = "import")]
+            (".load", [source, _]) => match db.bulk_load(table, source) {
+                Ok(count) => println!("Loaded {} rows into '{}'.", count, table),
+                Err(e) => println!("Error: {}", e),
+            },
+            #[cfg(feature = "import")]
+            (".copy", [source, _, format @ ..]) if format.len() <= 1 => {
+                // Without a format, `.ndjson` and `.jsonl` files are NDJSON.
+                let format = match format.first() {
+                    Some(format) => format.parse(),
+                    None if source.ends_with(".ndjson") || source.ends_with(".jsonl") => {
+                        Ok(IngestFormat::Ndjson)
+                    }
+                    None => Ok(IngestFormat::Csv),
+                };
+                let format = match format {
+                    Ok(format) => format,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        return;
+                    }
+                };
+                let file = match File::open(source) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        println!("Error: cannot open '{}': {}", source, e);
+                        return;
+                    }
+                };
+                let progress = |p: &IngestProgress| println!("... {} rows", p.rows);
+                match db.copy_from(table, file, format, progress) {
+                    Ok(done) => println!("Copied {} rows into '{}'.", done.rows, table),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            (".stats", []) => print!("{}", db.stats()),
+            (".functions", []) => {
+                for name in db.functions.names() {
+                    println!("{}", name);
+                }
+            }
+            (".check", []) => print!("{}", db.check()),
+            (".backfills", []) => {
+                for progress in db.backfills() {
+                    print!("{}", progress);
+                }
+            }
+            (".checkpoint", []) => match db.checkpoint() {
+                Ok(saved) if saved.is_empty() => println!("Nothing to save."),
+                Ok(saved) => println!("Saved {} tables: {}", saved.len(), saved.join(", ")),
+                Err(e) => println!("Error: {}", e),
+            },
+            (".repair", []) => match db.repair() {
+                Ok(report) => print!("{}", report),
+                Err(e) => println!("Error: {}", e),
+            },
+            (".recover", [millis]) => match millis.parse::<u64>() {
+                Ok(millis) => match db.recover_to(millis) {
+                    Ok(report) => print!("{}", report),
+                    Err(e) => println!("Error: {}", e),
+                },
+                Err(_) => println!("Error: expected a time in Unix milliseconds."),
+            },
+            _ => println!("Unknown meta command. Type HELP for a list of commands."),
+        }
+    }
+}
+
+/// Parse `column=value` arguments, printing an error for the first malformed one.
+fn parse_assignments(assignments: &[&str]) -> Option<HashMap<String, String>> {
+    let mut data = HashMap::new();
+    for assignment in assignments {
+        match assignment.split_once('=') {
+            Some((col, val)) => {
+                data.insert(col.to_string(), val.to_string());
+            }
+            None => {
+                println!("Expected column=value, got '{}'", assignment);
+                return None;
+            }
+        }
+    }
+    Some(data)
+}
+
+/// Parse `AUTOSAVE` arguments: a policy, with a write count for `threshold`
+/// or seconds for `interval`. Returns `None` (after printing why) when malformed.
+fn parse_autosave(args: &[&str]) -> Option<Autosave> {
+    let lower: Vec<String> = args.iter().map(|a| a.to_lowercase()).collect();
+    let lower: Vec<&str> = lower.iter().map(String::as_str).collect();
+    let (persistence, save_threshold) = match lower.as_slice() {
+        ["default"] => return Some(Autosave::default()),
+        ["write_through"] => (PersistencePolicy::WriteThrough, None),
+        ["threshold"] => (PersistencePolicy::Threshold, None),
+        ["threshold", n] if n.parse::<usize>().is_ok_and(|n| n > 0) => {
+            (PersistencePolicy::Threshold, n.parse().ok())
+        }
+        ["interval", secs] if secs.parse::<u64>().is_ok() => {
+            (PersistencePolicy::Interval(secs.parse().unwrap()), None)
+        }
+        ["checkpoint"] => (PersistencePolicy::Checkpoint, None),
+        _ => {
+            println!(
+                "Expected write_through, threshold [<writes>], interval <secs>, checkpoint or default"
+            );
+            return None;
+        }
+    };
+    Some(Autosave {
+        persistence: Some(persistence),
+        save_threshold,
+    })
+}
+
+/// Parse an optional trailing `SELECT col1,lower(col2)` clause into its items.
+/// Returns `None` (after printing why) when the clause is malformed.
+fn parse_projection(select: &[&str]) -> Option<Option<Vec<String>>> {
+    match select {
+        [] => Some(None),
+        [kw, items @ ..] if kw.eq_ignore_ascii_case("SELECT") && !items.is_empty() => {
+            Some(Some(expr::split_list(&items.join(" "))))
+        }
+        _ => {
+            println!("Expected SELECT <column>,<column>...");
+            None
+        }
+    }
+}
+
+fn print_help() {
+    println!("CREATE DATABASE <name>");
+    println!("USE <name>");
+    println!("DROP DATABASE <name>");
+    println!("ATTACH <dir> AS <name> [READONLY]");
+    println!("DETACH <name>");
+    println!("COPY <database>.<table> <database>.<table>");
+    println!("CREATE TABLE <table>   (tables may be written as <database>.<table>)");
+    println!("ADD COLUMN <table> <column> [int|float|string|bool|date]");
+    println!("ADD COLUMN <table> <column> [<datatype>] DEFAULT <value>   (rows filled in the background)");
+    println!("CREATE INDEX <table> <column> [hash|bitmap|geo]   (bitmap suits few distinct values)");
+    println!("INSERT <table> [<row_id>] <column>=<value> ...   (no row_id: see ROWID)");
+    println!("UPSERT <table> <row_id> <column>=<value> ...");
+    println!("GET <table> <row_id> [<row_id> ...]");
+    println!("UPDATE <table> <row_id> <column> <value>");
+    println!("UPDATE <table> WHERE <column> <operator> <value> SET <column>=<value> ...");
+    println!("FIND <table> <column> <value> [SELECT <column>|<function>(...),...]");
+    println!("SEARCH <table> <column>|<function>(...) <operator> <value> [SELECT ...]");
+    println!("  operators: == > < >= <= LIKE MATCHES IN (<value>,...) IN (SELECT <column> FROM <table> [WHERE ...])");
+    println!("  or EXISTS (SELECT * FROM <table> [WHERE ...]) in place of a condition");
+    println!("  conditions combine with AND and OR; indexed == conditions use the index");
+    println!("SELECT <column>|*,... FROM <table> [WHERE ...]");
+    println!("  [UNION|INTERSECT|EXCEPT [ALL] SELECT ...]");
+    println!("  [ORDER BY <column> [ASC|DESC], ...]");
+    println!("  information_schema.tables, .columns and .indexes describe the database");
+    println!("EXISTS <table> <row_id>");
+    println!("COUNT <table> [<column> <operator> <value>]");
+    println!("DISTINCT <table> <column>");
+    println!("ANALYZE <table>");
+    println!("SAMPLE <table> <n>");
+    println!("SCAN <table> <row id prefix>");
+    println!("RANGE <table> <from row id>|* <to row id>|* [DESC] [LIMIT <n>]");
+    println!("CLONE <table> <new_table> [WHERE <column> <operator> <value>]");
+    println!("RENAME <table> <new_name>");
+    println!("COLLATE <table> <column> <binary|nocase|unicode>");
+    println!("ENUM <table> <column> <value> [value ...]   (only those values are allowed)");
+    println!(
+        "ROWID <table> <caller|autoincrement|uuid|ulid>   (how INSERT without a row_id picks one)"
+    );
+    println!(
+        "AUTOSAVE <table> <write_through|threshold [<writes>]|interval <secs>|checkpoint|default>"
+    );
+    println!("MERGE <table> <into_table> [SKIP|OVERWRITE|ERROR]");
+    println!("SHOW <table>");
+    println!("SAVE <table>");
+    println!("VACUUM <table>");
+    println!("COLD <table> <rows to keep in memory>   (flush the rest to the table's cold segment)");
+    println!(
+        "ENCRYPT COLUMN <table> <column>   (key from {})",
+        crypto::KEY_ENV_VAR
+    );
+    println!(".tables");
+    println!(".schema <table>");
+    println!(".indexes");
+    if cfg!(feature = "import") {
+        println!(".import <file> <table> [<column>=<datatype> ...]   (other types are inferred)");
+        println!(".load <file> <table>   (bulk load without per-row logging)");
+        println!(".copy <file> <table> [csv|ndjson]   (stream into an existing table in chunks)");
+    }
+    println!(".backup [<dir>]   (default: a new directory under backups/)");
+    println!(".recover <unix_millis>   (restore the latest backup and replay the WAL archive up to then)");
+    println!(".databases");
+    println!(".stats");
+    println!(".check");
+    println!(".repair");
+    println!(".checkpoint");
+    println!(".backfills   (progress of columns added with DEFAULT)");
+    println!(".functions");
+    println!("EXIT");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::config::DatabaseConfig;
+    use crate::commands::storage::StorageKind;
+
+    #[test]
+    fn test_blank_line_keeps_shell_running() {
+        let config = DatabaseConfig {
+            storage: StorageKind::Memory,
+            ..DatabaseConfig::default()
+        };
+        let manager = DatabaseManager::new("databases", config, false);
+        let repl = Repl::new(Arc::new(Mutex::new(manager)));
+        assert!(repl.execute(""));
+        assert!(repl.execute(" \t "));
+    }
+}
@@ -0,0 +1,381 @@
+//! `QueryResult`: what reads and writes return instead of printing. Reads
+//! carry a `RowSet` (columns with their declared datatypes, and typed
+//! values); writes carry the number of rows they changed. Both record how
+//! long they took, and `Display` renders them for the REPL.
+
+use crate::commands::clock::Instant;
+use crate::commands::condition;
+use crate::commands::geo::GeoPoint;
+use crate::table::table::Table;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+/// One cell, parsed by its column's declared datatype. Values of undeclared
+/// ("string") columns, and any that don't parse, stay `Text`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// The row has no value for the column.
+    Null,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Date(i32, u32, u32),
+    /// Latitude and longitude in degrees.
+    GeoPoint(f64, f64),
+    Text(String),
+}
+
+impl Value {
+    pub fn parse(text: &str, datatype: Option<&str>) -> Value {
+        let trimmed = text.trim();
+        let typed = match datatype {
+            Some("int") => trimmed.parse().ok().map(Value::Int),
+            Some("float") => trimmed.parse().ok().map(Value::Float),
+            Some("bool") => match trimmed.to_lowercase().as_str() {
+                "true" => Some(Value::Bool(true)),
+                "false" => Some(Value::Bool(false)),
+                _ => None,
+            },
+            Some("date") => condition::parse_date(trimmed).map(|(y, m, d)| Value::Date(y, m, d)),
+            Some("geo") => GeoPoint::parse(trimmed).map(|p| Value::GeoPoint(p.lat, p.lon)),
+            _ => None,
+        };
+        typed.unwrap_or_else(|| Value::Text(text.to_string()))
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, Value::Int(_) | Value::Float(_))
+    }
+
+    /// A total order for sorting: nulls first, then booleans, numbers (ints
+    /// and floats by value), dates, points (by latitude, then longitude) and
+    /// text. NaN sorts after every number.
+    pub fn sort_cmp(&self, other: &Value) -> Ordering {
+        fn rank(value: &Value) -> u8 {
+            match value {
+                Value::Null => 0,
+                Value::Bool(_) => 1,
+                Value::Int(_) | Value::Float(_) => 2,
+                Value::Date(..) => 3,
+                Value::GeoPoint(..) => 4,
+                Value::Text(_) => 5,
+            }
+        }
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            (Value::Int(a), Value::Float(b)) => (*a as f64).total_cmp(b),
+            (Value::Float(a), Value::Int(b)) => a.total_cmp(&(*b as f64)),
+            (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+            (Value::Date(y1, m1, d1), Value::Date(y2, m2, d2)) => (y1, m1, d1).cmp(&(y2, m2, d2)),
+            (Value::GeoPoint(lat1, lon1), Value::GeoPoint(lat2, lon2)) => {
+                lat1.total_cmp(lat2).then(lon1.total_cmp(lon2))
+            }
+            (Value::Text(a), Value::Text(b)) => a.cmp(b),
+            _ => rank(self).cmp(&rank(other)),
+        }
+    }
+}
+
+/// One key of an `ORDER BY`: a column and its direction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortKey {
+    pub column: String,
+    pub descending: bool,
+}
+
+impl SortKey {
+    /// Parse a list like `age DESC, name ASC, email`; a key without a
+    /// direction is ascending. `None` if an item is empty or has a direction
+    /// other than ASC or DESC.
+    pub fn parse_list(text: &str) -> Option<Vec<SortKey>> {
+        text.split(',')
+            .map(|item| {
+                let mut words = item.split_whitespace();
+                let column = words.next()?.to_string();
+                let descending = match words.next() {
+                    None => false,
+                    Some(dir) if dir.eq_ignore_ascii_case("ASC") => false,
+                    Some(dir) if dir.eq_ignore_ascii_case("DESC") => true,
+                    Some(_) => return None,
+                };
+                words
+                    .next()
+                    .is_none()
+                    .then_some(SortKey { column, descending })
+            })
+            .collect()
+    }
+
+    /// Compare two rows by their values for `keys`, in order, each key
+    /// breaking ties left by the ones before it.
+    pub fn compare(keys: &[SortKey], a: &[Value], b: &[Value]) -> Ordering {
+        keys.iter()
+            .zip(a.iter().zip(b))
+            .map(|(key, (a, b))| {
+                let ordering = a.sort_cmp(b);
+                if key.descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Date(y, m, d) => write!(f, "{:04}-{:02}-{:02}", y, m, d),
+            Value::GeoPoint(lat, lon) => write!(f, "{},{}", lat, lon),
+            Value::Text(s) => f.write_str(s),
+        }
+    }
+}
+
+/// A result column: a table column, `row_id`, or a selected expression as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    /// The declared datatype, if the column has one.
+    pub datatype: Option<String>,
+}
+
+/// Rows of a result, each holding one value per column.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RowSet {
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl RowSet {
+    /// Rows read from `table`, led by a `row_id` column. `columns` come
+    /// first in that order; other keys found in the rows follow, sorted.
+    pub fn from_rows(
+        table: Option<&Table>,
+        columns: &[&str],
+        rows: Vec<(String, HashMap<String, String>)>,
+    ) -> Self {
+        let mut names: Vec<String> = columns.iter().map(|c| c.to_string()).collect();
+        let mut extra: Vec<&String> = rows
+            .iter()
+            .flat_map(|(_, row)| row.keys())
+            .filter(|key| !columns.contains(&key.as_str()))
+            .collect();
+        extra.sort();
+        extra.dedup();
+        names.extend(extra.into_iter().cloned());
+        let columns: Vec<Column> = std::iter::once(Column {
+            name: "row_id".to_string(),
+            datatype: None,
+        })
+        .chain(names.into_iter().map(|name| Column {
+            datatype: table.and_then(|t| t.row_datatypes.get(&name).cloned()),
+            name,
+        }))
+        .collect();
+        let rows = rows
+            .iter()
+            .map(|(row_id, row)| {
+                std::iter::once(Value::Text(row_id.clone()))
+                    .chain(columns[1..].iter().map(|column| {
+                        row.get(&column.name).map_or(Value::Null, |text| {
+                            Value::parse(text, column.datatype.as_deref())
+                        })
+                    }))
+                    .collect()
+            })
+            .collect();
+        RowSet { columns, rows }
+    }
+
+    /// Rows of text values, typed by `columns`.
+    pub fn from_values(columns: Vec<Column>, rows: Vec<Vec<String>>) -> Self {
+        let rows = rows
+            .into_iter()
+            .map(|row| {
+                columns
+                    .iter()
+                    .zip(row)
+                    .map(|(column, text)| Value::parse(&text, column.datatype.as_deref()))
+                    .collect()
+            })
+            .collect();
+        RowSet { columns, rows }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.name == name)
+    }
+
+    /// The value of `column` in row `row`, if both exist.
+    pub fn get(&self, row: usize, column: &str) -> Option<&Value> {
+        let index = self.column_index(column)?;
+        self.rows.get(row)?.get(index)
+    }
+
+    /// Sort the rows by `keys`, which must name columns of the set. The sort
+    /// is stable, so rows equal on every key keep their order. Returns the
+    /// first key that is not a column, leaving the rows unsorted.
+    pub fn sort_by_keys<'k>(&mut self, keys: &'k [SortKey]) -> Result<(), &'k SortKey> {
+        let indexes = keys
+            .iter()
+            .map(|key| self.column_index(&key.column).ok_or(key))
+            .collect::<Result<Vec<usize>, _>>()?;
+        let mut keyed: Vec<(Vec<Value>, Vec<Value>)> = std::mem::take(&mut self.rows)
+            .into_iter()
+            .map(|row| (indexes.iter().map(|&i| row[i].clone()).collect(), row))
+            .collect();
+        keyed.sort_by(|(a, _), (b, _)| SortKey::compare(keys, a, b));
+        self.rows = keyed.into_iter().map(|(_, row)| row).collect();
+        Ok(())
+    }
+
+    /// Rows as `(row_id, column -> value)` text maps, leaving out nulls.
+    /// Empty if there is no `row_id` column.
+    pub fn to_maps(&self) -> Vec<(String, HashMap<String, String>)> {
+        let Some(id) = self.column_index("row_id") else {
+            return Vec::new();
+        };
+        self.rows
+            .iter()
+            .map(|row| {
+                let values = self
+                    .columns
+                    .iter()
+                    .zip(row)
+                    .enumerate()
+                    .filter(|(i, (_, value))| *i != id && !value.is_null())
+                    .map(|(_, (column, value))| (column.name.clone(), value.to_string()))
+                    .collect();
+                (row[id].to_string(), values)
+            })
+            .collect()
+    }
+}
+
+/// Aligned columns under a header; numbers are right-aligned.
+impl fmt::Display for RowSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cells: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(Value::to_string).collect())
+            .collect();
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .fold(column.name.chars().count(), usize::max)
+            })
+            .collect();
+        let header: Vec<String> = self
+            .columns
+            .iter()
+            .zip(&widths)
+            .map(|(column, &width)| format!("{:<width$}", column.name))
+            .collect();
+        writeln!(f, "{}", header.join(" | ").trim_end())?;
+        let rule: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+        writeln!(f, "{}", rule.join("-+-"))?;
+        for (row, texts) in self.rows.iter().zip(&cells) {
+            let line: Vec<String> = row
+                .iter()
+                .zip(texts)
+                .zip(&widths)
+                .map(|((value, text), &width)| {
+                    if value.is_numeric() {
+                        format!("{:>width$}", text)
+                    } else {
+                        format!("{:<width$}", text)
+                    }
+                })
+                .collect();
+            writeln!(f, "{}", line.join(" | ").trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of one read or write.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryResult {
+    /// Rows read; no columns for a write.
+    pub rows: RowSet,
+    /// Rows inserted, updated or deleted; 0 for a read.
+    pub affected: usize,
+    pub elapsed: Duration,
+}
+
+impl QueryResult {
+    /// A read that started at `started`.
+    pub fn read(rows: RowSet, started: Instant) -> Self {
+        QueryResult {
+            rows,
+            affected: 0,
+            elapsed: started.elapsed(),
+        }
+    }
+
+    /// A write of `affected` rows that started at `started`.
+    pub fn write(affected: usize, started: Instant) -> Self {
+        QueryResult {
+            rows: RowSet::default(),
+            affected,
+            elapsed: started.elapsed(),
+        }
+    }
+
+    pub fn is_read(&self) -> bool {
+        !self.rows.columns.is_empty()
+    }
+}
+
+impl fmt::Display for QueryResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_read() {
+            let n = self.rows.len();
+            write!(f, "{}", self.rows)?;
+            write!(
+                f,
+                "({} row{}, {:.2?})",
+                n,
+                if n == 1 { "" } else { "s" },
+                self.elapsed
+            )
+        } else {
+            let n = self.affected;
+            write!(
+                f,
+                "{} row{} affected ({:.2?})",
+                n,
+                if n == 1 { "" } else { "s" },
+                self.elapsed
+            )
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Row id strategies: how `Database::insert_generated` picks the id of a row
+//! inserted without one. Chosen per table with `set_row_id_strategy`.
+
+use crate::commands::clock;
+use rand::Rng;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RowIdStrategy {
+    /// Every insert passes its own id.
+    #[default]
+    Caller,
+    /// One more than the largest numeric id in the table.
+    AutoIncrement,
+    /// A random (version 4) UUID, e.g. `9f1c2a4e-8b3d-4f6a-9c2e-1d7b5a3f0e84`.
+    Uuid,
+    /// A ULID: 26 characters that sort by creation time, then randomly.
+    Ulid,
+}
+
+impl FromStr for RowIdStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "caller" => Ok(RowIdStrategy::Caller),
+            "autoincrement" | "auto_increment" => Ok(RowIdStrategy::AutoIncrement),
+            "uuid" => Ok(RowIdStrategy::Uuid),
+            "ulid" => Ok(RowIdStrategy::Ulid),
+            _ => Err(format!(
+                "unknown row id strategy '{}'; use caller, autoincrement, uuid or ulid",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RowIdStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RowIdStrategy::Caller => "caller",
+            RowIdStrategy::AutoIncrement => "autoincrement",
+            RowIdStrategy::Uuid => "uuid",
+            RowIdStrategy::Ulid => "ulid",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A table's strategy and what it needs to remember between ids.
+#[derive(Debug, Clone, Default)]
+pub struct RowIdGenerator {
+    pub strategy: RowIdStrategy,
+    /// Last auto-increment id handed out; `None` until the table's ids are scanned.
+    last_auto: Option<u64>,
+    /// Time and random part of the last ULID, so ids made within the same
+    /// millisecond still increase.
+    last_ulid: Option<(u64, u128)>,
+}
+
+impl RowIdGenerator {
+    pub fn new(strategy: RowIdStrategy) -> Self {
+        RowIdGenerator {
+            strategy,
+            ..RowIdGenerator::default()
+        }
+    }
+
+    /// The next id, or `None` under `RowIdStrategy::Caller`. `existing` are the
+    /// table's ids; auto-increment scans them once to start after the largest.
+    pub fn next_id<'a>(&mut self, existing: impl Iterator<Item = &'a String>) -> Option<String> {
+        match self.strategy {
+            RowIdStrategy::Caller => None,
+            RowIdStrategy::AutoIncrement => {
+                let last = *self.last_auto.get_or_insert_with(|| {
+                    existing.filter_map(|id| id.parse().ok()).max().unwrap_or(0)
+                });
+                self.last_auto = Some(last + 1);
+                Some((last + 1).to_string())
+            }
+            RowIdStrategy::Uuid => {
+                let mut bytes: [u8; 16] = rand::thread_rng().gen();
+                bytes[6] = (bytes[6] & 0x0f) | 0x40;
+                bytes[8] = (bytes[8] & 0x3f) | 0x80;
+                let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                Some(format!(
+                    "{}-{}-{}-{}-{}",
+                    &hex[..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..]
+                ))
+            }
+            RowIdStrategy::Ulid => {
+                let now = clock::unix_millis();
+                let (time, random) = match self.last_ulid {
+                    Some((time, random)) if now <= time => (time, (random + 1) & ULID_RANDOM_MASK),
+                    _ => (now, rand::thread_rng().gen::<u128>() & ULID_RANDOM_MASK),
+                };
+                self.last_ulid = Some((time, random));
+                Some(encode_ulid(((time as u128) << 80) | random))
+            }
+        }
+    }
+}
+
+/// The 80 random bits of a ULID.
+const ULID_RANDOM_MASK: u128 = (1 << 80) - 1;
+
+/// Crockford's base32, which leaves out I, L, O and U.
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+fn encode_ulid(value: u128) -> String {
+    (0..26)
+        .rev()
+        .map(|i| CROCKFORD[((value >> (i * 5)) & 0x1f) as usize] as char)
+        .collect()
+}
@@ -0,0 +1,61 @@
+use crate::commands::db::Database;
+use crate::commands::scheduler::{MaintenanceJob, Priority};
+use std::time::Duration;
+use tracing::{debug, error};
+
+/// Saves tables whose persistence policy has come due, so inserts and
+/// updates don't pay for rewriting table files. Each step saves one table,
+/// after syncing the WAL: a table file never holds a write the WAL could
+/// still lose, and the checkpoint engine only truncates the WAL once every
+/// table is saved.
+pub struct SaveEngine {
+    interval: Duration,
+    /// Tables found due at the start of the run and not yet saved.
+    pending: Vec<String>,
+}
+
+impl SaveEngine {
+    pub fn new(interval: Duration) -> Self {
+        SaveEngine {
+            interval,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl MaintenanceJob for SaveEngine {
+    fn name(&self) -> &'static str {
+        "save_engine"
+    }
+
+    fn priority(&self) -> Priority {
+        Priority::Normal
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    fn step(&mut self, db: &mut Database) -> bool {
+        if self.pending.is_empty() {
+            self.pending = db.due_tables();
+            self.pending.reverse();
+        }
+        let Some(table_name) = self.pending.pop() else {
+            return false;
+        };
+        if let Err(e) = db.sync_wal() {
+            error!("Not saving table '{}': {}", table_name, e);
+            self.pending.clear();
+            return false;
+        }
+        // Saved or dropped since the run started.
+        if db.unsaved_tables().contains(&table_name) {
+            match db.save_unsaved(&table_name) {
+                Ok(()) => debug!("Saved table '{}'.", table_name),
+                Err(e) => error!("Failed to save table '{}': {}", table_name, e),
+            }
+        }
+        !self.pending.is_empty()
+    }
+}
@@ -0,0 +1,187 @@
+use crate::commands::backfill_engine::BackfillEngine;
+use crate::commands::checkpoint_engine::CheckpointEngine;
+use crate::commands::config::DatabaseConfig;
+use crate::commands::db::Database;
+use crate::commands::handle::DbHandle;
+#[cfg(feature = "index-engine")]
+use crate::commands::indexer_engine::IndexEngine;
+use crate::commands::save_engine::SaveEngine;
+use crate::commands::walengine::WalEngine;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info_span};
+
+/// Length of the window the maintenance budget applies to.
+const BUDGET_WINDOW: Duration = Duration::from_secs(1);
+/// Pause after every step so waiting foreground operations can take the lock.
+const STEP_PAUSE: Duration = Duration::from_millis(1);
+
+/// Which due job runs first when several are waiting. `High` sorts first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    High,
+    Normal,
+    Low,
+}
+
+/// Background work run by the `MaintenanceScheduler`. A run is split into
+/// steps and the database lock is released between them, so no single step
+/// should hold it for long.
+pub trait MaintenanceJob: Send {
+    fn name(&self) -> &'static str;
+    fn priority(&self) -> Priority;
+    /// Time from the end of one run to the start of the next.
+    fn interval(&self) -> Duration;
+    /// Run one step with the database locked. Returns true if the run has more steps.
+    fn step(&mut self, db: &mut Database) -> bool;
+}
+
+struct Scheduled {
+    job: Box<dyn MaintenanceJob>,
+    next_run: Instant,
+    /// A run is part way through its steps.
+    running: bool,
+}
+
+/// Runs all maintenance jobs of one database on a single thread, so they
+/// never pile up on the database lock behind each other. Jobs take the lock
+/// one step at a time, and at most `budget` of lock time is spent in any
+/// one-second window; past that the scheduler waits for the next window.
+pub struct MaintenanceScheduler {
+    db: DbHandle,
+    budget: Option<Duration>,
+    jobs: Vec<Scheduled>,
+}
+
+impl MaintenanceScheduler {
+    /// `None` lets maintenance hold the lock as long as it needs.
+    pub fn new(db: DbHandle, budget: Option<Duration>) -> Self {
+        MaintenanceScheduler {
+            db,
+            budget,
+            jobs: Vec::new(),
+        }
+    }
+
+    /// The WAL, save, checkpoint, backfill and index jobs with the intervals
+    /// and budget from `config`. The index job needs the `index-engine` feature.
+    pub fn for_config(db: DbHandle, config: &DatabaseConfig) -> Self {
+        let scheduler = MaintenanceScheduler::new(db, config.maintenance_budget_duration())
+            .job(WalEngine::new(config.wal_engine_interval_duration()))
+            .job(SaveEngine::new(config.save_engine_interval_duration()))
+            .job(CheckpointEngine::new(
+                config.checkpoint_interval_duration(),
+                config.checkpoint_wal_bytes,
+            ))
+            .job(BackfillEngine);
+        #[cfg(feature = "index-engine")]
+        let scheduler = scheduler.job(IndexEngine::new(config.index_engine_interval_duration()));
+        scheduler
+    }
+
+    /// Add a job. Its first run is due immediately.
+    pub fn job(mut self, job: impl MaintenanceJob + 'static) -> Self {
+        self.jobs.push(Scheduled {
+            job: Box::new(job),
+            next_run: Instant::now(),
+            running: false,
+        });
+        self
+    }
+
+    /// Run the jobs on a new thread until `MaintenanceHandle::stop`.
+    pub fn start(mut self) -> MaintenanceHandle {
+        let (stop, stopped) = channel();
+        let thread = thread::spawn(move || self.run(&stopped));
+        MaintenanceHandle { stop, thread }
+    }
+
+    /// The highest-priority job that is due or part way through a run.
+    fn next_job(&self, now: Instant) -> Option<usize> {
+        self.jobs
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.running || s.next_run <= now)
+            .min_by_key(|(_, s)| (s.job.priority(), s.next_run))
+            .map(|(i, _)| i)
+    }
+
+    fn run(&mut self, stopped: &Receiver<()>) {
+        let mut window_start = Instant::now();
+        let mut used = Duration::ZERO;
+        loop {
+            let now = Instant::now();
+            if now.duration_since(window_start) >= BUDGET_WINDOW {
+                window_start = now;
+                used = Duration::ZERO;
+            }
+            if self.budget.is_some_and(|budget| used >= budget) {
+                debug!("Maintenance budget used up, yielding.");
+                if pause(stopped, (window_start + BUDGET_WINDOW).saturating_duration_since(now)) {
+                    return;
+                }
+                continue;
+            }
+            let Some(i) = self.next_job(now) else {
+                let next = self.jobs.iter().map(|s| s.next_run).min();
+                let wait = next.map_or(BUDGET_WINDOW, |t| t.saturating_duration_since(now));
+                if pause(stopped, wait.min(BUDGET_WINDOW)) {
+                    return;
+                }
+                continue;
+            };
+
+            let scheduled = &mut self.jobs[i];
+            let started = Instant::now();
+            let more = {
+                let mut db = self.db.lock();
+                let _span = info_span!(
+                    "maintenance",
+                    job = scheduled.job.name(),
+                    data_dir = %db.config.data_dir
+                )
+                .entered();
+                scheduled.job.step(&mut db)
+            };
+            used += started.elapsed();
+            scheduled.running = more;
+            if !more {
+                scheduled.next_run = Instant::now() + scheduled.job.interval();
+            }
+            if pause(stopped, STEP_PAUSE) {
+                return;
+            }
+        }
+    }
+}
+
+/// Sleep for `wait`, waking early if the scheduler is stopped. Returns true
+/// once it is. A dropped `MaintenanceHandle` leaves the scheduler running.
+fn pause(stopped: &Receiver<()>, wait: Duration) -> bool {
+    match stopped.recv_timeout(wait) {
+        Ok(()) => true,
+        Err(RecvTimeoutError::Timeout) => false,
+        Err(RecvTimeoutError::Disconnected) => {
+            thread::sleep(wait);
+            false
+        }
+    }
+}
+
+/// A running `MaintenanceScheduler`.
+pub struct MaintenanceHandle {
+    stop: Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl MaintenanceHandle {
+    /// Stop the scheduler once its current step is done and wait for its
+    /// thread to exit. Don't hold the database lock while calling this.
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        if self.thread.join().is_err() {
+            error!("The maintenance scheduler panicked.");
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use crate::commands::db::{Database, DatabaseError, Result};
+use crate::commands::handle::DbHandle;
+use crate::commands::result::{QueryResult, RowSet};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::{debug, warn};
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// How a session renders query results.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("unknown format '{}'; use table, json or csv", s)),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OutputFormat::Table => "table",
+            OutputFormat::Json => "json",
+            OutputFormat::Csv => "csv",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A write staged inside a session transaction.
+#[derive(Debug, Clone)]
+enum StagedWrite {
+    Insert(String, String, HashMap<String, String>),
+    Upsert(String, String, HashMap<String, String>),
+    Update(String, String, String, String),
+}
+
+/// Per-connection state on top of a shared `DbHandle`: a table namespace,
+/// the authenticated user and tenant, an output format, an optional
+/// transaction and temporary tables. Temporary tables are dropped when the
+/// session is.
+pub struct Session {
+    db: DbHandle,
+    id: u64,
+    namespace: Option<String>,
+    user: Option<String>,
+    /// Reads and writes are scoped to this tenant; see `Database::set_tenant`.
+    tenant: Option<String>,
+    /// Reads return soft-deleted rows; see `Database::set_include_deleted`.
+    include_deleted: bool,
+    format: OutputFormat,
+    /// Writes staged since `begin()`. Reads do not see them until `commit()`.
+    transaction: Option<Vec<StagedWrite>>,
+    /// Temporary table name -> the session-private name it is stored under.
+    temp_tables: HashMap<String, String>,
+}
+
+impl DbHandle {
+    pub fn session(&self) -> Session {
+        Session {
+            db: self.clone(),
+            id: NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed),
+            namespace: None,
+            user: None,
+            tenant: None,
+            include_deleted: false,
+            format: OutputFormat::default(),
+            transaction: None,
+            temp_tables: HashMap::new(),
+        }
+    }
+}
+
+impl Session {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn db(&self) -> &DbHandle {
+        &self.db
+    }
+
+    /// Unqualified table names resolve to `<namespace>_<table>`.
+    pub fn set_namespace(&mut self, namespace: Option<&str>) {
+        self.namespace = namespace.map(str::to_string);
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// Record who the session belongs to once the caller has authenticated them.
+    pub fn set_user(&mut self, user: Option<&str>) {
+        self.user = user.map(str::to_string);
+    }
+
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Scope the session's reads and writes to one tenant's rows of tables
+    /// with tenancy enabled (`Database::enable_tenancy`).
+    pub fn set_tenant(&mut self, tenant: Option<&str>) {
+        self.tenant = tenant.map(str::to_string);
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    /// Let the session's reads return soft-deleted rows, e.g. to audit or
+    /// undo deletes. Other sessions still skip them.
+    pub fn set_include_deleted(&mut self, include: bool) {
+        self.include_deleted = include;
+    }
+
+    pub fn include_deleted(&self) -> bool {
+        self.include_deleted
+    }
+
+    /// Run `f` under the database lock with the session's tenant and
+    /// soft-delete setting.
+    fn scoped<T>(&self, f: impl FnOnce(&mut Database) -> T) -> T {
+        let mut db = self.db.lock();
+        let previous = db.set_tenant(self.tenant.as_deref());
+        let included = db.set_include_deleted(self.include_deleted);
+        let result = f(&mut db);
+        db.set_include_deleted(included);
+        db.set_tenant(previous.as_deref());
+        result
+    }
+
+    pub fn set_format(&mut self, format: OutputFormat) {
+        self.format = format;
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+
+    /// The name a table is stored under: the session's temporary table if one
+    /// has that name, otherwise the name inside the namespace.
+    pub fn table_name(&self, table: &str) -> String {
+        if let Some(internal) = self.temp_tables.get(table) {
+            return internal.clone();
+        }
+        match &self.namespace {
+            Some(ns) => format!("{}_{}", ns, table),
+            None => table.to_string(),
+        }
+    }
+
+    /// Create a table only this session can see by name. It is held in
+    /// memory only, never logged or saved (see `Database::create_temp_table`),
+    /// and dropped along with the session.
+    pub fn create_temp_table(&mut self, table: &str) -> Result<()> {
+        if self.temp_tables.contains_key(table) {
+            return Err(DatabaseError::TableAlreadyExists(table.to_string()));
+        }
+        let internal = format!("__session{}_{}", self.id, table);
+        self.db.create_temp_table(&internal)?;
+        self.temp_tables.insert(table.to_string(), internal);
+        Ok(())
+    }
+
+    pub fn temp_tables(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.temp_tables.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Start staging writes. They are applied together, under one lock, by `commit()`.
+    pub fn begin(&mut self) {
+        if self.transaction.is_some() {
+            warn!("Session {} already has an open transaction.", self.id);
+            return;
+        }
+        self.transaction = Some(Vec::new());
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    /// Apply the staged writes in order. Stops at the first failing write and
+    /// returns its error; writes before it stay applied. Returns the number
+    /// of writes applied.
+    pub fn commit(&mut self) -> Result<usize> {
+        let Some(writes) = self.transaction.take() else {
+            return Ok(0);
+        };
+        let count = writes.len();
+        let mut db = self.db.lock();
+        let previous = db.set_tenant(self.tenant.as_deref());
+        for (applied, write) in writes.into_iter().enumerate() {
+            let result = match write {
+                StagedWrite::Insert(table, row_id, data) => {
+                    db.insert_row(&table, &row_id, data).map(|_| ())
+                }
+                StagedWrite::Upsert(table, row_id, data) => {
+                    db.upsert_row(&table, &row_id, data).map(|_| ())
+                }
+                StagedWrite::Update(table, row_id, column, value) => {
+                    db.update_row(&table, &row_id, &column, &value).map(|_| ())
+                }
+            };
+            if let Err(e) = result {
+                warn!(
+                    "Session {} commit stopped after {} writes: {}",
+                    self.id, applied, e
+                );
+                db.set_tenant(previous.as_deref());
+                return Err(e);
+            }
+        }
+        db.set_tenant(previous.as_deref());
+        Ok(count)
+    }
+
+    /// Discard the staged writes. Returns how many were discarded.
+    pub fn rollback(&mut self) -> usize {
+        self.transaction.take().map_or(0, |writes| writes.len())
+    }
+
+    pub fn insert_row(
+        &mut self,
+        table: &str,
+        row_id: &str,
+        data: HashMap<String, String>,
+    ) -> Result<()> {
+        let table = self.table_name(table);
+        match self.transaction.as_mut() {
+            Some(tx) => tx.push(StagedWrite::Insert(table, row_id.to_string(), data)),
+            None => self.scoped(|db| db.insert_row(&table, row_id, data)).map(|_| ())?,
+        }
+        Ok(())
+    }
+
+    pub fn upsert_row(
+        &mut self,
+        table: &str,
+        row_id: &str,
+        data: HashMap<String, String>,
+    ) -> Result<()> {
+        let table = self.table_name(table);
+        match self.transaction.as_mut() {
+            Some(tx) => tx.push(StagedWrite::Upsert(table, row_id.to_string(), data)),
+            None => self.scoped(|db| db.upsert_row(&table, row_id, data)).map(|_| ())?,
+        }
+        Ok(())
+    }
+
+    pub fn update_row(
+        &mut self,
+        table: &str,
+        row_id: &str,
+        column: &str,
+        value: &str,
+    ) -> Result<()> {
+        let table = self.table_name(table);
+        match self.transaction.as_mut() {
+            Some(tx) => tx.push(StagedWrite::Update(
+                table,
+                row_id.to_string(),
+                column.to_string(),
+                value.to_string(),
+            )),
+            None => self
+                .scoped(|db| db.update_row(&table, row_id, column, value))
+                .map(|_| ())?,
+        }
+        Ok(())
+    }
+
+    pub fn get_row(&self, table: &str, row_id: &str) -> Result<QueryResult> {
+        let table = self.table_name(table);
+        self.scoped(|db| db.get_row(&table, row_id))
+    }
+
+    pub fn search(&self, table: &str, condition: &str) -> Result<QueryResult> {
+        let table = self.table_name(table);
+        self.scoped(|db| db.search_rows_by_condition_in_table(&table, condition, None))
+    }
+
+    /// Render rows in the session's output format.
+    pub fn render(&self, rows: &RowSet) -> String {
+        let rows = &rows.to_maps();
+        let mut columns: Vec<&String> = rows.iter().flat_map(|(_, row)| row.keys()).collect();
+        columns.sort();
+        columns.dedup();
+        match self.format {
+            OutputFormat::Json => {
+                let rows: Vec<serde_json::Value> = rows
+                    .iter()
+                    .map(|(row_id, row)| serde_json::json!({ "row_id": row_id, "values": row }))
+                    .collect();
+                serde_json::to_string_pretty(&rows).unwrap_or_default()
+            }
+            OutputFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(Vec::new());
+                let header = std::iter::once("row_id").chain(columns.iter().map(|c| c.as_str()));
+                let _ = writer.write_record(header);
+                for (row_id, row) in rows {
+                    let record = std::iter::once(row_id.as_str()).chain(
+                        columns
+                            .iter()
+                            .map(|c| row.get(*c).map(String::as_str).unwrap_or("")),
+                    );
+                    let _ = writer.write_record(record);
+                }
+                String::from_utf8(writer.into_inner().unwrap_or_default()).unwrap_or_default()
+            }
+            OutputFormat::Table => {
+                let mut out = format!("{:<10}", "Row ID");
+                for column in &columns {
+                    out.push_str(&format!(" | {:<15}", column));
+                }
+                out.push('\n');
+                for (row_id, row) in rows {
+                    out.push_str(&format!("{:<10}", row_id));
+                    for column in &columns {
+                        out.push_str(&format!(
+                            " | {:<15}",
+                            row.get(*column).map(String::as_str).unwrap_or("")
+                        ));
+                    }
+                    out.push('\n');
+                }
+                out
+            }
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let discarded = self.rollback();
+        if discarded > 0 {
+            warn!(
+                "Session {} ended with {} uncommitted writes; discarded.",
+                self.id, discarded
+            );
+        }
+        for internal in self.temp_tables.values() {
+            if let Err(e) = self.db.drop_table(internal) {
+                warn!("Failed to drop temporary table '{}': {}", internal, e);
+            }
+        }
+        debug!("Session {} closed.", self.id);
+    }
+}
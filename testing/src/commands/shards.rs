@@ -0,0 +1,203 @@
+use crate::commands::config::DatabaseConfig;
+use crate::commands::db::{Database, Result};
+use crate::commands::functions::{FunctionResult, ScalarFn};
+use crate::commands::handle::DbHandle;
+#[cfg(feature = "native")]
+use crate::commands::locking;
+#[cfg(feature = "native")]
+use crate::commands::scheduler::{MaintenanceHandle, MaintenanceScheduler};
+use crate::commands::storage::Storage;
+use crate::commands::twophase::{Coordinator, TxWrite};
+#[cfg(feature = "native")]
+use crate::commands::walwriter::WalWriter;
+use std::sync::Arc;
+#[cfg(feature = "native")]
+use std::sync::Mutex;
+#[cfg(feature = "native")]
+use std::thread::JoinHandle;
+#[cfg(feature = "native")]
+use tracing::error;
+use tracing::info;
+
+/// A database whose tables are spread over several `Database` shards by a
+/// hash of the table name. Each shard has its own lock, WAL files, indexes
+/// and background engines, so writers to tables on different shards don't
+/// wait on each other. Every table lives wholly on one shard.
+#[derive(Clone)]
+pub struct ShardedDatabase {
+    shards: Vec<DbHandle>,
+    /// Threads `start_engines` started, until `close` stops them.
+    #[cfg(feature = "native")]
+    engines: Arc<Mutex<Vec<Engines>>>,
+}
+
+/// The background threads of one shard.
+#[cfg(feature = "native")]
+struct Engines {
+    db: DbHandle,
+    wal_writer: JoinHandle<()>,
+    maintenance: MaintenanceHandle,
+}
+
+impl From<DbHandle> for ShardedDatabase {
+    fn from(db: DbHandle) -> Self {
+        ShardedDatabase::new(vec![db])
+    }
+}
+
+impl ShardedDatabase {
+    fn new(shards: Vec<DbHandle>) -> Self {
+        ShardedDatabase {
+            shards,
+            #[cfg(feature = "native")]
+            engines: Arc::default(),
+        }
+    }
+
+    /// Open `config.shards` shards (at least one) sharing `config.data_dir`
+    /// on `storage`.
+    pub fn open(config: &DatabaseConfig, key: Option<[u8; 32]>, storage: Arc<dyn Storage>) -> Self {
+        let shards = (0..config.shards.max(1))
+            .map(|i| {
+                let mut db = Database::with_storage(config.shard(i), storage.clone());
+                if let Some(key) = key {
+                    db.set_encryption_key(key);
+                }
+                DbHandle::new(db)
+            })
+            .collect::<Vec<_>>();
+        info!("Opened {} shards in '{}'.", shards.len(), config.data_dir);
+        ShardedDatabase::new(shards)
+    }
+
+    /// Give every shard its own async WAL writer and maintenance scheduler.
+    #[cfg(feature = "native")]
+    pub fn start_engines(&self) {
+        for db in &self.shards {
+            let config = db.lock().config.clone();
+            let (wal_writer, wal_writer_handle) =
+                WalWriter::new(config.wal_batch_interval_duration());
+            db.lock().wal_writer = Some(wal_writer);
+            db.lock().set_background_saves(true);
+            let (storage, cipher) = {
+                let db = db.lock();
+                (db.storage.clone(), db.cipher.clone())
+            };
+            let wal_writer = wal_writer_handle.start(storage, config.wal_path(), cipher);
+            let maintenance = MaintenanceScheduler::for_config(db.clone(), &config).start();
+            locking::recover(&self.engines).push(Engines {
+                db: db.clone(),
+                wal_writer,
+                maintenance,
+            });
+        }
+    }
+
+    /// Save every shard and stop its engines, e.g. before the database is
+    /// dropped: checkpoint each writable shard, then stop its maintenance
+    /// scheduler, write out what its WAL writer still holds and wait for both
+    /// threads to exit. Writes made meanwhile through other handles stay in
+    /// the WAL for the next open to replay. Fails, leaving the engines
+    /// running, if a checkpoint fails.
+    pub fn close(&self) -> Result<()> {
+        for db in &self.shards {
+            let mut db = db.lock();
+            if !db.read_only {
+                db.checkpoint()?;
+            }
+        }
+        #[cfg(feature = "native")]
+        for engines in std::mem::take(&mut *locking::recover(&self.engines)) {
+            engines.maintenance.stop();
+            let wal_writer = {
+                let mut db = engines.db.lock();
+                if let Err(e) = db.sync_wal() {
+                    error!("Failed to sync the WAL of '{}': {}", db.config.data_dir, e);
+                }
+                // From now on the shard writes its WAL itself.
+                db.set_background_saves(false);
+                db.wal_writer.take()
+            };
+            // The writer thread exits once its sender is gone.
+            drop(wal_writer);
+            if engines.wal_writer.join().is_err() {
+                error!("The WAL writer panicked.");
+            }
+        }
+        Ok(())
+    }
+
+    pub fn shards(&self) -> &[DbHandle] {
+        &self.shards
+    }
+
+    /// Index of the shard that owns `table_name`. FNV-1a keeps the mapping
+    /// stable across builds, which matters because it decides whose WAL
+    /// a table's writes go to.
+    pub fn shard_index(&self, table_name: &str) -> usize {
+        let hash = table_name.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+        (hash % self.shards.len() as u64) as usize
+    }
+
+    /// The shard that owns `table_name`, whether or not the table exists yet.
+    pub fn shard_for(&self, table_name: &str) -> &DbHandle {
+        &self.shards[self.shard_index(table_name)]
+    }
+
+    /// Coordinator for transactions across shards, logging to shard 0's
+    /// WAL directory.
+    fn coordinator(&self) -> Coordinator {
+        let db = self.shards[0].lock();
+        Coordinator::new(db.storage.clone(), &db.config.transaction_log_path())
+    }
+
+    /// Apply writes to tables on any shards atomically, by two-phase commit.
+    /// Returns the transaction id.
+    pub fn write_atomically(&self, writes: Vec<TxWrite>) -> Result<String> {
+        let mut parts: Vec<(DbHandle, Vec<TxWrite>)> = Vec::new();
+        for write in writes {
+            let db = self.shard_for(write.table()).clone();
+            match parts.iter_mut().find(|(p, _)| p.same_database(&db)) {
+                Some((_, part)) => part.push(write),
+                None => parts.push((db, vec![write])),
+            }
+        }
+        self.coordinator().execute(parts)
+    }
+
+    /// Settle transactions a crash left prepared on the shards. Returns how
+    /// many were settled.
+    pub fn recover_transactions(&self) -> Result<usize> {
+        self.coordinator().recover(&self.shards)
+    }
+
+    /// Register a scalar function on every shard.
+    pub fn register_function(
+        &self,
+        name: &str,
+        f: impl Fn(&[String]) -> FunctionResult + Send + Sync + 'static,
+    ) {
+        let f: ScalarFn = Arc::new(f);
+        for db in &self.shards {
+            db.lock().functions.register(name, f.clone());
+        }
+    }
+
+    /// Tables of every shard, sorted.
+    pub fn list_tables(&self) -> Vec<String> {
+        let mut tables: Vec<String> = self.shards.iter().flat_map(|db| db.list_tables()).collect();
+        tables.sort();
+        tables
+    }
+
+    /// Checkpoint every shard in turn. Returns the tables saved.
+    pub fn checkpoint(&self) -> Result<Vec<String>> {
+        let mut saved = Vec::new();
+        for db in &self.shards {
+            saved.extend(db.checkpoint()?);
+        }
+        Ok(saved)
+    }
+}
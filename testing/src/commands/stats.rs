@@ -0,0 +1,326 @@
+use crate::commands::result::Value;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Size information for one table.
+#[derive(Debug, Clone)]
+pub struct TableStats {
+    pub name: String,
+    /// Rows held in memory.
+    pub rows: usize,
+    /// Rows flushed to the table's cold segment by `Database::flush_cold`.
+    pub cold_rows: usize,
+    pub columns: usize,
+    /// Approximate bytes used by the table's rows in memory.
+    pub memory_bytes: usize,
+    /// Size of the table's CSV file, if it has been saved.
+    pub file_bytes: Option<u64>,
+}
+
+/// Snapshot returned by `Database::stats()`.
+#[derive(Debug, Clone)]
+pub struct DatabaseStats {
+    pub tables: Vec<TableStats>,
+    /// WAL entries held in memory that have not been committed yet.
+    pub wal_backlog: usize,
+    pub wal_file_bytes: Option<u64>,
+    pub wal_archive_bytes: Option<u64>,
+    pub index_file_bytes: Option<u64>,
+    pub bloom_file_bytes: Option<u64>,
+    /// Writes since the index / bloom filter were last rebuilt; `None` if never built.
+    pub index_stale_writes: Option<u64>,
+    pub bloom_stale_writes: Option<u64>,
+}
+
+impl DatabaseStats {
+    pub fn total_rows(&self) -> usize {
+        self.tables.iter().map(|t| t.rows + t.cold_rows).sum()
+    }
+
+    pub fn total_memory_bytes(&self) -> usize {
+        self.tables.iter().map(|t| t.memory_bytes).sum()
+    }
+}
+
+/// Result of `Database::vacuum()`.
+#[derive(Debug, Clone)]
+pub struct VacuumReport {
+    pub table: String,
+    pub table_bytes_before: u64,
+    pub table_bytes_after: u64,
+    pub archive_bytes_before: u64,
+    pub archive_bytes_after: u64,
+    /// Archived WAL entries for the table that the latest backup already held.
+    pub archive_entries_removed: usize,
+}
+
+impl VacuumReport {
+    pub fn reclaimed_bytes(&self) -> u64 {
+        (self.table_bytes_before + self.archive_bytes_before)
+            .saturating_sub(self.table_bytes_after + self.archive_bytes_after)
+    }
+}
+
+impl fmt::Display for VacuumReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Vacuumed '{}': table file {} -> {} bytes, WAL archive {} -> {} bytes ({} entries removed), {} bytes reclaimed",
+            self.table,
+            self.table_bytes_before,
+            self.table_bytes_after,
+            self.archive_bytes_before,
+            self.archive_bytes_after,
+            self.archive_entries_removed,
+            self.reclaimed_bytes()
+        )
+    }
+}
+
+/// Result of `Database::recover_to()`.
+#[derive(Debug, Clone)]
+pub struct RecoveryReport {
+    /// Directory of the backup restored.
+    pub backup: String,
+    /// Sequence number of the last operation the backup holds.
+    pub backup_lsn: u64,
+    /// Archived WAL records replayed on top of the backup.
+    pub records_replayed: usize,
+    /// Sequence number of the last record replayed, `backup_lsn` if none was.
+    pub recovered_lsn: u64,
+    /// Tables as recovered.
+    pub tables: Vec<String>,
+}
+
+impl fmt::Display for RecoveryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Restored backup '{}' (LSN {}), replayed {} WAL records up to LSN {}; {} tables: {}",
+            self.backup,
+            self.backup_lsn,
+            self.records_replayed,
+            self.recovered_lsn,
+            self.tables.len(),
+            self.tables.join(", ")
+        )
+    }
+}
+
+/// Result of `Database::recover()`.
+#[derive(Debug, Clone)]
+pub struct StartupReport {
+    /// Tables in memory once recovered: those the WAL named.
+    pub tables: Vec<String>,
+    pub wal_records_replayed: usize,
+    /// Two-phase transactions still prepared, waiting for their coordinator.
+    pub prepared_transactions: usize,
+    /// Whether the index and bloom filter were restored from their files and
+    /// are current; otherwise the index engine rebuilds them.
+    pub index_restored: bool,
+    pub bloom_restored: bool,
+}
+
+impl fmt::Display for StartupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let restored = |done: bool| if done { "restored" } else { "to rebuild" };
+        write!(
+            f,
+            "Recovered: replayed {} WAL records into {} tables, {} prepared transactions; index {}, bloom filter {}",
+            self.wal_records_replayed,
+            self.tables.len(),
+            self.prepared_transactions,
+            restored(self.index_restored),
+            restored(self.bloom_restored)
+        )
+    }
+}
+
+/// A column added by `Database::add_column_online` whose existing rows are
+/// still being given its default. Each backfill step is logged with the
+/// progress after it, so WAL replay fills the same rows and resumes the rest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackfillProgress {
+    pub table: String,
+    pub column: String,
+    /// Value given to rows that have none for the column.
+    pub default: String,
+    /// Last row id visited; rows are visited in row id order.
+    pub cursor: Option<String>,
+    pub rows_done: usize,
+    /// Rows in the table when the column was added.
+    pub rows_total: usize,
+    pub done: bool,
+}
+
+impl fmt::Display for BackfillProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.done {
+            return writeln!(f, "{}.{}: done", self.table, self.column);
+        }
+        writeln!(
+            f,
+            "{}.{}: {} of {} rows backfilled with '{}'",
+            self.table,
+            self.column,
+            self.rows_done.min(self.rows_total),
+            self.rows_total,
+            self.default
+        )
+    }
+}
+
+fn bytes(size: Option<u64>) -> String {
+    size.map_or("-".to_string(), |b| b.to_string())
+}
+
+fn staleness(writes: Option<u64>) -> String {
+    writes.map_or("never built".to_string(), |w| {
+        format!("{} writes behind", w)
+    })
+}
+
+impl fmt::Display for DatabaseStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<15} | {:>8} | {:>8} | {:>8} | {:>12} | {:>12}",
+            "table", "rows", "cold", "columns", "memory", "file"
+        )?;
+        writeln!(f, "{}", "-".repeat(78))?;
+        for t in &self.tables {
+            writeln!(
+                f,
+                "{:<15} | {:>8} | {:>8} | {:>8} | {:>12} | {:>12}",
+                t.name,
+                t.rows,
+                t.cold_rows,
+                t.columns,
+                t.memory_bytes,
+                bytes(t.file_bytes)
+            )?;
+        }
+        writeln!(
+            f,
+            "total: {} rows, ~{} bytes in memory",
+            self.total_rows(),
+            self.total_memory_bytes()
+        )?;
+        writeln!(
+            f,
+            "wal: {} pending entries, file {} bytes, archive {} bytes",
+            self.wal_backlog,
+            bytes(self.wal_file_bytes),
+            bytes(self.wal_archive_bytes)
+        )?;
+        writeln!(
+            f,
+            "index: {} (file {} bytes)",
+            staleness(self.index_stale_writes),
+            bytes(self.index_file_bytes)
+        )?;
+        writeln!(
+            f,
+            "bloom filter: {} (file {} bytes)",
+            staleness(self.bloom_stale_writes),
+            bytes(self.bloom_file_bytes)
+        )
+    }
+}
+
+/// Result of `Database::merge_tables()`.
+#[derive(Debug, Clone, Default)]
+pub struct MergeReport {
+    pub source: String,
+    pub destination: String,
+    pub added: usize,
+    pub overwritten: usize,
+    /// Colliding rows left as they were under `ConflictPolicy::Skip`.
+    pub skipped: usize,
+}
+
+impl fmt::Display for MergeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Merged '{}' into '{}': {} added, {} overwritten, {} skipped",
+            self.source, self.destination, self.added, self.overwritten, self.skipped
+        )
+    }
+}
+
+/// Number of buckets in the equi-depth histograms built by `Database::analyze()`.
+pub const HISTOGRAM_BUCKETS: usize = 10;
+
+/// Per-column profile computed by `Database::analyze()`.
+#[derive(Debug, Clone)]
+pub struct ColumnStats {
+    pub column: String,
+    /// Rows where the column is missing or empty.
+    pub null_count: usize,
+    pub distinct: usize,
+    /// Whether every non-null value parsed as a number; min/max and the
+    /// histogram are then ordered numerically.
+    pub numeric: bool,
+    pub min: Option<String>,
+    pub max: Option<String>,
+    /// Upper bound of each equi-depth bucket, so every bucket covers roughly
+    /// the same number of rows.
+    pub histogram: Vec<String>,
+}
+
+impl ColumnStats {
+    /// Profile one column from its values, `None` meaning the row has no value.
+    pub fn from_values<'a>(column: &str, values: impl Iterator<Item = Option<&'a str>>) -> Self {
+        let mut null_count = 0;
+        let mut present: Vec<&str> = Vec::new();
+        for value in values {
+            match value {
+                Some(v) if !v.is_empty() => present.push(v),
+                _ => null_count += 1,
+            }
+        }
+        let numeric = !present.is_empty() && present.iter().all(|v| v.parse::<f64>().is_ok());
+        if numeric {
+            present.sort_by(|a, b| {
+                Value::parse(a, Some("float")).sort_cmp(&Value::parse(b, Some("float")))
+            });
+        } else {
+            present.sort();
+        }
+        let distinct = present.iter().collect::<HashSet<_>>().len();
+        let buckets = HISTOGRAM_BUCKETS.min(present.len());
+        let histogram = (1..=buckets)
+            .map(|b| present[b * present.len() / buckets - 1].to_string())
+            .collect();
+        ColumnStats {
+            column: column.to_string(),
+            null_count,
+            distinct,
+            numeric,
+            min: present.first().map(|v| v.to_string()),
+            max: present.last().map(|v| v.to_string()),
+            histogram,
+        }
+    }
+}
+
+impl fmt::Display for ColumnStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {} distinct, {} null, min {}, max {}{}",
+            self.column,
+            self.distinct,
+            self.null_count,
+            self.min.as_deref().unwrap_or("-"),
+            self.max.as_deref().unwrap_or("-"),
+            if self.numeric { " (numeric)" } else { "" }
+        )?;
+        if !self.histogram.is_empty() {
+            writeln!(f, "  histogram: {}", self.histogram.join(" | "))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+//! Storage backends: every file the database reads or writes goes through a
+//! `Storage`, so the engine can run on local disk or entirely in memory (for
+//! tests and throwaway databases). An object store fits the same trait:
+//! `write` and `commit` map to a put, `rename` to copy then delete. So does a
+//! browser store such as IndexedDB, passed in through `DatabaseBuilder::storage`.
+//!
+//! Local disk needs the `native` feature; without it memory is the default.
+
+#[cfg(feature = "native")]
+use crate::commands::atomic::{self, AtomicFile};
+use crate::commands::locking;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(feature = "native")]
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A file being written. `commit` replaces the target with what was written;
+/// dropped uncommitted, it leaves the target as it was.
+pub trait StorageWriter: Write + Send {
+    fn commit(self: Box<Self>) -> io::Result<()>;
+}
+
+pub trait Storage: Send + Sync + fmt::Debug {
+    /// A reader over the whole file.
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read + Send>>;
+
+    /// A writer that replaces `path` when committed.
+    fn create(&self, path: &str) -> io::Result<Box<dyn StorageWriter>>;
+
+    /// Append to `path`, creating it if needed.
+    fn append(&self, path: &str, contents: &[u8]) -> io::Result<()>;
+
+    /// Make appends to `path` durable.
+    fn sync(&self, path: &str) -> io::Result<()>;
+
+    /// Size in bytes.
+    fn size(&self, path: &str) -> io::Result<u64>;
+
+    /// Paths of the files directly inside `dir`, sorted.
+    fn list(&self, dir: &str) -> io::Result<Vec<String>>;
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()>;
+
+    fn remove(&self, path: &str) -> io::Result<()>;
+
+    fn create_dir_all(&self, dir: &str) -> io::Result<()>;
+
+    /// Clean up after writes that a crash interrupted in `dir`. Returns how
+    /// many leftovers were removed.
+    fn recover(&self, _dir: &str) -> usize {
+        0
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.size(path).is_ok()
+    }
+
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.open(path)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn read_to_string(&self, path: &str) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Replace `path` with `contents` in one step.
+    fn write(&self, path: &str, contents: &[u8]) -> io::Result<()> {
+        let mut writer = self.create(path)?;
+        writer.write_all(contents)?;
+        writer.commit()
+    }
+
+    fn copy(&self, from: &str, to: &str) -> io::Result<()> {
+        self.write(to, &self.read(from)?)
+    }
+}
+
+/// Which backend a database opens, e.g. `storage = "memory"` in TOML.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageKind {
+    #[cfg(feature = "native")]
+    #[default]
+    Local,
+    /// Nothing survives the process; for tests and scratch databases.
+    #[cfg_attr(not(feature = "native"), default)]
+    Memory,
+}
+
+impl StorageKind {
+    /// A fresh backend of this kind.
+    pub fn open(self) -> Arc<dyn Storage> {
+        match self {
+            #[cfg(feature = "native")]
+            StorageKind::Local => Arc::new(LocalStorage),
+            StorageKind::Memory => Arc::new(MemoryStorage::default()),
+        }
+    }
+}
+
+impl FromStr for StorageKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            #[cfg(feature = "native")]
+            "local" => Ok(StorageKind::Local),
+            "memory" => Ok(StorageKind::Memory),
+            _ => Err(format!("unknown storage '{}'; use local or memory", s)),
+        }
+    }
+}
+
+impl fmt::Display for StorageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            #[cfg(feature = "native")]
+            StorageKind::Local => "local",
+            StorageKind::Memory => "memory",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Files on local disk. Whole-file writes are atomic (see `atomic`).
+#[cfg(feature = "native")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalStorage;
+
+#[cfg(feature = "native")]
+impl StorageWriter for AtomicFile {
+    fn commit(self: Box<Self>) -> io::Result<()> {
+        AtomicFile::commit(*self)
+    }
+}
+
+#[cfg(feature = "native")]
+impl Storage for LocalStorage {
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn create(&self, path: &str) -> io::Result<Box<dyn StorageWriter>> {
+        Ok(Box::new(AtomicFile::create(path)?))
+    }
+
+    fn append(&self, path: &str, contents: &[u8]) -> io::Result<()> {
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)?
+            .write_all(contents)
+    }
+
+    fn sync(&self, path: &str) -> io::Result<()> {
+        OpenOptions::new().append(true).open(path)?.sync_data()
+    }
+
+    fn size(&self, path: &str) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    fn list(&self, dir: &str) -> io::Result<Vec<String>> {
+        let mut files: Vec<String> = fs::read_dir(dir)?
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_file())
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &str) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn create_dir_all(&self, dir: &str) -> io::Result<()> {
+        fs::create_dir_all(dir)
+    }
+
+    fn recover(&self, dir: &str) -> usize {
+        atomic::remove_leftovers(dir)
+    }
+
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+}
+
+/// Files held in a map. Clones share the same files, so every shard of a
+/// database sees one directory tree.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    files: Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+}
+
+struct MemoryWriter {
+    files: Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+    path: String,
+    buffer: Vec<u8>,
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl StorageWriter for MemoryWriter {
+    fn commit(self: Box<Self>) -> io::Result<()> {
+        let MemoryWriter {
+            files,
+            path,
+            buffer,
+        } = *self;
+        lock(&files).insert(path, buffer);
+        Ok(())
+    }
+}
+
+fn lock(files: &Mutex<BTreeMap<String, Vec<u8>>>) -> MutexGuard<'_, BTreeMap<String, Vec<u8>>> {
+    locking::recover(files)
+}
+
+fn not_found(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{}: no such file", path))
+}
+
+impl Storage for MemoryStorage {
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read + Send>> {
+        let data = lock(&self.files).get(path).cloned();
+        Ok(Box::new(Cursor::new(data.ok_or_else(|| not_found(path))?)))
+    }
+
+    fn create(&self, path: &str) -> io::Result<Box<dyn StorageWriter>> {
+        Ok(Box::new(MemoryWriter {
+            files: self.files.clone(),
+            path: path.to_string(),
+            buffer: Vec::new(),
+        }))
+    }
+
+    fn append(&self, path: &str, contents: &[u8]) -> io::Result<()> {
+        lock(&self.files)
+            .entry(path.to_string())
+            .or_default()
+            .extend_from_slice(contents);
+        Ok(())
+    }
+
+    fn sync(&self, _path: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self, path: &str) -> io::Result<u64> {
+        lock(&self.files)
+            .get(path)
+            .map(|data| data.len() as u64)
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn list(&self, dir: &str) -> io::Result<Vec<String>> {
+        Ok(lock(&self.files)
+            .keys()
+            .filter(|path| Path::new(path).parent() == Some(Path::new(dir)))
+            .cloned()
+            .collect())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let mut files = lock(&self.files);
+        let data = files.remove(from).ok_or_else(|| not_found(from))?;
+        files.insert(to.to_string(), data);
+        Ok(())
+    }
+
+    fn remove(&self, path: &str) -> io::Result<()> {
+        lock(&self.files)
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn create_dir_all(&self, _dir: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+//! Atomic writes across shards and databases by two-phase commit.
+//!
+//! Each database taking part is first asked to `prepare` its share of the
+//! writes: it checks them, logs them to its WAL as a `prepare_tx` record and
+//! holds their rows against other writers. Once every one has prepared, the
+//! coordinator records the decision in its own log and tells each to
+//! `commit_prepared`; if any fails to prepare, the others are told to
+//! `abort_prepared`. A database keeps its prepared transactions across
+//! restarts, and `Coordinator::recover` settles them from the decision log:
+//! committed if the decision was logged, aborted otherwise.
+
+use crate::commands::clock;
+use crate::commands::db::{DatabaseError, Result};
+use crate::commands::handle::DbHandle;
+use crate::commands::storage::Storage;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+/// One write of a transaction, applied like the `Database` method of the same name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TxWrite {
+    Insert {
+        table: String,
+        row_id: String,
+        data: HashMap<String, String>,
+    },
+    Upsert {
+        table: String,
+        row_id: String,
+        data: HashMap<String, String>,
+    },
+    Update {
+        table: String,
+        row_id: String,
+        column: String,
+        value: String,
+    },
+}
+
+impl TxWrite {
+    pub fn table(&self) -> &str {
+        match self {
+            TxWrite::Insert { table, .. }
+            | TxWrite::Upsert { table, .. }
+            | TxWrite::Update { table, .. } => table,
+        }
+    }
+
+    pub fn row_id(&self) -> &str {
+        match self {
+            TxWrite::Insert { row_id, .. }
+            | TxWrite::Upsert { row_id, .. }
+            | TxWrite::Update { row_id, .. } => row_id,
+        }
+    }
+
+    /// The same write against another table name, e.g. once a qualified
+    /// `db.table` has been resolved.
+    pub(crate) fn with_table(mut self, name: &str) -> Self {
+        match &mut self {
+            TxWrite::Insert { table, .. }
+            | TxWrite::Upsert { table, .. }
+            | TxWrite::Update { table, .. } => *table = name.to_string(),
+        }
+        self
+    }
+}
+
+/// A fresh transaction id: the time in milliseconds and a random suffix.
+pub fn new_txid() -> String {
+    format!("{:x}-{:08x}", clock::unix_millis(), rand::random::<u32>())
+}
+
+/// Runs transactions over several databases and keeps the log of commit
+/// decisions, one `commit <txid>` line each.
+pub struct Coordinator {
+    storage: Arc<dyn Storage>,
+    log_path: String,
+}
+
+impl Coordinator {
+    pub fn new(storage: Arc<dyn Storage>, log_path: &str) -> Self {
+        Coordinator {
+            storage,
+            log_path: log_path.to_string(),
+        }
+    }
+
+    /// Apply every database's writes or none of them. The writes are grouped
+    /// by database in `parts`; handles to the same database are merged.
+    /// Returns the transaction id.
+    pub fn execute(&self, parts: Vec<(DbHandle, Vec<TxWrite>)>) -> Result<String> {
+        let txid = new_txid();
+        let mut participants: Vec<(DbHandle, Vec<TxWrite>)> = Vec::new();
+        for (db, writes) in parts {
+            match participants.iter_mut().find(|(p, _)| p.same_database(&db)) {
+                Some((_, existing)) => existing.extend(writes),
+                None => participants.push((db, writes)),
+            }
+        }
+
+        for (i, (db, writes)) in participants.iter().enumerate() {
+            if let Err(e) = db.lock().prepare(&txid, writes.clone()) {
+                warn!("Transaction {} failed to prepare: {}", txid, e);
+                for (db, _) in &participants[..i] {
+                    if let Err(e) = db.lock().abort_prepared(&txid) {
+                        // Left prepared; `recover` aborts it later.
+                        error!("Transaction {} failed to abort: {}", txid, e);
+                    }
+                }
+                return Err(e);
+            }
+        }
+
+        // The transaction is committed once this line is durable.
+        self.log_decision(&txid)?;
+        for (db, _) in &participants {
+            if let Err(e) = db.lock().commit_prepared(&txid) {
+                // Still prepared there; `recover` finishes the commit.
+                error!("Transaction {} failed to commit on a participant: {}", txid, e);
+            }
+        }
+        info!(
+            "Transaction {} committed on {} databases.",
+            txid,
+            participants.len()
+        );
+        Ok(txid)
+    }
+
+    fn log_decision(&self, txid: &str) -> Result<()> {
+        let line = format!("commit {}\n", txid);
+        self.storage
+            .append(&self.log_path, line.as_bytes())
+            .and_then(|_| self.storage.sync(&self.log_path))
+            .map_err(|e| DatabaseError::FileCreationError(self.log_path.clone(), e.to_string()))
+    }
+
+    /// Transactions the log says were committed.
+    pub fn committed(&self) -> HashSet<String> {
+        self.storage
+            .read_to_string(&self.log_path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.strip_prefix("commit "))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Settle the transactions left prepared on `databases` by a crash:
+    /// commit those the log says were committed and abort the rest. Run it
+    /// before starting new transactions, which it would otherwise abort
+    /// mid-prepare. Returns how many were settled.
+    pub fn recover(&self, databases: &[DbHandle]) -> Result<usize> {
+        let committed = self.committed();
+        let mut settled = 0;
+        for db in databases {
+            let mut db = db.lock();
+            for txid in db.prepared_transactions() {
+                if committed.contains(&txid) {
+                    db.commit_prepared(&txid)?;
+                    info!("Recovered transaction {}: committed.", txid);
+                } else {
+                    db.abort_prepared(&txid)?;
+                    info!("Recovered transaction {}: aborted.", txid);
+                }
+                settled += 1;
+            }
+        }
+        Ok(settled)
+    }
+}
@@ -0,0 +1,112 @@
+//! `TypedTable<T>`: a table read and written as a Rust type through the serde
+//! mapping in `mapping`, instead of `HashMap<String, String>` rows.
+
+use crate::commands::db::{DatabaseError, Result};
+use crate::commands::handle::DbHandle;
+use crate::commands::mapping;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+
+/// A table whose rows are `T`s. Its columns were checked against `T`'s fields
+/// when it was opened; each call locks the database once.
+pub struct TypedTable<T> {
+    db: DbHandle,
+    table_name: String,
+    fields: &'static [&'static str],
+    _rows: PhantomData<fn() -> T>,
+}
+
+impl<T: Serialize + DeserializeOwned> TypedTable<T> {
+    /// Open `table_name` as a table of `T`s. Fails unless `T` is a struct
+    /// whose fields are exactly the table's columns.
+    pub fn open(db: &DbHandle, table_name: &str) -> Result<Self> {
+        let type_name = mapping::type_name::<T>();
+        let fields = mapping::struct_fields::<T>()
+            .ok_or_else(|| DatabaseError::RowMapping(format!("{} is not a struct", type_name)))?;
+        let mut db_lock = db.lock();
+        db_lock.check_struct_columns::<T>(table_name)?;
+        let table = db_lock.get_table(table_name)?;
+        let mut unmapped: Vec<&str> = table
+            .columns
+            .iter()
+            .map(String::as_str)
+            .filter(|column| !fields.contains(column))
+            .collect();
+        if !unmapped.is_empty() {
+            unmapped.sort();
+            return Err(DatabaseError::RowMapping(format!(
+                "{} has no fields for columns of table '{}': {}",
+                type_name,
+                table_name,
+                unmapped.join(", ")
+            )));
+        }
+        drop(db_lock);
+        Ok(TypedTable {
+            db: db.clone(),
+            table_name: table_name.to_string(),
+            fields,
+            _rows: PhantomData,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// The row with id `row_id`, or `None` if there isn't one.
+    pub fn get(&self, row_id: &str) -> Result<Option<T>> {
+        let batch = self.db.get_rows(&self.table_name, &[row_id])?;
+        batch
+            .found
+            .into_iter()
+            .next()
+            .map(|(_, row)| mapping::from_row(&row).map_err(|e| self.mapping_error(row_id, e)))
+            .transpose()
+    }
+
+    /// Insert `value` as a new row.
+    pub fn insert(&self, row_id: &str, value: &T) -> Result<()> {
+        self.db.insert_struct(&self.table_name, row_id, value)?;
+        Ok(())
+    }
+
+    /// Replace every column of an existing row with the fields of `value`;
+    /// `None` fields clear their column.
+    pub fn update(&self, row_id: &str, value: &T) -> Result<()> {
+        let mut row = mapping::to_row(value).map_err(|e| self.mapping_error(row_id, e))?;
+        for field in self.fields {
+            row.entry(field.to_string()).or_default();
+        }
+        let mut db = self.db.lock();
+        if !db.row_exists(&self.table_name, row_id)? {
+            return Err(DatabaseError::RowDoesNotExist(
+                row_id.to_string(),
+                self.table_name.clone(),
+            ));
+        }
+        db.upsert_row(&self.table_name, row_id, row)?;
+        Ok(())
+    }
+
+    /// Every row with its id.
+    pub fn scan(&self) -> Result<Vec<(String, T)>> {
+        self.db.query_as(&self.table_name, None)
+    }
+
+    /// Rows matching `condition` ("column operator value") with their ids.
+    pub fn scan_where(&self, condition: &str) -> Result<Vec<(String, T)>> {
+        self.db.query_as(&self.table_name, Some(condition))
+    }
+
+    fn mapping_error(&self, row_id: &str, e: String) -> DatabaseError {
+        DatabaseError::RowMapping(format!(
+            "row '{}' of '{}' as {}: {}",
+            row_id,
+            self.table_name,
+            mapping::type_name::<T>(),
+            e
+        ))
+    }
+}
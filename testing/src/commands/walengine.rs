@@ -1,51 +1,57 @@
-//// filepath: c:\Users\srija\Documents\GitHub\Rust_DB\testing\src\commands\walengine.rs
 use super::db::Database;
-use log::{error, info};
-use std::sync::{Arc, Mutex};
-use std::thread;
+use super::scheduler::{MaintenanceJob, Priority};
 use std::time::Duration;
+use tracing::{debug, error};
 
+/// Persists the in-memory WAL to disk and replays it. Each run takes two
+/// steps so the database lock is released between them.
 pub struct WalEngine {
-    db: Arc<Mutex<Database>>,
     interval: Duration,
+    persisted: bool,
 }
 
 impl WalEngine {
-    pub fn new(db: Arc<Mutex<Database>>, interval: Duration) -> Self {
-        WalEngine { db, interval }
+    pub fn new(interval: Duration) -> Self {
+        WalEngine {
+            interval,
+            persisted: false,
+        }
+    }
+}
+
+impl MaintenanceJob for WalEngine {
+    fn name(&self) -> &'static str {
+        "wal_engine"
+    }
+
+    fn priority(&self) -> Priority {
+        Priority::High
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
     }
 
-    pub fn start(&self) {
-        let db_clone = Arc::clone(&self.db);
-        let interval = self.interval;
-        thread::spawn(move || {
-            loop {
-                {
-                    // Recover from a poisoned mutex by taking the inner value.
-                    let mut db = db_clone
-                        .lock()
-                        .unwrap_or_else(|poisoned| poisoned.into_inner());
-                    // Persist the working WAL.
-                    if let Err(e) = db.persist_wal() {
-                        error!("Failed to persist WAL: {}", e);
-                    } else {
-                        info!("WAL persisted successfully.");
-                    }
-                    // Replay the WAL to update in-memory state.
-                    if let Err(e) = db.replay_wal() {
-                        error!("Failed to replay WAL: {}", e);
-                    } else {
-                        info!("WAL replayed successfully.");
-                    }
-                    // Commit the WAL.
-                    if let Err(e) = db.commit_wal() {
-                        error!("Failed to commit WAL: {}", e);
-                    } else {
-                        info!("WAL commit completed.");
-                    }
-                }
-                thread::sleep(interval);
+    fn step(&mut self, db: &mut Database) -> bool {
+        if !self.persisted {
+            // Persist the working WAL.
+            if let Err(e) = db.persist_wal() {
+                error!("Failed to persist WAL: {}", e);
+            } else {
+                debug!("WAL persisted successfully.");
             }
-        });
+            self.persisted = true;
+            return true;
+        }
+        // Replay the WAL to update in-memory state.
+        if let Err(e) = db.replay_wal() {
+            error!("Failed to replay WAL: {}", e);
+        } else {
+            debug!("WAL replayed successfully.");
+        }
+        // The WAL is archived and truncated by the checkpoint engine once the
+        // tables it covers have been saved.
+        self.persisted = false;
+        false
     }
 }
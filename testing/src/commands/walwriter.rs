@@ -1,18 +1,49 @@
-use std::fs::OpenOptions;
-use std::io::{BufWriter, Write};
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::thread;
+//! The asynchronous WAL writer. `WalWriter` hands records to a background
+//! thread that batches them into the WAL file; the thread, and so any
+//! `WalWriter`, needs the `native` feature.
+
+#[cfg(feature = "native")]
+use crate::commands::checksum;
+use crate::commands::checksum::Stamp;
+#[cfg(feature = "native")]
+use crate::commands::crypto::ColumnCipher;
+#[cfg(feature = "native")]
+use crate::commands::storage::Storage;
+#[cfg(feature = "native")]
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+#[cfg(feature = "native")]
+use std::sync::Arc;
+#[cfg(feature = "native")]
+use std::thread::{self, JoinHandle};
+#[cfg(feature = "native")]
 use std::time::{Duration, Instant};
+#[cfg(feature = "native")]
+use tracing::{debug, error};
+
+#[cfg(feature = "native")]
+enum WalMessage {
+    Op(Stamp, String),
+    /// Flush everything received so far, then reply.
+    Sync(Sender<()>),
+}
 
+#[cfg(feature = "native")]
 pub struct WalWriter {
-    sender: Sender<String>,
+    sender: Sender<WalMessage>,
 }
 
+/// Without threads there is no writer to hand records to, so no `WalWriter`
+/// can be made and the database writes its WAL itself.
+#[cfg(not(feature = "native"))]
+pub enum WalWriter {}
+
+#[cfg(feature = "native")]
 pub struct WalWriterHandle {
-    receiver: Receiver<String>,
+    receiver: Receiver<WalMessage>,
     batch_interval: Duration,
 }
 
+#[cfg(feature = "native")]
 impl WalWriter {
     // Returns a WalWriter and its associated handle.
     pub fn new(batch_interval: Duration) -> (Self, WalWriterHandle) {
@@ -26,44 +57,88 @@ impl WalWriter {
         )
     }
 
-    pub fn log(&self, op: String) {
-        let _ = self.sender.send(op);
+    /// Queue an operation, stamped when it was logged rather than when the
+    /// batch is written.
+    pub fn log(&self, stamp: Stamp, op: String) {
+        let _ = self.sender.send(WalMessage::Op(stamp, op));
+    }
+
+    /// Block until every operation logged so far has been written to the WAL
+    /// file. Returns false if the writer thread has stopped.
+    pub fn sync(&self) -> bool {
+        let (done, wait) = channel();
+        self.sender.send(WalMessage::Sync(done)).is_ok() && wait.recv().is_ok()
+    }
+}
+
+#[cfg(not(feature = "native"))]
+impl WalWriter {
+    pub fn log(&self, _stamp: Stamp, _op: String) {
+        match *self {}
+    }
+
+    pub fn sync(&self) -> bool {
+        match *self {}
     }
 }
 
+#[cfg(feature = "native")]
 impl WalWriterHandle {
-    pub fn start(self, wal_file: String) {
+    /// Start the background writer. With a cipher every record is encrypted
+    /// before it is appended, so the WAL file never holds plaintext. Each line
+    /// carries the record's stamp and a checksum of both as written. The
+    /// thread exits once its `WalWriter` is dropped and every record is written.
+    pub fn start(
+        self,
+        storage: Arc<dyn Storage>,
+        wal_file: String,
+        cipher: Option<ColumnCipher>,
+    ) -> JoinHandle<()> {
         thread::spawn(move || {
             let mut buffer = Vec::new();
             let mut last_flush = Instant::now();
             loop {
+                let mut synced = None;
                 // Try to receive new WAL operations until the batch_interval or a batch size threshold is met.
                 match self.receiver.recv_timeout(self.batch_interval) {
-                    Ok(op) => buffer.push(op),
-                    Err(_) => {
+                    Ok(WalMessage::Op(stamp, op)) => buffer.push((stamp, op)),
+                    Ok(WalMessage::Sync(done)) => synced = Some(done),
+                    Err(RecvTimeoutError::Timeout) => {
                         // Timeout expired: time to flush the current batch.
                     }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        if buffer.is_empty() {
+                            break;
+                        }
+                    }
                 }
 
-                if last_flush.elapsed() >= self.batch_interval || buffer.len() >= 10 {
-                    if !buffer.is_empty() {
-                        let file = OpenOptions::new().append(true).create(true).open(&wal_file);
-                        if let Ok(file) = file {
-                            let mut writer = BufWriter::new(file);
-                            for op in &buffer {
-                                if writeln!(writer, "{}", op).is_err() {
-                                    eprintln!("Error writing to WAL file.");
-                                }
-                            }
-                            let _ = writer.flush();
-                        } else {
-                            eprintln!("Could not open WAL file: {}", wal_file);
+                let due = synced.is_some()
+                    || last_flush.elapsed() >= self.batch_interval
+                    || buffer.len() >= 10;
+                if due && !buffer.is_empty() {
+                    let mut records = String::new();
+                    for (stamp, op) in &buffer {
+                        let record = match cipher {
+                            Some(ref c) => c.encrypt(op),
+                            None => op.clone(),
+                        };
+                        records.push_str(&checksum::frame_stamped(*stamp, &record));
+                        records.push('\n');
+                    }
+                    match storage.append(&wal_file, records.as_bytes()) {
+                        Ok(()) => {
+                            debug!("Flushed {} WAL records to {}", buffer.len(), wal_file)
                         }
-                        buffer.clear();
-                        last_flush = Instant::now();
+                        Err(e) => error!("Error writing to WAL file {}: {}", wal_file, e),
                     }
+                    buffer.clear();
+                    last_flush = Instant::now();
+                }
+                if let Some(done) = synced {
+                    let _ = done.send(());
                 }
             }
-        });
+        })
     }
 }
@@ -0,0 +1,44 @@
+//! RustDB as a library: an embeddable table database with a WAL, indexes,
+//! background maintenance engines, and CSV, binary and LSM storage formats.
+//!
+//! Open a database with `Database::open(config)` or `Database::builder()`, or
+//! `Database::with_config` for a single unmanaged instance. The re-exports
+//! below are the supported API; the modules stay public for the REPL binary
+//! and for the engines' details.
+
+#![allow(non_snake_case)]
+
+#[cfg(feature = "binary")]
+pub mod binary;
+pub mod commands;
+#[cfg(feature = "lsm")]
+pub mod lsm;
+pub mod table;
+
+pub use commands::backup::{BackupEntry, BackupSnapshot};
+pub use commands::bench::{bench, OpTimings};
+pub use commands::builder::DatabaseBuilder;
+pub use commands::catalog::{Catalog, CatalogEntry};
+pub use commands::condition::{Condition, Operator};
+pub use commands::config::{Autosave, DatabaseConfig, PersistencePolicy};
+pub use commands::db::{ConflictPolicy, Database, DatabaseError, Result, ScanOptions};
+pub use commands::geo::GeoPoint;
+pub use commands::handle::DbHandle;
+pub use commands::index::{Consistency, Filter, IndexKind, QueryHints};
+pub use commands::integrity::{IntegrityReport, LoadReport, RepairReport};
+pub use commands::manager::DatabaseManager;
+pub use commands::matching::{Collation, MatchOptions};
+pub use commands::result::{Column, QueryResult, RowSet, Value};
+pub use commands::row_id::RowIdStrategy;
+pub use commands::session::Session;
+pub use commands::shards::ShardedDatabase;
+pub use commands::stats::{BackfillProgress, DatabaseStats, StartupReport, TableStats};
+pub use commands::storage::{MemoryStorage, Storage, StorageKind, StorageWriter};
+pub use commands::transfer::{TableChunk, TransferPosition};
+pub use commands::twophase::{Coordinator, TxWrite};
+pub use table::table::Table;
+
+#[cfg(feature = "native")]
+pub use commands::scheduler::{MaintenanceHandle, MaintenanceJob, MaintenanceScheduler, Priority};
+#[cfg(feature = "native")]
+pub use commands::storage::LocalStorage;
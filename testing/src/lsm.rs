@@ -0,0 +1,820 @@
+//! A log-structured merge tree of string keys and values: a concurrent
+//! memtable logged to a WAL, flushed to sorted SSTable files read through a
+//! shared block cache, and merged by parallel compaction.
+
+use crate::commands::locking;
+use crossbeam_skiplist::map::Entry;
+use crossbeam_skiplist::SkipMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write, BufReader, BufRead, BufWriter, Read, Seek, SeekFrom};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use thiserror::Error;
+use tracing::{debug, info, info_span, trace};
+
+/// Errors from the storage paths. Each names the file it happened on.
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("I/O error on '{0}': {1}")]
+    Io(String, #[source] io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, StorageError>;
+
+/// Map an I/O error on `path` into a `StorageError`.
+fn io_err(path: &str) -> impl FnOnce(io::Error) -> StorageError + '_ {
+    move |e| StorageError::Io(path.to_string(), e)
+}
+
+/// **Line Encoding**
+/// The WAL and SSTables hold one `key:value` line per entry. Keys and values
+/// are escaped so any content fits: `\` as `\\`, `:` as `\c`, and line
+/// feeds and carriage returns as `\n` and `\r`. Readers compare decoded
+/// keys, so SSTables stay sorted by the keys themselves.
+fn escape(s: &str) -> Cow<'_, str> {
+    if !s.contains(['\\', ':', '\n', '\r']) {
+        return Cow::Borrowed(s);
+    }
+    let mut out = String::with_capacity(s.len() + 8);
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            ':' => out.push_str("\\c"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// Undo `escape`. An unknown escape is kept as it is.
+fn unescape(s: &str) -> Cow<'_, str> {
+    if !s.contains('\\') {
+        return Cow::Borrowed(s);
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('c') => out.push(':'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    Cow::Owned(out)
+}
+
+fn encode_line(key: &str, value: &str) -> String {
+    format!("{}:{}", escape(key), escape(value))
+}
+
+/// The decoded key and value of a line, or `None` if it has no separator.
+fn decode_line(line: &str) -> Option<(String, String)> {
+    let (key, value) = line.split_once(':')?;
+    Some((unescape(key).into_owned(), unescape(value).into_owned()))
+}
+
+/// **Memtable (In-Memory Storage)**
+/// A lock-free skiplist, so any number of threads can insert and read at once.
+struct Memtable {
+    data: SkipMap<String, String>,
+}
+
+impl Memtable {
+    fn new() -> Self {
+        debug!("Creating new Memtable");
+        Self { data: SkipMap::new() }
+    }
+
+    fn insert(&self, key: String, value: String) {
+        trace!(key = %key, "Inserting into Memtable");
+        self.data.insert(key, value);
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        trace!(key = %key, "Getting from Memtable");
+        self.data.get(key).map(|entry| entry.value().clone())
+    }
+
+    fn size(&self) -> usize {
+        self.data.len()
+    }
+}
+
+/// **Write-Ahead Log (WAL)**
+struct Wal {
+    file: File,
+    path: String,
+}
+
+impl Wal {
+    fn new(path: &str) -> Result<Self> {
+        debug!("Creating new WAL at path: {}", path);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(io_err(path))?;
+        Ok(Self { file, path: path.to_string() })
+    }
+
+    fn log(&mut self, key: &str, value: &str) -> Result<()> {
+        trace!(key = %key, "Logging to WAL");
+        writeln!(self.file, "{}", encode_line(key, value)).map_err(io_err(&self.path))
+    }
+
+    #[cfg(test)]
+    fn read_logs(path: &str) -> Result<Vec<(String, String)>> {
+        debug!("Reading logs from WAL at path: {}", path);
+        let file = File::open(path).map_err(io_err(path))?;
+        let reader = BufReader::new(file);
+        let mut logs = Vec::new();
+        for line in reader.lines() {
+            let line = line.map_err(io_err(path))?;
+            match decode_line(&line) {
+                Some(entry) => logs.push(entry),
+                None => tracing::warn!(path = %path, "Skipping malformed WAL line: {}", line),
+            }
+        }
+        Ok(logs)
+    }
+}
+
+/// **Block Cache**
+/// Fixed-size blocks of SSTable files held in memory and shared by every
+/// reader, so repeated lookups on flushed data skip the disk. The least
+/// recently used blocks are evicted once `capacity` bytes are held.
+const BLOCK_SIZE: u64 = 4096;
+
+/// Counters for the cache; `bytes` is what it holds right now.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub bytes: usize,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "hits={} misses={} evictions={} bytes={}",
+            self.hits, self.misses, self.evictions, self.bytes
+        )
+    }
+}
+
+/// A block of a file: its path and block number.
+type BlockKey = (String, u64);
+
+#[derive(Default)]
+struct CacheState {
+    blocks: HashMap<BlockKey, (Arc<Vec<u8>>, u64)>,
+    /// Last use of each cached block, oldest first.
+    recency: BTreeMap<u64, BlockKey>,
+    tick: u64,
+    /// Bumped whenever a file is rewritten, so a block read from the old
+    /// contents is not cached after the invalidation.
+    generations: HashMap<String, u64>,
+    stats: CacheStats,
+}
+
+pub struct BlockCache {
+    capacity: usize,
+    state: Mutex<CacheState>,
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> Self {
+        debug!("Creating block cache of {} bytes", capacity);
+        Self { capacity, state: Mutex::new(CacheState::default()) }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        locking::recover(&self.state).stats
+    }
+
+    /// Block `index` of `path`, empty past the end of the file.
+    fn block(&self, path: &str, index: u64) -> io::Result<Arc<Vec<u8>>> {
+        let key = (path.to_string(), index);
+        let generation = {
+            let mut state = locking::recover(&self.state);
+            state.tick += 1;
+            let tick = state.tick;
+            if let Some((data, last_use)) = state.blocks.get_mut(&key) {
+                let (data, previous) = (data.clone(), std::mem::replace(last_use, tick));
+                state.recency.remove(&previous);
+                state.recency.insert(tick, key);
+                state.stats.hits += 1;
+                return Ok(data);
+            }
+            state.stats.misses += 1;
+            state.generations.get(path).copied().unwrap_or(0)
+        };
+
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(index * BLOCK_SIZE))?;
+        let mut data = Vec::with_capacity(BLOCK_SIZE as usize);
+        file.take(BLOCK_SIZE).read_to_end(&mut data)?;
+        let data = Arc::new(data);
+
+        let mut state = locking::recover(&self.state);
+        if data.len() <= self.capacity
+            && state.generations.get(path).copied().unwrap_or(0) == generation
+            && !state.blocks.contains_key(&key)
+        {
+            state.tick += 1;
+            let tick = state.tick;
+            state.stats.bytes += data.len();
+            state.recency.insert(tick, key.clone());
+            state.blocks.insert(key, (data.clone(), tick));
+            while state.stats.bytes > self.capacity {
+                let Some((_, oldest)) = state.recency.pop_first() else { break };
+                if let Some((evicted, _)) = state.blocks.remove(&oldest) {
+                    state.stats.bytes -= evicted.len();
+                    state.stats.evictions += 1;
+                }
+            }
+        }
+        Ok(data)
+    }
+
+    /// Drop every cached block of `path`; call after the file is rewritten or removed.
+    fn invalidate(&self, path: &str) {
+        let mut state = locking::recover(&self.state);
+        *state.generations.entry(path.to_string()).or_insert(0) += 1;
+        let stale: Vec<BlockKey> = state.blocks.keys().filter(|(p, _)| p == path).cloned().collect();
+        for key in stale {
+            if let Some((data, last_use)) = state.blocks.remove(&key) {
+                state.recency.remove(&last_use);
+                state.stats.bytes -= data.len();
+            }
+        }
+        trace!(path = %path, "Invalidated cached blocks");
+    }
+}
+
+/// Reads a file through the block cache.
+struct CachedFile<'a> {
+    cache: &'a BlockCache,
+    path: &'a str,
+    pos: u64,
+}
+
+impl Read for CachedFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let block = self.cache.block(self.path, self.pos / BLOCK_SIZE)?;
+        let offset = (self.pos % BLOCK_SIZE) as usize;
+        if offset >= block.len() {
+            return Ok(0);
+        }
+        let n = buf.len().min(block.len() - offset);
+        buf[..n].copy_from_slice(&block[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for CachedFile<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(_) => {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, "Cannot seek from the end of a cached file"))
+            }
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Seek before the start of the file"))?;
+        Ok(self.pos)
+    }
+}
+
+/// **SSTables (On-Disk Storage)**
+fn flush_to_sstable(memtable: &Memtable, path: &str, cache: &BlockCache) -> Result<()> {
+    let _span = info_span!("flush_to_sstable", path = %path, entries = memtable.size()).entered();
+    info!("Flushing Memtable to SSTable");
+    let mut file = BufWriter::new(File::create(path).map_err(io_err(path))?);
+    for entry in memtable.data.iter() {
+        writeln!(file, "{}", encode_line(entry.key(), entry.value())).map_err(io_err(path))?;
+    }
+    let result = file.flush().map_err(io_err(path));
+    cache.invalidate(path);
+    result
+}
+
+/// The value stored for `key`, or `None` if it isn't there or no SSTable has
+/// been written yet.
+fn read_sstable(path: &str, key: &str, cache: &BlockCache) -> Result<Option<String>> {
+    trace!(path = %path, key = %key, "Reading SSTable");
+    let reader = BufReader::new(CachedFile { cache, path, pos: 0 });
+
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(StorageError::Io(path.to_string(), e)),
+        };
+        if let Some((k, v)) = decode_line(&line) {
+            if k == key {
+                return Ok(Some(v));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// **Compaction (Merge SSTables)**
+/// Limits on one compaction. The key space is split into `ranges` pieces
+/// that `threads` workers merge independently.
+#[derive(Debug, Clone, Copy)]
+pub struct CompactionOptions {
+    pub threads: usize,
+    /// Input files open for reading at once, across all workers.
+    pub io_slots: usize,
+    /// More ranges than threads evens out ranges that turn out larger.
+    pub ranges: usize,
+}
+
+impl Default for CompactionOptions {
+    fn default() -> Self {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        Self { threads, io_slots: threads, ranges: threads * 4 }
+    }
+}
+
+/// A counting semaphore bounding concurrent reads of compaction inputs.
+struct IoSlots {
+    free: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// One slot of `IoSlots`, given back when dropped.
+struct IoSlot<'a>(&'a IoSlots);
+
+impl IoSlots {
+    fn new(slots: usize) -> Self {
+        Self { free: Mutex::new(slots), freed: Condvar::new() }
+    }
+
+    fn acquire(&self) -> IoSlot<'_> {
+        let mut free = locking::recover(&self.free);
+        while *free == 0 {
+            free = locking::wait(&self.freed, free);
+        }
+        *free -= 1;
+        IoSlot(self)
+    }
+}
+
+impl Drop for IoSlot<'_> {
+    fn drop(&mut self) {
+        *locking::recover(&self.0.free) += 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// Move the reader to the first line starting at or after `offset`.
+fn line_start<R: Read + Seek>(reader: &mut BufReader<R>, offset: u64) -> io::Result<()> {
+    if offset == 0 {
+        reader.seek(SeekFrom::Start(0))?;
+        return Ok(());
+    }
+    reader.seek(SeekFrom::Start(offset - 1))?;
+    reader.read_until(b'\n', &mut Vec::new())?;
+    Ok(())
+}
+
+/// The key of the line at the reader's position, or `None` at the end.
+fn next_key<R: Read>(reader: &mut BufReader<R>) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let key = line.trim_end_matches('\n').split(':').next().unwrap_or_default();
+    Ok(Some(unescape(key).into_owned()))
+}
+
+/// Move the reader to the first line whose key is at least `key`, by
+/// bisecting byte offsets. SSTable lines are sorted by key.
+fn seek_key<R: Read + Seek>(reader: &mut BufReader<R>, len: u64, key: &str) -> io::Result<()> {
+    let (mut lo, mut hi) = (0, len);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        line_start(reader, mid)?;
+        match next_key(reader)? {
+            Some(k) if k.as_str() < key => lo = mid + 1,
+            _ => hi = mid,
+        }
+    }
+    line_start(reader, lo)
+}
+
+/// Pass the entries of the SSTable at `path` to `visit` in key order, from
+/// the first key at least `start` (found by bisecting) until `visit` returns
+/// false. Nothing is visited if no SSTable has been written.
+fn visit_sstable(
+    path: &str,
+    start: &str,
+    cache: &BlockCache,
+    mut visit: impl FnMut(&str, &str) -> bool,
+) -> Result<()> {
+    trace!(path = %path, start = %start, "Scanning SSTable");
+    let len = match std::fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(StorageError::Io(path.to_string(), e)),
+    };
+    let mut reader = BufReader::new(CachedFile { cache, path, pos: 0 });
+    seek_key(&mut reader, len, start).map_err(io_err(path))?;
+    for line in reader.lines() {
+        let line = line.map_err(io_err(path))?;
+        if let Some((k, v)) = decode_line(&line) {
+            if !visit(&k, &v) {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Keys splitting the inputs into about `ranges` pieces, taken at even byte
+/// offsets of the largest input.
+fn split_keys(paths: &[&str], ranges: usize) -> Result<Vec<String>> {
+    let mut largest = ("", 0);
+    for &path in paths {
+        let len = std::fs::metadata(path).map_err(io_err(path))?.len();
+        if len > largest.1 {
+            largest = (path, len);
+        }
+    }
+    let (path, len) = largest;
+    let mut keys = Vec::new();
+    if len == 0 {
+        return Ok(keys);
+    }
+    let mut reader = BufReader::new(File::open(path).map_err(io_err(path))?);
+    for i in 1..ranges as u64 {
+        line_start(&mut reader, len * i / ranges as u64).map_err(io_err(path))?;
+        if let Some(key) = next_key(&mut reader).map_err(io_err(path))? {
+            keys.push(key);
+        }
+    }
+    keys.dedup();
+    Ok(keys)
+}
+
+/// The merged `key:value` lines of `paths` with keys in `[start, end)`;
+/// `None` leaves that side open. Later inputs win.
+fn merge_range(paths: &[&str], start: Option<&str>, end: Option<&str>, io: &IoSlots) -> Result<String> {
+    let mut merged_data = BTreeMap::new();
+    for &path in paths {
+        let _slot = io.acquire();
+        let file = File::open(path).map_err(io_err(path))?;
+        let len = file.metadata().map_err(io_err(path))?.len();
+        let mut reader = BufReader::new(file);
+        if let Some(start) = start {
+            seek_key(&mut reader, len, start).map_err(io_err(path))?;
+        }
+
+        for line in reader.lines() {
+            let line = line.map_err(io_err(path))?;
+            if let Some((k, v)) = decode_line(&line) {
+                if end.is_some_and(|end| k.as_str() >= end) {
+                    break;
+                }
+                merged_data.insert(k, v);
+            }
+        }
+    }
+    let mut out = String::new();
+    for (key, value) in merged_data {
+        out.push_str(&encode_line(&key, &value));
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// The inputs are only removed once the merged output is fully written.
+pub fn compact_sstables(
+    sstable_paths: Vec<&str>,
+    output_path: &str,
+    cache: &BlockCache,
+    options: CompactionOptions,
+) -> Result<()> {
+    let _span = info_span!("compact_sstables", output = %output_path).entered();
+    info!("Compacting SSTables: {:?}", sstable_paths);
+    let splits = split_keys(&sstable_paths, options.ranges.max(1))?;
+    // Range i runs from split i - 1 up to split i; the first and last are open.
+    let ranges: Vec<(Option<&str>, Option<&str>)> = (0..=splits.len())
+        .map(|i| (i.checked_sub(1).map(|j| splits[j].as_str()), splits.get(i).map(String::as_str)))
+        .collect();
+    let threads = options.threads.clamp(1, ranges.len());
+    debug!("Merging {} key ranges on {} threads", ranges.len(), threads);
+
+    let next = AtomicUsize::new(0);
+    let io = IoSlots::new(options.io_slots.max(1));
+    let results: Vec<Result<Vec<(usize, String)>>> = thread::scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                s.spawn(|| -> Result<Vec<(usize, String)>> {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(&(start, end)) = ranges.get(i) else {
+                            return Ok(done);
+                        };
+                        done.push((i, merge_range(&sstable_paths, start, end, &io)?));
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    let mut chunks = vec![String::new(); ranges.len()];
+    for result in results {
+        for (i, chunk) in result? {
+            chunks[i] = chunk;
+        }
+    }
+
+    let mut output_file = BufWriter::new(File::create(output_path).map_err(io_err(output_path))?);
+    for chunk in chunks {
+        output_file.write_all(chunk.as_bytes()).map_err(io_err(output_path))?;
+    }
+    output_file.flush().map_err(io_err(output_path))?;
+    cache.invalidate(output_path);
+
+    // Remove old SSTables
+    for path in sstable_paths {
+        std::fs::remove_file(path).map_err(io_err(path))?;
+        cache.invalidate(path);
+    }
+    Ok(())
+}
+
+/// **LSM Tree (Main Database)**
+/// Shared between threads by reference: inserts and reads never wait on each
+/// other. Only swapping in a fresh memtable at flush time takes a write lock.
+pub struct LSMTree {
+    memtable: RwLock<Arc<Memtable>>,
+    /// The memtable being written out, still readable until its SSTable exists.
+    flushing: RwLock<Option<Arc<Memtable>>>,
+    /// Held for the whole flush so only one thread flushes at a time.
+    flush_lock: Mutex<()>,
+    wal: Mutex<Wal>,
+    sstable_path: String,
+    threshold: usize,
+    /// May be shared with other trees; its size limit then covers all of them.
+    cache: Arc<BlockCache>,
+}
+
+impl LSMTree {
+    pub fn new(wal_path: &str, sstable_path: &str, threshold: usize, cache: Arc<BlockCache>) -> Result<Self> {
+        info!("Creating new LSMTree with WAL: {}, SSTable: {}, Threshold: {}", wal_path, sstable_path, threshold);
+        let wal = Wal::new(wal_path)?;
+        let memtable = Memtable::new();
+        Ok(Self {
+            memtable: RwLock::new(Arc::new(memtable)),
+            flushing: RwLock::new(None),
+            flush_lock: Mutex::new(()),
+            wal: Mutex::new(wal),
+            sstable_path: sstable_path.to_string(),
+            threshold,
+            cache,
+        })
+    }
+
+    /// Log and insert a key. Fails without touching the memtable if the WAL
+    /// write fails; a failed flush leaves the entries in memory.
+    pub fn insert(&self, key: String, value: String) -> Result<()> {
+        trace!(key = %key, "Inserting into LSMTree");
+        locking::recover(&self.wal).log(&key, &value)?;
+        // The read guard is shared, so writers still run in parallel; it only
+        // keeps a flush from swapping the memtable out mid-insert.
+        let size = {
+            let memtable = locking::read(&self.memtable);
+            memtable.insert(key, value);
+            memtable.size()
+        };
+
+        if size >= self.threshold {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Swap in an empty memtable and write the full one to the SSTable.
+    pub fn flush(&self) -> Result<()> {
+        let _guard = locking::recover(&self.flush_lock);
+        // Another thread may have flushed while this one waited.
+        if locking::read(&self.memtable).size() < self.threshold {
+            return Ok(());
+        }
+        let full = {
+            let mut memtable = locking::write(&self.memtable);
+            let full = std::mem::replace(&mut *memtable, Arc::new(Memtable::new()));
+            *locking::write(&self.flushing) = Some(full.clone());
+            full
+        };
+        let result = flush_to_sstable(&full, &self.sstable_path, &self.cache);
+        if result.is_err() {
+            // Put the entries back so they stay readable and go out with the
+            // next flush; anything written since the swap is newer and wins.
+            let memtable = locking::read(&self.memtable);
+            for entry in full.data.iter() {
+                memtable.data.get_or_insert(entry.key().clone(), entry.value().clone());
+            }
+        }
+        *locking::write(&self.flushing) = None;
+        result
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        trace!(key = %key, "Getting from LSMTree");
+        let memtable = locking::read(&self.memtable).clone();
+        if let Some(value) = memtable.get(key) {
+            return Ok(Some(value));
+        }
+        if let Some(flushing) = locking::read(&self.flushing).clone() {
+            if let Some(value) = flushing.get(key) {
+                return Ok(Some(value));
+            }
+        }
+        read_sstable(&self.sstable_path, key, &self.cache)
+    }
+
+    /// Every entry whose key starts with `prefix`, in key order. The memtables
+    /// and the SSTable are all sorted, so each is read from the prefix on.
+    pub fn scan_prefix(&self, prefix: &str) -> Result<Vec<(String, String)>> {
+        trace!(prefix = %prefix, "Scanning LSMTree");
+        let memtable = locking::read(&self.memtable).clone();
+        let flushing = locking::read(&self.flushing).clone();
+        // Oldest first, so newer values overwrite older ones.
+        let mut merged = BTreeMap::new();
+        visit_sstable(&self.sstable_path, prefix, &self.cache, |k, v| {
+            let matches = k.starts_with(prefix);
+            if matches {
+                merged.insert(k.to_string(), v.to_string());
+            }
+            matches
+        })?;
+        for table in flushing.iter().chain(std::iter::once(&memtable)) {
+            let from_prefix = table.data.range::<str, _>((Bound::Included(prefix), Bound::Unbounded));
+            for entry in from_prefix.take_while(|e| e.key().starts_with(prefix)) {
+                merged.insert(entry.key().clone(), entry.value().clone());
+            }
+        }
+        Ok(merged.into_iter().collect())
+    }
+
+    /// Up to `limit` entries with keys in `[start, end)`, in key order or,
+    /// when `descending`, reverse key order; `None` leaves that side open.
+    /// Each memtable is walked from the requested end, so only the SSTable
+    /// is read past the entries returned.
+    pub fn scan(
+        &self,
+        start: Option<&str>,
+        end: Option<&str>,
+        descending: bool,
+        limit: Option<usize>,
+    ) -> Result<Vec<(String, String)>> {
+        trace!(?start, ?end, descending, "Scanning LSMTree");
+        // SkipMap::range panics on an inverted range; it holds no entries anyway.
+        if matches!((start, end), (Some(start), Some(end)) if start > end) {
+            return Ok(Vec::new());
+        }
+        let limit = limit.unwrap_or(usize::MAX);
+        let memtable = locking::read(&self.memtable).clone();
+        let flushing = locking::read(&self.flushing).clone();
+
+        // Each source contributes its first `limit` entries in scan order; a
+        // key the result needs is always among those of its newest source.
+        let mut from_sstable = VecDeque::new();
+        visit_sstable(&self.sstable_path, start.unwrap_or(""), &self.cache, |k, v| {
+            if end.is_some_and(|end| k >= end) {
+                return false;
+            }
+            from_sstable.push_back((k.to_string(), v.to_string()));
+            // Descending keeps the last `limit` seen, ascending stops at `limit`.
+            if from_sstable.len() > limit {
+                from_sstable.pop_front();
+            }
+            descending || from_sstable.len() < limit
+        })?;
+
+        // Oldest first, so newer values overwrite older ones.
+        let mut merged: BTreeMap<String, String> = from_sstable.into_iter().collect();
+        let bounds = (
+            start.map_or(Bound::Unbounded, Bound::Included),
+            end.map_or(Bound::Unbounded, Bound::Excluded),
+        );
+        for table in flushing.iter().chain(std::iter::once(&memtable)) {
+            let entries = table.data.range::<str, _>(bounds);
+            let entries: Box<dyn Iterator<Item = Entry<'_, String, String>>> =
+                if descending { Box::new(entries.rev()) } else { Box::new(entries) };
+            for entry in entries.take(limit) {
+                merged.insert(entry.key().clone(), entry.value().clone());
+            }
+        }
+        let entries = merged.into_iter();
+        Ok(if descending { entries.rev().take(limit).collect() } else { entries.take(limit).collect() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const ADVERSARIAL: &[(&str, &str)] = &[
+        ("a:b", "value:with:colons"),
+        ("line\nbreak", "multi\nline\r\nvalue"),
+        ("back\\slash", "ends with \\"),
+        ("\\c", "\\n is not a newline"),
+        ("", ":"),
+        ("plain", ""),
+    ];
+
+    fn temp_path(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("lsm_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_escape_round_trip() {
+        for (key, value) in ADVERSARIAL {
+            let line = encode_line(key, value);
+            assert!(!line.contains('\n') && !line.contains('\r'));
+            assert_eq!(decode_line(&line), Some((key.to_string(), value.to_string())));
+        }
+    }
+
+    #[test]
+    fn test_wal_adversarial_keys() {
+        let path = temp_path("adversarial.wal");
+        let _ = fs::remove_file(&path);
+        let mut wal = Wal::new(&path).unwrap();
+        for (key, value) in ADVERSARIAL {
+            wal.log(key, value).unwrap();
+        }
+        let logs = Wal::read_logs(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let expected: Vec<(String, String)> =
+            ADVERSARIAL.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        assert_eq!(logs, expected);
+    }
+
+    #[test]
+    fn test_sstable_adversarial_keys() {
+        let wal_path = temp_path("adversarial_tree.wal");
+        let sstable_path = temp_path("adversarial_tree.sst");
+        let _ = fs::remove_file(&wal_path);
+        let cache = Arc::new(BlockCache::new(1 << 20));
+        let tree = LSMTree::new(&wal_path, &sstable_path, ADVERSARIAL.len(), cache.clone()).unwrap();
+        for (key, value) in ADVERSARIAL {
+            tree.insert(key.to_string(), value.to_string()).unwrap();
+        }
+
+        // Everything was flushed, so these are read back from the SSTable.
+        for (key, value) in ADVERSARIAL {
+            assert_eq!(read_sstable(&sstable_path, key, &cache).unwrap().as_deref(), Some(*value));
+            assert_eq!(tree.get(key).unwrap().as_deref(), Some(*value));
+        }
+        let scanned = tree.scan_prefix("a:").unwrap();
+        assert_eq!(scanned, vec![("a:b".to_string(), "value:with:colons".to_string())]);
+        let all = tree.scan(None, None, false, None).unwrap();
+        let mut expected: Vec<(String, String)> =
+            ADVERSARIAL.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        expected.sort();
+        assert_eq!(all, expected);
+
+        // Compaction keeps the entries intact.
+        let compacted = temp_path("adversarial_tree.compacted.sst");
+        compact_sstables(vec![sstable_path.as_str()], &compacted, &cache, CompactionOptions::default())
+            .unwrap();
+        for (key, value) in ADVERSARIAL {
+            assert_eq!(read_sstable(&compacted, key, &cache).unwrap().as_deref(), Some(*value));
+        }
+        fs::remove_file(&compacted).unwrap();
+        fs::remove_file(&wal_path).unwrap();
+    }
+}
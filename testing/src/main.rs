@@ -1,18 +1,18 @@
-use core::num;
-use env_logger;
 #[warn(unused_imports)]
 use std::fs;
-pub mod table;
-
-mod commands;
+#[allow(dead_code)]
 const FOLDER_PATH: &str = "./src/commands";
-use commands::indexer_engine::IndexEngine;
-use commands::{db, walengine, walwriter};
+const CONFIG_FILE: &str = "rustdb.toml";
+use rust_db::commands::crypto;
+use rust_db::commands::manager::{DatabaseManager, DEFAULT_DATABASE};
+use rust_db::commands::repl::Repl;
+use rust_db::{Database, DatabaseConfig};
 
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
+use tracing::error;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::prelude::*;
 
 // fn get_command_names() -> Vec<String> {
 //     let folder_path = FOLDER_PATH;
@@ -36,97 +36,130 @@ use std::time::Duration;
 // }
 
 use rand::Rng;
+use rust_db::bench;
 use std::collections::HashMap;
-use std::time::Instant;
-
-fn test_entire_db(db: &mut db::Database, num_rows: usize) {
-    // Time table creation and adding columns.
-    let start_table = Instant::now();
-    db.create_table("test_table").unwrap();
-    db.add_column("test_table", "name").unwrap();
-    db.add_column("test_table", "age").unwrap();
-    db.add_column("test_table", "email").unwrap();
-    let duration_table = start_table.elapsed();
-    println!(
-        "Table creation and column addition took: {:?}",
-        duration_table
-    );
 
+fn test_entire_db(db: &mut Database, num_rows: usize) {
     let mut rng = rand::thread_rng();
-
-    // Time bulk insertion.
-    let start_insert = Instant::now();
-    for i in 0..num_rows {
-        let id = i.to_string();
-        let name = format!("User_{:05}", rng.gen_range(1..100000));
-        let age = rng.gen_range(18..=80).to_string();
-        let email = format!("user{}@example.com", rng.gen_range(1..100000));
-
-        let mut row_data = std::collections::HashMap::new();
-        row_data.insert("name".to_string(), name);
-        row_data.insert("age".to_string(), age);
-        row_data.insert("email".to_string(), email);
-
-        db.insert_row("test_table", &id, row_data).unwrap();
-    }
-    let duration_insert = start_insert.elapsed();
-    println!("Insertion of {} rows took: {:?}", num_rows, duration_insert);
-
-    // Time random searches.
-    let start_search = Instant::now();
-    for _ in 0..5 {
-        let random_age = rng.gen_range(18..=80).to_string();
-        match db.find_rows_by_value_in_table("test_table", "age", &random_age, true) {
-            Ok(rows) => println!("Search for age {}: found {} rows", random_age, rows.len()),
-            Err(e) => println!("Search error: {}", e),
-        }
+    let timings = [
+        bench("create table", 1, |_| {
+            db.create_table("test_table").unwrap();
+            db.add_column("test_table", "name").unwrap();
+            db.add_column("test_table", "age").unwrap();
+            db.add_column("test_table", "email").unwrap();
+        }),
+        bench("insert", num_rows, |i| {
+            let mut row_data = HashMap::new();
+            row_data.insert(
+                "name".to_string(),
+                format!("User_{:05}", rng.gen_range(1..100000)),
+            );
+            row_data.insert("age".to_string(), rng.gen_range(18..=80).to_string());
+            row_data.insert(
+                "email".to_string(),
+                format!("user{}@example.com", rng.gen_range(1..100000)),
+            );
+            db.insert_row("test_table", &i.to_string(), row_data).unwrap();
+        }),
+        bench("get row", num_rows, |i| {
+            db.get_row("test_table", &i.to_string()).unwrap();
+        }),
+        bench("find by age", 5, |_| {
+            let age = rng.gen_range(18..=80).to_string();
+            db.find_rows_by_value_in_table("test_table", "age", &age, true, None)
+                .unwrap();
+        }),
+    ];
+    for timing in &timings {
+        println!("{}", timing);
     }
-    let duration_search = start_search.elapsed();
-    println!("Performing 5 random searches took: {:?}", duration_search);
 }
 
-fn main() {
-    env_logger::init();
-
-    // Initialize the database wrapped in Arc<Mutex<>>
-    let db = Arc::new(Mutex::new(db::Database::new()));
-    let running = Arc::new(AtomicBool::new(true));
+/// Send diagnostics to stderr. `RUST_LOG` selects levels per target, e.g.
+/// `RUST_LOG=info,rust_db::commands::db=debug`; the default is warnings only.
+fn init_tracing() {
+    let filter = std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|spec| spec.parse::<Targets>().ok())
+        .unwrap_or_else(|| Targets::new().with_default(LevelFilter::WARN));
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(filter)
+        .init();
+}
 
-    // Load the WAL at startup
-    {
-        let mut db_lock = db.lock().unwrap();
-        if let Err(e) = db_lock.load_wal() {
-            eprintln!("Failed to load WAL: {}", e);
-        }
-        if let Err(e) = db_lock.flush_wal() {
-            eprintln!("Failed to flush WAL: {}", e);
+fn main() {
+    init_tracing();
+
+    // Load settings from rustdb.toml (or the path given with --config), falling back to defaults.
+    let args: Vec<String> = std::env::args().collect();
+
+    // `--convert <file> <version>` rewrites a binary database file at another
+    // format version and exits without opening the database.
+    #[cfg(feature = "binary")]
+    if let Some(i) = args.iter().position(|arg| arg == "--convert") {
+        let (Some(path), Some(version)) = (args.get(i + 1), args.get(i + 2)) else {
+            error!("Usage: --convert <file> <version>");
+            return;
+        };
+        let Ok(version) = version.parse::<u32>() else {
+            error!("Format version must be a number, got '{}'", version);
+            return;
+        };
+        match rust_db::binary::convert(path, version) {
+            Ok(from) => println!("Converted {} from version {} to {}", path, from, version),
+            Err(e) => error!("Failed to convert {}: {}", path, e),
         }
+        return;
     }
 
-    // Setup the asynchronous WAL writer:
-    // Create the WAL writer with a batch interval of 1 second.
-    let (wal_writer_instance, wal_writer_handle) =
-        walwriter::WalWriter::new(Duration::from_secs(1));
-    {
-        // Inject the wal_writer into the database.
-        let mut db_lock = db.lock().unwrap();
-        db_lock.wal_writer = Some(wal_writer_instance);
+    let config_path = args
+        .iter()
+        .position(|arg| arg == "--config")
+        .and_then(|i| args.get(i + 1))
+        .map(|s| s.as_str())
+        .unwrap_or(CONFIG_FILE);
+    let config = if fs::metadata(config_path).is_ok() {
+        DatabaseConfig::from_file(config_path).unwrap_or_else(|e| {
+            error!("Failed to load config: {}", e);
+            DatabaseConfig::default()
+        })
+    } else {
+        DatabaseConfig::default()
+    };
+
+    // Columns marked as encrypted need a key from the environment to be read or written.
+    let encryption_key = std::env::var(crypto::KEY_ENV_VAR).ok().and_then(|hex| {
+        let key = crypto::key_from_hex(&hex);
+        if key.is_none() {
+            error!(
+                "{} must be 64 hex characters; ignoring it.",
+                crypto::KEY_ENV_VAR
+            );
+        }
+        key
+    });
+
+    // Open the database: one or more shards, each behind its own handle, with
+    // their WAL replayed and their asynchronous WAL writers and background
+    // engines (WAL persist/replay, checkpoints and index rebuilds) running.
+    let mut builder = Database::builder().config(config.clone());
+    if let Some(key) = encryption_key {
+        builder = builder.encryption_key(key);
     }
-    // Start the asynchronous WAL writer thread.
-    wal_writer_handle.start("wal.log".to_string());
-
-    // Start the WAL engine to persist/replay WAL periodically
-    let wal_engine = walengine::WalEngine::new(Arc::clone(&db), Duration::from_secs(10));
-    thread::spawn(move || wal_engine.start());
-
-    // Start the Index and Bloom Engine to rebuild indexes and bloom filter periodically.
-    let index_engine = IndexEngine::new(Arc::clone(&db), Duration::from_secs(15));
-    index_engine.start();
+    let database = match builder.open() {
+        Ok(database) => database,
+        Err(e) => {
+            error!("Failed to open database: {}", e);
+            return;
+        }
+    };
+    let running = Arc::new(AtomicBool::new(true));
 
     // Simulate database operations
-    {
-        let mut db_lock = db.lock().unwrap();
-        test_entire_db(&mut *db_lock, 10_000);
+    if args.iter().any(|arg| arg == "--bench") {
+        let mut db_lock = database.shards()[0].lock();
+        test_entire_db(&mut db_lock, 10_000);
         // test_entire_db(&mut db_lock);
         // db_lock.commit_wal().unwrap();
         // db_lock.create_table("users").unwrap();
@@ -181,8 +214,20 @@ fn main() {
         // // db_lock.commit_wal().unwrap();
     }
 
-    // Run for a finite duration then exit.
-    thread::sleep(Duration::from_secs(60));
+    // Hand control to the interactive shell until the user exits. The startup
+    // database is registered as "main"; more can be created from the shell.
+    let mut manager = DatabaseManager::new(&config.data_dir, config.clone(), true);
+    if let Some(key) = encryption_key {
+        manager.set_encryption_key(key);
+    }
+    manager.register(DEFAULT_DATABASE, database.clone());
+    if let Err(e) = Repl::new(Arc::new(Mutex::new(manager))).run() {
+        error!("REPL error: {}", e);
+    }
     running.store(false, Ordering::SeqCst);
+    // Tables under a checkpoint or interval policy may still hold unsaved writes.
+    if let Err(e) = database.checkpoint() {
+        error!("Checkpoint on shutdown failed: {}", e);
+    }
     println!("Shutting down.");
 }
@@ -1,22 +1,117 @@
+use std::borrow::Cow;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
+use indexmap::IndexSet;
+use crate::commands::config::Autosave;
+use crate::commands::matching::Collation;
+use crate::commands::row_id::RowIdGenerator;
+use crate::commands::stats::ColumnStats;
+use tracing::debug;
 
-#[derive(Debug)]
+/// Column holding the time, in Unix milliseconds, a row was soft-deleted.
+/// A table has soft deletes when it has this column; see
+/// `Database::enable_soft_delete`.
+pub const DELETED_AT: &str = "_deleted_at";
+
+/// Column holding the tenant a row belongs to. A table is scoped by tenant
+/// when it has this column; see `Database::enable_tenancy`.
+pub const TENANT_ID: &str = "_tenant_id";
+
+/// The allowed values of an enum datatype, `enum(a|b|c)`, in order. A
+/// value's position is the code it is stored as in table files.
+pub fn enum_members(datatype: &str) -> Option<Vec<&str>> {
+    let members = datatype.strip_prefix("enum(")?.strip_suffix(')')?;
+    Some(members.split('|').collect())
+}
+
+/// The form of a value stored in a table file: its code if the datatype is
+/// an enum with the value as a member, else the value itself.
+pub fn encode_enum<'a>(datatype: &str, value: &'a str) -> Cow<'a, str> {
+    match enum_members(datatype).and_then(|members| members.iter().position(|m| *m == value)) {
+        Some(code) => Cow::Owned(code.to_string()),
+        None => Cow::Borrowed(value),
+    }
+}
+
+/// The value stored as `stored` by `encode_enum`.
+pub fn decode_enum<'a>(datatype: &str, stored: &'a str) -> Cow<'a, str> {
+    let member = enum_members(datatype).and_then(|members| {
+        let code: usize = stored.parse().ok()?;
+        members.get(code).map(|m| m.to_string())
+    });
+    member.map_or(Cow::Borrowed(stored), Cow::Owned)
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Table {
-    pub columns: HashSet<String>,  // List of allowed column names
+    pub columns: IndexSet<String>, // Allowed column names, in the order they were added
     pub rows: BTreeMap<String, HashMap<String, String>>, // row_id -> { column_name -> value }
     pub row_datatypes: HashMap<String, String>, // column_name -> datatype
+    pub encrypted_columns: HashSet<String>, // columns stored as ciphertext on disk
+    pub column_stats: HashMap<String, ColumnStats>, // filled in by Database::analyze
+    pub collations: HashMap<String, Collation>, // columns not listed use binary order
+    pub row_ids: RowIdGenerator, // how rows inserted without an id get one
+    pub autosave: Autosave, // unset fields use the config's save settings
 }
 
 impl Table {
     pub fn new() -> Self {
         Table {
-            columns: HashSet::new(),
+            columns: IndexSet::new(),
             rows: BTreeMap::new(),
             row_datatypes: HashMap::new(),
+            encrypted_columns: HashSet::new(),
+            column_stats: HashMap::new(),
+            collations: HashMap::new(),
+            row_ids: RowIdGenerator::default(),
+            autosave: Autosave::default(),
         }
     }
 
+    /// Approximate heap bytes held by row ids, column names and values.
+    pub fn approx_size(&self) -> usize {
+        self.rows
+            .iter()
+            .map(|(row_id, row)| Self::row_size(row_id, row))
+            .sum()
+    }
+
+    /// Approximate bytes of one row: its id plus every column name and value.
+    pub fn row_size(row_id: &str, row: &HashMap<String, String>) -> usize {
+        row_id.len() + row.iter().map(|(c, v)| c.len() + v.len()).sum::<usize>()
+    }
+
+    /// Whether deleting rows of this table only marks them deleted.
+    pub fn soft_deletes(&self) -> bool {
+        self.columns.contains(DELETED_AT)
+    }
+
+    /// Whether rows of this table belong to tenants.
+    pub fn tenant_scoped(&self) -> bool {
+        self.columns.contains(TENANT_ID)
+    }
+
+    /// When `row` was soft-deleted, if it was.
+    pub fn deleted_at(row: &HashMap<String, String>) -> Option<u64> {
+        row.get(DELETED_AT).and_then(|at| at.parse().ok())
+    }
+
+    /// A column's declared datatype: from the datatypes row, which is what
+    /// table files and the WAL keep, else from `row_datatypes`.
+    pub fn datatype(&self, column: &str) -> Option<&str> {
+        self.rows
+            .get("datatypes")
+            .and_then(|row| row.get(column))
+            .filter(|datatype| !datatype.is_empty())
+            .or_else(|| self.row_datatypes.get(column))
+            .map(String::as_str)
+    }
+
+    /// The allowed values of a column declared as an enum.
+    pub fn enum_values(&self, column: &str) -> Option<Vec<&str>> {
+        self.datatype(column).and_then(enum_members)
+    }
+
     /// Add a new column to the table. Existing rows do not automatically get a value for this column.
     pub fn add_column(&mut self, column_name: &str) {
         self.columns.insert(column_name.to_string());
@@ -25,10 +120,10 @@ impl Table {
 
     pub fn add_datatype(&mut self, column_name: &str, datatype: &str) {
         if self.row_datatypes.contains_key(column_name) {
-            println!(" - already exists");
+            debug!("Datatype for column {} already exists", column_name);
             return;
         }
-        println!("Adding datatype {} to column {}", datatype, column_name);
+        debug!("Adding datatype {} to column {}", datatype, column_name);
         self.row_datatypes.insert(column_name.to_string(), datatype.to_string());
     }
 
@@ -75,10 +170,8 @@ impl Table {
 
 impl fmt::Display for Table {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // Sort columns for predictable order
-        let mut cols: Vec<&String> = self.columns.iter().collect();
-        cols.sort();
-        
+        let cols: Vec<&String> = self.columns.iter().collect();
+
         // Write header row
         write!(f, "{:<10}", "Row ID")?;
         for col in &cols {